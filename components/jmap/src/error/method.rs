@@ -45,6 +45,7 @@ pub enum MethodError {
     AccountNotFound,
     AccountNotSupportedByMethod,
     AccountReadOnly,
+    UnknownDataType(String),
     NotFound,
 }
 
@@ -84,6 +85,7 @@ impl Display for MethodError {
                 write!(f, "Account not supported by method")
             }
             MethodError::AccountReadOnly => write!(f, "Account read only"),
+            MethodError::UnknownDataType(err) => write!(f, "Unknown data type: {}", err),
             MethodError::NotFound => write!(f, "Not found"),
         }
     }
@@ -172,6 +174,7 @@ impl Serialize for MethodError {
                 "accountReadOnly",
                 "This method modifies state, but the account is read-only.",
             ),
+            MethodError::UnknownDataType(description) => ("unknownDataType", description.as_str()),
         };
 
         map.serialize_entry("type", error_type)?;
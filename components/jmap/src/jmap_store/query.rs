@@ -171,6 +171,7 @@ where
         if let Some(sort) = self.request.sort.take() {
             let mut terms: Vec<Comparator> = Vec::with_capacity(sort.len());
             for comp in sort {
+                comp.collation()?;
                 terms.push(parse_fnc(comp)?);
             }
             self.comparator = Comparator::List(terms);
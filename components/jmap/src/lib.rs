@@ -50,6 +50,10 @@ pub enum URI {
     WebSocket,
     #[serde(rename(serialize = "urn:ietf:params:jmap:sieve"))]
     Sieve,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:quota"))]
+    Quota,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:blob"))]
+    Blob,
 }
 
 pub type Result<T> = std::result::Result<T, MethodError>;
@@ -92,6 +96,17 @@ pub fn sanitize_email(email: &str) -> Option<String> {
     }
 }
 
+// Splits a subaddress ("user+detail@domain") into its base address and detail
+pub fn split_subaddress<'x>(email: &'x str, separators: &str) -> Option<(String, &'x str)> {
+    let (local_part, domain) = email.rsplit_once('@')?;
+    let (user, detail) = local_part.split_once(|ch| separators.contains(ch))?;
+    if !user.is_empty() {
+        Some((format!("{}@{}", user, domain), detail))
+    } else {
+        None
+    }
+}
+
 // Basic domain sanitizer
 pub fn sanitize_domain(domain: &str) -> Option<String> {
     let mut result = String::with_capacity(domain.len());
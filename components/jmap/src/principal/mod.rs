@@ -45,6 +45,11 @@ impl Display for Property {
             Property::Capabilities => f.write_str("capabilities"),
             Property::Secret => f.write_str("secret"),
             Property::DKIM => f.write_str("dkim"),
+            Property::DKIMAlgorithm => f.write_str("dkimAlgorithm"),
+            Property::DKIMRecord => f.write_str("dkimRecord"),
+            Property::DKIMRotation => f.write_str("dkimRotation"),
+            Property::CatchAll => f.write_str("catchAll"),
+            Property::AliasPatterns => f.write_str("aliasPatterns"),
             Property::Quota => f.write_str("quota"),
             Property::Picture => f.write_str("picture"),
             Property::Members => f.write_str("members"),
@@ -78,6 +83,11 @@ impl From<FieldId> for Property {
             11 => Property::Picture,
             12 => Property::Members,
             13 => Property::ACL,
+            15 => Property::DKIMAlgorithm,
+            16 => Property::DKIMRecord,
+            17 => Property::DKIMRotation,
+            18 => Property::CatchAll,
+            19 => Property::AliasPatterns,
             _ => Property::Invalid,
         }
     }
@@ -107,6 +117,11 @@ impl Property {
             "secret" => Property::Secret,
             "aliases" => Property::Aliases,
             "dkim" => Property::DKIM,
+            "dkimAlgorithm" => Property::DKIMAlgorithm,
+            "dkimRecord" => Property::DKIMRecord,
+            "dkimRotation" => Property::DKIMRotation,
+            "catchAll" => Property::CatchAll,
+            "aliasPatterns" => Property::AliasPatterns,
             "quota" => Property::Quota,
             "picture" => Property::Picture,
             "members" => Property::Members,
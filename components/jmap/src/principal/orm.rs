@@ -92,10 +92,21 @@ impl orm::Value for Value {
                     + std::mem::size_of::<i64>()
             }
             Value::Members { value } => value.len() * std::mem::size_of::<JMAPId>(),
+            Value::AliasPatterns { value } => value.iter().fold(0, |acc, (k, _)| {
+                acc + k.len() + std::mem::size_of::<JMAPId>()
+            }),
             Value::ACL(value) => value.iter().fold(0, |acc, (k, v)| {
                 acc + k.len() + v.len() * std::mem::size_of::<ACL>()
             }),
             Value::Patch(_) => std::mem::size_of::<Patch>(),
+            Value::DKIMRotation { value } => value.retired.iter().fold(
+                value.pending.as_ref().map_or(0, |pending| {
+                    pending.selector.len() + pending.record.len() + pending.secret.len()
+                }) + std::mem::size_of::<i64>(),
+                |acc, item| {
+                    acc + item.selector.len() + item.record.len() + std::mem::size_of::<i64>()
+                },
+            ),
             Value::Null => 0,
         }
     }
@@ -169,6 +180,7 @@ impl Object for Principal {
             (Property::Timezone, 100),
             (Property::Secret, 2048),
             (Property::DKIM, 100),
+            (Property::DKIMAlgorithm, 100),
         ]
     }
 }
@@ -52,6 +52,11 @@ pub enum Property {
     Members = 12,
     ACL = 13,
     Invalid = 14,
+    DKIMAlgorithm = 15,
+    DKIMRecord = 16,
+    DKIMRotation = 17,
+    CatchAll = 18,
+    AliasPatterns = 19,
 }
 
 pub const ACCOUNTS_TO_DELETE: u8 = u8::MAX;
@@ -82,6 +87,29 @@ pub struct DKIM {
     pub dkim_expiration: Option<i64>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DKIMRotation {
+    pub created: i64,
+    pub auto_rotate: bool,
+    pub retired: Vec<DKIMRetired>,
+    pub pending: Option<DKIMPending>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DKIMPending {
+    pub selector: String,
+    pub record: String,
+    pub secret: String,
+    pub activate: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DKIMRetired {
+    pub selector: String,
+    pub record: String,
+    pub until: i64,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Value {
     Id { value: JMAPId },
@@ -95,6 +123,8 @@ pub enum Value {
     ACL(VecMap<String, Vec<ACL>>),
     Patch(Patch),
     Null,
+    DKIMRotation { value: DKIMRotation },
+    AliasPatterns { value: VecMap<String, JMAPId> },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -54,7 +54,8 @@ impl Serialize for Principal {
                 Value::Blob { value } => map.serialize_entry(name, value)?,
                 Value::DKIM { value } => map.serialize_entry(name, value)?,
                 Value::ACL(value) => map.serialize_entry(name, value)?,
-                Value::Patch(_) => (),
+                Value::AliasPatterns { value } => map.serialize_entry(name, value)?,
+                Value::Patch(_) | Value::DKIMRotation { .. } => (),
             }
         }
 
@@ -170,6 +171,46 @@ impl<'de> serde::de::Visitor<'de> for PrincipalVisitor {
                         },
                     );
                 }
+                "dkimAlgorithm" => {
+                    properties.append(
+                        Property::DKIMAlgorithm,
+                        if let Some(value) = map.next_value::<Option<String>>()? {
+                            Value::Text { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "dkimRecord" => {
+                    properties.append(
+                        Property::DKIMRecord,
+                        if let Some(value) = map.next_value::<Option<String>>()? {
+                            Value::Text { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "catchAll" => {
+                    properties.append(
+                        Property::CatchAll,
+                        if let Some(value) = map.next_value::<Option<JMAPId>>()? {
+                            Value::Id { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "aliasPatterns" => {
+                    properties.append(
+                        Property::AliasPatterns,
+                        if let Some(value) = map.next_value::<Option<VecMap<String, JMAPId>>>()? {
+                            Value::AliasPatterns { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
                 "members" => {
                     properties.append(
                         Property::Members,
@@ -30,7 +30,11 @@ use store::{
     AccountId, JMAPStore, Store,
 };
 
-use crate::{error::set::SetError, orm::serialize::JMAPOrm, sanitize_email, SUPERUSER_ID};
+use crate::{
+    error::set::{SetError, SetErrorType},
+    orm::serialize::JMAPOrm,
+    sanitize_email, SUPERUSER_ID,
+};
 
 use super::schema::{Principal, Property, Value};
 
@@ -40,6 +44,8 @@ where
 {
     fn principal_to_email(&self, id: AccountId) -> crate::Result<Option<String>>;
     fn principal_to_id<U>(&self, email: &str) -> crate::error::set::Result<AccountId, U>;
+    fn principal_quota(&self, id: AccountId) -> store::Result<Option<i64>>;
+    fn quota_check<U>(&self, id: AccountId, size: u64) -> crate::error::set::Result<(), U>;
 }
 
 impl<T> JMAPPrincipals<T> for JMAPStore<T>
@@ -83,4 +89,24 @@ where
                 .with_description(format!("E-mail {:?} does not exist.", email))
         })
     }
+
+    fn principal_quota(&self, id: AccountId) -> store::Result<Option<i64>> {
+        Ok(self
+            .get_orm::<Principal>(SUPERUSER_ID, id)?
+            .and_then(|mut p| p.remove(&Property::Quota))
+            .and_then(|p| match p {
+                Value::Number { value } if value > 0 => Some(value),
+                _ => None,
+            }))
+    }
+
+    fn quota_check<U>(&self, id: AccountId, size: u64) -> crate::error::set::Result<(), U> {
+        if let Some(quota) = self.principal_quota(id)? {
+            if self.get_used_quota(id)? + size as i64 > quota {
+                return Err(SetError::new(SetErrorType::OverQuota)
+                    .with_description("Account quota exceeded."));
+            }
+        }
+        Ok(())
+    }
 }
@@ -25,6 +25,7 @@ use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use store::core::{acl::ACLToken, vec_map::VecMap};
+use store::log::changes::ChangeId;
 
 use crate::{
     error::set::SetError,
@@ -62,3 +63,232 @@ pub struct CopyBlobResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub not_copied: Option<VecMap<JMAPBlob, SetError<()>>>,
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "create")]
+    pub create: VecMap<String, UploadObject>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadObject {
+    #[serde(rename = "data")]
+    pub data: Vec<DataSourceObject>,
+
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum DataSourceObject {
+    Text {
+        #[serde(rename = "data:asText")]
+        value: String,
+    },
+    Base64 {
+        #[serde(rename = "data:asBase64")]
+        value: String,
+    },
+    Blob {
+        #[serde(rename = "blobId")]
+        blob_id: MaybeBlobReference,
+        #[serde(rename = "offset")]
+        offset: Option<usize>,
+        #[serde(rename = "length")]
+        length: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaybeBlobReference {
+    Value(JMAPBlob),
+    Reference(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "created")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<VecMap<String, UploadedBlob>>,
+
+    #[serde(rename = "notCreated")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_created: Option<VecMap<String, SetError<()>>>,
+
+    #[serde(skip)]
+    pub change_id: Option<ChangeId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadedBlob {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+
+    #[serde(rename = "size")]
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GetBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "ids")]
+    pub ids: Option<Vec<JMAPBlob>>,
+
+    #[serde(rename = "properties")]
+    pub properties: Option<Vec<BlobProperty>>,
+
+    #[serde(rename = "offset")]
+    pub offset: Option<usize>,
+
+    #[serde(rename = "length")]
+    pub length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum BlobProperty {
+    #[serde(rename = "data")]
+    Data,
+    #[serde(rename = "data:asText")]
+    DataAsText,
+    #[serde(rename = "data:asBase64")]
+    DataAsBase64,
+    #[serde(rename = "digest:sha")]
+    DigestSha,
+    #[serde(rename = "digest:sha-256")]
+    DigestSha256,
+    #[serde(rename = "size")]
+    Size,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GetBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "list")]
+    pub list: Vec<BlobObject>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BlobObject {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "data:asText")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_as_text: Option<Option<String>>,
+
+    #[serde(rename = "data:asBase64")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_as_base64: Option<String>,
+
+    #[serde(rename = "digest:sha")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_sha: Option<String>,
+
+    #[serde(rename = "digest:sha-256")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_sha256: Option<String>,
+
+    #[serde(rename = "size")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+
+    #[serde(rename = "isEncodingProblem")]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub is_encoding_problem: bool,
+
+    #[serde(rename = "isTruncated")]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub is_truncated: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LookupBlobRequest {
+    #[serde(skip)]
+    pub acl: Option<Arc<ACLToken>>,
+
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "typeNames")]
+    pub type_names: Vec<String>,
+
+    #[serde(rename = "ids")]
+    pub ids: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupBlobResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: JMAPId,
+
+    #[serde(rename = "list")]
+    pub list: Vec<BlobLookup>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<JMAPBlob>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlobLookup {
+    #[serde(rename = "id")]
+    pub id: JMAPBlob,
+
+    #[serde(rename = "matchedIds")]
+    pub matched_ids: VecMap<String, Vec<JMAPId>>,
+}
+
+struct MaybeBlobReferenceVisitor;
+
+impl<'de> serde::de::Visitor<'de> for MaybeBlobReferenceVisitor {
+    type Value = MaybeBlobReference;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a valid blob id or creation id reference")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(if let Some(reference) = v.strip_prefix('#') {
+            MaybeBlobReference::Reference(reference.to_string())
+        } else {
+            MaybeBlobReference::Value(JMAPBlob::parse(v).ok_or_else(|| {
+                serde::de::Error::custom(format!("Failed to parse blob id '{}'", v))
+            })?)
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for MaybeBlobReference {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(MaybeBlobReferenceVisitor)
+    }
+}
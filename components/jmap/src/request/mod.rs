@@ -168,6 +168,9 @@ impl MaybeIdReference {
 pub enum Method {
     Echo,
     CopyBlob,
+    GetBlob,
+    LookupBlob,
+    UploadBlob,
     GetPushSubscription,
     SetPushSubscription,
     GetMailbox,
@@ -203,6 +206,29 @@ pub enum Method {
     GetPrincipal,
     SetPrincipal,
     QueryPrincipal,
+    GetQuota,
+    ChangesQuota,
+    QueryQuota,
+    GetAddressBook,
+    ChangesAddressBook,
+    QueryAddressBook,
+    QueryChangesAddressBook,
+    SetAddressBook,
+    GetContactCard,
+    ChangesContactCard,
+    QueryContactCard,
+    QueryChangesContactCard,
+    SetContactCard,
+    GetCalendar,
+    ChangesCalendar,
+    QueryCalendar,
+    QueryChangesCalendar,
+    SetCalendar,
+    GetCalendarEvent,
+    ChangesCalendarEvent,
+    QueryCalendarEvent,
+    QueryChangesCalendarEvent,
+    SetCalendarEvent,
     Error,
 }
 
@@ -215,6 +241,9 @@ impl serde::Serialize for Method {
         serializer.serialize_str(match self {
             Method::Echo => "Core/echo",
             Method::CopyBlob => "Blob/copy",
+            Method::GetBlob => "Blob/get",
+            Method::LookupBlob => "Blob/lookup",
+            Method::UploadBlob => "Blob/upload",
             Method::GetPushSubscription => "PushSubscription/get",
             Method::SetPushSubscription => "PushSubscription/set",
             Method::GetMailbox => "Mailbox/get",
@@ -250,6 +279,29 @@ impl serde::Serialize for Method {
             Method::GetPrincipal => "Principal/get",
             Method::SetPrincipal => "Principal/set",
             Method::QueryPrincipal => "Principal/query",
+            Method::GetQuota => "Quota/get",
+            Method::ChangesQuota => "Quota/changes",
+            Method::QueryQuota => "Quota/query",
+            Method::GetAddressBook => "AddressBook/get",
+            Method::ChangesAddressBook => "AddressBook/changes",
+            Method::QueryAddressBook => "AddressBook/query",
+            Method::QueryChangesAddressBook => "AddressBook/queryChanges",
+            Method::SetAddressBook => "AddressBook/set",
+            Method::GetContactCard => "ContactCard/get",
+            Method::ChangesContactCard => "ContactCard/changes",
+            Method::QueryContactCard => "ContactCard/query",
+            Method::QueryChangesContactCard => "ContactCard/queryChanges",
+            Method::SetContactCard => "ContactCard/set",
+            Method::GetCalendar => "Calendar/get",
+            Method::ChangesCalendar => "Calendar/changes",
+            Method::QueryCalendar => "Calendar/query",
+            Method::QueryChangesCalendar => "Calendar/queryChanges",
+            Method::SetCalendar => "Calendar/set",
+            Method::GetCalendarEvent => "CalendarEvent/get",
+            Method::ChangesCalendarEvent => "CalendarEvent/changes",
+            Method::QueryCalendarEvent => "CalendarEvent/query",
+            Method::QueryChangesCalendarEvent => "CalendarEvent/queryChanges",
+            Method::SetCalendarEvent => "CalendarEvent/set",
             Method::Error => "error",
         })
     }
@@ -271,6 +323,9 @@ impl<'de> serde::de::Visitor<'de> for MethodVisitor {
         Ok(match v {
             "Core/echo" => Method::Echo,
             "Blob/copy" => Method::CopyBlob,
+            "Blob/get" => Method::GetBlob,
+            "Blob/lookup" => Method::LookupBlob,
+            "Blob/upload" => Method::UploadBlob,
             "PushSubscription/get" => Method::GetPushSubscription,
             "PushSubscription/set" => Method::SetPushSubscription,
             "Mailbox/get" => Method::GetMailbox,
@@ -306,6 +361,29 @@ impl<'de> serde::de::Visitor<'de> for MethodVisitor {
             "Principal/get" => Method::GetPrincipal,
             "Principal/set" => Method::SetPrincipal,
             "Principal/query" => Method::QueryPrincipal,
+            "Quota/get" => Method::GetQuota,
+            "Quota/changes" => Method::ChangesQuota,
+            "Quota/query" => Method::QueryQuota,
+            "AddressBook/get" => Method::GetAddressBook,
+            "AddressBook/changes" => Method::ChangesAddressBook,
+            "AddressBook/query" => Method::QueryAddressBook,
+            "AddressBook/queryChanges" => Method::QueryChangesAddressBook,
+            "AddressBook/set" => Method::SetAddressBook,
+            "ContactCard/get" => Method::GetContactCard,
+            "ContactCard/changes" => Method::ChangesContactCard,
+            "ContactCard/query" => Method::QueryContactCard,
+            "ContactCard/queryChanges" => Method::QueryChangesContactCard,
+            "ContactCard/set" => Method::SetContactCard,
+            "Calendar/get" => Method::GetCalendar,
+            "Calendar/changes" => Method::ChangesCalendar,
+            "Calendar/query" => Method::QueryCalendar,
+            "Calendar/queryChanges" => Method::QueryChangesCalendar,
+            "Calendar/set" => Method::SetCalendar,
+            "CalendarEvent/get" => Method::GetCalendarEvent,
+            "CalendarEvent/changes" => Method::ChangesCalendarEvent,
+            "CalendarEvent/query" => Method::QueryCalendarEvent,
+            "CalendarEvent/queryChanges" => Method::QueryChangesCalendarEvent,
+            "CalendarEvent/set" => Method::SetCalendarEvent,
             _ => Method::Error,
         })
     }
@@ -28,9 +28,10 @@ use std::{
 };
 
 use serde::Deserialize;
-use store::core::acl::ACLToken;
+use store::{core::acl::ACLToken, read::comparator::Collation};
 
 use crate::{
+    error::method::MethodError,
     jmap_store::query::QueryObject,
     types::json_pointer::{JSONPointer, JSONPointerEval},
     types::{jmap::JMAPId, state::JMAPState},
@@ -109,6 +110,18 @@ pub struct Comparator<A> {
     pub property: A,
 }
 
+impl<A> Comparator<A> {
+    pub fn collation(&self) -> crate::Result<Collation> {
+        if let Some(collation) = &self.collation {
+            Collation::parse(collation).ok_or_else(|| {
+                MethodError::UnsupportedSort(format!("Collation '{}' is not supported.", collation))
+            })
+        } else {
+            Ok(Collation::default())
+        }
+    }
+}
+
 fn is_true() -> bool {
     true
 }
@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::schema::{Calendar, Comparator, Filter, Property};
+use crate::calendar_event::sharing::JMAPShareCalendars;
+use jmap::error::method::MethodError;
+use jmap::jmap_store::query::{ExtraFilterFnc, QueryHelper, QueryObject};
+use jmap::request::query::{QueryRequest, QueryResponse};
+use jmap::request::ACLEnforce;
+use store::core::acl::ACL;
+use store::core::tag::Tag;
+use store::read::comparator::{self, FieldComparator};
+use store::read::default_filter_mapper;
+use store::read::filter::{self, Query};
+use store::Store;
+use store::{AccountId, JMAPStore};
+
+impl QueryObject for Calendar {
+    type QueryArguments = ();
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPCalendarQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_query(&self, request: QueryRequest<Calendar>) -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPCalendarQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_query(&self, request: QueryRequest<Calendar>) -> jmap::Result<QueryResponse> {
+        let mut helper = QueryHelper::new(
+            self,
+            request,
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.calendars_shared_calendars(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let primary_account_id = helper.request.acl.as_ref().unwrap().primary_id();
+
+        helper.parse_filter(|filter| {
+            Ok(match filter {
+                Filter::Name { value } => {
+                    filter::Filter::eq(Property::Name.into(), Query::Tokenize(value.to_lowercase()))
+                }
+                Filter::IsDefault { value } => {
+                    let filter =
+                        filter::Filter::eq(Property::IsDefault.into(), Query::Tag(Tag::Default));
+                    if !value {
+                        filter::Filter::not(vec![filter])
+                    } else {
+                        filter
+                    }
+                }
+                Filter::IsSubscribed { value } => {
+                    let filter = filter::Filter::eq(
+                        Property::IsSubscribed.into(),
+                        Query::Integer(primary_account_id),
+                    );
+                    if !value {
+                        filter::Filter::not(vec![filter])
+                    } else {
+                        filter
+                    }
+                }
+                Filter::Unsupported { value } => {
+                    return Err(MethodError::UnsupportedFilter(value));
+                }
+            })
+        })?;
+
+        helper.parse_comparator(|comparator| {
+            Ok(comparator::Comparator::Field(FieldComparator {
+                field: {
+                    match comparator.property {
+                        Comparator::Name => Property::Name,
+                        Comparator::SortOrder => Property::SortOrder,
+                    }
+                }
+                .into(),
+                ascending: comparator.is_ascending,
+            }))
+        })?;
+
+        helper.query(default_filter_mapper, None::<ExtraFilterFnc>)
+    }
+}
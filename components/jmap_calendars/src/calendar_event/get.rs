@@ -0,0 +1,317 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::recurrence::{format_local_datetime, EventTime, Occurrence};
+use super::schema::{CalendarEvent, Property, Value};
+use super::set::apply_patch;
+use super::sharing::JMAPShareCalendars;
+use chrono_tz::Tz;
+use jmap::jmap_store::get::{default_mapper, GetHelper, GetObject};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::request::get::{GetRequest, GetResponse};
+use jmap::request::MaybeIdReference;
+use jmap::types::date::JMAPDate;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::error::StoreError;
+use store::core::vec_map::VecMap;
+use store::{AccountId, JMAPStore, Store};
+
+#[derive(Debug, Clone, Default)]
+pub struct GetArguments {
+    pub time_zone: Option<String>,
+}
+
+impl GetObject for CalendarEvent {
+    type GetArguments = GetArguments;
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::CalendarIds,
+            Property::Type,
+            Property::Uid,
+            Property::RelatedTo,
+            Property::ProdId,
+            Property::Created,
+            Property::Updated,
+            Property::Sequence,
+            Property::Method,
+            Property::Title,
+            Property::Description,
+            Property::DescriptionContentType,
+            Property::ShowWithoutTime,
+            Property::Locations,
+            Property::VirtualLocations,
+            Property::Links,
+            Property::Locale,
+            Property::Keywords,
+            Property::Categories,
+            Property::Color,
+            Property::RecurrenceId,
+            Property::RecurrenceRules,
+            Property::ExcludedRecurrenceRules,
+            Property::RecurrenceOverrides,
+            Property::Priority,
+            Property::FreeBusyStatus,
+            Property::Privacy,
+            Property::ReplyTo,
+            Property::SentBy,
+            Property::Participants,
+            Property::UseDefaultAlerts,
+            Property::Alerts,
+            Property::Localizations,
+            Property::Start,
+            Property::Duration,
+            Property::TimeZone,
+            Property::TimeZones,
+            Property::Status,
+            Property::IsDraft,
+            Property::IsOrigin,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match self.properties.get(property)? {
+            Value::Id { value } => Some(vec![*value]),
+            Value::CalendarIds { value, .. } => {
+                Some(value.keys().filter_map(|id| Some(*id.value()?)).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetCalendarEvent<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_get(
+        &self,
+        request: GetRequest<CalendarEvent>,
+    ) -> jmap::Result<GetResponse<CalendarEvent>>;
+}
+
+impl<T> JMAPGetCalendarEvent<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_get(
+        &self,
+        request: GetRequest<CalendarEvent>,
+    ) -> jmap::Result<GetResponse<CalendarEvent>> {
+        let default_tz = request
+            .arguments
+            .time_zone
+            .as_ref()
+            .and_then(|tz| tz.parse::<Tz>().ok())
+            .unwrap_or(Tz::UTC);
+        let mut helper = GetHelper::new(
+            self,
+            request,
+            default_mapper.into(),
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.calendars_shared_events(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let account_id = helper.account_id;
+
+        // Add Id Property
+        if !helper.properties.contains(&Property::Id) {
+            helper.properties.push(Property::Id);
+        }
+
+        helper.get(|id, properties| {
+            let mut fields = self
+                .get_orm::<CalendarEvent>(account_id, id.get_document_id())?
+                .ok_or_else(|| StoreError::NotFound("CalendarEvent data not found".to_string()))?;
+            let event_time = EventTime::from_properties(|property| fields.get(property)).ok();
+
+            // Ids with a prefix refer to an occurrence of a recurring event
+            let instance_id = id.get_prefix_id();
+            let (occurrence, patch) = if instance_id > 0 {
+                let event_time = if let Some(event_time) = &event_time {
+                    event_time
+                } else {
+                    return Ok(None);
+                };
+                let occurrence = if let Some(occurrence) = event_time
+                    .recurrence_id(instance_id)
+                    .filter(|recurrence_id| {
+                        event_time.instance_id(recurrence_id) == Some(instance_id)
+                    })
+                    .and_then(|recurrence_id| event_time.occurrence(recurrence_id, default_tz))
+                {
+                    occurrence
+                } else {
+                    return Ok(None);
+                };
+                let patch = fields
+                    .get(&Property::RecurrenceOverrides)
+                    .and_then(|value| value.as_json())
+                    .and_then(|mut overrides| {
+                        overrides
+                            .get_mut(format_local_datetime(&occurrence.recurrence_id))
+                            .map(|patch| patch.take())
+                    })
+                    .and_then(|patch| match patch {
+                        serde_json::Value::Object(patch) => Some(patch),
+                        _ => None,
+                    });
+                (Some(occurrence), patch)
+            } else {
+                (
+                    event_time
+                        .as_ref()
+                        .map(|event_time| event_time.to_occurrence(event_time.start, default_tz)),
+                    None,
+                )
+            };
+
+            let mut calendar_event = VecMap::with_capacity(properties.len());
+
+            for property in properties {
+                let value = match property {
+                    Property::Id => Value::Id { value: id },
+                    Property::Type => Value::Text {
+                        value: "Event".to_string(),
+                    },
+                    Property::CalendarIds => Value::CalendarIds {
+                        value: fields
+                            .get_tags(&Property::CalendarIds)
+                            .map(|tags| {
+                                tags.iter()
+                                    .map(|tag| (MaybeIdReference::Value(tag.as_id().into()), true))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        set: true,
+                    },
+                    Property::UtcStart | Property::UtcEnd => match &occurrence {
+                        Some(Occurrence {
+                            utc_start, utc_end, ..
+                        }) => Value::Date {
+                            value: JMAPDate::from_timestamp(if property == &Property::UtcStart {
+                                *utc_start
+                            } else {
+                                *utc_end
+                            }),
+                        },
+                        None => Value::Null,
+                    },
+                    Property::RangeStart | Property::RangeEnd | Property::Invalid => continue,
+                    _ if instance_id > 0 => match property {
+                        Property::RecurrenceId => Value::Text {
+                            value: format_local_datetime(
+                                &occurrence.as_ref().unwrap().recurrence_id,
+                            ),
+                        },
+                        Property::Start => Value::Text {
+                            value: format_local_datetime(&occurrence.as_ref().unwrap().start),
+                        },
+                        Property::RecurrenceRules
+                        | Property::ExcludedRecurrenceRules
+                        | Property::RecurrenceOverrides => Value::Null,
+                        _ => patch_value(
+                            *property,
+                            fields.remove(property).unwrap_or_default(),
+                            patch.as_ref(),
+                        ),
+                    },
+                    Property::RecurrenceId => Value::Null,
+                    _ => fields.remove(property).unwrap_or_default(),
+                };
+
+                calendar_event.append(*property, value);
+            }
+            Ok(Some(CalendarEvent {
+                properties: calendar_event,
+            }))
+        })
+    }
+}
+
+// Applies the recurrence override patch of an occurrence to a property
+fn patch_value(
+    property: Property,
+    value: Value,
+    patch: Option<&serde_json::Map<String, serde_json::Value>>,
+) -> Value {
+    let patch = if let Some(patch) = patch {
+        patch
+    } else {
+        return value;
+    };
+    let name = property.to_string();
+
+    if let Some(patched_value) = patch.get(&name) {
+        return json_to_value(property, patched_value);
+    } else if property.is_json() {
+        let prefix = format!("{}/", name);
+        let mut json = None;
+        for (pointer, patched_value) in patch {
+            if let Some(path) = pointer.strip_prefix(&prefix) {
+                let json = json.get_or_insert_with(|| {
+                    value
+                        .as_json()
+                        .unwrap_or_else(|| serde_json::Value::Object(Default::default()))
+                });
+                let path = path.split('/').map(|p| p.to_string()).collect::<Vec<_>>();
+                apply_patch(json, &path, patched_value.clone());
+            }
+        }
+        if let Some(json) = json {
+            return Value::Json {
+                value: json.to_string(),
+            };
+        }
+    }
+
+    value
+}
+
+fn json_to_value(property: Property, value: &serde_json::Value) -> Value {
+    match (property, value) {
+        (_, serde_json::Value::Null) => Value::Null,
+        (property, value) if property.is_json() => Value::Json {
+            value: value.to_string(),
+        },
+        (property, serde_json::Value::String(value)) if property.is_text() => Value::Text {
+            value: value.to_string(),
+        },
+        (Property::Created | Property::Updated, serde_json::Value::String(value)) => {
+            JMAPDate::parse(value)
+                .map(|value| Value::Date { value })
+                .unwrap_or_default()
+        }
+        (Property::Sequence | Property::Priority, serde_json::Value::Number(value)) => value
+            .as_u64()
+            .map(|value| Value::Number {
+                value: value as u32,
+            })
+            .unwrap_or_default(),
+        (_, serde_json::Value::Bool(value)) => Value::Bool { value: *value },
+        _ => Value::Null,
+    }
+}
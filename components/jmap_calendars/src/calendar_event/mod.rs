@@ -0,0 +1,102 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+pub mod changes;
+pub mod get;
+pub mod query;
+pub mod raft;
+pub mod recurrence;
+pub mod schema;
+pub mod serialize;
+pub mod set;
+pub mod sharing;
+
+use jmap::jmap_store::Object;
+use jmap::types::jmap::JMAPId;
+
+use store::core::collection::Collection;
+use store::write::options::Options;
+
+use self::schema::{CalendarEvent, Property, Value};
+
+impl Object for CalendarEvent {
+    type Property = Property;
+
+    type Value = Value;
+
+    fn id(&self) -> Option<&JMAPId> {
+        self.properties.get(&Property::Id).and_then(|id| match id {
+            Value::Id { value } => Some(value),
+            _ => None,
+        })
+    }
+
+    fn required() -> &'static [Self::Property] {
+        &[Property::Uid, Property::Start]
+    }
+
+    fn indexed() -> &'static [(Self::Property, u64)] {
+        &[
+            (Property::Uid, <u64 as Options>::F_KEYWORD),
+            (Property::Created, <u64 as Options>::F_INDEX),
+            (Property::Updated, <u64 as Options>::F_INDEX),
+            (Property::RangeStart, <u64 as Options>::F_INDEX),
+            (Property::RangeEnd, <u64 as Options>::F_INDEX),
+            (Property::Title, <u64 as Options>::F_TOKENIZE),
+            (Property::Description, <u64 as Options>::F_TOKENIZE),
+            (Property::Locations, <u64 as Options>::F_TOKENIZE),
+            (Property::VirtualLocations, <u64 as Options>::F_TOKENIZE),
+            (Property::Participants, <u64 as Options>::F_TOKENIZE),
+        ]
+    }
+
+    fn max_len() -> &'static [(Self::Property, usize)] {
+        &[
+            (Property::Uid, 255),
+            (Property::ProdId, 255),
+            (Property::Method, 255),
+            (Property::Title, 1024),
+            (Property::DescriptionContentType, 255),
+            (Property::Locale, 255),
+            (Property::Color, 255),
+            (Property::FreeBusyStatus, 255),
+            (Property::Privacy, 255),
+            (Property::SentBy, 255),
+            (Property::Start, 255),
+            (Property::Duration, 255),
+            (Property::TimeZone, 255),
+            (Property::Status, 255),
+        ]
+    }
+
+    fn collection() -> Collection {
+        Collection::CalendarEvent
+    }
+
+    fn new(id: JMAPId) -> Self {
+        let mut item = CalendarEvent::default();
+        item.properties
+            .append(Property::Id, Value::Id { value: id });
+        item
+    }
+}
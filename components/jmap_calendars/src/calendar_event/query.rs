@@ -0,0 +1,281 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::recurrence::{EventTime, MAX_TZ_OFFSET};
+use super::schema::{CalendarEvent, Comparator, Filter, Property};
+use super::sharing::JMAPShareCalendars;
+use chrono_tz::Tz;
+use jmap::error::method::MethodError;
+use jmap::jmap_store::query::{QueryHelper, QueryObject};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::request::query::{self, Operator, QueryRequest, QueryResponse};
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::tag::Tag;
+use store::read::comparator::{self, FieldComparator};
+use store::read::default_filter_mapper;
+use store::read::filter::{self, Query};
+use store::{AccountId, JMAPStore, LongInteger, Store};
+
+#[derive(Debug, Clone, serde::Deserialize, Default)]
+pub struct QueryArguments {
+    #[serde(rename = "expandRecurrences")]
+    expand_recurrences: Option<bool>,
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+}
+
+impl QueryObject for CalendarEvent {
+    type QueryArguments = QueryArguments;
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPCalendarEventQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_query(
+        &self,
+        request: QueryRequest<CalendarEvent>,
+    ) -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPCalendarEventQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_query(
+        &self,
+        request: QueryRequest<CalendarEvent>,
+    ) -> jmap::Result<QueryResponse> {
+        let mut helper = QueryHelper::new(
+            self,
+            request,
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.calendars_shared_events(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let account_id = helper.account_id;
+        let expand_recurrences = helper.request.arguments.expand_recurrences.unwrap_or(false);
+        let default_tz = match &helper.request.arguments.time_zone {
+            Some(time_zone) => time_zone.parse::<Tz>().map_err(|_| {
+                MethodError::InvalidArguments(format!("Unsupported time zone '{}'.", time_zone))
+            })?,
+            None => Tz::UTC,
+        };
+
+        // Time-range conditions at the top level of the filter
+        let mut after = None;
+        let mut before = None;
+        match &helper.request.filter {
+            Some(query::Filter::FilterCondition(condition)) => {
+                time_range(condition, &mut after, &mut before)
+            }
+            Some(query::Filter::FilterOperator(op)) if op.operator == Operator::And => {
+                for condition in &op.conditions {
+                    match condition {
+                        query::Filter::FilterCondition(condition) => {
+                            time_range(condition, &mut after, &mut before)
+                        }
+                        query::Filter::FilterOperator(op) => reject_nested_time_range(op)?,
+                        query::Filter::Empty => (),
+                    }
+                }
+            }
+            Some(query::Filter::FilterOperator(op)) => reject_nested_time_range(op)?,
+            _ => (),
+        }
+        if expand_recurrences && (after.is_none() || before.is_none()) {
+            return Err(MethodError::InvalidArguments(
+                "Expanding recurrences requires both 'after' and 'before' filters.".to_string(),
+            ));
+        }
+
+        helper.parse_filter(|filter| {
+            Ok(match filter {
+                Filter::InCalendars { value } => filter::Filter::or(
+                    value
+                        .into_iter()
+                        .map(|calendar_id| {
+                            filter::Filter::eq(
+                                Property::CalendarIds.into(),
+                                Query::Tag(Tag::Id(calendar_id.get_document_id())),
+                            )
+                        })
+                        .collect(),
+                ),
+                // Stored ranges treat floating times as UTC, widen them by the
+                // maximum UTC offset and apply the exact match afterwards.
+                Filter::After { value } => filter::Filter::gt(
+                    Property::RangeEnd.into(),
+                    Query::LongInteger((value.timestamp() - MAX_TZ_OFFSET).max(0) as LongInteger),
+                ),
+                Filter::Before { value } => filter::Filter::lt(
+                    Property::RangeStart.into(),
+                    Query::LongInteger((value.timestamp() + MAX_TZ_OFFSET).max(0) as LongInteger),
+                ),
+                Filter::Text { value } => filter::Filter::or(
+                    [
+                        Property::Uid,
+                        Property::Title,
+                        Property::Description,
+                        Property::Locations,
+                        Property::VirtualLocations,
+                        Property::Participants,
+                    ]
+                    .into_iter()
+                    .map(|property| {
+                        filter::Filter::eq(
+                            property.into(),
+                            if property == Property::Uid {
+                                Query::Keyword(value.clone())
+                            } else {
+                                Query::Tokenize(value.clone())
+                            },
+                        )
+                    })
+                    .collect(),
+                ),
+                Filter::Title { value } => {
+                    filter::Filter::eq(Property::Title.into(), Query::Tokenize(value))
+                }
+                Filter::Description { value } => {
+                    filter::Filter::eq(Property::Description.into(), Query::Tokenize(value))
+                }
+                Filter::Location { value } => filter::Filter::or(vec![
+                    filter::Filter::eq(Property::Locations.into(), Query::Tokenize(value.clone())),
+                    filter::Filter::eq(Property::VirtualLocations.into(), Query::Tokenize(value)),
+                ]),
+                Filter::Owner { value } | Filter::Attendee { value } => {
+                    filter::Filter::eq(Property::Participants.into(), Query::Tokenize(value))
+                }
+                Filter::Uid { value } => {
+                    filter::Filter::eq(Property::Uid.into(), Query::Keyword(value))
+                }
+                Filter::Unsupported { value } => {
+                    return Err(MethodError::UnsupportedFilter(value));
+                }
+            })
+        })?;
+
+        helper.parse_comparator(|comparator| {
+            Ok(comparator::Comparator::Field(FieldComparator {
+                field: {
+                    match comparator.property {
+                        Comparator::Start => Property::RangeStart,
+                        Comparator::Uid => Property::Uid,
+                        Comparator::Created => Property::Created,
+                        Comparator::Updated => Property::Updated,
+                    }
+                }
+                .into(),
+                ascending: comparator.is_ascending,
+            }))
+        })?;
+
+        helper.query(
+            default_filter_mapper,
+            if after.is_some() || before.is_some() {
+                Some(|ids: Vec<JMAPId>| {
+                    let after = after.unwrap_or(i64::MIN);
+                    let before = before.unwrap_or(i64::MAX);
+                    let mut results = Vec::with_capacity(ids.len());
+
+                    for id in ids {
+                        let document_id = id.get_document_id();
+                        let event_time = if let Some(event_time) = self
+                            .get_orm::<CalendarEvent>(account_id, document_id)?
+                            .and_then(|fields| {
+                                EventTime::from_properties(|property| fields.get(property)).ok()
+                            }) {
+                            event_time
+                        } else {
+                            continue;
+                        };
+
+                        if expand_recurrences && event_time.is_recurrent() {
+                            // Return one id per occurrence within the time range
+                            for occurrence in event_time.occurrences(after, before, default_tz) {
+                                if let Some(instance_id) =
+                                    event_time.instance_id(&occurrence.recurrence_id)
+                                {
+                                    results.push((
+                                        occurrence.utc_start,
+                                        JMAPId::from_parts(instance_id, document_id),
+                                    ));
+                                }
+                            }
+                        } else if event_time.is_infinite() && before == i64::MAX {
+                            results.push((i64::MIN, id));
+                        } else if let Some(occurrence) = event_time
+                            .occurrences(after, before, default_tz)
+                            .into_iter()
+                            .next()
+                        {
+                            results.push((occurrence.utc_start, id));
+                        }
+                    }
+
+                    // Expanded occurrences are returned in chronological order
+                    if expand_recurrences {
+                        results.sort_by_key(|(utc_start, _)| *utc_start);
+                    }
+
+                    Ok(results.into_iter().map(|(_, id)| id).collect())
+                })
+            } else {
+                None
+            },
+        )
+    }
+}
+
+// Occurrences are matched against a single time range, which cannot be
+// derived from conditions nested under other operators.
+fn reject_nested_time_range(op: &query::FilterOperator<Filter>) -> jmap::Result<()> {
+    for condition in &op.conditions {
+        match condition {
+            query::Filter::FilterCondition(Filter::After { .. } | Filter::Before { .. }) => {
+                return Err(MethodError::UnsupportedFilter(
+                    "'after' and 'before' are only supported at the top level or under 'AND'."
+                        .to_string(),
+                ));
+            }
+            query::Filter::FilterOperator(op) => reject_nested_time_range(op)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn time_range(condition: &Filter, after: &mut Option<i64>, before: &mut Option<i64>) {
+    match condition {
+        Filter::After { value } => *after = value.timestamp().into(),
+        Filter::Before { value } => *before = value.timestamp().into(),
+        _ => (),
+    }
+}
@@ -0,0 +1,1148 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::collections::BTreeSet;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use super::schema::{Property, Value};
+
+// Expansion limits, protect against rules that never (or very rarely)
+// produce an occurrence and against unbounded expansions.
+const MAX_EMPTY_PERIODS: usize = 10000;
+pub const MAX_OCCURRENCES: usize = 50000;
+
+// Maximum offset between local time and UTC.
+pub const MAX_TZ_OFFSET: i64 = 14 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Yearly,
+    Monthly,
+    Weekly,
+    Daily,
+    Hourly,
+    Minutely,
+    Secondly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    Omit,
+    Backward,
+    Forward,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub skip: Skip,
+    pub first_day_of_week: Weekday,
+    pub by_day: Vec<(Weekday, Option<i32>)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub by_year_day: Vec<i32>,
+    pub by_week_no: Vec<i32>,
+    pub by_hour: Vec<u32>,
+    pub by_minute: Vec<u32>,
+    pub by_second: Vec<u32>,
+    pub by_set_position: Vec<i32>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NDay {
+    day: String,
+    #[serde(default)]
+    nth_of_period: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JSRecurrenceRule {
+    frequency: Frequency,
+    #[serde(default)]
+    interval: Option<u32>,
+    #[serde(default)]
+    rscale: Option<String>,
+    #[serde(default)]
+    skip: Option<String>,
+    #[serde(default)]
+    first_day_of_week: Option<String>,
+    #[serde(default)]
+    by_day: Vec<NDay>,
+    #[serde(default)]
+    by_month_day: Vec<i32>,
+    #[serde(default)]
+    by_month: Vec<String>,
+    #[serde(default)]
+    by_year_day: Vec<i32>,
+    #[serde(default)]
+    by_week_no: Vec<i32>,
+    #[serde(default)]
+    by_hour: Vec<u32>,
+    #[serde(default)]
+    by_minute: Vec<u32>,
+    #[serde(default)]
+    by_second: Vec<u32>,
+    #[serde(default)]
+    by_set_position: Vec<i32>,
+    #[serde(default)]
+    count: Option<u32>,
+    #[serde(default)]
+    until: Option<String>,
+}
+
+impl RecurrenceRule {
+    pub fn parse(value: &serde_json::Value) -> Option<Self> {
+        let rule = JSRecurrenceRule::deserialize(value).ok()?;
+
+        // Only the Gregorian calendar is supported
+        if !rule
+            .rscale
+            .as_ref()
+            .map_or(true, |r| r.eq_ignore_ascii_case("gregorian"))
+            || rule.count.is_some() && rule.until.is_some()
+            || rule.count == Some(0)
+            || rule.interval == Some(0)
+        {
+            return None;
+        }
+
+        let mut by_day = Vec::with_capacity(rule.by_day.len());
+        for nday in rule.by_day {
+            if matches!(nday.nth_of_period, Some(nth) if nth == 0 || !(-53..=53).contains(&nth)) {
+                return None;
+            }
+            by_day.push((parse_weekday(&nday.day)?, nday.nth_of_period));
+        }
+        let mut by_month = Vec::with_capacity(rule.by_month.len());
+        for month in rule.by_month {
+            match month.parse::<u32>() {
+                Ok(month @ 1..=12) => by_month.push(month),
+                _ => return None,
+            }
+        }
+
+        if rule
+            .by_month_day
+            .iter()
+            .any(|&d| d == 0 || !(-31..=31).contains(&d))
+            || rule
+                .by_year_day
+                .iter()
+                .any(|&d| d == 0 || !(-366..=366).contains(&d))
+            || rule
+                .by_week_no
+                .iter()
+                .any(|&d| d == 0 || !(-53..=53).contains(&d))
+            || rule
+                .by_set_position
+                .iter()
+                .any(|&d| d == 0 || !(-366..=366).contains(&d))
+            || rule.by_hour.iter().any(|&h| h > 23)
+            || rule.by_minute.iter().any(|&m| m > 59)
+            || rule.by_second.iter().any(|&s| s > 59)
+        {
+            return None;
+        }
+
+        Some(RecurrenceRule {
+            frequency: rule.frequency,
+            interval: rule.interval.unwrap_or(1),
+            skip: match rule.skip.as_deref() {
+                None | Some("omit") => Skip::Omit,
+                Some("backward") => Skip::Backward,
+                Some("forward") => Skip::Forward,
+                _ => return None,
+            },
+            first_day_of_week: match rule.first_day_of_week {
+                Some(day) => parse_weekday(&day)?,
+                None => Weekday::Mon,
+            },
+            by_day,
+            by_month_day: rule.by_month_day,
+            by_month,
+            by_year_day: rule.by_year_day,
+            by_week_no: rule.by_week_no,
+            by_hour: rule.by_hour,
+            by_minute: rule.by_minute,
+            by_second: rule.by_second,
+            by_set_position: rule.by_set_position,
+            count: rule.count,
+            until: match rule.until {
+                Some(until) => parse_local_datetime(&until)?.into(),
+                None => None,
+            },
+        })
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        self.count.is_none() && self.until.is_none()
+    }
+
+    pub fn iter(&self, start: NaiveDateTime, include_start: bool) -> RuleIterator<'_> {
+        RuleIterator {
+            rule: self,
+            start,
+            include_start,
+            period: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            emitted: 0,
+            empty_periods: 0,
+            done: false,
+        }
+    }
+
+    // Returns the candidate days of a period before applying BYSETPOS
+    fn period_days(&self, start: &NaiveDateTime, period: i64) -> Option<Vec<NaiveDate>> {
+        let interval = self.interval as i64;
+        let start_date = start.date();
+
+        let days = match self.frequency {
+            Frequency::Yearly => {
+                let year = i32::try_from(start_date.year() as i64 + period * interval).ok()?;
+                if year > 9999 {
+                    return None;
+                }
+                if self.by_week_no.is_empty()
+                    && self.by_year_day.is_empty()
+                    && self.by_month_day.is_empty()
+                    && self.by_day.is_empty()
+                {
+                    let months = if self.by_month.is_empty() {
+                        vec![start_date.month()]
+                    } else {
+                        self.by_month.clone()
+                    };
+                    let mut days = Vec::with_capacity(months.len());
+                    for month in months {
+                        if let Some(day) = self.skip_day(year, month, start_date.day()) {
+                            days.push(day);
+                        }
+                    }
+                    return Some(days);
+                }
+                days_between(
+                    NaiveDate::from_ymd_opt(year, 1, 1)?,
+                    NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+                )
+            }
+            Frequency::Monthly => {
+                let month =
+                    start_date.year() as i64 * 12 + start_date.month0() as i64 + period * interval;
+                let (year, month) = (i32::try_from(month / 12).ok()?, (month % 12) as u32 + 1);
+                if year > 9999 {
+                    return None;
+                }
+                if !self.by_month.is_empty() && !self.by_month.contains(&month) {
+                    return Some(Vec::new());
+                }
+                if self.by_month_day.is_empty()
+                    && self.by_day.is_empty()
+                    && self.by_year_day.is_empty()
+                {
+                    return Some(
+                        self.skip_day(year, month, start_date.day())
+                            .into_iter()
+                            .collect(),
+                    );
+                }
+                let first_day = NaiveDate::from_ymd_opt(year, month, 1)?;
+                days_between(first_day, first_day_of_next_month(first_day)?)
+            }
+            Frequency::Weekly => {
+                let week_start = start_date
+                    - Duration::days(days_from_week_start(
+                        start_date.weekday(),
+                        self.first_day_of_week,
+                    ))
+                    + Duration::days(period.checked_mul(interval * 7)?);
+                if week_start.year() > 9999 {
+                    return None;
+                }
+                let days = days_between(week_start, week_start + Duration::days(7));
+                if self.by_day.is_empty() {
+                    return Some(
+                        days.into_iter()
+                            .filter(|day| {
+                                day.weekday() == start_date.weekday() && self.matches_month(day)
+                            })
+                            .collect(),
+                    );
+                }
+                days
+            }
+            Frequency::Daily => {
+                let day = start_date + Duration::days(period.checked_mul(interval)?);
+                if day.year() > 9999 {
+                    return None;
+                }
+                vec![day]
+            }
+            Frequency::Hourly | Frequency::Minutely | Frequency::Secondly => {
+                let day = self.period_start(start, period)?.date();
+                if day.year() > 9999 {
+                    return None;
+                }
+                vec![day]
+            }
+        };
+
+        Some(
+            days.into_iter()
+                .filter(|day| {
+                    self.matches_month(day)
+                        && self.matches_week_no(day)
+                        && self.matches_year_day(day)
+                        && self.matches_month_day(day)
+                        && self.matches_day(day)
+                })
+                .collect(),
+        )
+    }
+
+    // Returns the start of an hourly, minutely or secondly period
+    fn period_start(&self, start: &NaiveDateTime, period: i64) -> Option<NaiveDateTime> {
+        let offset = period.checked_mul(self.interval as i64)?;
+        match self.frequency {
+            Frequency::Hourly => {
+                Some(start.date().and_hms_opt(start.hour(), 0, 0)? + Duration::hours(offset))
+            }
+            Frequency::Minutely => Some(
+                start.date().and_hms_opt(start.hour(), start.minute(), 0)?
+                    + Duration::minutes(offset),
+            ),
+            Frequency::Secondly => Some(*start + Duration::seconds(offset)),
+            _ => None,
+        }
+    }
+
+    // Expands a period into its occurrences
+    fn period_occurrences(&self, start: &NaiveDateTime, period: i64) -> Option<Vec<NaiveDateTime>> {
+        let days = self.period_days(start, period)?;
+        let period_start = self.period_start(start, period);
+        let expand_or_limit = |values: &[u32], frequency: Frequency, default: u32| {
+            if self.frequency < frequency {
+                if values.is_empty() {
+                    vec![default]
+                } else {
+                    let mut values = values.to_vec();
+                    values.sort_unstable();
+                    values.dedup();
+                    values
+                }
+            } else if values.is_empty() || values.contains(&default) {
+                vec![default]
+            } else {
+                Vec::new()
+            }
+        };
+        let (hour, minute, second) = period_start
+            .map(|p| (p.hour(), p.minute(), p.second()))
+            .unwrap_or_else(|| (start.hour(), start.minute(), start.second()));
+        let hours = expand_or_limit(&self.by_hour, Frequency::Hourly, hour);
+        let minutes = expand_or_limit(&self.by_minute, Frequency::Minutely, minute);
+        let seconds = expand_or_limit(&self.by_second, Frequency::Secondly, second);
+
+        let mut occurrences = Vec::with_capacity(days.len() * hours.len());
+        for day in days {
+            for &hour in &hours {
+                for &minute in &minutes {
+                    for &second in &seconds {
+                        if let Some(dt) = day.and_hms_opt(hour, minute, second) {
+                            occurrences.push(dt);
+                        }
+                    }
+                }
+            }
+        }
+        occurrences.sort_unstable();
+
+        if !self.by_set_position.is_empty() && !occurrences.is_empty() {
+            let total = occurrences.len() as i32;
+            let mut positions = self
+                .by_set_position
+                .iter()
+                .filter_map(|&pos| {
+                    let pos = if pos > 0 { pos - 1 } else { total + pos };
+                    if (0..total).contains(&pos) {
+                        Some(pos as usize)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            positions.sort_unstable();
+            positions.dedup();
+            occurrences = positions.into_iter().map(|pos| occurrences[pos]).collect();
+        }
+
+        Some(occurrences)
+    }
+
+    fn skip_day(&self, year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+        if let Some(date) = NaiveDate::from_ymd_opt(year, month, day) {
+            return Some(date);
+        }
+        let first_day = NaiveDate::from_ymd_opt(year, month, 1)?;
+        match self.skip {
+            Skip::Omit => None,
+            Skip::Backward => Some(first_day_of_next_month(first_day)? - Duration::days(1)),
+            Skip::Forward => first_day_of_next_month(first_day),
+        }
+    }
+
+    fn matches_month(&self, day: &NaiveDate) -> bool {
+        self.by_month.is_empty() || self.by_month.contains(&day.month())
+    }
+
+    fn matches_week_no(&self, day: &NaiveDate) -> bool {
+        if self.by_week_no.is_empty() || self.frequency != Frequency::Yearly {
+            return true;
+        }
+        if let Some((week_no, total_weeks)) = week_number(*day, self.first_day_of_week) {
+            self.by_week_no.iter().any(|&n| {
+                if n > 0 {
+                    n == week_no
+                } else {
+                    total_weeks + n + 1 == week_no
+                }
+            })
+        } else {
+            false
+        }
+    }
+
+    fn matches_year_day(&self, day: &NaiveDate) -> bool {
+        if self.by_year_day.is_empty() {
+            return true;
+        }
+        let ordinal = day.ordinal() as i32;
+        let total = days_in_year(day.year());
+        self.by_year_day.iter().any(|&n| {
+            if n > 0 {
+                n == ordinal
+            } else {
+                total + n + 1 == ordinal
+            }
+        })
+    }
+
+    fn matches_month_day(&self, day: &NaiveDate) -> bool {
+        if self.by_month_day.is_empty() {
+            return true;
+        }
+        let month_day = day.day() as i32;
+        let total = days_in_month(*day);
+        self.by_month_day.iter().any(|&n| {
+            if n > 0 {
+                n == month_day
+            } else {
+                total + n + 1 == month_day
+            }
+        })
+    }
+
+    fn matches_day(&self, day: &NaiveDate) -> bool {
+        if self.by_day.is_empty() {
+            return true;
+        }
+        let weekday = day.weekday();
+        self.by_day.iter().any(|(by_weekday, nth)| {
+            if *by_weekday != weekday {
+                return false;
+            }
+            let nth = match nth {
+                Some(nth) => *nth,
+                None => return true,
+            };
+
+            // The nth occurrence is relative to the month for monthly rules
+            // (or yearly rules with BYMONTH), and relative to the year otherwise.
+            let (position, total) = match self.frequency {
+                Frequency::Monthly => (day.day() as i32, days_in_month(*day)),
+                Frequency::Yearly if !self.by_month.is_empty() => {
+                    (day.day() as i32, days_in_month(*day))
+                }
+                Frequency::Yearly => (day.ordinal() as i32, days_in_year(day.year())),
+                _ => return true,
+            };
+            if nth > 0 {
+                (position - 1) / 7 + 1 == nth
+            } else {
+                (total - position) / 7 + 1 == -nth
+            }
+        })
+    }
+}
+
+pub struct RuleIterator<'x> {
+    rule: &'x RecurrenceRule,
+    start: NaiveDateTime,
+    include_start: bool,
+    period: i64,
+    buffer: Vec<NaiveDateTime>,
+    buffer_pos: usize,
+    emitted: u32,
+    empty_periods: usize,
+    done: bool,
+}
+
+impl<'x> Iterator for RuleIterator<'x> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // The event start is always the first occurrence
+        if self.include_start {
+            self.include_start = false;
+            self.emitted += 1;
+            return Some(self.start);
+        }
+
+        loop {
+            if self.rule.count.map_or(false, |count| self.emitted >= count) {
+                self.done = true;
+                return None;
+            }
+
+            if let Some(dt) = self.buffer.get(self.buffer_pos) {
+                let dt = *dt;
+                self.buffer_pos += 1;
+                if dt <= self.start {
+                    continue;
+                }
+                if self.rule.until.map_or(false, |until| dt > until) {
+                    self.done = true;
+                    return None;
+                }
+                self.emitted += 1;
+                return Some(dt);
+            }
+
+            match self.rule.period_occurrences(&self.start, self.period) {
+                Some(occurrences) => {
+                    self.period += 1;
+                    if !occurrences.is_empty() {
+                        self.empty_periods = 0;
+                    } else {
+                        self.empty_periods += 1;
+                        if self.empty_periods > MAX_EMPTY_PERIODS {
+                            self.done = true;
+                            return None;
+                        }
+                    }
+                    self.buffer = occurrences;
+                    self.buffer_pos = 0;
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    pub excluded: bool,
+    pub start: Option<NaiveDateTime>,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Occurrence {
+    pub recurrence_id: NaiveDateTime,
+    pub start: NaiveDateTime,
+    pub utc_start: i64,
+    pub utc_end: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventTime {
+    pub start: NaiveDateTime,
+    pub duration: Duration,
+    pub time_zone: Option<Tz>,
+    pub rules: Vec<RecurrenceRule>,
+    pub excluded_rules: Vec<RecurrenceRule>,
+    pub overrides: Vec<(NaiveDateTime, Override)>,
+}
+
+impl EventTime {
+    pub fn parse(
+        start: &str,
+        duration: Option<&str>,
+        time_zone: Option<&str>,
+        rules: Option<&serde_json::Value>,
+        excluded_rules: Option<&serde_json::Value>,
+        overrides: Option<&serde_json::Value>,
+    ) -> Result<Self, Property> {
+        Ok(EventTime {
+            start: parse_local_datetime(start).ok_or(Property::Start)?,
+            duration: match duration {
+                Some(duration) => parse_duration(duration).ok_or(Property::Duration)?,
+                None => Duration::zero(),
+            },
+            time_zone: match time_zone {
+                Some(time_zone) => Some(time_zone.parse::<Tz>().map_err(|_| Property::TimeZone)?),
+                None => None,
+            },
+            rules: parse_rules(rules).ok_or(Property::RecurrenceRules)?,
+            excluded_rules: parse_rules(excluded_rules).ok_or(Property::ExcludedRecurrenceRules)?,
+            overrides: match overrides {
+                Some(serde_json::Value::Object(overrides)) => {
+                    let mut result = Vec::with_capacity(overrides.len());
+                    for (recurrence_id, patch) in overrides {
+                        let recurrence_id = parse_local_datetime(recurrence_id)
+                            .ok_or(Property::RecurrenceOverrides)?;
+                        let patch = patch.as_object().ok_or(Property::RecurrenceOverrides)?;
+                        result.push((
+                            recurrence_id,
+                            Override {
+                                excluded: patch
+                                    .get("excluded")
+                                    .and_then(|v| v.as_bool())
+                                    .unwrap_or(false),
+                                start: match patch.get("start") {
+                                    Some(start) => Some(
+                                        start
+                                            .as_str()
+                                            .and_then(parse_local_datetime)
+                                            .ok_or(Property::RecurrenceOverrides)?,
+                                    ),
+                                    None => None,
+                                },
+                                duration: match patch.get("duration") {
+                                    Some(duration) => Some(
+                                        duration
+                                            .as_str()
+                                            .and_then(parse_duration)
+                                            .ok_or(Property::RecurrenceOverrides)?,
+                                    ),
+                                    None => None,
+                                },
+                            },
+                        ));
+                    }
+                    result.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                    result
+                }
+                Some(serde_json::Value::Null) | None => Vec::new(),
+                Some(_) => return Err(Property::RecurrenceOverrides),
+            },
+        })
+    }
+
+    pub fn from_properties<'x>(
+        get: impl Fn(&Property) -> Option<&'x Value>,
+    ) -> Result<Self, Property> {
+        let json = |property| get(&property).and_then(|value| value.as_json());
+        EventTime::parse(
+            get(&Property::Start)
+                .and_then(|value| value.as_text())
+                .ok_or(Property::Start)?,
+            get(&Property::Duration).and_then(|value| value.as_text()),
+            get(&Property::TimeZone).and_then(|value| value.as_text()),
+            json(Property::RecurrenceRules).as_ref(),
+            json(Property::ExcludedRecurrenceRules).as_ref(),
+            json(Property::RecurrenceOverrides).as_ref(),
+        )
+    }
+
+    pub fn is_recurrent(&self) -> bool {
+        !self.rules.is_empty() || !self.overrides.is_empty()
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        self.rules.iter().any(|rule| rule.is_infinite())
+    }
+
+    // Returns the recurrence ids of all occurrences up to (and including) 'until'.
+    // The second value is true when the expansion was truncated.
+    pub fn recurrence_ids(&self, until: Option<NaiveDateTime>) -> (BTreeSet<NaiveDateTime>, bool) {
+        let mut ids = BTreeSet::new();
+        let mut truncated = false;
+
+        ids.insert(self.start);
+        for rule in &self.rules {
+            for (pos, dt) in rule.iter(self.start, true).enumerate() {
+                if until.map_or(false, |until| dt > until) {
+                    break;
+                } else if pos == MAX_OCCURRENCES {
+                    truncated = true;
+                    break;
+                }
+                ids.insert(dt);
+            }
+        }
+
+        // Overrides not matching any generated recurrence id are additional occurrences
+        for (recurrence_id, _) in &self.overrides {
+            if *recurrence_id >= self.start && until.map_or(true, |until| *recurrence_id <= until) {
+                ids.insert(*recurrence_id);
+            }
+        }
+
+        for rule in &self.excluded_rules {
+            for (pos, dt) in rule.iter(self.start, false).enumerate() {
+                if until.map_or(false, |until| dt > until) || pos == MAX_OCCURRENCES {
+                    break;
+                }
+                ids.remove(&dt);
+            }
+        }
+        for (recurrence_id, patch) in &self.overrides {
+            if patch.excluded {
+                ids.remove(recurrence_id);
+            }
+        }
+
+        (ids, truncated)
+    }
+
+    pub fn occurrence(&self, recurrence_id: NaiveDateTime, default_tz: Tz) -> Option<Occurrence> {
+        if self
+            .recurrence_ids(recurrence_id.into())
+            .0
+            .contains(&recurrence_id)
+        {
+            Some(self.to_occurrence(recurrence_id, default_tz))
+        } else {
+            None
+        }
+    }
+
+    // Returns all occurrences overlapping the specified UTC time range, sorted by start time.
+    pub fn occurrences(&self, after: i64, before: i64, default_tz: Tz) -> Vec<Occurrence> {
+        let until = from_timestamp(before.saturating_add(MAX_TZ_OFFSET));
+        let mut occurrences = self
+            .recurrence_ids(until.into())
+            .0
+            .into_iter()
+            .map(|recurrence_id| self.to_occurrence(recurrence_id, default_tz))
+            .filter(|o| o.overlaps(after, before))
+            .collect::<Vec<_>>();
+
+        // Overridden occurrences might have been moved into the requested range
+        for (recurrence_id, patch) in &self.overrides {
+            if *recurrence_id > until && patch.start.is_some() && !patch.excluded {
+                if let Some(occurrence) = self.occurrence(*recurrence_id, default_tz) {
+                    if occurrence.overlaps(after, before) {
+                        occurrences.push(occurrence);
+                    }
+                }
+            }
+        }
+
+        occurrences.sort_unstable_by(|a, b| {
+            a.utc_start
+                .cmp(&b.utc_start)
+                .then_with(|| a.recurrence_id.cmp(&b.recurrence_id))
+        });
+        occurrences
+    }
+
+    // Returns the UTC time span covered by all occurrences, floating times are treated as UTC.
+    pub fn range(&self) -> (u64, u64) {
+        let master = self.to_occurrence(self.start, Tz::UTC);
+        let mut range_start = master.utc_start;
+        let mut range_end = master.utc_end;
+
+        for (recurrence_id, patch) in &self.overrides {
+            if !patch.excluded {
+                let occurrence = self.to_occurrence(*recurrence_id, Tz::UTC);
+                range_start = std::cmp::min(range_start, occurrence.utc_start);
+                range_end = std::cmp::max(range_end, occurrence.utc_end);
+            }
+        }
+
+        if self.is_infinite() {
+            return (range_start.max(0) as u64, u64::MAX);
+        }
+
+        let (recurrence_ids, truncated) = self.recurrence_ids(None);
+        if truncated {
+            return (range_start.max(0) as u64, u64::MAX);
+        }
+        for recurrence_id in recurrence_ids {
+            let occurrence = self.to_occurrence(recurrence_id, Tz::UTC);
+            range_start = std::cmp::min(range_start, occurrence.utc_start);
+            range_end = std::cmp::max(range_end, occurrence.utc_end);
+        }
+
+        (range_start.max(0) as u64, range_end.max(0) as u64)
+    }
+
+    pub fn to_occurrence(&self, recurrence_id: NaiveDateTime, default_tz: Tz) -> Occurrence {
+        let (start, duration) = match self
+            .overrides
+            .binary_search_by(|(id, _)| id.cmp(&recurrence_id))
+        {
+            Ok(pos) => {
+                let patch = &self.overrides[pos].1;
+                (
+                    patch.start.unwrap_or(recurrence_id),
+                    patch.duration.unwrap_or(self.duration),
+                )
+            }
+            Err(_) => (recurrence_id, self.duration),
+        };
+        let utc_start = to_timestamp(start, self.time_zone.unwrap_or(default_tz));
+
+        Occurrence {
+            recurrence_id,
+            start,
+            utc_start,
+            utc_end: utc_start + duration.num_seconds(),
+        }
+    }
+
+    // Instance ids encode the number of minutes between the event start and
+    // the recurrence id, plus one as zero is reserved for the master event.
+    pub fn instance_id(&self, recurrence_id: &NaiveDateTime) -> Option<u32> {
+        u32::try_from((*recurrence_id - self.start).num_minutes() + 1)
+            .ok()
+            .filter(|&id| id > 0 && id < u32::MAX)
+    }
+
+    pub fn recurrence_id(&self, instance_id: u32) -> Option<NaiveDateTime> {
+        let from = self.start + Duration::minutes(instance_id.checked_sub(1)? as i64);
+        let (ids, _) = self.recurrence_ids(Some(from + Duration::seconds(59)));
+        ids.range(from..).next().copied()
+    }
+}
+
+impl Occurrence {
+    pub fn overlaps(&self, after: i64, before: i64) -> bool {
+        self.utc_start < before && (self.utc_end > after || self.utc_start >= after)
+    }
+}
+
+fn parse_rules(rules: Option<&serde_json::Value>) -> Option<Vec<RecurrenceRule>> {
+    match rules {
+        Some(serde_json::Value::Array(rules)) => {
+            let mut result = Vec::with_capacity(rules.len());
+            for rule in rules {
+                result.push(RecurrenceRule::parse(rule)?);
+            }
+            Some(result)
+        }
+        Some(serde_json::Value::Null) | None => Some(Vec::new()),
+        Some(_) => None,
+    }
+}
+
+pub fn parse_local_datetime(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok()
+}
+
+pub fn format_local_datetime(value: &NaiveDateTime) -> String {
+    value.format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+// Parses an ISO 8601 duration such as P1W, P1DT2H or PT30M
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.strip_prefix('P')?;
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) if !time.is_empty() => (date, Some(time)),
+        Some(_) => return None,
+        None => (value, None),
+    };
+    let mut seconds: i64 = 0;
+    let mut has_value = false;
+
+    for (part, units) in [
+        (date, &[('W', 7 * 86400), ('D', 86400)][..]),
+        (time.unwrap_or(""), &[('H', 3600), ('M', 60), ('S', 1)][..]),
+    ] {
+        let mut number = String::new();
+        let mut units = units.iter();
+        for ch in part.chars() {
+            if ch.is_ascii_digit() {
+                number.push(ch);
+            } else {
+                let multiplier = units.find(|(unit, _)| *unit == ch)?.1;
+                if number.is_empty() {
+                    return None;
+                }
+                seconds =
+                    seconds.checked_add(number.parse::<i64>().ok()?.checked_mul(multiplier)?)?;
+                number.clear();
+                has_value = true;
+            }
+        }
+        if !number.is_empty() {
+            return None;
+        }
+    }
+
+    if has_value {
+        Some(Duration::seconds(seconds))
+    } else {
+        None
+    }
+}
+
+pub fn to_timestamp(value: NaiveDateTime, tz: Tz) -> i64 {
+    tz.from_local_datetime(&value)
+        .earliest()
+        // Local times falling into a DST gap are moved forward
+        .or_else(|| {
+            tz.from_local_datetime(&(value + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|dt| dt.timestamp())
+        .unwrap_or_else(|| Utc.from_utc_datetime(&value).timestamp())
+}
+
+pub fn from_timestamp(timestamp: i64) -> NaiveDateTime {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|dt| dt.naive_utc())
+        .unwrap_or(NaiveDateTime::MAX)
+}
+
+fn parse_weekday(day: &str) -> Option<Weekday> {
+    match day {
+        "mo" => Some(Weekday::Mon),
+        "tu" => Some(Weekday::Tue),
+        "we" => Some(Weekday::Wed),
+        "th" => Some(Weekday::Thu),
+        "fr" => Some(Weekday::Fri),
+        "sa" => Some(Weekday::Sat),
+        "su" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn days_from_week_start(day: Weekday, week_start: Weekday) -> i64 {
+    (day.num_days_from_monday() as i64 - week_start.num_days_from_monday() as i64).rem_euclid(7)
+}
+
+fn days_between(from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+    let mut days = Vec::with_capacity((to - from).num_days().max(0) as usize);
+    let mut day = from;
+    while day < to {
+        days.push(day);
+        day += Duration::days(1);
+    }
+    days
+}
+
+fn first_day_of_next_month(day: NaiveDate) -> Option<NaiveDate> {
+    if day.month() == 12 {
+        NaiveDate::from_ymd_opt(day.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(day.year(), day.month() + 1, 1)
+    }
+}
+
+fn days_in_month(day: NaiveDate) -> i32 {
+    NaiveDate::from_ymd_opt(day.year(), day.month(), 1)
+        .and_then(|first_day| {
+            first_day_of_next_month(first_day).map(|next| (next - first_day).num_days() as i32)
+        })
+        .unwrap_or(31)
+}
+
+fn days_in_year(year: i32) -> i32 {
+    if NaiveDate::from_ymd_opt(year, 2, 29).is_some() {
+        366
+    } else {
+        365
+    }
+}
+
+// Returns the week number and the number of weeks of the week-numbering year,
+// week 1 being the first week containing at least four days of the year.
+fn week_number(day: NaiveDate, week_start: Weekday) -> Option<(i32, i32)> {
+    let first_week = |year: i32| -> Option<NaiveDate> {
+        let jan1 = NaiveDate::from_ymd_opt(year, 1, 1)?;
+        let offset = days_from_week_start(jan1.weekday(), week_start);
+        Some(if offset <= 3 {
+            jan1 - Duration::days(offset)
+        } else {
+            jan1 + Duration::days(7 - offset)
+        })
+    };
+
+    let mut year = day.year();
+    let mut week_start_date = first_week(year)?;
+    if day < week_start_date {
+        year -= 1;
+        week_start_date = first_week(year)?;
+    } else {
+        let next_year_start = first_week(year + 1)?;
+        if day >= next_year_start {
+            year += 1;
+            week_start_date = next_year_start;
+        }
+    }
+    let total_weeks = ((first_week(year + 1)? - week_start_date).num_days() / 7) as i32;
+
+    Some((
+        ((day - week_start_date).num_days() / 7) as i32 + 1,
+        total_weeks,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(start: &str, rule: serde_json::Value, limit: usize) -> Vec<String> {
+        RecurrenceRule::parse(&rule)
+            .unwrap()
+            .iter(parse_local_datetime(start).unwrap(), true)
+            .take(limit)
+            .map(|dt| format_local_datetime(&dt))
+            .collect()
+    }
+
+    #[test]
+    fn expand_rules() {
+        for (start, rule, expected) in [
+            (
+                "2022-03-01T09:00:00",
+                serde_json::json!({
+                    "frequency": "weekly",
+                    "byDay": [{"day": "mo"}, {"day": "we"}, {"day": "fr"}],
+                    "count": 4
+                }),
+                vec![
+                    "2022-03-01T09:00:00",
+                    "2022-03-02T09:00:00",
+                    "2022-03-04T09:00:00",
+                    "2022-03-07T09:00:00",
+                ],
+            ),
+            (
+                "2022-01-31T14:00:00",
+                serde_json::json!({
+                    "frequency": "monthly",
+                    "interval": 3,
+                    "byMonthDay": [-1]
+                }),
+                vec![
+                    "2022-01-31T14:00:00",
+                    "2022-04-30T14:00:00",
+                    "2022-07-31T14:00:00",
+                    "2022-10-31T14:00:00",
+                ],
+            ),
+            (
+                "2022-01-31T10:00:00",
+                serde_json::json!({"frequency": "monthly"}),
+                vec![
+                    "2022-01-31T10:00:00",
+                    "2022-03-31T10:00:00",
+                    "2022-05-31T10:00:00",
+                ],
+            ),
+            (
+                "2022-01-01T00:00:00",
+                serde_json::json!({
+                    "frequency": "monthly",
+                    "byDay": [{"day": "fr", "nthOfPeriod": -1}],
+                    "until": "2022-04-01T00:00:00"
+                }),
+                vec![
+                    "2022-01-01T00:00:00",
+                    "2022-01-28T00:00:00",
+                    "2022-02-25T00:00:00",
+                    "2022-03-25T00:00:00",
+                ],
+            ),
+            (
+                "2020-02-29T08:00:00",
+                serde_json::json!({"frequency": "yearly", "count": 3}),
+                vec![
+                    "2020-02-29T08:00:00",
+                    "2024-02-29T08:00:00",
+                    "2028-02-29T08:00:00",
+                ],
+            ),
+            (
+                "2022-03-01T09:00:00",
+                serde_json::json!({
+                    "frequency": "daily",
+                    "byHour": [9, 17],
+                    "count": 3
+                }),
+                vec![
+                    "2022-03-01T09:00:00",
+                    "2022-03-01T17:00:00",
+                    "2022-03-02T09:00:00",
+                ],
+            ),
+        ] {
+            assert_eq!(expand(start, rule.clone(), 10), expected, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_rules() {
+        for rule in [
+            serde_json::json!({"frequency": "fortnightly"}),
+            serde_json::json!({"frequency": "daily", "interval": 0}),
+            serde_json::json!({"frequency": "daily", "count": 2, "until": "2022-01-01T00:00:00"}),
+            serde_json::json!({"frequency": "monthly", "byMonthDay": [32]}),
+            serde_json::json!({"frequency": "weekly", "byDay": [{"day": "xx"}]}),
+            serde_json::json!({"frequency": "daily", "rscale": "hebrew"}),
+        ] {
+            assert!(RecurrenceRule::parse(&rule).is_none(), "{}", rule);
+        }
+    }
+
+    #[test]
+    fn parse_durations() {
+        for (value, expected) in [
+            ("PT15M", Some(15 * 60)),
+            ("P1D", Some(86400)),
+            ("P1W", Some(7 * 86400)),
+            ("P1DT2H30M", Some(86400 + 2 * 3600 + 30 * 60)),
+            ("-PT1H", None),
+            ("1H", None),
+            ("PT", None),
+        ] {
+            assert_eq!(
+                parse_duration(value).map(|d| d.num_seconds()),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+}
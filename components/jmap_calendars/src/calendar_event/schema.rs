@@ -0,0 +1,512 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use jmap::{
+    orm,
+    request::{MaybeIdReference, ResultReference},
+    types::{date::JMAPDate, jmap::JMAPId},
+};
+use serde::{Deserialize, Serialize};
+use store::{core::vec_map::VecMap, FieldId};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CalendarEvent {
+    pub properties: VecMap<Property, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Id {
+        value: JMAPId,
+    },
+    Text {
+        value: String,
+    },
+    Bool {
+        value: bool,
+    },
+    Number {
+        value: u32,
+    },
+    Date {
+        value: JMAPDate,
+    },
+    Timestamp {
+        value: u64,
+    },
+    // JSCalendar objects are stored as serialized JSON
+    Json {
+        value: String,
+    },
+    CalendarIds {
+        value: VecMap<MaybeIdReference, bool>,
+        set: bool,
+    },
+    JsonPatch {
+        value: Vec<(Vec<String>, String)>,
+    },
+    ResultReference {
+        value: ResultReference,
+    },
+    Null,
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Null
+    }
+}
+
+impl orm::Value for Value {
+    fn index_as(&self) -> orm::Index {
+        match self {
+            Value::Id { value } => u64::from(value).into(),
+            Value::Text { value } => value.to_string().into(),
+            Value::Number { value } => (*value).into(),
+            Value::Date { value } => (value.timestamp() as u64).into(),
+            Value::Timestamp { value } => (*value).into(),
+            Value::Json { value } => {
+                let mut values = Vec::new();
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(value) {
+                    collect_text(&value, &mut values);
+                }
+                if !values.is_empty() {
+                    values.into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            _ => orm::Index::Null,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { value } => value.is_empty(),
+            Value::Null => true,
+            _ => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Id { .. } => std::mem::size_of::<JMAPId>(),
+            Value::Text { value } => value.len(),
+            Value::Bool { .. } => std::mem::size_of::<bool>(),
+            Value::Number { .. } => std::mem::size_of::<u32>(),
+            Value::Date { .. } => std::mem::size_of::<JMAPDate>(),
+            Value::Timestamp { .. } => std::mem::size_of::<u64>(),
+            Value::Json { value } => value.len(),
+            Value::CalendarIds { value, .. } => {
+                value.len() * (std::mem::size_of::<JMAPId>() + std::mem::size_of::<bool>())
+            }
+            Value::JsonPatch { value } => value.iter().fold(0, |acc, (path, value)| {
+                acc + path.iter().map(|p| p.len()).sum::<usize>() + value.len()
+            }),
+            Value::ResultReference { .. } => std::mem::size_of::<ResultReference>(),
+            Value::Null => 0,
+        }
+    }
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool { value } => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_json(&self) -> Option<serde_json::Value> {
+        match self {
+            Value::Json { value } => serde_json::from_str(value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn get_calendar_ids(&mut self) -> Option<&mut VecMap<MaybeIdReference, bool>> {
+        match self {
+            Value::CalendarIds { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_json_patch(&mut self) -> Option<&mut Vec<(Vec<String>, String)>> {
+        match self {
+            Value::JsonPatch { value } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Collects the searchable strings of a JSCalendar object, skipping
+// type annotations and enumerated values.
+fn collect_text(value: &serde_json::Value, values: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(text) => {
+            if !text.is_empty() && !values.contains(text) {
+                values.push(text.to_string());
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_text(item, values);
+            }
+        }
+        serde_json::Value::Object(items) => {
+            for (key, item) in items {
+                if !matches!(
+                    key.as_str(),
+                    "@type" | "kind" | "roles" | "relativeTo" | "participationStatus"
+                ) {
+                    collect_text(item, values);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    Id = 0,
+    CalendarIds = 1,
+    Type = 2,
+    Uid = 3,
+    RelatedTo = 4,
+    ProdId = 5,
+    Created = 6,
+    Updated = 7,
+    Sequence = 8,
+    Method = 9,
+    Title = 10,
+    Description = 11,
+    DescriptionContentType = 12,
+    ShowWithoutTime = 13,
+    Locations = 14,
+    VirtualLocations = 15,
+    Links = 16,
+    Locale = 17,
+    Keywords = 18,
+    Categories = 19,
+    Color = 20,
+    RecurrenceRules = 21,
+    ExcludedRecurrenceRules = 22,
+    RecurrenceOverrides = 23,
+    Priority = 24,
+    FreeBusyStatus = 25,
+    Privacy = 26,
+    ReplyTo = 27,
+    SentBy = 28,
+    Participants = 29,
+    UseDefaultAlerts = 30,
+    Alerts = 31,
+    Localizations = 32,
+    Start = 33,
+    Duration = 34,
+    TimeZone = 35,
+    TimeZones = 36,
+    Status = 37,
+    IsDraft = 38,
+    IsOrigin = 39,
+    UtcStart = 40,
+    UtcEnd = 41,
+    RecurrenceId = 42,
+    // Hidden properties holding the UTC time span covered by all occurrences
+    RangeStart = 43,
+    RangeEnd = 44,
+    Invalid = 45,
+}
+
+impl Property {
+    pub fn is_json(&self) -> bool {
+        matches!(
+            self,
+            Property::RelatedTo
+                | Property::Locations
+                | Property::VirtualLocations
+                | Property::Links
+                | Property::Keywords
+                | Property::Categories
+                | Property::RecurrenceRules
+                | Property::ExcludedRecurrenceRules
+                | Property::RecurrenceOverrides
+                | Property::ReplyTo
+                | Property::Participants
+                | Property::Alerts
+                | Property::Localizations
+                | Property::TimeZones
+        )
+    }
+
+    pub fn is_json_list(&self) -> bool {
+        matches!(
+            self,
+            Property::RecurrenceRules | Property::ExcludedRecurrenceRules
+        )
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            Property::Uid
+                | Property::ProdId
+                | Property::Method
+                | Property::Title
+                | Property::Description
+                | Property::DescriptionContentType
+                | Property::Locale
+                | Property::Color
+                | Property::FreeBusyStatus
+                | Property::Privacy
+                | Property::SentBy
+                | Property::Start
+                | Property::Duration
+                | Property::TimeZone
+                | Property::Status
+        )
+    }
+
+    pub fn is_time(&self) -> bool {
+        matches!(
+            self,
+            Property::Start
+                | Property::Duration
+                | Property::TimeZone
+                | Property::ShowWithoutTime
+                | Property::RecurrenceRules
+                | Property::ExcludedRecurrenceRules
+                | Property::RecurrenceOverrides
+        )
+    }
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Id => write!(f, "id"),
+            Property::CalendarIds => write!(f, "calendarIds"),
+            Property::Type => write!(f, "@type"),
+            Property::Uid => write!(f, "uid"),
+            Property::RelatedTo => write!(f, "relatedTo"),
+            Property::ProdId => write!(f, "prodId"),
+            Property::Created => write!(f, "created"),
+            Property::Updated => write!(f, "updated"),
+            Property::Sequence => write!(f, "sequence"),
+            Property::Method => write!(f, "method"),
+            Property::Title => write!(f, "title"),
+            Property::Description => write!(f, "description"),
+            Property::DescriptionContentType => write!(f, "descriptionContentType"),
+            Property::ShowWithoutTime => write!(f, "showWithoutTime"),
+            Property::Locations => write!(f, "locations"),
+            Property::VirtualLocations => write!(f, "virtualLocations"),
+            Property::Links => write!(f, "links"),
+            Property::Locale => write!(f, "locale"),
+            Property::Keywords => write!(f, "keywords"),
+            Property::Categories => write!(f, "categories"),
+            Property::Color => write!(f, "color"),
+            Property::RecurrenceRules => write!(f, "recurrenceRules"),
+            Property::ExcludedRecurrenceRules => write!(f, "excludedRecurrenceRules"),
+            Property::RecurrenceOverrides => write!(f, "recurrenceOverrides"),
+            Property::Priority => write!(f, "priority"),
+            Property::FreeBusyStatus => write!(f, "freeBusyStatus"),
+            Property::Privacy => write!(f, "privacy"),
+            Property::ReplyTo => write!(f, "replyTo"),
+            Property::SentBy => write!(f, "sentBy"),
+            Property::Participants => write!(f, "participants"),
+            Property::UseDefaultAlerts => write!(f, "useDefaultAlerts"),
+            Property::Alerts => write!(f, "alerts"),
+            Property::Localizations => write!(f, "localizations"),
+            Property::Start => write!(f, "start"),
+            Property::Duration => write!(f, "duration"),
+            Property::TimeZone => write!(f, "timeZone"),
+            Property::TimeZones => write!(f, "timeZones"),
+            Property::Status => write!(f, "status"),
+            Property::IsDraft => write!(f, "isDraft"),
+            Property::IsOrigin => write!(f, "isOrigin"),
+            Property::UtcStart => write!(f, "utcStart"),
+            Property::UtcEnd => write!(f, "utcEnd"),
+            Property::RecurrenceId => write!(f, "recurrenceId"),
+            Property::RangeStart | Property::RangeEnd | Property::Invalid => Ok(()),
+        }
+    }
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "calendarIds" => Property::CalendarIds,
+            "@type" => Property::Type,
+            "uid" => Property::Uid,
+            "relatedTo" => Property::RelatedTo,
+            "prodId" => Property::ProdId,
+            "created" => Property::Created,
+            "updated" => Property::Updated,
+            "sequence" => Property::Sequence,
+            "method" => Property::Method,
+            "title" => Property::Title,
+            "description" => Property::Description,
+            "descriptionContentType" => Property::DescriptionContentType,
+            "showWithoutTime" => Property::ShowWithoutTime,
+            "locations" => Property::Locations,
+            "virtualLocations" => Property::VirtualLocations,
+            "links" => Property::Links,
+            "locale" => Property::Locale,
+            "keywords" => Property::Keywords,
+            "categories" => Property::Categories,
+            "color" => Property::Color,
+            "recurrenceRules" => Property::RecurrenceRules,
+            "excludedRecurrenceRules" => Property::ExcludedRecurrenceRules,
+            "recurrenceOverrides" => Property::RecurrenceOverrides,
+            "priority" => Property::Priority,
+            "freeBusyStatus" => Property::FreeBusyStatus,
+            "privacy" => Property::Privacy,
+            "replyTo" => Property::ReplyTo,
+            "sentBy" => Property::SentBy,
+            "participants" => Property::Participants,
+            "useDefaultAlerts" => Property::UseDefaultAlerts,
+            "alerts" => Property::Alerts,
+            "localizations" => Property::Localizations,
+            "start" => Property::Start,
+            "duration" => Property::Duration,
+            "timeZone" => Property::TimeZone,
+            "timeZones" => Property::TimeZones,
+            "status" => Property::Status,
+            "isDraft" => Property::IsDraft,
+            "isOrigin" => Property::IsOrigin,
+            "utcStart" => Property::UtcStart,
+            "utcEnd" => Property::UtcEnd,
+            "recurrenceId" => Property::RecurrenceId,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    InCalendars { value: Vec<JMAPId> },
+    After { value: JMAPDate },
+    Before { value: JMAPDate },
+    Text { value: String },
+    Title { value: String },
+    Description { value: String },
+    Location { value: String },
+    Owner { value: String },
+    Attendee { value: String },
+    Uid { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "start")]
+    Start,
+    #[serde(rename = "uid")]
+    Uid,
+    #[serde(rename = "created")]
+    Created,
+    #[serde(rename = "updated")]
+    Updated,
+}
+
+impl From<Property> for FieldId {
+    fn from(field: Property) -> Self {
+        field as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::CalendarIds,
+            2 => Property::Type,
+            3 => Property::Uid,
+            4 => Property::RelatedTo,
+            5 => Property::ProdId,
+            6 => Property::Created,
+            7 => Property::Updated,
+            8 => Property::Sequence,
+            9 => Property::Method,
+            10 => Property::Title,
+            11 => Property::Description,
+            12 => Property::DescriptionContentType,
+            13 => Property::ShowWithoutTime,
+            14 => Property::Locations,
+            15 => Property::VirtualLocations,
+            16 => Property::Links,
+            17 => Property::Locale,
+            18 => Property::Keywords,
+            19 => Property::Categories,
+            20 => Property::Color,
+            21 => Property::RecurrenceRules,
+            22 => Property::ExcludedRecurrenceRules,
+            23 => Property::RecurrenceOverrides,
+            24 => Property::Priority,
+            25 => Property::FreeBusyStatus,
+            26 => Property::Privacy,
+            27 => Property::ReplyTo,
+            28 => Property::SentBy,
+            29 => Property::Participants,
+            30 => Property::UseDefaultAlerts,
+            31 => Property::Alerts,
+            32 => Property::Localizations,
+            33 => Property::Start,
+            34 => Property::Duration,
+            35 => Property::TimeZone,
+            36 => Property::TimeZones,
+            37 => Property::Status,
+            38 => Property::IsDraft,
+            39 => Property::IsOrigin,
+            40 => Property::UtcStart,
+            41 => Property::UtcEnd,
+            42 => Property::RecurrenceId,
+            43 => Property::RangeStart,
+            44 => Property::RangeEnd,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid => Err(()),
+            property => Ok(property),
+        }
+    }
+}
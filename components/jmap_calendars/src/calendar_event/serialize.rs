@@ -0,0 +1,337 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, fmt};
+
+use jmap::{
+    request::{query::FilterDeserializer, ArgumentDeserializer, MaybeIdReference},
+    types::{date::JMAPDate, jmap::JMAPId, json_pointer::JSONPointer},
+};
+use serde::{de::IgnoredAny, ser::SerializeMap, Deserialize, Serialize};
+use store::core::vec_map::VecMap;
+
+use super::{
+    get::GetArguments,
+    schema::{CalendarEvent, Filter, Property, Value},
+};
+
+// Property de/serialization
+impl Serialize for Property {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+struct PropertyVisitor;
+
+impl<'de> serde::de::Visitor<'de> for PropertyVisitor {
+    type Value = Property;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a valid JMAP CalendarEvent property")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Property::parse(v))
+    }
+}
+
+impl<'de> Deserialize<'de> for Property {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PropertyVisitor)
+    }
+}
+
+// CalendarEvent de/serialization
+impl Serialize for CalendarEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(self.properties.len().into())?;
+
+        for (name, value) in &self.properties {
+            match value {
+                Value::Id { value } => map.serialize_entry(name, value)?,
+                Value::Text { value } => map.serialize_entry(name, value)?,
+                Value::Bool { value } => map.serialize_entry(name, value)?,
+                Value::Number { value } => map.serialize_entry(name, value)?,
+                Value::Date { value } => map.serialize_entry(name, value)?,
+                Value::Json { value } => map.serialize_entry(
+                    name,
+                    &serde_json::from_str::<serde_json::Value>(value)
+                        .unwrap_or(serde_json::Value::Null),
+                )?,
+                Value::CalendarIds { value, .. } => map.serialize_entry(name, value)?,
+                Value::Null => map.serialize_entry(name, &None::<&str>)?,
+                Value::Timestamp { .. }
+                | Value::ResultReference { .. }
+                | Value::JsonPatch { .. } => (),
+            }
+        }
+
+        map.end()
+    }
+}
+
+struct CalendarEventVisitor;
+
+impl<'de> serde::de::Visitor<'de> for CalendarEventVisitor {
+    type Value = CalendarEvent;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a valid JMAP CalendarEvent object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut properties: VecMap<Property, Value> = VecMap::new();
+
+        while let Some(key) = map.next_key::<Cow<str>>()? {
+            match key.as_ref() {
+                "calendarIds" => {
+                    if let Some(value) =
+                        map.next_value::<Option<VecMap<MaybeIdReference, bool>>>()?
+                    {
+                        properties.append(
+                            Property::CalendarIds,
+                            Value::CalendarIds { value, set: true },
+                        );
+                    }
+                }
+                "@type"
+                | "uid"
+                | "prodId"
+                | "method"
+                | "title"
+                | "description"
+                | "descriptionContentType"
+                | "locale"
+                | "color"
+                | "freeBusyStatus"
+                | "privacy"
+                | "sentBy"
+                | "start"
+                | "duration"
+                | "timeZone"
+                | "status" => {
+                    properties.append(
+                        Property::parse(key.as_ref()),
+                        if let Some(value) = map.next_value::<Option<String>>()? {
+                            Value::Text { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "showWithoutTime" | "useDefaultAlerts" | "isDraft" | "isOrigin" => {
+                    properties.append(
+                        Property::parse(key.as_ref()),
+                        if let Some(value) = map.next_value::<Option<bool>>()? {
+                            Value::Bool { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "sequence" | "priority" => {
+                    properties.append(
+                        Property::parse(key.as_ref()),
+                        if let Some(value) = map.next_value::<Option<u32>>()? {
+                            Value::Number { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "created" | "updated" => {
+                    properties.append(
+                        Property::parse(key.as_ref()),
+                        if let Some(value) = map.next_value::<Option<JMAPDate>>()? {
+                            Value::Date { value }
+                        } else {
+                            Value::Null
+                        },
+                    );
+                }
+                "#calendarIds" => {
+                    properties.append(
+                        Property::CalendarIds,
+                        Value::ResultReference {
+                            value: map.next_value()?,
+                        },
+                    );
+                }
+                key => match (Property::parse(key), JSONPointer::parse(key)) {
+                    (property, _) if property.is_json() => {
+                        properties.append(
+                            property,
+                            match map.next_value::<serde_json::Value>()? {
+                                serde_json::Value::Null => Value::Null,
+                                value => Value::Json {
+                                    value: value.to_string(),
+                                },
+                            },
+                        );
+                    }
+                    (_, Some(JSONPointer::Path(mut path))) if path.len() >= 2 => {
+                        match path.remove(0).to_string().map(Property::parse) {
+                            Some(Property::CalendarIds) if path.len() == 1 => {
+                                if let Some(id) = path
+                                    .get(0)
+                                    .and_then(|p| p.to_string())
+                                    .and_then(JMAPId::parse)
+                                {
+                                    let value = map.next_value::<Option<bool>>()?.unwrap_or(false);
+                                    properties
+                                        .get_mut_or_insert_with(Property::CalendarIds, || {
+                                            Value::CalendarIds {
+                                                value: VecMap::new(),
+                                                set: false,
+                                            }
+                                        })
+                                        .get_calendar_ids()
+                                        .unwrap()
+                                        .append(MaybeIdReference::Value(id), value);
+                                } else {
+                                    map.next_value::<IgnoredAny>()?;
+                                }
+                            }
+                            Some(property) if property.is_json() => {
+                                let path = path
+                                    .into_iter()
+                                    .filter_map(|p| match p {
+                                        JSONPointer::String(p) => p.into(),
+                                        JSONPointer::Number(p) => p.to_string().into(),
+                                        _ => None,
+                                    })
+                                    .collect::<Vec<_>>();
+                                let value = map.next_value::<serde_json::Value>()?.to_string();
+                                properties
+                                    .get_mut_or_insert_with(property, || Value::JsonPatch {
+                                        value: Vec::new(),
+                                    })
+                                    .get_json_patch()
+                                    .unwrap()
+                                    .push((path, value));
+                            }
+                            _ => {
+                                map.next_value::<IgnoredAny>()?;
+                            }
+                        }
+                    }
+                    _ => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                },
+            }
+        }
+
+        Ok(CalendarEvent { properties })
+    }
+}
+
+impl<'de> Deserialize<'de> for CalendarEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_map(CalendarEventVisitor)
+    }
+}
+
+// Argument serializers
+impl ArgumentDeserializer for GetArguments {
+    fn deserialize<'x: 'y, 'y, 'z>(
+        &'y mut self,
+        property: &'z str,
+        value: &mut impl serde::de::MapAccess<'x>,
+    ) -> Result<(), String> {
+        match property {
+            "timeZone" => {
+                self.time_zone = value.next_value().unwrap_or_default();
+            }
+            _ => {
+                value
+                    .next_value::<IgnoredAny>()
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Filter deserializer
+impl FilterDeserializer for Filter {
+    fn deserialize<'x>(property: &str, map: &mut impl serde::de::MapAccess<'x>) -> Option<Self> {
+        match property {
+            "inCalendars" => Filter::InCalendars {
+                value: map.next_value().ok()?,
+            },
+            "after" => Filter::After {
+                value: map.next_value().ok()?,
+            },
+            "before" => Filter::Before {
+                value: map.next_value().ok()?,
+            },
+            "text" => Filter::Text {
+                value: map.next_value().ok()?,
+            },
+            "title" => Filter::Title {
+                value: map.next_value().ok()?,
+            },
+            "description" => Filter::Description {
+                value: map.next_value().ok()?,
+            },
+            "location" => Filter::Location {
+                value: map.next_value().ok()?,
+            },
+            "owner" => Filter::Owner {
+                value: map.next_value().ok()?,
+            },
+            "attendee" => Filter::Attendee {
+                value: map.next_value().ok()?,
+            },
+            "uid" => Filter::Uid {
+                value: map.next_value().ok()?,
+            },
+            unsupported => {
+                map.next_value::<IgnoredAny>().ok()?;
+                Filter::Unsupported {
+                    value: unsupported.to_string(),
+                }
+            }
+        }
+        .into()
+    }
+}
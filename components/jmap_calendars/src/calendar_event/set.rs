@@ -0,0 +1,662 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use super::recurrence::{parse_duration, parse_local_datetime, EventTime};
+use super::schema::{CalendarEvent, Property, Value};
+use super::sharing::JMAPShareCalendars;
+use chrono_tz::Tz;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::jmap_store::set::{SetHelper, SetObject};
+use jmap::jmap_store::Object;
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::set::{SetRequest, SetResponse};
+use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
+use jmap::types::date::JMAPDate;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
+use store::rand::{self, Rng};
+use store::roaring::RoaringBitmap;
+use store::{AccountId, JMAPStore, SharedBitmap, Store};
+
+impl SetObject for CalendarEvent {
+    type SetArguments = ();
+
+    type NextCall = ();
+
+    fn eval_id_references(&mut self, mut fnc: impl FnMut(&str) -> Option<JMAPId>) {
+        if let Some(Value::CalendarIds { value, .. }) =
+            self.properties.get_mut(&Property::CalendarIds)
+        {
+            if value
+                .keys()
+                .any(|k| matches!(k, MaybeIdReference::Reference(_)))
+            {
+                let mut new_values = VecMap::with_capacity(value.len());
+
+                for (id, value) in std::mem::take(value).into_iter() {
+                    if let MaybeIdReference::Reference(id) = &id {
+                        if let Some(id) = fnc(id) {
+                            new_values.append(MaybeIdReference::Value(id), value);
+                            continue;
+                        }
+                    }
+                    new_values.append(id, value);
+                }
+
+                *value = new_values;
+            }
+        }
+    }
+
+    fn eval_result_references(
+        &mut self,
+        mut fnc: impl FnMut(&ResultReference) -> Option<Vec<u64>>,
+    ) {
+        for (property, entry) in self.properties.iter_mut() {
+            if let (Property::CalendarIds, Value::ResultReference { value }) = (property, &entry) {
+                if let Some(value) = fnc(value) {
+                    *entry = Value::CalendarIds {
+                        value: value
+                            .into_iter()
+                            .map(|v| (MaybeIdReference::Value(v.into()), true))
+                            .collect(),
+                        set: true,
+                    };
+                }
+            }
+        }
+    }
+
+    fn set_property(&mut self, property: Self::Property, value: Self::Value) {
+        self.properties.set(property, value);
+    }
+}
+
+pub trait JMAPSetCalendarEvent<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        &self,
+        request: SetRequest<CalendarEvent>,
+    ) -> jmap::Result<SetResponse<CalendarEvent>>;
+    fn calendar_event_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPSetCalendarEvent<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        &self,
+        request: SetRequest<CalendarEvent>,
+    ) -> jmap::Result<SetResponse<CalendarEvent>> {
+        let mut helper = SetHelper::new(self, request)?;
+        let calendar_ids = self
+            .get_document_ids(helper.account_id, Collection::Calendar)?
+            .unwrap_or_default();
+
+        helper.create(|_create_id, item, helper, document| {
+            let mut fields = TinyORM::<CalendarEvent>::new().calendar_event_set(
+                helper,
+                item,
+                None,
+                &calendar_ids,
+            )?;
+            let mut calendar_event = CalendarEvent::new(document.document_id.into());
+
+            // Set server-generated properties
+            if !fields.has_property(&Property::Uid) {
+                let uid = Value::Text {
+                    value: generate_uid(),
+                };
+                calendar_event.properties.append(Property::Uid, uid.clone());
+                fields.set(Property::Uid, uid);
+            }
+            for property in [Property::Created, Property::Updated] {
+                if !fields.has_property(&property) {
+                    let now = Value::Date {
+                        value: JMAPDate::from_timestamp(now()),
+                    };
+                    calendar_event.properties.append(property, now.clone());
+                    fields.set(property, now);
+                }
+            }
+
+            // Make sure the event belongs to at least one calendar
+            if !fields.has_tags(&Property::CalendarIds) {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::CalendarIds)
+                    .with_description("Event has to belong to at least one calendar."));
+            }
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                let allowed_calendars = helper.store.calendars_shared_calendars(
+                    helper.account_id,
+                    &helper.acl.member_of,
+                    ACL::AddItems,
+                )?;
+                for calendar in fields.get_tags(&Property::CalendarIds).unwrap() {
+                    let calendar_id = calendar.as_id();
+                    if !allowed_calendars.has_access(calendar_id) {
+                        return Err(SetError::forbidden().with_description(format!(
+                            "You are not allowed to add events to calendar {}.",
+                            JMAPId::from(calendar_id)
+                        )));
+                    }
+                }
+            }
+
+            fields.insert_validate(document)?;
+
+            Ok(calendar_event)
+        })?;
+
+        helper.update(|id, item, helper, document| {
+            // Occurrences are modified through the recurrenceOverrides property of the
+            // master event
+            if id.get_prefix_id() != 0 {
+                return Err(SetError::forbidden().with_description(
+                    "Recurrence instances have to be updated through recurrenceOverrides.",
+                ));
+            }
+
+            let document_id = id.get_document_id();
+            let current_fields = self
+                .get_orm::<CalendarEvent>(helper.account_id, document_id)?
+                .ok_or_else(|| SetError::new(SetErrorType::NotFound))?;
+            let mut fields = TinyORM::track_changes(&current_fields).calendar_event_set(
+                helper,
+                item,
+                Some(&current_fields),
+                &calendar_ids,
+            )?;
+
+            // Make sure the event belongs to at least one calendar
+            if !fields.has_tags(&Property::CalendarIds) {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::CalendarIds)
+                    .with_description("Event has to belong to at least one calendar."));
+            }
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                if !helper
+                    .store
+                    .calendars_shared_events(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::ModifyItems,
+                    )?
+                    .has_access(document_id)
+                {
+                    return Err(SetError::forbidden()
+                        .with_description("You are not allowed to modify this event."));
+                }
+
+                // All added calendars have to allow insertions
+                let added_calendars =
+                    current_fields.get_added_tags(&fields, &Property::CalendarIds);
+                if !added_calendars.is_empty() {
+                    let allowed_calendars = helper.store.calendars_shared_calendars(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::AddItems,
+                    )?;
+                    for calendar in added_calendars {
+                        let calendar_id = calendar.as_id();
+                        if !allowed_calendars.has_access(calendar_id) {
+                            return Err(SetError::forbidden().with_description(format!(
+                                "You are not allowed to add events to calendar {}.",
+                                JMAPId::from(calendar_id)
+                            )));
+                        }
+                    }
+                }
+
+                // All removed calendars have to allow deletions
+                let removed_calendars =
+                    current_fields.get_removed_tags(&fields, &Property::CalendarIds);
+                if !removed_calendars.is_empty() {
+                    let allowed_calendars = helper.store.calendars_shared_calendars(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?;
+                    for calendar in removed_calendars {
+                        let calendar_id = calendar.as_id();
+                        if !allowed_calendars.has_access(calendar_id) {
+                            return Err(SetError::forbidden().with_description(format!(
+                                "You are not allowed to remove events from calendar {}.",
+                                JMAPId::from(calendar_id)
+                            )));
+                        }
+                    }
+                }
+            }
+
+            // Update the modification date unless the client provided one
+            if !fields.has_property(&Property::Updated) {
+                fields.set(
+                    Property::Updated,
+                    Value::Date {
+                        value: JMAPDate::from_timestamp(now()),
+                    },
+                );
+            }
+
+            // Merge changes
+            current_fields.merge_validate(document, fields)?;
+
+            Ok(None)
+        })?;
+
+        helper.destroy(|id, helper, document| {
+            let document_id = document.document_id;
+
+            if id.get_prefix_id() != 0 {
+                return Err(SetError::forbidden().with_description(
+                    "Recurrence instances have to be removed through recurrenceOverrides.",
+                ));
+            }
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id)
+                && !helper
+                    .store
+                    .calendars_shared_events(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?
+                    .has_access(document_id)
+            {
+                return Err(SetError::forbidden()
+                    .with_description("You are not allowed to delete this event."));
+            }
+
+            // Delete ORM and index
+            if let Some(orm) = helper
+                .store
+                .get_orm::<CalendarEvent>(helper.account_id, document_id)?
+            {
+                orm.delete(document);
+            }
+
+            Ok(())
+        })?;
+
+        helper.into_response()
+    }
+
+    fn calendar_event_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()> {
+        // Delete ORM
+        self.get_orm::<CalendarEvent>(account_id, document.document_id)?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Failed to fetch CalendarEvent ORM for {}:{}.",
+                    account_id, document.document_id
+                ))
+            })?
+            .delete(document);
+
+        Ok(())
+    }
+}
+
+trait CalendarEventSet<T>: Sized
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        self,
+        helper: &mut SetHelper<CalendarEvent, T>,
+        calendar_event: CalendarEvent,
+        current_fields: Option<&TinyORM<CalendarEvent>>,
+        calendar_ids: &RoaringBitmap,
+    ) -> jmap::error::set::Result<Self, Property>;
+}
+
+impl<T> CalendarEventSet<T> for TinyORM<CalendarEvent>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn calendar_event_set(
+        mut self,
+        helper: &mut SetHelper<CalendarEvent, T>,
+        calendar_event: CalendarEvent,
+        current_fields: Option<&TinyORM<CalendarEvent>>,
+        calendar_ids: &RoaringBitmap,
+    ) -> jmap::error::set::Result<Self, Property> {
+        let mut has_time_changes = current_fields.is_none();
+
+        for (property, value) in calendar_event.properties {
+            let value = match (property, value) {
+                (Property::CalendarIds, Value::CalendarIds { value, set }) => {
+                    if set {
+                        self.untag_all(&Property::CalendarIds);
+                    }
+
+                    for (calendar_id, is_set) in value {
+                        let calendar_id = helper
+                            .unwrap_id_reference(Property::CalendarIds, &calendar_id)?
+                            .get_document_id();
+
+                        if calendar_ids.contains(calendar_id) {
+                            if is_set {
+                                self.tag(Property::CalendarIds, Tag::Id(calendar_id));
+                            } else {
+                                self.untag(&Property::CalendarIds, &Tag::Id(calendar_id));
+                            }
+                        } else {
+                            return Err(SetError::invalid_properties()
+                                .with_property(Property::CalendarIds)
+                                .with_description(format!(
+                                    "calendarId {} does not exist.",
+                                    JMAPId::from(calendar_id)
+                                )));
+                        }
+                    }
+                    continue;
+                }
+                (Property::Type, Value::Text { value }) => {
+                    if value == "Event" {
+                        continue;
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid object type."));
+                    }
+                }
+                (Property::Uid, Value::Text { value }) => {
+                    match current_fields.and_then(|f| f.get(&Property::Uid)) {
+                        Some(Value::Text { value: current_uid }) if current_uid != &value => {
+                            return Err(SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("The uid of an event cannot be changed."));
+                        }
+                        _ => Value::Text { value },
+                    }
+                }
+                (Property::Start, Value::Text { value }) => {
+                    if parse_local_datetime(&value).is_some() {
+                        Value::Text { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid local date-time."));
+                    }
+                }
+                (Property::Duration, Value::Text { value }) => {
+                    if parse_duration(&value).is_some() {
+                        Value::Text { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid duration."));
+                    }
+                }
+                (Property::TimeZone, Value::Text { value }) => {
+                    if value.parse::<Tz>().is_ok() {
+                        Value::Text { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Unsupported time zone."));
+                    }
+                }
+                (Property::FreeBusyStatus, Value::Text { value }) => {
+                    validate_value(property, value, &["free", "busy"])?
+                }
+                (Property::Privacy, Value::Text { value }) => {
+                    validate_value(property, value, &["public", "private", "secret"])?
+                }
+                (Property::Status, Value::Text { value }) => {
+                    validate_value(property, value, &["confirmed", "cancelled", "tentative"])?
+                }
+                (property, value @ (Value::Text { .. } | Value::Null))
+                    if property.is_text() && property != Property::Start =>
+                {
+                    value
+                }
+                (
+                    Property::ShowWithoutTime
+                    | Property::UseDefaultAlerts
+                    | Property::IsDraft
+                    | Property::IsOrigin,
+                    value @ (Value::Bool { .. } | Value::Null),
+                ) => value,
+                (Property::Priority, Value::Number { value }) if value > 9 => {
+                    return Err(SetError::invalid_properties()
+                        .with_property(property)
+                        .with_description("Priority has to be between 0 and 9."));
+                }
+                (
+                    Property::Sequence | Property::Priority,
+                    value @ (Value::Number { .. } | Value::Null),
+                ) => value,
+                (Property::Created | Property::Updated, Value::Date { value }) => {
+                    if value.is_valid() {
+                        Value::Date { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid date."));
+                    }
+                }
+                (Property::Created | Property::Updated, Value::Null) => Value::Null,
+                (property, Value::Json { value }) if property.is_json() => {
+                    if value.starts_with(if property.is_json_list() { '[' } else { '{' }) {
+                        Value::Json { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description(if property.is_json_list() {
+                                "Expected a JSON array."
+                            } else {
+                                "Expected a JSON object."
+                            }));
+                    }
+                }
+                (property, Value::JsonPatch { value }) if property.is_json() => {
+                    let mut json = match self
+                        .get(&property)
+                        .or_else(|| current_fields.and_then(|f| f.get(&property)))
+                    {
+                        Some(Value::Json { value }) => serde_json::from_str(value).ok(),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| {
+                        if property.is_json_list() {
+                            serde_json::Value::Array(Default::default())
+                        } else {
+                            serde_json::Value::Object(Default::default())
+                        }
+                    });
+                    for (path, value) in value {
+                        if !apply_patch(
+                            &mut json,
+                            &path,
+                            serde_json::from_str(&value).unwrap_or_default(),
+                        ) {
+                            return Err(SetError::new(SetErrorType::InvalidPatch)
+                                .with_property(property)
+                                .with_description(format!(
+                                    "Failed to apply patch '{}/{}'.",
+                                    property,
+                                    path.join("/")
+                                )));
+                        }
+                    }
+                    if match &json {
+                        serde_json::Value::Object(map) => !map.is_empty(),
+                        serde_json::Value::Array(items) => !items.is_empty(),
+                        _ => false,
+                    } {
+                        Value::Json {
+                            value: json.to_string(),
+                        }
+                    } else {
+                        Value::Null
+                    }
+                }
+                (property, Value::Null) if property.is_json() => Value::Null,
+                (_, _) => {
+                    return Err(SetError::invalid_properties()
+                        .with_property(property)
+                        .with_description("Unexpected value."));
+                }
+            };
+
+            if property.is_time() {
+                has_time_changes = true;
+            }
+
+            self.set(property, value);
+        }
+
+        // Keep the indexed time range covered by all occurrences up to date
+        if has_time_changes {
+            let event_time = EventTime::from_properties(|property| match self.get(property) {
+                Some(value) => Some(value),
+                None => current_fields.and_then(|f| f.get(property)),
+            })
+            .map_err(|property| {
+                SetError::invalid_properties()
+                    .with_property(property)
+                    .with_description(if property == Property::Start {
+                        "Missing or invalid start date-time."
+                    } else {
+                        "Invalid or unsupported value."
+                    })
+            })?;
+            let (range_start, range_end) = event_time.range();
+            self.set(
+                Property::RangeStart,
+                Value::Timestamp { value: range_start },
+            );
+            self.set(Property::RangeEnd, Value::Timestamp { value: range_end });
+        }
+
+        Ok(self)
+    }
+}
+
+fn validate_value(
+    property: Property,
+    value: String,
+    valid_values: &[&str],
+) -> jmap::error::set::Result<Value, Property> {
+    if valid_values.contains(&value.as_str()) {
+        Ok(Value::Text { value })
+    } else {
+        Err(SetError::invalid_properties()
+            .with_property(property)
+            .with_description(format!("Invalid {} value.", property)))
+    }
+}
+
+pub fn apply_patch(
+    target: &mut serde_json::Value,
+    path: &[String],
+    value: serde_json::Value,
+) -> bool {
+    let (key, path) = if let Some(item) = path.split_first() {
+        item
+    } else {
+        return false;
+    };
+
+    match target {
+        serde_json::Value::Object(map) => {
+            if path.is_empty() {
+                if value.is_null() {
+                    map.remove(key);
+                } else {
+                    map.insert(key.to_string(), value);
+                }
+                true
+            } else {
+                apply_patch(
+                    map.entry(key.to_string())
+                        .or_insert_with(|| serde_json::Value::Object(Default::default())),
+                    path,
+                    value,
+                )
+            }
+        }
+        serde_json::Value::Array(items) => match key.parse::<usize>() {
+            Ok(pos) if pos < items.len() => {
+                if path.is_empty() {
+                    items[pos] = value;
+                    true
+                } else {
+                    apply_patch(&mut items[pos], path, value)
+                }
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn generate_uid() -> String {
+    let mut bytes = rand::thread_rng().gen::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as i64
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::schema::{ContactCard, Property, Value};
+use super::sharing::JMAPShareContacts;
+use jmap::jmap_store::get::{default_mapper, GetHelper, GetObject};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::request::get::{GetRequest, GetResponse};
+use jmap::request::MaybeIdReference;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::error::StoreError;
+use store::core::vec_map::VecMap;
+use store::{AccountId, JMAPStore, Store};
+
+impl GetObject for ContactCard {
+    type GetArguments = ();
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::AddressBookIds,
+            Property::Type,
+            Property::Version,
+            Property::Created,
+            Property::Kind,
+            Property::Language,
+            Property::Members,
+            Property::ProdId,
+            Property::RelatedTo,
+            Property::Uid,
+            Property::Updated,
+            Property::Name,
+            Property::Nicknames,
+            Property::Organizations,
+            Property::SpeakToAs,
+            Property::Titles,
+            Property::Emails,
+            Property::OnlineServices,
+            Property::Phones,
+            Property::PreferredLanguages,
+            Property::Calendars,
+            Property::SchedulingAddresses,
+            Property::Addresses,
+            Property::CryptoKeys,
+            Property::Directories,
+            Property::Links,
+            Property::Media,
+            Property::Localizations,
+            Property::Anniversaries,
+            Property::Keywords,
+            Property::Notes,
+            Property::PersonalInfo,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match self.properties.get(property)? {
+            Value::Id { value } => Some(vec![*value]),
+            Value::AddressBookIds { value, .. } => {
+                Some(value.keys().filter_map(|id| Some(*id.value()?)).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetContactCard<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_get(
+        &self,
+        request: GetRequest<ContactCard>,
+    ) -> jmap::Result<GetResponse<ContactCard>>;
+}
+
+impl<T> JMAPGetContactCard<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_get(
+        &self,
+        request: GetRequest<ContactCard>,
+    ) -> jmap::Result<GetResponse<ContactCard>> {
+        let mut helper = GetHelper::new(
+            self,
+            request,
+            default_mapper.into(),
+            (|account_id: AccountId, member_of: &[AccountId]| {
+                self.contacts_shared_cards(account_id, member_of, ACL::ReadItems)
+            })
+            .into(),
+        )?;
+        let account_id = helper.account_id;
+
+        // Add Id Property
+        if !helper.properties.contains(&Property::Id) {
+            helper.properties.push(Property::Id);
+        }
+
+        helper.get(|id, properties| {
+            let mut fields = self
+                .get_orm::<ContactCard>(account_id, id.get_document_id())?
+                .ok_or_else(|| StoreError::NotFound("ContactCard data not found".to_string()))?;
+            let mut contact_card = VecMap::with_capacity(properties.len());
+
+            for property in properties {
+                let value = match property {
+                    Property::Id => Value::Id { value: id },
+                    Property::Type => Value::Text {
+                        value: "Card".to_string(),
+                    },
+                    Property::Version => fields.remove(property).unwrap_or(Value::Text {
+                        value: "1.0".to_string(),
+                    }),
+                    Property::AddressBookIds => Value::AddressBookIds {
+                        value: fields
+                            .get_tags(&Property::AddressBookIds)
+                            .map(|tags| {
+                                tags.iter()
+                                    .map(|tag| (MaybeIdReference::Value(tag.as_id().into()), true))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        set: true,
+                    },
+                    Property::NameGiven | Property::NameSurname | Property::Invalid => continue,
+                    _ => fields.remove(property).unwrap_or_default(),
+                };
+
+                contact_card.append(*property, value);
+            }
+            Ok(Some(ContactCard {
+                properties: contact_card,
+            }))
+        })
+    }
+}
@@ -0,0 +1,429 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use jmap::{
+    orm,
+    request::{MaybeIdReference, ResultReference},
+    types::{date::JMAPDate, jmap::JMAPId},
+};
+use serde::{Deserialize, Serialize};
+use store::{core::vec_map::VecMap, FieldId};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ContactCard {
+    pub properties: VecMap<Property, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Id {
+        value: JMAPId,
+    },
+    Text {
+        value: String,
+    },
+    Date {
+        value: JMAPDate,
+    },
+    // JSContact objects are stored as serialized JSON
+    Json {
+        value: String,
+    },
+    AddressBookIds {
+        value: VecMap<MaybeIdReference, bool>,
+        set: bool,
+    },
+    JsonPatch {
+        value: Vec<(Vec<String>, String)>,
+    },
+    ResultReference {
+        value: ResultReference,
+    },
+    Null,
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Null
+    }
+}
+
+impl orm::Value for Value {
+    fn index_as(&self) -> orm::Index {
+        match self {
+            Value::Id { value } => u64::from(value).into(),
+            Value::Text { value } => value.to_string().into(),
+            Value::Date { value } => (value.timestamp() as u64).into(),
+            Value::Json { value } => {
+                let mut values = Vec::new();
+                if let Ok(value) = serde_json::from_str::<serde_json::Value>(value) {
+                    collect_text(&value, &mut values);
+                }
+                if !values.is_empty() {
+                    values.into()
+                } else {
+                    orm::Index::Null
+                }
+            }
+            _ => orm::Index::Null,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::Text { value } => value.is_empty(),
+            Value::Null => true,
+            _ => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Value::Id { .. } => std::mem::size_of::<JMAPId>(),
+            Value::Text { value } => value.len(),
+            Value::Date { .. } => std::mem::size_of::<JMAPDate>(),
+            Value::Json { value } => value.len(),
+            Value::AddressBookIds { value, .. } => {
+                value.len() * (std::mem::size_of::<JMAPId>() + std::mem::size_of::<bool>())
+            }
+            Value::JsonPatch { value } => value.iter().fold(0, |acc, (path, value)| {
+                acc + path.iter().map(|p| p.len()).sum::<usize>() + value.len()
+            }),
+            Value::ResultReference { .. } => std::mem::size_of::<ResultReference>(),
+            Value::Null => 0,
+        }
+    }
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text { value } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_address_book_ids(&mut self) -> Option<&mut VecMap<MaybeIdReference, bool>> {
+        match self {
+            Value::AddressBookIds { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_json_patch(&mut self) -> Option<&mut Vec<(Vec<String>, String)>> {
+        match self {
+            Value::JsonPatch { value } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Collects the searchable strings of a JSContact object, skipping
+// type and kind annotations.
+fn collect_text(value: &serde_json::Value, values: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(text) => {
+            if !text.is_empty() && !values.contains(text) {
+                values.push(text.to_string());
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_text(item, values);
+            }
+        }
+        serde_json::Value::Object(items) => {
+            for (key, item) in items {
+                if !matches!(key.as_str(), "@type" | "kind") {
+                    collect_text(item, values);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    Id = 0,
+    AddressBookIds = 1,
+    Type = 2,
+    Version = 3,
+    Created = 4,
+    Kind = 5,
+    Language = 6,
+    Members = 7,
+    ProdId = 8,
+    RelatedTo = 9,
+    Uid = 10,
+    Updated = 11,
+    Name = 12,
+    Nicknames = 13,
+    Organizations = 14,
+    SpeakToAs = 15,
+    Titles = 16,
+    Emails = 17,
+    OnlineServices = 18,
+    Phones = 19,
+    PreferredLanguages = 20,
+    Calendars = 21,
+    SchedulingAddresses = 22,
+    Addresses = 23,
+    CryptoKeys = 24,
+    Directories = 25,
+    Links = 26,
+    Media = 27,
+    Localizations = 28,
+    Anniversaries = 29,
+    Keywords = 30,
+    Notes = 31,
+    PersonalInfo = 32,
+    NameGiven = 33,
+    NameSurname = 34,
+    Invalid = 35,
+}
+
+impl Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Id => write!(f, "id"),
+            Property::AddressBookIds => write!(f, "addressBookIds"),
+            Property::Type => write!(f, "@type"),
+            Property::Version => write!(f, "version"),
+            Property::Created => write!(f, "created"),
+            Property::Kind => write!(f, "kind"),
+            Property::Language => write!(f, "language"),
+            Property::Members => write!(f, "members"),
+            Property::ProdId => write!(f, "prodId"),
+            Property::RelatedTo => write!(f, "relatedTo"),
+            Property::Uid => write!(f, "uid"),
+            Property::Updated => write!(f, "updated"),
+            Property::Name => write!(f, "name"),
+            Property::Nicknames => write!(f, "nicknames"),
+            Property::Organizations => write!(f, "organizations"),
+            Property::SpeakToAs => write!(f, "speakToAs"),
+            Property::Titles => write!(f, "titles"),
+            Property::Emails => write!(f, "emails"),
+            Property::OnlineServices => write!(f, "onlineServices"),
+            Property::Phones => write!(f, "phones"),
+            Property::PreferredLanguages => write!(f, "preferredLanguages"),
+            Property::Calendars => write!(f, "calendars"),
+            Property::SchedulingAddresses => write!(f, "schedulingAddresses"),
+            Property::Addresses => write!(f, "addresses"),
+            Property::CryptoKeys => write!(f, "cryptoKeys"),
+            Property::Directories => write!(f, "directories"),
+            Property::Links => write!(f, "links"),
+            Property::Media => write!(f, "media"),
+            Property::Localizations => write!(f, "localizations"),
+            Property::Anniversaries => write!(f, "anniversaries"),
+            Property::Keywords => write!(f, "keywords"),
+            Property::Notes => write!(f, "notes"),
+            Property::PersonalInfo => write!(f, "personalInfo"),
+            Property::NameGiven => write!(f, "name/given"),
+            Property::NameSurname => write!(f, "name/surname"),
+            Property::Invalid => Ok(()),
+        }
+    }
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "addressBookIds" => Property::AddressBookIds,
+            "@type" => Property::Type,
+            "version" => Property::Version,
+            "created" => Property::Created,
+            "kind" => Property::Kind,
+            "language" => Property::Language,
+            "members" => Property::Members,
+            "prodId" => Property::ProdId,
+            "relatedTo" => Property::RelatedTo,
+            "uid" => Property::Uid,
+            "updated" => Property::Updated,
+            "name" => Property::Name,
+            "nicknames" => Property::Nicknames,
+            "organizations" => Property::Organizations,
+            "speakToAs" => Property::SpeakToAs,
+            "titles" => Property::Titles,
+            "emails" => Property::Emails,
+            "onlineServices" => Property::OnlineServices,
+            "phones" => Property::Phones,
+            "preferredLanguages" => Property::PreferredLanguages,
+            "calendars" => Property::Calendars,
+            "schedulingAddresses" => Property::SchedulingAddresses,
+            "addresses" => Property::Addresses,
+            "cryptoKeys" => Property::CryptoKeys,
+            "directories" => Property::Directories,
+            "links" => Property::Links,
+            "media" => Property::Media,
+            "localizations" => Property::Localizations,
+            "anniversaries" => Property::Anniversaries,
+            "keywords" => Property::Keywords,
+            "notes" => Property::Notes,
+            "personalInfo" => Property::PersonalInfo,
+            _ => Property::Invalid,
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        matches!(
+            self,
+            Property::Members
+                | Property::RelatedTo
+                | Property::Name
+                | Property::Nicknames
+                | Property::Organizations
+                | Property::SpeakToAs
+                | Property::Titles
+                | Property::Emails
+                | Property::OnlineServices
+                | Property::Phones
+                | Property::PreferredLanguages
+                | Property::Calendars
+                | Property::SchedulingAddresses
+                | Property::Addresses
+                | Property::CryptoKeys
+                | Property::Directories
+                | Property::Links
+                | Property::Media
+                | Property::Localizations
+                | Property::Anniversaries
+                | Property::Keywords
+                | Property::Notes
+                | Property::PersonalInfo
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    InAddressBook { value: JMAPId },
+    Uid { value: String },
+    Kind { value: String },
+    CreatedBefore { value: JMAPDate },
+    CreatedAfter { value: JMAPDate },
+    UpdatedBefore { value: JMAPDate },
+    UpdatedAfter { value: JMAPDate },
+    Text { value: String },
+    Name { value: String },
+    NameGiven { value: String },
+    NameSurname { value: String },
+    Nickname { value: String },
+    Organization { value: String },
+    Email { value: String },
+    Phone { value: String },
+    OnlineService { value: String },
+    Address { value: String },
+    Note { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "created")]
+    Created,
+    #[serde(rename = "updated")]
+    Updated,
+    #[serde(rename = "name/given")]
+    NameGiven,
+    #[serde(rename = "name/surname")]
+    NameSurname,
+}
+
+impl From<Property> for FieldId {
+    fn from(field: Property) -> Self {
+        field as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::AddressBookIds,
+            2 => Property::Type,
+            3 => Property::Version,
+            4 => Property::Created,
+            5 => Property::Kind,
+            6 => Property::Language,
+            7 => Property::Members,
+            8 => Property::ProdId,
+            9 => Property::RelatedTo,
+            10 => Property::Uid,
+            11 => Property::Updated,
+            12 => Property::Name,
+            13 => Property::Nicknames,
+            14 => Property::Organizations,
+            15 => Property::SpeakToAs,
+            16 => Property::Titles,
+            17 => Property::Emails,
+            18 => Property::OnlineServices,
+            19 => Property::Phones,
+            20 => Property::PreferredLanguages,
+            21 => Property::Calendars,
+            22 => Property::SchedulingAddresses,
+            23 => Property::Addresses,
+            24 => Property::CryptoKeys,
+            25 => Property::Directories,
+            26 => Property::Links,
+            27 => Property::Media,
+            28 => Property::Localizations,
+            29 => Property::Anniversaries,
+            30 => Property::Keywords,
+            31 => Property::Notes,
+            32 => Property::PersonalInfo,
+            33 => Property::NameGiven,
+            34 => Property::NameSurname,
+            _ => Property::Invalid,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match Property::parse(value) {
+            Property::Invalid => Err(()),
+            property => Ok(property),
+        }
+    }
+}
+
+#[inline(always)]
+pub fn is_valid_kind(kind: &str) -> bool {
+    [
+        "individual",
+        "group",
+        "org",
+        "location",
+        "device",
+        "application",
+    ]
+    .contains(&kind)
+}
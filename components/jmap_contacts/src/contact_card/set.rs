@@ -0,0 +1,584 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use super::schema::{is_valid_kind, ContactCard, Property, Value};
+use super::sharing::JMAPShareContacts;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::jmap_store::set::{SetHelper, SetObject};
+use jmap::jmap_store::Object;
+use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::request::set::{SetRequest, SetResponse};
+use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
+use jmap::types::date::JMAPDate;
+use jmap::types::jmap::JMAPId;
+use store::core::acl::ACL;
+use store::core::collection::Collection;
+use store::core::document::Document;
+use store::core::error::StoreError;
+use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
+use store::rand::{self, Rng};
+use store::roaring::RoaringBitmap;
+use store::{AccountId, JMAPStore, SharedBitmap, Store};
+
+impl SetObject for ContactCard {
+    type SetArguments = ();
+
+    type NextCall = ();
+
+    fn eval_id_references(&mut self, mut fnc: impl FnMut(&str) -> Option<JMAPId>) {
+        if let Some(Value::AddressBookIds { value, .. }) =
+            self.properties.get_mut(&Property::AddressBookIds)
+        {
+            if value
+                .keys()
+                .any(|k| matches!(k, MaybeIdReference::Reference(_)))
+            {
+                let mut new_values = VecMap::with_capacity(value.len());
+
+                for (id, value) in std::mem::take(value).into_iter() {
+                    if let MaybeIdReference::Reference(id) = &id {
+                        if let Some(id) = fnc(id) {
+                            new_values.append(MaybeIdReference::Value(id), value);
+                            continue;
+                        }
+                    }
+                    new_values.append(id, value);
+                }
+
+                *value = new_values;
+            }
+        }
+    }
+
+    fn eval_result_references(
+        &mut self,
+        mut fnc: impl FnMut(&ResultReference) -> Option<Vec<u64>>,
+    ) {
+        for (property, entry) in self.properties.iter_mut() {
+            if let (Property::AddressBookIds, Value::ResultReference { value }) = (property, &entry)
+            {
+                if let Some(value) = fnc(value) {
+                    *entry = Value::AddressBookIds {
+                        value: value
+                            .into_iter()
+                            .map(|v| (MaybeIdReference::Value(v.into()), true))
+                            .collect(),
+                        set: true,
+                    };
+                }
+            }
+        }
+    }
+
+    fn set_property(&mut self, property: Self::Property, value: Self::Value) {
+        self.properties.set(property, value);
+    }
+}
+
+pub trait JMAPSetContactCard<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        &self,
+        request: SetRequest<ContactCard>,
+    ) -> jmap::Result<SetResponse<ContactCard>>;
+    fn contact_card_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()>;
+}
+
+impl<T> JMAPSetContactCard<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        &self,
+        request: SetRequest<ContactCard>,
+    ) -> jmap::Result<SetResponse<ContactCard>> {
+        let mut helper = SetHelper::new(self, request)?;
+        let address_book_ids = self
+            .get_document_ids(helper.account_id, Collection::AddressBook)?
+            .unwrap_or_default();
+
+        helper.create(|_create_id, item, helper, document| {
+            let mut fields = TinyORM::<ContactCard>::new().contact_card_set(
+                helper,
+                item,
+                None,
+                &address_book_ids,
+            )?;
+            let mut contact_card = ContactCard::new(document.document_id.into());
+
+            // Set server-generated properties
+            if !fields.has_property(&Property::Uid) {
+                let uid = Value::Text {
+                    value: generate_uid(),
+                };
+                contact_card.properties.append(Property::Uid, uid.clone());
+                fields.set(Property::Uid, uid);
+            }
+            for property in [Property::Created, Property::Updated] {
+                if !fields.has_property(&property) {
+                    let now = Value::Date {
+                        value: JMAPDate::from_timestamp(now()),
+                    };
+                    contact_card.properties.append(property, now.clone());
+                    fields.set(property, now);
+                }
+            }
+
+            // Make sure the card belongs to at least one address book
+            if !fields.has_tags(&Property::AddressBookIds) {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::AddressBookIds)
+                    .with_description("Card has to belong to at least one address book."));
+            }
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                let allowed_address_books = helper.store.contacts_shared_address_books(
+                    helper.account_id,
+                    &helper.acl.member_of,
+                    ACL::AddItems,
+                )?;
+                for address_book in fields.get_tags(&Property::AddressBookIds).unwrap() {
+                    let address_book_id = address_book.as_id();
+                    if !allowed_address_books.has_access(address_book_id) {
+                        return Err(SetError::forbidden().with_description(format!(
+                            "You are not allowed to add cards to address book {}.",
+                            JMAPId::from(address_book_id)
+                        )));
+                    }
+                }
+            }
+
+            fields.insert_validate(document)?;
+
+            Ok(contact_card)
+        })?;
+
+        helper.update(|id, item, helper, document| {
+            let document_id = id.get_document_id();
+            let current_fields = self
+                .get_orm::<ContactCard>(helper.account_id, document_id)?
+                .ok_or_else(|| SetError::new(SetErrorType::NotFound))?;
+            let mut fields = TinyORM::track_changes(&current_fields).contact_card_set(
+                helper,
+                item,
+                Some(&current_fields),
+                &address_book_ids,
+            )?;
+
+            // Make sure the card belongs to at least one address book
+            if !fields.has_tags(&Property::AddressBookIds) {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::AddressBookIds)
+                    .with_description("Card has to belong to at least one address book."));
+            }
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id) {
+                if !helper
+                    .store
+                    .contacts_shared_cards(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::ModifyItems,
+                    )?
+                    .has_access(document_id)
+                {
+                    return Err(SetError::forbidden()
+                        .with_description("You are not allowed to modify this card."));
+                }
+
+                // All added address books have to allow insertions
+                let added_address_books =
+                    current_fields.get_added_tags(&fields, &Property::AddressBookIds);
+                if !added_address_books.is_empty() {
+                    let allowed_address_books = helper.store.contacts_shared_address_books(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::AddItems,
+                    )?;
+                    for address_book in added_address_books {
+                        let address_book_id = address_book.as_id();
+                        if !allowed_address_books.has_access(address_book_id) {
+                            return Err(SetError::forbidden().with_description(format!(
+                                "You are not allowed to add cards to address book {}.",
+                                JMAPId::from(address_book_id)
+                            )));
+                        }
+                    }
+                }
+
+                // All removed address books have to allow deletions
+                let removed_address_books =
+                    current_fields.get_removed_tags(&fields, &Property::AddressBookIds);
+                if !removed_address_books.is_empty() {
+                    let allowed_address_books = helper.store.contacts_shared_address_books(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?;
+                    for address_book in removed_address_books {
+                        let address_book_id = address_book.as_id();
+                        if !allowed_address_books.has_access(address_book_id) {
+                            return Err(SetError::forbidden().with_description(format!(
+                                "You are not allowed to remove cards from address book {}.",
+                                JMAPId::from(address_book_id)
+                            )));
+                        }
+                    }
+                }
+            }
+
+            // Update the modification date unless the client provided one
+            if !fields.has_property(&Property::Updated) {
+                fields.set(
+                    Property::Updated,
+                    Value::Date {
+                        value: JMAPDate::from_timestamp(now()),
+                    },
+                );
+            }
+
+            // Merge changes
+            current_fields.merge_validate(document, fields)?;
+
+            Ok(None)
+        })?;
+
+        helper.destroy(|_id, helper, document| {
+            let document_id = document.document_id;
+
+            // Check ACLs
+            if helper.acl.is_shared(helper.account_id)
+                && !helper
+                    .store
+                    .contacts_shared_cards(
+                        helper.account_id,
+                        &helper.acl.member_of,
+                        ACL::RemoveItems,
+                    )?
+                    .has_access(document_id)
+            {
+                return Err(SetError::forbidden()
+                    .with_description("You are not allowed to delete this card."));
+            }
+
+            // Delete ORM and index
+            if let Some(orm) = helper
+                .store
+                .get_orm::<ContactCard>(helper.account_id, document_id)?
+            {
+                orm.delete(document);
+            }
+
+            Ok(())
+        })?;
+
+        helper.into_response()
+    }
+
+    fn contact_card_delete(
+        &self,
+        account_id: AccountId,
+        document: &mut Document,
+    ) -> store::Result<()> {
+        // Delete ORM
+        self.get_orm::<ContactCard>(account_id, document.document_id)?
+            .ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Failed to fetch ContactCard ORM for {}:{}.",
+                    account_id, document.document_id
+                ))
+            })?
+            .delete(document);
+
+        Ok(())
+    }
+}
+
+trait ContactCardSet<T>: Sized
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        self,
+        helper: &mut SetHelper<ContactCard, T>,
+        contact_card: ContactCard,
+        current_fields: Option<&TinyORM<ContactCard>>,
+        address_book_ids: &RoaringBitmap,
+    ) -> jmap::error::set::Result<Self, Property>;
+}
+
+impl<T> ContactCardSet<T> for TinyORM<ContactCard>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn contact_card_set(
+        mut self,
+        helper: &mut SetHelper<ContactCard, T>,
+        contact_card: ContactCard,
+        current_fields: Option<&TinyORM<ContactCard>>,
+        address_book_ids: &RoaringBitmap,
+    ) -> jmap::error::set::Result<Self, Property> {
+        for (property, value) in contact_card.properties {
+            let value = match (property, value) {
+                (Property::AddressBookIds, Value::AddressBookIds { value, set }) => {
+                    if set {
+                        self.untag_all(&Property::AddressBookIds);
+                    }
+
+                    for (address_book_id, is_set) in value {
+                        let address_book_id = helper
+                            .unwrap_id_reference(Property::AddressBookIds, &address_book_id)?
+                            .get_document_id();
+
+                        if address_book_ids.contains(address_book_id) {
+                            if is_set {
+                                self.tag(Property::AddressBookIds, Tag::Id(address_book_id));
+                            } else {
+                                self.untag(&Property::AddressBookIds, &Tag::Id(address_book_id));
+                            }
+                        } else {
+                            return Err(SetError::invalid_properties()
+                                .with_property(Property::AddressBookIds)
+                                .with_description(format!(
+                                    "addressBookId {} does not exist.",
+                                    JMAPId::from(address_book_id)
+                                )));
+                        }
+                    }
+                    continue;
+                }
+                (Property::Type, Value::Text { value }) => {
+                    if value == "Card" {
+                        continue;
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid object type."));
+                    }
+                }
+                (Property::Uid, Value::Text { value }) => {
+                    match current_fields.and_then(|f| f.get(&Property::Uid)) {
+                        Some(Value::Text { value: current_uid }) if current_uid != &value => {
+                            return Err(SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("The uid of a card cannot be changed."));
+                        }
+                        _ => Value::Text { value },
+                    }
+                }
+                (Property::Kind, Value::Text { value }) => {
+                    let kind = value.to_lowercase();
+                    if is_valid_kind(&kind) {
+                        Value::Text { value: kind }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid kind."));
+                    }
+                }
+                (
+                    Property::Kind | Property::Language | Property::ProdId | Property::Version,
+                    value @ (Value::Text { .. } | Value::Null),
+                ) => value,
+                (Property::Created | Property::Updated, Value::Date { value }) => {
+                    if value.is_valid() {
+                        Value::Date { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Invalid date."));
+                    }
+                }
+                (Property::Created | Property::Updated, Value::Null) => Value::Null,
+                (property, Value::Json { value }) if property.is_json() => {
+                    if value.starts_with('{') {
+                        Value::Json { value }
+                    } else {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description("Expected a JSON object."));
+                    }
+                }
+                (property, Value::JsonPatch { value }) if property.is_json() => {
+                    let mut json = match self
+                        .get(&property)
+                        .or_else(|| current_fields.and_then(|f| f.get(&property)))
+                    {
+                        Some(Value::Json { value }) => serde_json::from_str(value)
+                            .unwrap_or_else(|_| serde_json::Value::Object(Default::default())),
+                        _ => serde_json::Value::Object(Default::default()),
+                    };
+                    for (path, value) in value {
+                        if !apply_patch(
+                            &mut json,
+                            &path,
+                            serde_json::from_str(&value).unwrap_or_default(),
+                        ) {
+                            return Err(SetError::new(SetErrorType::InvalidPatch)
+                                .with_property(property)
+                                .with_description(format!(
+                                    "Failed to apply patch '{}/{}'.",
+                                    property,
+                                    path.join("/")
+                                )));
+                        }
+                    }
+                    if json.as_object().map_or(false, |o| !o.is_empty()) {
+                        Value::Json {
+                            value: json.to_string(),
+                        }
+                    } else {
+                        Value::Null
+                    }
+                }
+                (property, Value::Null) if property.is_json() => Value::Null,
+                (_, _) => {
+                    return Err(SetError::invalid_properties()
+                        .with_property(property)
+                        .with_description("Unexpected value."));
+                }
+            };
+
+            // Keep the sortable name components in sync
+            if property == Property::Name {
+                let name = if let Value::Json { value } = &value {
+                    serde_json::from_str::<serde_json::Value>(value).ok()
+                } else {
+                    None
+                };
+                for (kind, property) in [
+                    ("given", Property::NameGiven),
+                    ("surname", Property::NameSurname),
+                ] {
+                    self.set(
+                        property,
+                        name.as_ref()
+                            .and_then(|name| name_component(name, kind))
+                            .map(|value| Value::Text { value })
+                            .unwrap_or_default(),
+                    );
+                }
+            }
+
+            self.set(property, value);
+        }
+
+        Ok(self)
+    }
+}
+
+// Returns the components of a JSContact name matching the given kind.
+fn name_component(name: &serde_json::Value, kind: &str) -> Option<String> {
+    let mut result = String::new();
+    for component in name.get("components")?.as_array()? {
+        if component.get("kind").and_then(|k| k.as_str()) == Some(kind) {
+            if let Some(value) = component.get("value").and_then(|v| v.as_str()) {
+                if !result.is_empty() {
+                    result.push(' ');
+                }
+                result.push_str(value);
+            }
+        }
+    }
+    if !result.is_empty() {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+fn apply_patch(target: &mut serde_json::Value, path: &[String], value: serde_json::Value) -> bool {
+    let (key, path) = if let Some(item) = path.split_first() {
+        item
+    } else {
+        return false;
+    };
+
+    match target {
+        serde_json::Value::Object(map) => {
+            if path.is_empty() {
+                if value.is_null() {
+                    map.remove(key);
+                } else {
+                    map.insert(key.to_string(), value);
+                }
+                true
+            } else {
+                apply_patch(
+                    map.entry(key.to_string())
+                        .or_insert_with(|| serde_json::Value::Object(Default::default())),
+                    path,
+                    value,
+                )
+            }
+        }
+        serde_json::Value::Array(items) => match key.parse::<usize>() {
+            Ok(pos) if pos < items.len() => {
+                if path.is_empty() {
+                    items[pos] = value;
+                    true
+                } else {
+                    apply_patch(&mut items[pos], path, value)
+                }
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn generate_uid() -> String {
+    let mut bytes = rand::thread_rng().gen::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as i64
+}
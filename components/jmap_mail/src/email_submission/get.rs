@@ -98,12 +98,10 @@ where
             for property in properties {
                 email_submission.append(
                     *property,
-                    if let Property::Id = property {
-                        Value::Id { value: id }
-                    } else if let Some(value) = fields.remove(property) {
-                        value
-                    } else {
-                        Value::Null
+                    match property {
+                        Property::Id => Value::Id { value: id },
+                        Property::DeliveryQueue => Value::Null,
+                        _ => fields.remove(property).unwrap_or(Value::Null),
                     },
                 );
             }
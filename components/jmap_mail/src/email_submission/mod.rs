@@ -24,7 +24,9 @@
 pub mod changes;
 pub mod get;
 pub mod query;
+pub mod queue;
 pub mod raft;
+pub mod report;
 pub mod schema;
 pub mod serialize;
 pub mod set;
@@ -64,6 +66,7 @@ impl Object for EmailSubmission {
             (Property::IdentityId, <u64 as Options>::F_INDEX),
             (Property::ThreadId, <u64 as Options>::F_INDEX),
             (Property::SendAt, <u64 as Options>::F_INDEX),
+            (Property::Envelope, <u64 as Options>::F_KEYWORD),
         ]
     }
 
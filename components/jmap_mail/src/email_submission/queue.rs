@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{orm::serialize::JMAPOrm, SUPERUSER_ID};
+use store::{
+    core::{collection::Collection, tag::Tag},
+    AccountId, DocumentId, JMAPStore, Store,
+};
+
+use super::schema::{EmailSubmission, Property, Value};
+
+pub struct QueuedSubmission {
+    pub account_id: AccountId,
+    pub document_id: DocumentId,
+    pub next_attempt: u64,
+}
+
+pub trait JMAPEmailSubmissionQueue<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_queued(&self) -> store::Result<Vec<QueuedSubmission>>;
+}
+
+impl<T> JMAPEmailSubmissionQueue<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_queued(&self) -> store::Result<Vec<QueuedSubmission>> {
+        let mut queued = Vec::new();
+
+        for account_id in self
+            .get_document_ids(SUPERUSER_ID, Collection::Principal)?
+            .unwrap_or_default()
+        {
+            for document_id in self
+                .get_tag(
+                    account_id,
+                    Collection::EmailSubmission,
+                    Property::DeliveryQueue.into(),
+                    Tag::Default,
+                )?
+                .unwrap_or_default()
+            {
+                if let Some(Value::DeliveryQueue { value }) = self
+                    .get_orm::<EmailSubmission>(account_id, document_id)?
+                    .and_then(|mut fields| fields.remove(&Property::DeliveryQueue))
+                {
+                    queued.push(QueuedSubmission {
+                        account_id,
+                        document_id,
+                        next_attempt: value.next_attempt,
+                    });
+                }
+            }
+        }
+
+        Ok(queued)
+    }
+}
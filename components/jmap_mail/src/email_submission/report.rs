@@ -0,0 +1,491 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{
+    orm::{serialize::JMAPOrm, TinyORM},
+    types::{blob::JMAPBlob, jmap::JMAPId},
+};
+use mail_parser::{HeaderName, HeaderValue, Message, MessagePart, PartType, RfcHeader};
+use store::{
+    blob::BlobId,
+    core::{collection::Collection, document::Document, JMAPIdPrefix},
+    read::{
+        comparator::Comparator,
+        filter::{Filter, Query},
+        FilterMapper,
+    },
+    write::{batch::WriteBatch, update::Changes},
+    AccountId, DocumentId, FieldId, JMAPStore, Store,
+};
+
+use crate::mail::MessageField;
+
+use super::schema::{Delivered, Displayed, EmailSubmission, Property, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportType {
+    Dsn,
+    Mdn,
+}
+
+#[derive(Debug, Default)]
+pub struct ReportRecipient {
+    pub addresses: Vec<String>,
+    pub delivered: Option<Delivered>,
+    pub displayed: Option<Displayed>,
+    pub reply: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct DeliveryReport {
+    pub report_type: ReportType,
+    pub message_id: Option<String>,
+    pub envelope_id: Option<String>,
+    pub recipients: Vec<ReportRecipient>,
+}
+
+pub trait JMAPEmailSubmissionReport<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_report(
+        &self,
+        account_id: AccountId,
+        message: &Message,
+        blob_id: &BlobId,
+        envelope_from: &str,
+        is_forged: bool,
+    ) -> store::Result<Option<Changes>>;
+
+    fn email_submission_find(
+        &self,
+        account_id: AccountId,
+        report: &DeliveryReport,
+    ) -> store::Result<Vec<DocumentId>>;
+}
+
+impl<T> JMAPEmailSubmissionReport<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn email_submission_report(
+        &self,
+        account_id: AccountId,
+        message: &Message,
+        blob_id: &BlobId,
+        envelope_from: &str,
+        is_forged: bool,
+    ) -> store::Result<Option<Changes>> {
+        let report = if let Some(report) = DeliveryReport::parse(message) {
+            report
+        } else {
+            return Ok(None);
+        };
+
+        // DSNs are sent with a null reverse-path (RFC 3464, section 2) and reports
+        // whose DKIM signatures failed verification are not trusted.
+        if (report.report_type == ReportType::Dsn && !envelope_from.is_empty()) || is_forged {
+            return Ok(None);
+        }
+
+        // Deliveries update the same submissions
+        let _lock = self.lock_collection(account_id, Collection::EmailSubmission);
+
+        let mut batch = WriteBatch::new(account_id);
+        for document_id in self.email_submission_find(account_id, &report)? {
+            let current_email_submission =
+                if let Some(fields) = self.get_orm::<EmailSubmission>(account_id, document_id)? {
+                    fields
+                } else {
+                    continue;
+                };
+
+            // Only accept reports about recipients of this submission
+            let rcpt_to = match current_email_submission.get(&Property::Envelope) {
+                Some(Value::Envelope { value }) => &value.rcpt_to,
+                _ => continue,
+            };
+            if !report.recipients.iter().any(|recipient| {
+                recipient.addresses.iter().any(|addr| {
+                    rcpt_to
+                        .iter()
+                        .any(|rcpt| rcpt.email.eq_ignore_ascii_case(addr))
+                })
+            }) {
+                continue;
+            }
+
+            let mut email_submission = TinyORM::track_changes(&current_email_submission);
+
+            // Update the status of the recipients included in the report
+            if let Some(Value::DeliveryStatus { value }) =
+                current_email_submission.get(&Property::DeliveryStatus)
+            {
+                let mut delivery_status = value.clone();
+                for recipient in &report.recipients {
+                    if let Some(status) = delivery_status.iter_mut().find_map(|(rcpt, status)| {
+                        if recipient
+                            .addresses
+                            .iter()
+                            .any(|addr| addr.eq_ignore_ascii_case(rcpt))
+                        {
+                            Some(status)
+                        } else {
+                            None
+                        }
+                    }) {
+                        if let Some(delivered) = &recipient.delivered {
+                            status.delivered = delivered.clone();
+                        }
+                        if let Some(displayed) = &recipient.displayed {
+                            status.displayed = displayed.clone();
+                        }
+                        if let Some(reply) = &recipient.reply {
+                            status.smtp_reply = reply.clone();
+                        }
+                    }
+                }
+                if &delivery_status != value {
+                    email_submission.set(
+                        Property::DeliveryStatus,
+                        Value::DeliveryStatus {
+                            value: delivery_status,
+                        },
+                    );
+                }
+            }
+
+            // Link the report
+            let property = match report.report_type {
+                ReportType::Dsn => Property::DsnBlobIds,
+                ReportType::Mdn => Property::MdnBlobIds,
+            };
+            let blob = JMAPBlob::new(blob_id.clone());
+            let mut blob_ids = match current_email_submission.get(&property) {
+                Some(Value::BlobIds { value }) => value.clone(),
+                _ => Vec::new(),
+            };
+            if !blob_ids.contains(&blob) {
+                blob_ids.push(blob);
+                email_submission.set(property, Value::BlobIds { value: blob_ids });
+            }
+
+            // Merge changes
+            let mut document = Document::new(Collection::EmailSubmission, document_id);
+            current_email_submission.merge(&mut document, email_submission)?;
+            if !document.is_empty() {
+                batch.update_document(document);
+                batch.log_update(Collection::EmailSubmission, document_id);
+            }
+        }
+
+        if !batch.is_empty() {
+            self.write(batch)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn email_submission_find(
+        &self,
+        account_id: AccountId,
+        report: &DeliveryReport,
+    ) -> store::Result<Vec<DocumentId>> {
+        // Find the submitted e-mail by its Message-ID
+        if let Some(message_id) = &report.message_id {
+            let document_ids = self
+                .query_store::<FilterMapper>(
+                    account_id,
+                    Collection::Mail,
+                    Filter::eq(
+                        RfcHeader::MessageId as FieldId,
+                        Query::Keyword(message_id.to_string()),
+                    ),
+                    Comparator::None,
+                )?
+                .into_iter()
+                .map(|id| id.get_document_id())
+                .collect::<Vec<_>>();
+
+            if !document_ids.is_empty() {
+                let thread_ids = self.get_multi_document_value::<DocumentId>(
+                    account_id,
+                    Collection::Mail,
+                    document_ids.iter().copied(),
+                    MessageField::ThreadId.into(),
+                )?;
+                let email_ids = document_ids
+                    .into_iter()
+                    .zip(thread_ids)
+                    .filter_map(|(document_id, thread_id)| {
+                        Filter::eq(
+                            Property::EmailId.into(),
+                            Query::LongInteger(JMAPId::from_parts(thread_id?, document_id).into()),
+                        )
+                        .into()
+                    })
+                    .collect::<Vec<_>>();
+
+                if !email_ids.is_empty() {
+                    let submission_ids = self
+                        .query_store::<FilterMapper>(
+                            account_id,
+                            Collection::EmailSubmission,
+                            Filter::or(email_ids),
+                            Comparator::None,
+                        )?
+                        .into_iter()
+                        .map(|id| id.get_document_id())
+                        .collect::<Vec<_>>();
+                    if !submission_ids.is_empty() {
+                        return Ok(submission_ids);
+                    }
+                }
+            }
+        }
+
+        // Fall back to the envelope id set with the ENVID parameter
+        if let Some(envelope_id) = &report.envelope_id {
+            Ok(self
+                .query_store::<FilterMapper>(
+                    account_id,
+                    Collection::EmailSubmission,
+                    Filter::eq(
+                        Property::Envelope.into(),
+                        Query::Keyword(envelope_id.to_string()),
+                    ),
+                    Comparator::None,
+                )?
+                .into_iter()
+                .map(|id| id.get_document_id())
+                .collect())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+impl DeliveryReport {
+    /// Parses a multipart/report delivery status (RFC 3464) or
+    /// disposition notification (RFC 8098).
+    pub fn parse(message: &Message) -> Option<Self> {
+        let (c_type, c_subtype) = message.parts.get(0).and_then(content_type)?;
+        if !c_type.eq_ignore_ascii_case("multipart") || !c_subtype.eq_ignore_ascii_case("report") {
+            return None;
+        }
+
+        let mut report = None;
+        let mut message_id = None;
+
+        for part in message.parts.iter().skip(1) {
+            match content_type(part) {
+                Some((c_type, c_subtype)) if c_type.eq_ignore_ascii_case("message") => {
+                    if c_subtype.eq_ignore_ascii_case("delivery-status") {
+                        report = Some((ReportType::Dsn, parse_fields(part.get_contents())));
+                    } else if c_subtype.eq_ignore_ascii_case("disposition-notification") {
+                        report = Some((ReportType::Mdn, parse_fields(part.get_contents())));
+                    } else if let (PartType::Message(message), None) = (&part.body, &message_id) {
+                        message_id = message.parts.get(0).and_then(|part| {
+                            part.headers.iter().find_map(|header| {
+                                match (&header.name, &header.value) {
+                                    (
+                                        HeaderName::Rfc(RfcHeader::MessageId),
+                                        HeaderValue::Text(id),
+                                    ) => Some(id.to_string()),
+                                    _ => None,
+                                }
+                            })
+                        });
+                    }
+                }
+                Some((c_type, c_subtype))
+                    if c_type.eq_ignore_ascii_case("text")
+                        && c_subtype.eq_ignore_ascii_case("rfc822-headers")
+                        && message_id.is_none() =>
+                {
+                    message_id = parse_fields(part.get_contents())
+                        .into_iter()
+                        .flatten()
+                        .find_map(|(name, value)| {
+                            if name == "message-id" {
+                                Some(strip_angle_brackets(&value))
+                            } else {
+                                None
+                            }
+                        });
+                }
+                _ => (),
+            }
+        }
+
+        let (report_type, blocks) = report?;
+        let mut blocks = blocks.into_iter();
+        let per_message = blocks.next().unwrap_or_default();
+        let mut envelope_id = None;
+
+        for (name, value) in &per_message {
+            match name.as_str() {
+                "original-envelope-id" => {
+                    envelope_id = Some(value.to_string());
+                }
+                "original-message-id" => {
+                    message_id = Some(strip_angle_brackets(value));
+                }
+                _ => (),
+            }
+        }
+
+        let mut recipients = Vec::new();
+        match report_type {
+            ReportType::Dsn => {
+                for block in blocks {
+                    let mut recipient = ReportRecipient::default();
+                    let mut status = None;
+                    let mut diagnostic = None;
+
+                    for (name, value) in block {
+                        match name.as_str() {
+                            "original-recipient" | "final-recipient" => {
+                                recipient.addresses.push(parse_address(&value));
+                            }
+                            "action" => {
+                                recipient.delivered = match value.to_ascii_lowercase().as_str() {
+                                    "failed" => Delivered::No.into(),
+                                    "delivered" => Delivered::Yes.into(),
+                                    "relayed" | "expanded" => Delivered::Unknown.into(),
+                                    _ => None,
+                                };
+                            }
+                            "status" => {
+                                status = Some(value);
+                            }
+                            "diagnostic-code" => {
+                                diagnostic = Some(parse_address(&value));
+                            }
+                            _ => (),
+                        }
+                    }
+
+                    // Delays do not change the status, the message is still queued
+                    if recipient.delivered.is_some() && !recipient.addresses.is_empty() {
+                        recipient.reply = diagnostic.or(status);
+                        recipients.push(recipient);
+                    }
+                }
+            }
+            ReportType::Mdn => {
+                // MDNs carry a single recipient in the per-message fields
+                let mut recipient = ReportRecipient::default();
+                for (name, value) in per_message {
+                    match name.as_str() {
+                        "original-recipient" | "final-recipient" => {
+                            recipient.addresses.push(parse_address(&value));
+                        }
+                        "disposition" => {
+                            if value
+                                .rsplit_once(';')
+                                .map(|(_, disposition)| disposition)
+                                .unwrap_or_default()
+                                .split('/')
+                                .next()
+                                .unwrap_or_default()
+                                .trim()
+                                .eq_ignore_ascii_case("displayed")
+                            {
+                                recipient.displayed = Displayed::Yes.into();
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                if recipient.displayed.is_some() && !recipient.addresses.is_empty() {
+                    recipients.push(recipient);
+                }
+            }
+        }
+
+        Some(DeliveryReport {
+            report_type,
+            message_id,
+            envelope_id,
+            recipients,
+        })
+    }
+}
+
+fn content_type<'x>(part: &'x MessagePart) -> Option<(&'x str, &'x str)> {
+    part.headers
+        .iter()
+        .find_map(|header| match (&header.name, &header.value) {
+            (HeaderName::Rfc(RfcHeader::ContentType), HeaderValue::ContentType(content_type)) => (
+                content_type.c_type.as_ref(),
+                content_type.c_subtype.as_deref().unwrap_or_default(),
+            )
+                .into(),
+            _ => None,
+        })
+}
+
+// Parses the blocks of header-like fields used by delivery and disposition reports
+fn parse_fields(bytes: &[u8]) -> Vec<Vec<(String, String)>> {
+    let mut blocks = Vec::new();
+    let mut block: Vec<(String, String)> = Vec::new();
+
+    for line in String::from_utf8_lossy(bytes).lines() {
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                blocks.push(std::mem::take(&mut block));
+            }
+        } else if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = block.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            block.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+
+    blocks
+}
+
+// Removes the type prefix from fields such as "rfc822; jdoe@example.com"
+fn parse_address(value: &str) -> String {
+    value
+        .split_once(';')
+        .map(|(_, value)| value)
+        .unwrap_or(value)
+        .trim()
+        .to_string()
+}
+
+fn strip_angle_brackets(value: &str) -> String {
+    value
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_string()
+}
@@ -59,6 +59,9 @@ pub enum Value {
     BlobIds {
         value: Vec<JMAPBlob>,
     },
+    DeliveryQueue {
+        value: DeliveryQueue,
+    },
     IdReference {
         value: String,
     },
@@ -143,6 +146,31 @@ impl DeliveryStatus {
     }
 }
 
+impl DeliveryStatus {
+    pub fn is_pending(&self) -> bool {
+        // Recipients queued locally have not been accepted by the relay yet
+        self.delivered == Delivered::Queued && !self.smtp_reply.starts_with('2')
+    }
+}
+
+// Internal delivery state, never returned to JMAP clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryQueue {
+    pub queued_at: u64,
+    pub next_attempt: u64,
+    pub attempts: u32,
+}
+
+impl DeliveryQueue {
+    pub fn new(send_at: u64) -> Self {
+        DeliveryQueue {
+            queued_at: send_at,
+            next_attempt: send_at,
+            attempts: 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Delivered {
     #[serde(rename = "queued")]
@@ -176,7 +204,8 @@ pub enum Property {
     DeliveryStatus = 7,
     DsnBlobIds = 8,
     MdnBlobIds = 9,
-    Invalid = 10,
+    DeliveryQueue = 10,
+    Invalid = 11,
 }
 
 impl Property {
@@ -192,6 +221,7 @@ impl Property {
             "deliveryStatus" => Property::DeliveryStatus,
             "dsnBlobIds" => Property::DsnBlobIds,
             "mdnBlobIds" => Property::MdnBlobIds,
+            "deliveryQueue" => Property::DeliveryQueue,
             _ => Property::Invalid,
         }
     }
@@ -210,6 +240,7 @@ impl Display for Property {
             Property::DeliveryStatus => write!(f, "deliveryStatus"),
             Property::DsnBlobIds => write!(f, "dsnBlobIds"),
             Property::MdnBlobIds => write!(f, "mdnBlobIds"),
+            Property::DeliveryQueue => write!(f, "deliveryQueue"),
             Property::Invalid => Ok(()),
         }
     }
@@ -234,6 +265,7 @@ impl From<FieldId> for Property {
             7 => Property::DeliveryStatus,
             8 => Property::DsnBlobIds,
             9 => Property::MdnBlobIds,
+            10 => Property::DeliveryQueue,
             _ => Property::Invalid,
         }
     }
@@ -282,6 +314,16 @@ impl orm::Value for Value {
                 UndoStatus::Final => "f".to_string().into(),
                 UndoStatus::Canceled => "c".to_string().into(),
             },
+            // Envelopes are indexed by their ENVID, used to match delivery reports
+            Value::Envelope { value } => value
+                .mail_from
+                .parameters
+                .as_ref()
+                .and_then(|p| p.get("ENVID"))
+                .and_then(|v| v.clone())
+                .into_iter()
+                .collect::<Vec<_>>()
+                .into(),
             _ => orm::Index::Null,
         }
     }
@@ -305,6 +347,7 @@ impl orm::Value for Value {
             }),
             Value::Envelope { value } => value.len(),
             Value::BlobIds { value } => value.len() * std::mem::size_of::<JMAPBlob>(),
+            Value::DeliveryQueue { .. } => std::mem::size_of::<DeliveryQueue>(),
             Value::IdReference { value } => value.len(),
             Value::ResultReference { .. } => std::mem::size_of::<ResultReference>(),
             Value::Null => 0,
@@ -88,6 +88,7 @@ impl Serialize for EmailSubmission {
                 Value::UndoStatus { value } => map.serialize_entry(name, value)?,
                 Value::DeliveryStatus { value } => map.serialize_entry(name, value)?,
                 Value::BlobIds { value } => map.serialize_entry(name, value)?,
+                Value::DeliveryQueue { value } => map.serialize_entry(name, value)?,
                 Value::Envelope { value } => map.serialize_entry(name, value)?,
             }
         }
@@ -21,7 +21,9 @@
  * for more details.
 */
 
-use super::schema::{Address, EmailSubmission, Envelope, Property, Value};
+use super::schema::{
+    Address, DeliveryQueue, EmailSubmission, Envelope, Property, UndoStatus, Value,
+};
 use crate::identity;
 use crate::identity::schema::Identity;
 use crate::mail::schema::Email;
@@ -42,6 +44,7 @@ use store::blob::BlobId;
 use store::core::collection::Collection;
 use store::core::document::Document;
 use store::core::error::StoreError;
+use store::core::tag::Tag;
 use store::core::vec_map::VecMap;
 use store::serialize::{StoreDeserialize, StoreSerialize};
 use store::write::options::{IndexOptions, Options};
@@ -199,10 +202,11 @@ where
                 })?;
 
             // Make sure the envelope address matches the identity email address
-            let mut send_at = SystemTime::now()
+            let now = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .map(|d| d.as_secs())
-                .unwrap_or(0) as i64;
+                .unwrap_or(0);
+            let mut send_at = now as i64;
             let mut envelope = if let Some(envelope) = envelope {
                 if !envelope.mail_from.email.eq_ignore_ascii_case(&mail_from) {
                     return Err(SetError::invalid_properties()
@@ -232,6 +236,16 @@ where
                 Envelope::new(mail_from)
             };
 
+            // Make sure the delayed send is within the allowed limit
+            if send_at - now as i64 > helper.store.config.submission_max_delayed_send as i64 {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::Envelope)
+                    .with_description(format!(
+                        "Messages cannot be delayed more than {} seconds.",
+                        helper.store.config.submission_max_delayed_send
+                    )));
+            }
+
             // Make sure we have all required fields.
             if email_id.get_document_id() == u32::MAX || identity_id == u32::MAX {
                 return Err(SetError::invalid_properties()
@@ -247,6 +261,36 @@ where
                 },
             );
 
+            // Queue the submission for delivery at sendAt, unless it was created as canceled
+            match fields.get(&Property::UndoStatus) {
+                Some(Value::UndoStatus {
+                    value: UndoStatus::Canceled,
+                }) => (),
+                Some(Value::UndoStatus {
+                    value: UndoStatus::Pending,
+                })
+                | None => {
+                    fields.set(
+                        Property::UndoStatus,
+                        Value::UndoStatus {
+                            value: UndoStatus::Pending,
+                        },
+                    );
+                    fields.set(
+                        Property::DeliveryQueue,
+                        Value::DeliveryQueue {
+                            value: DeliveryQueue::new(send_at as u64),
+                        },
+                    );
+                    fields.tag(Property::DeliveryQueue, Tag::Default);
+                }
+                _ => {
+                    return Err(SetError::invalid_properties()
+                        .with_property(Property::UndoStatus)
+                        .with_description("undoStatus must be pending on creation."));
+                }
+            }
+
             // Fetch message data
             let mut message_data = MessageData::deserialize(
                 &helper
@@ -371,7 +415,41 @@ where
                     .ok_or_else(|| SetError::new(SetErrorType::NotFound))?;
                 let mut fields = TinyORM::track_changes(&current_fields);
 
-                fields.set(Property::UndoStatus, Value::UndoStatus { value });
+                match (value, current_fields.get(&Property::UndoStatus)) {
+                    (
+                        value,
+                        Some(Value::UndoStatus {
+                            value: current_value,
+                        }),
+                    ) if &value == current_value => {}
+                    (UndoStatus::Canceled, _) => {
+                        // Only submissions that have not been released yet can be canceled
+                        if !current_fields.has_property(&Property::DeliveryQueue)
+                            || matches!(
+                                current_fields.get(&Property::UndoStatus),
+                                Some(Value::UndoStatus {
+                                    value: UndoStatus::Final
+                                })
+                            )
+                        {
+                            return Err(SetError::new(SetErrorType::CannotUnsend)
+                                .with_description("The message has already been sent."));
+                        }
+                        fields.set(
+                            Property::UndoStatus,
+                            Value::UndoStatus {
+                                value: UndoStatus::Canceled,
+                            },
+                        );
+                        fields.set(Property::DeliveryQueue, Value::Null);
+                        fields.untag(&Property::DeliveryQueue, &Tag::Default);
+                    }
+                    _ => {
+                        return Err(SetError::invalid_properties()
+                            .with_property(Property::UndoStatus)
+                            .with_description("undoStatus can only be changed to canceled."));
+                    }
+                }
 
                 // Merge changes
                 current_fields.merge_validate(document, fields)?;
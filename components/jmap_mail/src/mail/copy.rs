@@ -31,6 +31,7 @@ use jmap::{
     error::set::SetError,
     jmap_store::copy::CopyHelper,
     orm::TinyORM,
+    principal::store::JMAPPrincipals,
     request::{
         copy::{CopyRequest, CopyResponse},
         set::SetRequest,
@@ -223,6 +224,9 @@ where
                 ))
             })?;
 
+            // Make sure the target account has enough quota
+            self.quota_check(helper.account_id, message_data.size as u64)?;
+
             // Set receivedAt
             if let Some(received_at) = received_at {
                 // Serialize message data and outline
@@ -240,7 +244,7 @@ where
             // Copy properties and build index
             let raw_blob = JMAPBlob::from(&message_data.raw_message);
             let size = message_data.size;
-            message_data.build_index(document, true)?;
+            message_data.build_index(document, true, &self.config)?;
 
             // Link metadata blob
             document.binary(
@@ -155,6 +155,12 @@ where
         acl: &Arc<ACLToken>,
         blob: &JMAPBlob,
     ) -> store::Result<BlobResult>;
+    fn mail_blob_has_access(
+        &self,
+        account_id: AccountId,
+        acl: &Arc<ACLToken>,
+        blob: &JMAPBlob,
+    ) -> store::Result<bool>;
 }
 
 impl<T> JMAPGetMail<T> for JMAPStore<T>
@@ -579,23 +585,8 @@ where
         acl: &Arc<ACLToken>,
         blob: &JMAPBlob,
     ) -> store::Result<BlobResult> {
-        if !self.blob_account_has_access(&blob.id, &acl.member_of)? && !acl.is_member(SUPERUSER_ID)
-        {
-            if let Some(shared_ids) = self
-                .mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
-                .as_ref()
-            {
-                if !self.blob_document_has_access(
-                    &blob.id,
-                    account_id,
-                    Collection::Mail,
-                    shared_ids,
-                )? {
-                    return Ok(BlobResult::Unauthorized);
-                }
-            } else {
-                return Ok(BlobResult::Unauthorized);
-            }
+        if !self.mail_blob_has_access(account_id, acl, blob)? {
+            return Ok(BlobResult::Unauthorized);
         }
 
         Ok(if let Some(section) = &blob.section {
@@ -623,6 +614,24 @@ where
         .map(BlobResult::Blob)
         .unwrap_or(BlobResult::NotFound))
     }
+
+    fn mail_blob_has_access(
+        &self,
+        account_id: AccountId,
+        acl: &Arc<ACLToken>,
+        blob: &JMAPBlob,
+    ) -> store::Result<bool> {
+        if self.blob_account_has_access(&blob.id, &acl.member_of)? || acl.is_member(SUPERUSER_ID) {
+            Ok(true)
+        } else if let Some(shared_ids) = self
+            .mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
+            .as_ref()
+        {
+            self.blob_document_has_access(&blob.id, account_id, Collection::Mail, shared_ids)
+        } else {
+            Ok(false)
+        }
+    }
 }
 
 impl MimePart {
@@ -30,6 +30,7 @@ use jmap::jmap_store::changes::JMAPChanges;
 use jmap::jmap_store::Object;
 use jmap::orm::serialize::JMAPOrm;
 use jmap::orm::TinyORM;
+use jmap::principal::store::JMAPPrincipals;
 use jmap::request::{ACLEnforce, MaybeIdReference, MaybeResultReference, ResultReference};
 use jmap::types::blob::JMAPBlob;
 use jmap::types::date::JMAPDate;
@@ -41,23 +42,27 @@ use mail_parser::{GetHeader, HeaderName, HeaderValue, Message, PartType, RfcHead
 use store::ahash::AHashMap;
 use store::ahash::AHashSet;
 use store::blob::BlobId;
+use store::config::jmap::JMAPConfig;
 use store::core::acl::{ACLToken, ACL};
 use store::core::collection::Collection;
-use store::core::document::{Document, MAX_ID_LENGTH, MAX_SORT_FIELD_LENGTH};
+use store::core::document::{Document, MAX_ID_LENGTH, MAX_SORT_FIELD_LENGTH, MAX_TOKEN_LENGTH};
 use store::core::error::StoreError;
 use store::core::tag::Tag;
 use store::core::vec_map::VecMap;
 use store::core::JMAPIdPrefix;
 use store::log::changes::ChangeId;
+use store::nlp::tokenizers::Tokenizer;
 use store::nlp::Language;
 use store::read::comparator::Comparator;
 use store::read::filter::{Filter, Query};
 use store::read::FilterMapper;
-use store::serialize::StoreSerialize;
+use store::serialize::{StoreDeserialize, StoreSerialize};
 
+use store::roaring::RoaringBitmap;
 use store::tracing::error;
 use store::write::batch::WriteBatch;
 use store::write::options::{IndexOptions, Options};
+use store::write::update::Changes;
 use store::{AccountId, JMAPStore, SharedBitmap, Store, ThreadId};
 use store::{DocumentId, Integer, LongInteger};
 
@@ -67,7 +72,9 @@ use super::conv::HeaderValueInto;
 use super::get::{BlobResult, JMAPGetMail};
 use super::schema::{Email, Keyword, Property};
 use super::sharing::JMAPShareMail;
-use super::{MessageData, MessagePart, MimePart, MimePartType, MAX_MESSAGE_PARTS};
+use super::{
+    MessageData, MessagePart, MimePart, MimePartType, ATTACHMENTS_PENDING, MAX_MESSAGE_PARTS,
+};
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct EmailImportRequest {
@@ -140,6 +147,21 @@ pub trait JMAPMailImport {
         document: &mut Document,
     ) -> store::Result<DocumentId>;
 
+    fn mail_index_attachments(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<Changes>>;
+
+    fn mail_build_attachment_index(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        document: &mut Document,
+    ) -> store::Result<Option<BlobId>>;
+
+    fn mail_pending_attachments(&self, account_id: AccountId) -> store::Result<RoaringBitmap>;
+
     fn mail_merge_threads(
         &self,
         documents: &mut WriteBatch,
@@ -268,11 +290,24 @@ where
 
                 match self.mail_blob_get(account_id, &acl, &item.blob_id)? {
                     BlobResult::Blob(blob) => {
+                        // Hold the lock until the message is written so concurrent
+                        // imports see the updated quota. Blobs uploaded to this
+                        // account were already charged when they were uploaded.
+                        let _lock = self.lock_collection(account_id, Collection::Mail);
+                        if let Err(err) = self.quota_check(
+                            account_id,
+                            (blob.len() as u64).saturating_sub(
+                                self.blob_upload_size(&item.blob_id.id, account_id)?,
+                            ),
+                        ) {
+                            not_created.append(id, err);
+                            continue;
+                        }
                         created.append(
                             id,
                             self.mail_import_item(
                                 account_id,
-                                item.blob_id.id,
+                                item.blob_id.id.clone(),
                                 &blob,
                                 mailbox_ids
                                     .into_iter()
@@ -297,6 +332,7 @@ where
                                 item.received_at.map(|t| t.timestamp()),
                             )?,
                         );
+                        self.blob_release_upload(&item.blob_id.id, account_id)?;
                     }
                     BlobResult::Unauthorized => {
                         not_created.append(
@@ -386,10 +422,7 @@ where
         // Serialize ORM
         orm.insert(&mut document)?;
 
-        // Lock account while threads are merged
-        let _lock = self.lock_collection(batch.account_id, Collection::Mail);
-
-        // Obtain thread Id
+        // Obtain thread Id, the caller holds the collection lock
         let thread_id = self.mail_set_thread(&mut batch, &mut document)?;
 
         // Write document to store
@@ -418,6 +451,7 @@ where
         let root_part = message.get_root_part();
         let mut message_data = MessageData {
             headers: VecMap::with_capacity(root_part.headers.len()),
+            indexed_headers: Vec::new(),
             body_offset: root_part.offset_body,
             mime_parts: Vec::with_capacity(message.parts.len()),
             html_body: message.html_body,
@@ -441,6 +475,25 @@ where
             ));
         }
 
+        // Keep the values of the headers configured to be indexed
+        for header in &message.parts[0].headers {
+            let header_name = header.name.as_str().to_lowercase();
+            if self.config.mail_index_headers.contains(&header_name) {
+                if let Some(value) = message
+                    .raw_message
+                    .get(header.offset_start..header.offset_end)
+                {
+                    message_data.indexed_headers.push((
+                        header_name,
+                        String::from_utf8_lossy(value)
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ));
+                }
+            }
+        }
+
         // Build JMAP headers
         let root_part = &mut message.parts[0];
         let message_language = root_part.get_language().unwrap_or(Language::Unknown);
@@ -581,12 +634,14 @@ where
                 PartType::Multipart(subparts) => (MimePartType::MultiPart { subparts }, 0),
             };
 
-            message_data.mime_parts.push(MimePart::from_headers(
+            let mime_part = MimePart::from_headers(
                 message_part.headers,
                 mime_type,
                 message_part.is_encoding_problem,
                 part_size,
-            ));
+            );
+
+            message_data.mime_parts.push(mime_part);
         }
 
         // Set attachment properties
@@ -609,7 +664,133 @@ where
         document.blob(metadata_blob_id, IndexOptions::new());
 
         // Build index
-        message_data.build_index(document, true)
+        message_data.build_index(document, true, &self.config)
+    }
+
+    // Runs on the leader, the extracted text is replicated by logging the update,
+    // which makes followers extract it as well.
+    fn mail_index_attachments(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+    ) -> store::Result<Option<Changes>> {
+        if !self
+            .mail_pending_attachments(account_id)?
+            .contains(document_id)
+        {
+            return Ok(None);
+        }
+
+        let mut document = Document::new(Collection::Mail, document_id);
+        let metadata_blob_id = if let Some(metadata_blob_id) =
+            self.mail_build_attachment_index(account_id, document_id, &mut document)?
+        {
+            metadata_blob_id
+        } else {
+            return Ok(None);
+        };
+
+        // Skip messages that were deleted while their attachments were extracted
+        let _lock = self.lock_collection(account_id, Collection::Mail);
+        if self.get_document_value::<BlobId>(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::Metadata.into(),
+        )? != Some(metadata_blob_id)
+        {
+            return Ok(None);
+        }
+        let thread_id = if let Some(thread_id) = self.get_document_value::<DocumentId>(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::ThreadId.into(),
+        )? {
+            thread_id
+        } else {
+            return Ok(None);
+        };
+
+        let mut batch = WriteBatch::new(account_id);
+        batch.update_document(document);
+        batch.log_update(Collection::Mail, JMAPId::from_parts(thread_id, document_id));
+        self.write(batch)
+    }
+
+    fn mail_build_attachment_index(
+        &self,
+        account_id: AccountId,
+        document_id: DocumentId,
+        document: &mut Document,
+    ) -> store::Result<Option<BlobId>> {
+        let metadata_blob_id = if let Some(metadata_blob_id) = self.get_document_value::<BlobId>(
+            account_id,
+            Collection::Mail,
+            document_id,
+            MessageField::Metadata.into(),
+        )? {
+            metadata_blob_id
+        } else {
+            return Ok(None);
+        };
+        let message_data =
+            MessageData::deserialize(&self.blob_get(&metadata_blob_id)?.ok_or_else(|| {
+                StoreError::NotFound(format!(
+                    "Message data blob for {}:{} not found.",
+                    account_id, document_id
+                ))
+            })?)
+            .ok_or_else(|| {
+                StoreError::DataCorruption(format!(
+                    "Failed to deserialize message data for {}:{}.",
+                    account_id, document_id
+                ))
+            })?;
+        let raw_message = self.blob_get(&message_data.raw_message)?.ok_or_else(|| {
+            StoreError::NotFound(format!(
+                "Raw message blob for {}:{} not found.",
+                account_id, document_id
+            ))
+        })?;
+
+        // Extract the text of PDF and Office attachments
+        for (part_id, part) in message_data.mime_parts.iter().enumerate() {
+            if let MimePartType::Other { part: message_part } = &part.mime_type {
+                if part.attachment_type(&self.config).is_none() {
+                    continue;
+                }
+                if let Some(text) = message_part
+                    .decode(&raw_message)
+                    .and_then(|bytes| part.extract_text(&bytes, &self.config))
+                {
+                    document.text(
+                        MessageField::Attachment,
+                        text,
+                        Language::Unknown,
+                        IndexOptions::new().full_text((part_id + 1) as u32),
+                    );
+                }
+            }
+        }
+        document.tag(
+            MessageField::Attachment,
+            Tag::Static(ATTACHMENTS_PENDING),
+            IndexOptions::new().clear(),
+        );
+
+        Ok(Some(metadata_blob_id))
+    }
+
+    fn mail_pending_attachments(&self, account_id: AccountId) -> store::Result<RoaringBitmap> {
+        Ok(self
+            .get_tag(
+                account_id,
+                Collection::Mail,
+                MessageField::Attachment.into(),
+                Tag::Static(ATTACHMENTS_PENDING),
+            )?
+            .unwrap_or_default())
     }
 
     fn mail_set_thread(
@@ -883,18 +1064,44 @@ impl AddMessage for Document {
 }
 
 impl MessageData {
-    pub fn build_index(self, document: &mut Document, is_insert: bool) -> store::Result<()> {
+    pub fn has_indexable_attachments(&self, config: &JMAPConfig) -> bool {
+        self.mime_parts.iter().any(|part| {
+            matches!(part.mime_type, MimePartType::Other { .. })
+                && part.attachment_type(config).is_some()
+        })
+    }
+
+    pub fn build_index(
+        self,
+        document: &mut Document,
+        is_insert: bool,
+        config: &JMAPConfig,
+    ) -> store::Result<()> {
         let options = if is_insert {
             IndexOptions::new()
         } else {
             IndexOptions::new().clear()
         };
 
+        // Text from PDF and Office attachments is indexed after the message is written,
+        // pending messages are tagged so they are not lost if the queue is full.
+        if !is_insert || self.has_indexable_attachments(config) {
+            document.tag(
+                MessageField::Attachment,
+                Tag::Static(ATTACHMENTS_PENDING),
+                IndexOptions::new() | options,
+            );
+            if is_insert {
+                document.defer_attachments();
+            }
+        }
+
         document.number(
             MessageField::Size,
             self.size as Integer,
             IndexOptions::new().index() | options,
         );
+        document.quota(self.size as u64, IndexOptions::new() | options);
 
         document.number(
             MessageField::ReceivedAt,
@@ -910,6 +1117,22 @@ impl MessageData {
             );
         }
 
+        for (header_name, value) in self.indexed_headers {
+            document.tag(
+                MessageField::HasHeader,
+                Tag::Text(header_name.clone()),
+                IndexOptions::new() | options,
+            );
+            for token in Tokenizer::new(&value, Language::Unknown, MAX_TOKEN_LENGTH) {
+                document.text(
+                    MessageField::Header,
+                    format!("{}:{}", header_name, token.word),
+                    Language::Unknown,
+                    IndexOptions::new().keyword() | options,
+                );
+            }
+        }
+
         for (header_name, mut values) in self.headers {
             document.tag(
                 MessageField::HasHeader,
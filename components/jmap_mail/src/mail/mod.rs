@@ -37,7 +37,7 @@ pub mod sharing;
 
 use jmap::{jmap_store::Object, types::jmap::JMAPId};
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, fmt::Display};
+use std::{borrow::Cow, fmt::Display, time::Duration};
 
 use mail_parser::{
     decoders::{
@@ -50,15 +50,21 @@ use mail_parser::{
 use store::{
     bincode,
     blob::BlobId,
+    config::jmap::JMAPConfig,
     core::{collection::Collection, vec_map::VecMap},
+    nlp::attachment::{extract_attachment, AttachmentType},
     serialize::{StoreDeserialize, StoreSerialize},
-    FieldId,
+    FieldId, TagId,
 };
 
 use self::schema::{Email, EmailAddress, EmailAddressGroup, Property, Value};
 
 pub const MAX_MESSAGE_PARTS: usize = 1000;
 
+/// Tag on `MessageField::Attachment` of messages whose attachments are waiting
+/// to have their text extracted and indexed.
+pub const ATTACHMENTS_PENDING: TagId = 1;
+
 impl Object for Email {
     type Property = Property;
 
@@ -99,6 +105,7 @@ impl Object for Email {
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MessageData {
     pub headers: VecMap<RfcHeader, Vec<HeaderValue>>,
+    pub indexed_headers: Vec<(String, String)>,
     pub mime_parts: Vec<MimePart>,
     pub html_body: Vec<MessagePartId>,
     pub text_body: Vec<MessagePartId>,
@@ -312,6 +319,30 @@ pub struct MimePart {
     pub size: usize,
 }
 
+impl MimePart {
+    pub fn attachment_type(&self, config: &JMAPConfig) -> Option<AttachmentType> {
+        if self.size <= config.mail_attachments_index_max_size {
+            AttachmentType::parse(self.type_.as_deref(), self.name.as_deref())
+        } else {
+            None
+        }
+    }
+
+    pub fn extract_text(&self, bytes: &[u8], config: &JMAPConfig) -> Option<String> {
+        let attachment_type = AttachmentType::parse(self.type_.as_deref(), self.name.as_deref())?;
+        if bytes.len() <= config.mail_attachments_index_max_size {
+            extract_attachment(
+                bytes.to_vec(),
+                attachment_type,
+                config.mail_attachments_index_max_size,
+                Duration::from_millis(config.mail_attachments_index_timeout),
+            )
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[repr(u8)]
 pub enum MessageField {
@@ -327,6 +358,7 @@ pub enum MessageField {
     ThreadId = 136,
     Mailbox = 137,
     HasHeader = 138,
+    Header = 139,
 }
 
 impl From<MessageField> for FieldId {
@@ -23,7 +23,7 @@
 
 use super::schema::{Comparator, Email, Filter};
 use super::sharing::JMAPShareMail;
-use crate::mail::MessageField;
+use crate::mail::{MessageData, MessageField};
 use jmap::error::method::MethodError;
 use jmap::jmap_store::query::{ExtraFilterFnc, QueryHelper, QueryObject};
 use jmap::request::query::{QueryRequest, QueryResponse};
@@ -32,14 +32,23 @@ use mail_parser::{HeaderName, RfcHeader};
 use store::ahash::AHashSet;
 use store::core::acl::ACL;
 use store::core::collection::Collection;
+use store::core::document::MAX_TOKEN_LENGTH;
 use store::core::error::StoreError;
 use store::core::tag::Tag;
+use store::nlp::tokenizers::Tokenizer;
 use store::nlp::Language;
-use store::read::comparator::{self, DocumentSetComparator, FieldComparator};
-use store::read::filter::{self, Query};
-use store::{roaring::RoaringBitmap, AccountId, JMAPStore, Store};
+use store::read::comparator::{self, DocumentSetComparator, FieldComparator, RelevanceComparator};
+use store::read::filter::{self, Query, Text};
+use store::serialize::StoreDeserialize;
+use store::{blob::BlobId, roaring::RoaringBitmap, AccountId, JMAPStore, Store};
 use store::{FieldId, Integer, LongInteger};
 
+// Weight of each search term occurrence when sorting by relevance
+const RELEVANCE_SUBJECT: u32 = 8;
+const RELEVANCE_FROM: u32 = 4;
+const RELEVANCE_BODY: u32 = 2;
+const RELEVANCE_ATTACHMENT: u32 = 1;
+
 #[derive(Debug, Clone, serde::Deserialize, Default)]
 pub struct QueryArguments {
     #[serde(rename = "collapseThreads")]
@@ -65,6 +74,13 @@ where
         keyword: Tag,
         match_all: bool,
     ) -> store::Result<RoaringBitmap>;
+    fn mail_scan_header(
+        &self,
+        account_id: AccountId,
+        document_ids: RoaringBitmap,
+        header: &str,
+        value: Option<&str>,
+    ) -> store::Result<RoaringBitmap>;
 }
 
 impl<T> JMAPMailQuery<T> for JMAPStore<T>
@@ -85,6 +101,7 @@ where
         let mut document_ids = None;
         let mut is_immutable_filter = true;
         let mut is_immutable_sort = true;
+        let mut search_terms = Vec::new();
 
         helper.parse_filter(|filter| {
             Ok(match filter {
@@ -177,25 +194,29 @@ where
                         filter
                     }
                 }
-                Filter::Text { value } => filter::Filter::or(vec![
-                    filter::Filter::eq(RfcHeader::From.into(), Query::Tokenize(value.clone())),
-                    filter::Filter::eq(RfcHeader::To.into(), Query::Tokenize(value.clone())),
-                    filter::Filter::eq(RfcHeader::Cc.into(), Query::Tokenize(value.clone())),
-                    filter::Filter::eq(RfcHeader::Bcc.into(), Query::Tokenize(value.clone())),
-                    filter::Filter::eq(
-                        RfcHeader::Subject.into(),
-                        Query::match_text(value.clone(), Language::Unknown),
-                    ),
-                    filter::Filter::eq(
-                        MessageField::Body.into(),
-                        Query::match_text(value.clone(), Language::Unknown),
-                    ),
-                    filter::Filter::eq(
-                        MessageField::Attachment.into(),
-                        Query::match_text(value, Language::Unknown),
-                    ),
-                ]),
+                Filter::Text { value } => {
+                    search_terms.push(value.clone());
+                    filter::Filter::or(vec![
+                        filter::Filter::eq(RfcHeader::From.into(), Query::Tokenize(value.clone())),
+                        filter::Filter::eq(RfcHeader::To.into(), Query::Tokenize(value.clone())),
+                        filter::Filter::eq(RfcHeader::Cc.into(), Query::Tokenize(value.clone())),
+                        filter::Filter::eq(RfcHeader::Bcc.into(), Query::Tokenize(value.clone())),
+                        filter::Filter::eq(
+                            RfcHeader::Subject.into(),
+                            Query::match_text(value.clone(), Language::Unknown),
+                        ),
+                        filter::Filter::eq(
+                            MessageField::Body.into(),
+                            Query::match_text(value.clone(), Language::Unknown),
+                        ),
+                        filter::Filter::eq(
+                            MessageField::Attachment.into(),
+                            Query::match_text(value, Language::Unknown),
+                        ),
+                    ])
+                }
                 Filter::From { value } => {
+                    search_terms.push(value.clone());
                     filter::Filter::eq(RfcHeader::From.into(), Query::Tokenize(value))
                 }
                 Filter::To { value } => {
@@ -207,14 +228,26 @@ where
                 Filter::Bcc { value } => {
                     filter::Filter::eq(RfcHeader::Bcc.into(), Query::Tokenize(value))
                 }
-                Filter::Subject { value } => filter::Filter::eq(
-                    RfcHeader::Subject.into(),
-                    Query::match_text(value, Language::Unknown),
-                ),
-                Filter::Body { value } => filter::Filter::eq(
-                    MessageField::Body.into(),
-                    Query::match_text(value, Language::Unknown),
-                ),
+                Filter::Subject { value } => {
+                    search_terms.push(value.clone());
+                    filter::Filter::eq(
+                        RfcHeader::Subject.into(),
+                        Query::match_text(value, Language::Unknown),
+                    )
+                }
+                Filter::Body { value } => {
+                    search_terms.push(value.clone());
+                    filter::Filter::or(vec![
+                        filter::Filter::eq(
+                            MessageField::Body.into(),
+                            Query::match_text(value.clone(), Language::Unknown),
+                        ),
+                        filter::Filter::eq(
+                            MessageField::Attachment.into(),
+                            Query::match_text(value, Language::Unknown),
+                        ),
+                    ])
+                }
                 Filter::Header { mut value } => {
                     let (value, header) = match value.len() {
                         1 => (None, value.pop().unwrap()),
@@ -225,15 +258,61 @@ where
                             ));
                         }
                     };
-                    let header =
-                        if let Some(HeaderName::Rfc(rfc_header)) = HeaderName::parse(&header) {
-                            rfc_header
-                        } else {
-                            return Err(MethodError::InvalidArguments(format!(
-                                "Querying non-RFC header '{}' is not allowed.",
+                    let header_name = header.to_lowercase();
+                    let rfc_header = match HeaderName::parse(&header) {
+                        Some(HeaderName::Rfc(rfc_header)) => Some(rfc_header),
+                        _ => None,
+                    };
+                    let header = if let (Some(rfc_header), None) = (rfc_header, &value) {
+                        // RFC headers are always tagged, including messages indexed
+                        // before they were added to the indexed headers list
+                        rfc_header
+                    } else if self.config.mail_index_headers.contains(&header_name) {
+                        // Configured headers are indexed by token, messages imported
+                        // before the header was added to the list are not matched
+                        let mut conditions = value
+                            .as_deref()
+                            .map(|value| {
+                                Tokenizer::new(value, Language::Unknown, MAX_TOKEN_LENGTH)
+                                    .map(|token| {
+                                        filter::Filter::eq(
+                                            MessageField::Header.into(),
+                                            Query::Keyword(format!(
+                                                "{}:{}",
+                                                header_name, token.word
+                                            )),
+                                        )
+                                    })
+                                    .collect::<Vec<_>>()
+                            })
+                            .unwrap_or_default();
+                        if conditions.is_empty() {
+                            conditions.push(filter::Filter::eq(
+                                MessageField::HasHeader.into(),
+                                Query::Tag(Tag::Text(header_name)),
+                            ));
+                        }
+                        return Ok(filter::Filter::and(conditions));
+                    } else if let Some(rfc_header) = rfc_header {
+                        rfc_header
+                    } else {
+                        // Any other header is matched by scanning the message headers
+                        let document_ids = self
+                            .get_document_ids(account_id, Collection::Mail)?
+                            .unwrap_or_default();
+                        if document_ids.len() > self.config.mail_header_scan_max_items as u64 {
+                            return Err(MethodError::UnsupportedFilter(format!(
+                                "Header {:?} is not indexed and there are too many messages to scan.",
                                 header
                             )));
-                        };
+                        }
+                        return Ok(filter::Filter::DocumentSet(self.mail_scan_header(
+                            account_id,
+                            document_ids,
+                            &header,
+                            value.as_deref(),
+                        )?));
+                    };
 
                     if let Some(value) = value {
                         filter::Filter::eq(
@@ -309,18 +388,21 @@ where
                     field: MessageField::Size.into(),
                     ascending: comparator.is_ascending,
                 }),
-                Comparator::From => comparator::Comparator::Field(FieldComparator {
-                    field: RfcHeader::From.into(),
-                    ascending: comparator.is_ascending,
-                }),
-                Comparator::To => comparator::Comparator::Field(FieldComparator {
-                    field: RfcHeader::To.into(),
-                    ascending: comparator.is_ascending,
-                }),
-                Comparator::Subject => comparator::Comparator::Field(FieldComparator {
-                    field: MessageField::ThreadName.into(),
-                    ascending: comparator.is_ascending,
-                }),
+                Comparator::From => comparator::Comparator::collated(
+                    RfcHeader::From.into(),
+                    comparator.is_ascending,
+                    comparator.collation()?,
+                ),
+                Comparator::To => comparator::Comparator::collated(
+                    RfcHeader::To.into(),
+                    comparator.is_ascending,
+                    comparator.collation()?,
+                ),
+                Comparator::Subject => comparator::Comparator::collated(
+                    MessageField::ThreadName.into(),
+                    comparator.is_ascending,
+                    comparator.collation()?,
+                ),
                 Comparator::SentAt => comparator::Comparator::Field(FieldComparator {
                     field: RfcHeader::Date.into(),
                     ascending: comparator.is_ascending,
@@ -361,8 +443,22 @@ where
                 }
 
                 // Non-standard
-                Comparator::Cc => comparator::Comparator::Field(FieldComparator {
-                    field: RfcHeader::Cc.into(),
+                Comparator::Cc => comparator::Comparator::collated(
+                    RfcHeader::Cc.into(),
+                    comparator.is_ascending,
+                    comparator.collation()?,
+                ),
+                Comparator::Relevance => comparator::Comparator::Relevance(RelevanceComparator {
+                    text: search_terms
+                        .iter()
+                        .map(|value| Text::new(value.clone(), Language::Unknown))
+                        .collect(),
+                    full_text_fields: vec![
+                        (RfcHeader::Subject.into(), RELEVANCE_SUBJECT),
+                        (MessageField::Body.into(), RELEVANCE_BODY),
+                        (MessageField::Attachment.into(), RELEVANCE_ATTACHMENT),
+                    ],
+                    tokenized_fields: vec![(RfcHeader::From.into(), RELEVANCE_FROM)],
                     ascending: comparator.is_ascending,
                 }),
             })
@@ -453,4 +549,73 @@ where
             Ok(RoaringBitmap::new())
         }
     }
+
+    fn mail_scan_header(
+        &self,
+        account_id: AccountId,
+        document_ids: RoaringBitmap,
+        header: &str,
+        value: Option<&str>,
+    ) -> store::Result<RoaringBitmap> {
+        let mut matched_ids = RoaringBitmap::new();
+        let value = value.map(|value| value.to_lowercase());
+
+        for document_id in document_ids {
+            let message_data = if let Some(message_data) = self
+                .get_document_value::<BlobId>(
+                    account_id,
+                    Collection::Mail,
+                    document_id,
+                    MessageField::Metadata.into(),
+                )?
+                .and_then(|blob_id| self.blob_get(&blob_id).transpose())
+                .transpose()?
+                .and_then(|bytes| MessageData::deserialize(&bytes))
+            {
+                message_data
+            } else {
+                continue;
+            };
+
+            let offsets = message_data
+                .mime_parts
+                .first()
+                .map(|part| {
+                    part.raw_headers
+                        .iter()
+                        .filter(|(name, _, _)| name.as_str().eq_ignore_ascii_case(header))
+                        .map(|(_, start, end)| (*start, *end))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            if offsets.is_empty() {
+                continue;
+            }
+
+            if let Some(value) = &value {
+                // Fetch the message headers and look for the value
+                if let Some(raw_headers) = self.blob_get_range(
+                    &message_data.raw_message,
+                    0..message_data.body_offset as u32,
+                )? {
+                    if offsets.into_iter().any(|(start, end)| {
+                        raw_headers.get(start..end).map_or(false, |bytes| {
+                            String::from_utf8_lossy(bytes)
+                                .split_whitespace()
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                                .to_lowercase()
+                                .contains(value.as_str())
+                        })
+                    }) {
+                        matched_ids.insert(document_id);
+                    }
+                }
+            } else {
+                matched_ids.insert(document_id);
+            }
+        }
+
+        Ok(matched_ids)
+    }
 }
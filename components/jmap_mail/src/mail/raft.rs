@@ -34,6 +34,7 @@ use store::{
     JMAPStore, Store,
 };
 
+use super::import::JMAPMailImport;
 use super::schema::Email;
 use super::MessageData;
 use super::MessageField;
@@ -71,7 +72,7 @@ where
                     document.document_id
                 ))
             })?
-            .build_index(document, true)?;
+            .build_index(document, true, &store.config)?;
 
             // Add thread id
             let thread_id = jmap_id.get_prefix_id();
@@ -94,6 +95,15 @@ where
             );
             document.blob(metadata_blob_id, IndexOptions::new());
         } else {
+            // Attachments pending extraction are indexed as the leader logs them
+            let account_id = write_batch.account_id;
+            if store
+                .mail_pending_attachments(account_id)?
+                .contains(document.document_id)
+            {
+                store.mail_build_attachment_index(account_id, document.document_id, document)?;
+            }
+
             let thread_id = jmap_id.get_prefix_id();
             let current_thread_id = store
                 .get_document_value::<DocumentId>(
@@ -798,4 +798,6 @@ pub enum Comparator {
     // Non-standard
     #[serde(rename = "cc")]
     Cc,
+    #[serde(rename = "relevance")]
+    Relevance,
 }
@@ -40,14 +40,20 @@ use store::{
         document::MAX_TOKEN_LENGTH,
         error::StoreError,
     },
-    nlp::{search_snippet::generate_snippet, stemmer::Stemmer, tokenizers::Tokenizer, Language},
-    read::filter::{LogicalOperator, Text},
+    nlp::{
+        search_snippet::generate_snippet,
+        stemmer::Stemmer,
+        term_index::{MatchTerm, Term, TermGroup},
+        tokenizers::Tokenizer,
+        Language,
+    },
+    read::filter::{LogicalOperator, Text, TextMatch},
     serialize::StoreDeserialize,
     tracing::error,
     JMAPStore, Store,
 };
 
-use super::{sharing::JMAPShareMail, MessageData, MessageField};
+use super::{sharing::JMAPShareMail, MessageData, MessageField, MimePartType};
 
 #[derive(Debug, Clone)]
 pub struct SearchSnippetGetRequest {
@@ -270,44 +276,74 @@ where
                         account_id, document_id
                     ))
                 })?;
-            let mut match_terms = Vec::new();
-            let mut match_phrase = false;
+            let mut term_groups: Vec<TermGroup> = Vec::new();
+            let mut match_terms: Vec<MatchTerm> = Vec::new();
 
-            // Tokenize and stem terms
+            // Tokenize and stem words, expand prefixes and fuzzy terms and match phrases
             for term in &terms {
-                if !term.match_phrase {
-                    for token in Stemmer::new(&term.text, term.language, MAX_TOKEN_LENGTH) {
-                        match_terms.push(term_index.get_match_term(
-                            token.word.as_ref(),
-                            token.stemmed_word.as_ref().map(|w| w.as_ref()),
-                        ));
-                    }
-                } else {
-                    match_phrase = true;
-                    for token in Tokenizer::new(&term.text, term.language, MAX_TOKEN_LENGTH) {
-                        match_terms.push(term_index.get_match_term(token.word.as_ref(), None));
+                for text_match in &term.matches {
+                    match text_match {
+                        TextMatch::Words(words) => {
+                            for token in Stemmer::new(words, term.language, MAX_TOKEN_LENGTH) {
+                                match_terms.push(term_index.get_match_term(
+                                    token.word.as_ref(),
+                                    token.stemmed_word.as_ref().map(|w| w.as_ref()),
+                                ));
+                            }
+                        }
+                        TextMatch::Prefix(prefix) => {
+                            match_terms.extend(term_index.get_prefix_terms(prefix));
+                        }
+                        TextMatch::Fuzzy(word) => {
+                            match_terms.extend(term_index.get_fuzzy_terms(word));
+                        }
+                        TextMatch::Phrase(phrase) => {
+                            let phrase_terms =
+                                Tokenizer::new(phrase, term.language, MAX_TOKEN_LENGTH)
+                                    .map(|token| {
+                                        term_index.get_match_term(token.word.as_ref(), None)
+                                    })
+                                    .collect::<Vec<_>>();
+                            if phrase_terms.is_empty() {
+                                continue;
+                            }
+
+                            for term_group in term_index
+                                .match_terms(&phrase_terms, None, true, true, true)
+                                .map_err(map_term_index_error)?
+                                .unwrap_or_default()
+                            {
+                                add_term_group(
+                                    &mut term_groups,
+                                    TermGroup {
+                                        terms: merge_phrase(term_group.terms, phrase_terms.len()),
+                                        ..term_group
+                                    },
+                                );
+                            }
+                        }
                     }
                 }
             }
 
+            // Terms are matched using a 64-bit mask, discard any excess expansions
+            match_terms.dedup();
+            match_terms.truncate(64);
+            if !match_terms.is_empty() {
+                for term_group in term_index
+                    .match_terms(&match_terms, None, false, true, true)
+                    .map_err(map_term_index_error)?
+                    .unwrap_or_default()
+                {
+                    add_term_group(&mut term_groups, term_group);
+                }
+            }
+            term_groups.sort_by_key(|term_group| term_group.part_id);
+
             let mut subject = None;
             let mut preview = None;
 
-            for term_group in term_index
-                .match_terms(&match_terms, None, match_phrase, true, true)
-                .map_err(|err| match err {
-                    store::nlp::term_index::Error::InvalidArgument => {
-                        MethodError::UnsupportedFilter("Too many search terms.".to_string())
-                    }
-                    err => {
-                        error!("Failed to generate search snippet: {:?}", err);
-                        MethodError::UnsupportedFilter(
-                            "Failed to generate search snippet.".to_string(),
-                        )
-                    }
-                })?
-                .unwrap_or_default()
-            {
+            for term_group in term_groups {
                 if term_group.part_id == 0 {
                     // Generate subject snippent
                     subject = generate_snippet(
@@ -323,7 +359,15 @@ where
                     // Generate snippet of a body part
                     let part = &message_data.mime_parts[(term_group.part_id - 1) as usize];
 
-                    if let Some(message_part) = part.mime_type.part() {
+                    if let MimePartType::Other { part: message_part } = &part.mime_type {
+                        // Generate snippet of text extracted from an attachment
+                        if let Some(text) = message_part
+                            .decode(&raw_message)
+                            .and_then(|bytes| part.extract_text(&bytes, &self.config))
+                        {
+                            preview = generate_snippet(&term_group.terms, &text);
+                        }
+                    } else if let Some(message_part) = part.mime_type.part() {
                         let mut text = message_part
                             .decode_text(&raw_message, part.charset.as_deref(), false)
                             .unwrap_or_else(|| {
@@ -427,3 +471,66 @@ where
         })
     }
 }
+
+fn map_term_index_error(err: store::nlp::term_index::Error) -> MethodError {
+    match err {
+        store::nlp::term_index::Error::InvalidArgument => {
+            MethodError::UnsupportedFilter("Too many search terms.".to_string())
+        }
+        err => {
+            error!("Failed to generate search snippet: {:?}", err);
+            MethodError::UnsupportedFilter("Failed to generate search snippet.".to_string())
+        }
+    }
+}
+
+// Adds the matched terms to the group of the same field and part, dropping any
+// terms that overlap a previously matched phrase.
+fn add_term_group(term_groups: &mut Vec<TermGroup>, term_group: TermGroup) {
+    if let Some(existing_group) = term_groups.iter_mut().find(|existing_group| {
+        existing_group.field_id == term_group.field_id
+            && existing_group.part_id == term_group.part_id
+    }) {
+        let mut terms = std::mem::take(&mut existing_group.terms);
+        terms.extend(term_group.terms);
+        terms.sort_by(|a, b| a.offset.cmp(&b.offset).then(b.len.cmp(&a.len)));
+
+        let mut end_offset = 0;
+        for term in terms {
+            if term.offset >= end_offset {
+                end_offset = term.offset + term.len as u32;
+                existing_group.terms.push(term);
+            }
+        }
+    } else {
+        term_groups.push(term_group);
+    }
+}
+
+// Merges each matched phrase into a single term so it is highlighted as a whole.
+fn merge_phrase(terms: Vec<Term>, phrase_len: usize) -> Vec<Term> {
+    let mut merged_terms = Vec::with_capacity(terms.len() / phrase_len + 1);
+    let mut terms = terms.into_iter();
+
+    loop {
+        let mut phrase = terms.by_ref().take(phrase_len).collect::<Vec<_>>();
+        let (first, last) = match (phrase.first(), phrase.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => break,
+        };
+        let len = last.offset + last.len as u32 - first.offset;
+
+        if len <= u8::MAX as u32 {
+            merged_terms.push(Term {
+                id: first.id,
+                id_stemmed: first.id_stemmed,
+                offset: first.offset,
+                len: len as u8,
+            });
+        } else {
+            merged_terms.append(&mut phrase);
+        }
+    }
+
+    merged_terms
+}
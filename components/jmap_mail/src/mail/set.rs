@@ -31,6 +31,7 @@ use crate::mail::import::JMAPMailImport;
 use jmap::error::set::{SetError, SetErrorType};
 use jmap::jmap_store::set::{SetHelper, SetObject};
 use jmap::orm::{serialize::JMAPOrm, TinyORM};
+use jmap::principal::store::JMAPPrincipals;
 use jmap::request::set::{SetRequest, SetResponse};
 use jmap::request::{ACLEnforce, MaybeIdReference, ResultReference};
 use jmap::types::blob::JMAPBlob;
@@ -530,6 +531,9 @@ where
             builder.write_to(&mut blob).map_err(|_| {
                 StoreError::SerializeError("Failed to write to memory.".to_string())
             })?;
+
+            // Make sure the account has enough quota
+            self.quota_check(helper.account_id, blob.len() as u64)?;
             let blob_id = BlobId::new_external(&blob);
             let raw_blob: JMAPBlob = (&blob_id).into();
 
@@ -802,7 +806,7 @@ where
                 account_id, document_id
             ))
         })?
-        .build_index(document, false)?;
+        .build_index(document, false, &self.config)?;
 
         // Remove thread related data
         let thread_id = self
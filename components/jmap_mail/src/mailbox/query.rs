@@ -136,17 +136,21 @@ where
         })?;
 
         helper.parse_comparator(|comparator| {
-            Ok(comparator::Comparator::Field(FieldComparator {
-                field: {
-                    match comparator.property {
-                        Comparator::Name => Property::Name,
-                        Comparator::SortOrder => Property::SortOrder,
-                        Comparator::ParentId => Property::ParentId,
-                    }
-                }
-                .into(),
-                ascending: comparator.is_ascending,
-            }))
+            Ok(match comparator.property {
+                Comparator::Name => comparator::Comparator::collated(
+                    Property::Name.into(),
+                    comparator.is_ascending,
+                    comparator.collation()?,
+                ),
+                Comparator::SortOrder => comparator::Comparator::Field(FieldComparator {
+                    field: Property::SortOrder.into(),
+                    ascending: comparator.is_ascending,
+                }),
+                Comparator::ParentId => comparator::Comparator::Field(FieldComparator {
+                    field: Property::ParentId.into(),
+                    ascending: comparator.is_ascending,
+                }),
+            })
         })?;
 
         if filter_as_tree || sort_as_tree {
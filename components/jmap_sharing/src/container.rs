@@ -0,0 +1,120 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use store::{
+    core::{acl::ACL, collection::Collection, error::StoreError, tag::Tag},
+    roaring::RoaringBitmap,
+    AccountId, FieldId, JMAPStore, SharedResource, Store,
+};
+
+// Shared access to collections whose items are filed into shareable
+// containers, such as address books and calendars.
+pub trait JMAPShareContainers<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn shared_containers(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        container: Collection,
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>>;
+    fn shared_container_items(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        container: Collection,
+        item: (Collection, FieldId),
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>>;
+}
+
+impl<T> JMAPShareContainers<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn shared_containers(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        container: Collection,
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>> {
+        self.shared_documents
+            .try_get_with::<_, StoreError>(
+                SharedResource::new(
+                    owner_id,
+                    shared_to.first().copied().unwrap(),
+                    container,
+                    acl,
+                ),
+                || {
+                    Ok(Arc::new(self.get_shared_documents(
+                        shared_to,
+                        owner_id,
+                        container,
+                        acl.into(),
+                    )?))
+                },
+            )
+            .map_err(|e| e.as_ref().clone())
+    }
+
+    fn shared_container_items(
+        &self,
+        owner_id: AccountId,
+        shared_to: &[AccountId],
+        container: Collection,
+        (item_collection, container_field): (Collection, FieldId),
+        acl: ACL,
+    ) -> store::Result<Arc<Option<RoaringBitmap>>> {
+        Ok(Arc::new(
+            if let Some(shared_containers) = self
+                .shared_containers(owner_id, shared_to, container, acl)?
+                .as_ref()
+            {
+                let mut shared_items = RoaringBitmap::new();
+                for container_id in shared_containers {
+                    if let Some(item_ids) = self.get_tag(
+                        owner_id,
+                        item_collection,
+                        container_field,
+                        Tag::Id(container_id),
+                    )? {
+                        shared_items |= item_ids;
+                    }
+                }
+                if !shared_items.is_empty() {
+                    shared_items.into()
+                } else {
+                    None
+                }
+            } else {
+                None
+            },
+        ))
+    }
+}
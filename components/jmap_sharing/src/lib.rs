@@ -21,5 +21,7 @@
  * for more details.
 */
 
+pub mod container;
 pub mod principal;
+pub mod quota;
 pub use argon2;
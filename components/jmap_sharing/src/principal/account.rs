@@ -26,6 +26,7 @@ use std::sync::Arc;
 use jmap::{
     orm::serialize::JMAPOrm,
     principal::schema::{Principal, Property, Type, Value},
+    split_subaddress,
     types::jmap::JMAPId,
     SUPERUSER_ID,
 };
@@ -50,6 +51,7 @@ pub trait JMAPAccountStore {
     ) -> store::Result<Option<(String, String, Type)>>;
     fn get_account_secret_hash(&self, account_id: AccountId) -> store::Result<Option<String>>;
     fn expand_rcpt(&self, email: String) -> store::Result<Arc<RecipientType>>;
+    fn rcpt_subaddress<'x>(&self, email: &'x str) -> store::Result<Option<(String, &'x str)>>;
 }
 
 impl<T> JMAPAccountStore for JMAPStore<T>
@@ -265,61 +267,170 @@ where
     fn expand_rcpt(&self, email: String) -> store::Result<Arc<RecipientType>> {
         self.recipients
             .try_get_with::<_, StoreError>(email.clone(), || {
-                Ok(Arc::new(
-                    if let Some(account_id) = self
-                        .query_store::<FilterMapper>(
-                            SUPERUSER_ID,
-                            Collection::Principal,
-                            Filter::or(vec![
-                                Filter::eq(Property::Email.into(), Query::Index(email.clone())),
-                                Filter::eq(Property::Aliases.into(), Query::Index(email)),
-                            ]),
-                            Comparator::None,
-                        )?
-                        .into_iter()
-                        .next()
-                        .map(|id| id.get_document_id())
+                let mut rcpt = expand_address(self, email.clone())?;
+
+                // Fall back to the base address of subaddresses ("user+detail@domain")
+                if matches!(rcpt, RecipientType::NotFound) {
+                    if let Some((base_address, _)) =
+                        split_subaddress(&email, &self.config.lmtp_subaddress_separator)
                     {
-                        if let Some(mut fields) =
-                            self.get_orm::<Principal>(SUPERUSER_ID, account_id)?
-                        {
-                            match fields.get(&Property::Type) {
-                                Some(Value::Type { value: Type::List }) => {
-                                    if let Some(Value::Members { value }) =
-                                        fields.remove(&Property::Members)
-                                    {
-                                        if !value.is_empty() {
-                                            let mut list = Vec::with_capacity(value.len());
-                                            for id in value {
-                                                let account_id = id.get_document_id();
-                                                match self.get_account_details(account_id)? {
-                                                    Some((email, _, ptype))
-                                                        if ptype == Type::Individual =>
-                                                    {
-                                                        list.push((account_id, email));
-                                                    }
-                                                    _ => (),
-                                                }
-                                            }
-                                            return Ok(Arc::new(RecipientType::List(list)));
-                                        }
+                        rcpt = expand_address(self, base_address)?;
+                    }
+                }
+
+                // Then try the domain's wildcard aliases and catch-all address
+                if matches!(rcpt, RecipientType::NotFound) {
+                    rcpt = expand_wildcard(self, &email)?;
+                }
+
+                Ok(Arc::new(rcpt))
+            })
+            .map_err(|e| e.as_ref().clone())
+    }
+
+    fn rcpt_subaddress<'x>(&self, email: &'x str) -> store::Result<Option<(String, &'x str)>> {
+        if let Some((base_address, detail)) =
+            split_subaddress(email, &self.config.lmtp_subaddress_separator)
+        {
+            // Addresses containing a separator might belong to a principal
+            if matches!(
+                expand_address(self, email.to_string())?,
+                RecipientType::NotFound
+            ) {
+                return Ok(Some((base_address, detail)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn expand_address<T>(store: &JMAPStore<T>, email: String) -> store::Result<RecipientType>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if let Some(account_id) = store
+        .query_store::<FilterMapper>(
+            SUPERUSER_ID,
+            Collection::Principal,
+            Filter::or(vec![
+                Filter::eq(Property::Email.into(), Query::Index(email.clone())),
+                Filter::eq(Property::Aliases.into(), Query::Index(email)),
+            ]),
+            Comparator::None,
+        )?
+        .into_iter()
+        .next()
+        .map(|id| id.get_document_id())
+    {
+        expand_principal(store, account_id)
+    } else {
+        Ok(RecipientType::NotFound)
+    }
+}
+
+fn expand_wildcard<T>(store: &JMAPStore<T>, email: &str) -> store::Result<RecipientType>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let domain = if let Some((_, domain)) = email.rsplit_once('@') {
+        domain
+    } else {
+        return Ok(RecipientType::NotFound);
+    };
+
+    if let Some(domain_id) = store
+        .query_store::<FilterMapper>(
+            SUPERUSER_ID,
+            Collection::Principal,
+            Filter::and(vec![
+                Filter::eq(Property::Name.into(), Query::Index(domain.to_string())),
+                Filter::eq(Property::Type.into(), Query::Keyword("d".to_string())),
+            ]),
+            Comparator::None,
+        )?
+        .into_iter()
+        .next()
+        .map(|id| id.get_document_id())
+    {
+        if let Some(mut fields) = store.get_orm::<Principal>(SUPERUSER_ID, domain_id)? {
+            // The most specific matching pattern wins
+            let target = match fields.remove(&Property::AliasPatterns) {
+                Some(Value::AliasPatterns { value }) => value
+                    .into_iter()
+                    .filter(|(pattern, _)| wildcard_match(pattern, email))
+                    .max_by_key(|(pattern, _)| pattern.len())
+                    .map(|(_, id)| id),
+                _ => None,
+            }
+            .or_else(|| match fields.remove(&Property::CatchAll) {
+                Some(Value::Id { value }) => Some(value),
+                _ => None,
+            });
+
+            if let Some(target) = target {
+                return expand_principal(store, target.get_document_id());
+            }
+        }
+    }
+
+    Ok(RecipientType::NotFound)
+}
+
+fn expand_principal<T>(store: &JMAPStore<T>, account_id: AccountId) -> store::Result<RecipientType>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    Ok(
+        if let Some(mut fields) = store.get_orm::<Principal>(SUPERUSER_ID, account_id)? {
+            match fields.get(&Property::Type) {
+                Some(Value::Type { value: Type::List }) => {
+                    if let Some(Value::Members { value }) = fields.remove(&Property::Members) {
+                        if !value.is_empty() {
+                            let mut list = Vec::with_capacity(value.len());
+                            for id in value {
+                                let account_id = id.get_document_id();
+                                match store.get_account_details(account_id)? {
+                                    Some((email, _, ptype)) if ptype == Type::Individual => {
+                                        list.push((account_id, email));
                                     }
-                                    RecipientType::NotFound
+                                    _ => (),
                                 }
-                                _ => RecipientType::Individual(account_id),
                             }
-                        } else {
-                            debug!(
-                                "Rcpt expand failed: ORM for account {} does not exist.",
-                                JMAPId::from(account_id)
-                            );
-                            RecipientType::NotFound
+                            return Ok(RecipientType::List(list));
                         }
-                    } else {
-                        RecipientType::NotFound
-                    },
-                ))
-            })
-            .map_err(|e| e.as_ref().clone())
+                    }
+                    RecipientType::NotFound
+                }
+                _ => RecipientType::Individual(account_id),
+            }
+        } else {
+            debug!(
+                "Rcpt expand failed: ORM for account {} does not exist.",
+                JMAPId::from(account_id)
+            );
+            RecipientType::NotFound
+        },
+    )
+}
+
+// Matches an address against a pattern where '*' matches any sequence of characters
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let mut value = match parts.next() {
+        Some(prefix) if value.starts_with(prefix) => &value[prefix.len()..],
+        _ => return false,
+    };
+    let mut parts = parts.peekable();
+
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return value.ends_with(part);
+        } else if let Some(pos) = value.find(part) {
+            value = &value[pos + part.len()..];
+        } else {
+            return false;
+        }
     }
+
+    value.is_empty()
 }
@@ -0,0 +1,190 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use rsa::{pkcs1::DecodeRsaPrivateKey, Hash, PaddingScheme, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
+use store::sha2::{Digest, Sha256};
+
+use super::{
+    verify::{
+        arc_instance, find_arc_header, now, signed_headers, split_message, Canonicalization, Header,
+    },
+    DkimAlgorithm,
+};
+
+pub const ARC_MAX_INSTANCES: u32 = 50;
+
+/// Chain validation status of an ARC set (RFC 8617, section 4.1.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChainValidation {
+    None,
+    Pass,
+    Fail,
+}
+
+// Seals ARC sets using the domain's RSA DKIM key.
+pub struct ArcSealer {
+    key: RsaPrivateKey,
+    domain: String,
+    selector: String,
+    headers: Vec<String>,
+}
+
+impl ChainValidation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChainValidation::None => "none",
+            ChainValidation::Pass => "pass",
+            ChainValidation::Fail => "fail",
+        }
+    }
+}
+
+impl ArcSealer {
+    pub fn new(secret: &str, domain: String, selector: String) -> Result<Self, String> {
+        Ok(ArcSealer {
+            key: RsaPrivateKey::from_pkcs1_pem(secret).map_err(|err| err.to_string())?,
+            domain,
+            selector,
+            headers: Vec::new(),
+        })
+    }
+
+    pub fn headers(mut self, headers: &[&str]) -> Self {
+        self.headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Adds a new ARC set to the message (RFC 8617), returning the ARC-Seal,
+    /// ARC-Message-Signature and ARC-Authentication-Results headers to prepend to it.
+    pub fn seal(
+        &self,
+        message: &[u8],
+        auth_results: &str,
+        cv: ChainValidation,
+    ) -> Result<String, String> {
+        let (headers, body) = split_message(message);
+        let instance = headers
+            .iter()
+            .filter(|(name, _)| {
+                String::from_utf8_lossy(name)
+                    .trim_end()
+                    .eq_ignore_ascii_case("ARC-Seal")
+            })
+            .filter_map(|(_, value)| arc_instance(value))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        if instance > ARC_MAX_INSTANCES {
+            return Err("Too many ARC sets.".to_string());
+        }
+
+        let aar = format!("i={}; {}", instance, auth_results);
+        let ams = self.message_signature(instance, &headers, body)?;
+
+        // The seal covers all previous ARC sets followed by the new one
+        let mut data = Vec::new();
+        for prev_instance in 1..instance {
+            for name in [
+                "ARC-Authentication-Results",
+                "ARC-Message-Signature",
+                "ARC-Seal",
+            ] {
+                if let Some((name, value)) = find_arc_header(&headers, name, prev_instance) {
+                    Canonicalization::Relaxed.header(name, value, &mut data);
+                    data.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+        Canonicalization::Relaxed.header(b"ARC-Authentication-Results", aar.as_bytes(), &mut data);
+        data.extend_from_slice(b"\r\n");
+        Canonicalization::Relaxed.header(b"ARC-Message-Signature", ams.as_bytes(), &mut data);
+        data.extend_from_slice(b"\r\n");
+        let seal = format!(
+            "i={}; a={}; cv={}; d={}; s={}; t={}; b=",
+            instance,
+            DkimAlgorithm::RsaSha256.as_str(),
+            cv.as_str(),
+            self.domain,
+            self.selector,
+            now()
+        );
+        Canonicalization::Relaxed.header(b"ARC-Seal", seal.as_bytes(), &mut data);
+
+        Ok(format!(
+            "ARC-Seal: {}{}\r\nARC-Message-Signature: {}\r\nARC-Authentication-Results: {}\r\n",
+            seal,
+            self.sign_data(&data)?,
+            ams,
+            aar
+        ))
+    }
+
+    // Builds the ARC-Message-Signature value using relaxed/relaxed canonicalization.
+    fn message_signature(
+        &self,
+        instance: u32,
+        headers: &[Header<'_>],
+        body: &[u8],
+    ) -> Result<String, String> {
+        // Sign every occurrence of the selected headers
+        let mut names = Vec::new();
+        for name in &self.headers {
+            for (message_header, _) in headers {
+                if String::from_utf8_lossy(message_header)
+                    .trim_end()
+                    .eq_ignore_ascii_case(name)
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        let mut data = Vec::new();
+        signed_headers(headers, &names, Canonicalization::Relaxed, &mut data);
+
+        let mut signature = format!(
+            "i={}; a={}; s={}; d={}; c=relaxed/relaxed; h={}; t={}; bh={}; b=",
+            instance,
+            DkimAlgorithm::RsaSha256.as_str(),
+            self.selector,
+            self.domain,
+            names.join(":"),
+            now(),
+            base64::encode(Sha256::digest(&Canonicalization::Relaxed.body(body)))
+        );
+        Canonicalization::Relaxed.header(b"ARC-Message-Signature", signature.as_bytes(), &mut data);
+
+        signature.push_str(&self.sign_data(&data)?);
+        Ok(signature)
+    }
+
+    fn sign_data(&self, data: &[u8]) -> Result<String, String> {
+        self.key
+            .sign(
+                PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                &Sha256::digest(data),
+            )
+            .map(base64::encode)
+            .map_err(|err| err.to_string())
+    }
+}
@@ -0,0 +1,470 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::{
+    orm::{serialize::JMAPOrm, TinyORM},
+    principal::schema::{
+        DKIMPending, DKIMRetired, DKIMRotation, Principal, Property, Value, DKIM as DKIMSettings,
+    },
+    SUPERUSER_ID,
+};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey, LineEnding},
+    pkcs8::EncodePublicKey,
+    RsaPrivateKey, RsaPublicKey,
+};
+use store::{
+    chrono::{TimeZone, Utc},
+    core::{collection::Collection, document::Document, tag::Tag},
+    rand,
+    tracing::{error, info},
+    write::{batch::WriteBatch, update::Changes},
+    JMAPStore, Store,
+};
+
+pub mod arc;
+pub mod verify;
+
+pub const DEFAULT_DKIM_SELECTOR: &str = "default";
+const RSA_KEY_BITS: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DkimAlgorithm {
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+pub struct DkimKey {
+    pub algorithm: DkimAlgorithm,
+    pub secret: String,
+    pub public_key: Vec<u8>,
+}
+
+impl DkimAlgorithm {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rsa-sha256" => Some(DkimAlgorithm::RsaSha256),
+            "ed25519-sha256" => Some(DkimAlgorithm::Ed25519Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DkimAlgorithm::RsaSha256 => "rsa-sha256",
+            DkimAlgorithm::Ed25519Sha256 => "ed25519-sha256",
+        }
+    }
+
+    /// Whether keys of this algorithm can be used to sign outgoing messages.
+    pub fn can_sign(&self) -> bool {
+        matches!(self, DkimAlgorithm::RsaSha256)
+    }
+}
+
+impl DkimKey {
+    // Outgoing messages are signed by mail-send, which only supports RSA keys.
+    pub fn generate(algorithm: DkimAlgorithm) -> Result<Self, String> {
+        match algorithm {
+            DkimAlgorithm::RsaSha256 => {
+                let key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)
+                    .map_err(|err| format!("Failed to generate RSA key: {}", err))?;
+                DkimKey::from_rsa(key)
+            }
+            DkimAlgorithm::Ed25519Sha256 => {
+                Err("Only RSA keys are supported for signing.".to_string())
+            }
+        }
+    }
+
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        DkimKey::from_rsa(RsaPrivateKey::from_pkcs1_pem(pem).map_err(|err| err.to_string())?)
+    }
+
+    fn from_rsa(key: RsaPrivateKey) -> Result<Self, String> {
+        Ok(DkimKey {
+            algorithm: DkimAlgorithm::RsaSha256,
+            public_key: RsaPublicKey::from(&key)
+                .to_public_key_der()
+                .map_err(|err| err.to_string())?
+                .as_ref()
+                .to_vec(),
+            secret: key
+                .to_pkcs1_pem(LineEnding::LF)
+                .map_err(|err| err.to_string())?
+                .as_str()
+                .to_string(),
+        })
+    }
+
+    /// Returns the value of the DNS TXT record to publish under
+    /// <selector>._domainkey.<domain>.
+    pub fn record(&self) -> String {
+        format!("v=DKIM1; k=rsa; p={}", base64::encode(&self.public_key))
+    }
+}
+
+pub trait DkimKeyInstall: Sized {
+    fn dkim_install(
+        &mut self,
+        current_fields: Option<&TinyORM<Principal>>,
+        key: DkimKey,
+        selector: Option<String>,
+        auto_rotate: bool,
+        grace_period: i64,
+    );
+    fn dkim_schedule(&mut self, current_fields: &TinyORM<Principal>, key: DkimKey, lead: i64);
+    fn dkim_remove(&mut self, current_fields: Option<&TinyORM<Principal>>);
+}
+
+impl DkimKeyInstall for TinyORM<Principal> {
+    // Replaces the domain's DKIM key, keeping the previous record published until
+    // the grace period (or the signature expiration, if longer) has elapsed.
+    fn dkim_install(
+        &mut self,
+        current_fields: Option<&TinyORM<Principal>>,
+        key: DkimKey,
+        selector: Option<String>,
+        auto_rotate: bool,
+        grace_period: i64,
+    ) {
+        let now = Utc::now().timestamp();
+        let record = key.record();
+        let current_settings = match current_fields.and_then(|f| f.get(&Property::DKIM)) {
+            Some(Value::DKIM { value }) => value.clone(),
+            _ => DKIMSettings {
+                dkim_selector: None,
+                dkim_expiration: None,
+            },
+        };
+        let mut settings = match self.get(&Property::DKIM) {
+            Some(Value::DKIM { value }) => value.clone(),
+            _ => current_settings.clone(),
+        };
+        let mut rotation = match current_fields.and_then(|f| f.get(&Property::DKIMRotation)) {
+            Some(Value::DKIMRotation { value }) => value.clone(),
+            _ => DKIMRotation {
+                created: now,
+                auto_rotate,
+                retired: Vec::new(),
+                pending: None,
+            },
+        };
+        rotation.retired.retain(|retired| retired.until > now);
+
+        // Retire the current key
+        if let Some(Value::Text {
+            value: current_record,
+        }) = current_fields.and_then(|f| f.get(&Property::DKIMRecord))
+        {
+            if current_record != &record {
+                let current_selector = current_settings
+                    .dkim_selector
+                    .unwrap_or_else(|| DEFAULT_DKIM_SELECTOR.to_string());
+
+                // Publish the new key under a different selector
+                let next_selector =
+                    selector.unwrap_or_else(|| match settings.dkim_selector.as_deref() {
+                        Some(selector) if selector != current_selector => selector.to_string(),
+                        _ => next_selector(&current_selector, now),
+                    });
+                if settings.dkim_selector.as_ref() != Some(&next_selector) {
+                    settings.dkim_selector = next_selector.into();
+                    self.set(
+                        Property::DKIM,
+                        Value::DKIM {
+                            value: settings.clone(),
+                        },
+                    );
+                }
+
+                rotation.retired.push(DKIMRetired {
+                    selector: current_selector,
+                    record: current_record.clone(),
+                    until: now + std::cmp::max(grace_period, settings.dkim_expiration.unwrap_or(0)),
+                });
+            }
+        }
+
+        rotation.created = now;
+        rotation.auto_rotate = auto_rotate;
+        rotation.pending = None;
+        self.set(Property::Secret, Value::Text { value: key.secret });
+        self.set(
+            Property::DKIMAlgorithm,
+            Value::Text {
+                value: key.algorithm.as_str().to_string(),
+            },
+        );
+        self.set(Property::DKIMRecord, Value::Text { value: record });
+        self.set(
+            Property::DKIMRotation,
+            Value::DKIMRotation { value: rotation },
+        );
+        self.tag(Property::DKIM, Tag::Default);
+    }
+
+    // Schedules a key to replace the current one once it has been published for
+    // `lead` seconds, the replacement is done by the next rotation run.
+    fn dkim_schedule(&mut self, current_fields: &TinyORM<Principal>, key: DkimKey, lead: i64) {
+        let now = Utc::now().timestamp();
+        let current_settings = match current_fields.get(&Property::DKIM) {
+            Some(Value::DKIM { value }) => value.clone(),
+            _ => DKIMSettings {
+                dkim_selector: None,
+                dkim_expiration: None,
+            },
+        };
+        let current_selector = current_settings
+            .dkim_selector
+            .as_deref()
+            .unwrap_or(DEFAULT_DKIM_SELECTOR);
+
+        // A selector requested along with the new key applies to the new key only
+        let mut selector = None;
+        if let Some(Value::DKIM { value }) = self.get(&Property::DKIM) {
+            if value.dkim_selector.as_deref() != Some(current_selector) {
+                selector = value.dkim_selector.clone();
+                let settings = DKIMSettings {
+                    dkim_selector: current_settings.dkim_selector.clone(),
+                    dkim_expiration: value.dkim_expiration,
+                };
+                self.set(Property::DKIM, Value::DKIM { value: settings });
+            }
+        }
+
+        let mut rotation = match current_fields.get(&Property::DKIMRotation) {
+            Some(Value::DKIMRotation { value }) => value.clone(),
+            _ => DKIMRotation {
+                created: now,
+                auto_rotate: true,
+                retired: Vec::new(),
+                pending: None,
+            },
+        };
+        rotation.auto_rotate = true;
+        rotation.pending = DKIMPending {
+            selector: selector
+                .filter(|selector| selector != current_selector)
+                .unwrap_or_else(|| next_selector(current_selector, now)),
+            record: key.record(),
+            secret: key.secret,
+            activate: now + lead,
+        }
+        .into();
+        self.set(
+            Property::DKIMRotation,
+            Value::DKIMRotation { value: rotation },
+        );
+    }
+
+    fn dkim_remove(&mut self, current_fields: Option<&TinyORM<Principal>>) {
+        self.untag(&Property::DKIM, &Tag::Default);
+        for property in [
+            Property::DKIMAlgorithm,
+            Property::DKIMRecord,
+            Property::DKIMRotation,
+        ] {
+            if current_fields.map_or(false, |f| f.has_property(&property)) {
+                self.set(property, Value::Null);
+            }
+        }
+    }
+}
+
+pub trait JMAPDkimRotate<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn dkim_rotate(&self) -> store::Result<Option<Changes>>;
+}
+
+impl<T> JMAPDkimRotate<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Rotation happens in two steps: the next key is generated and logged for
+    // publishing `dkim-rotation-lead` seconds ahead, then it replaces the current
+    // key, which stays published until the grace period has elapsed.
+    fn dkim_rotate(&self) -> store::Result<Option<Changes>> {
+        let grace_period = self.config.dkim_rotation_grace as i64;
+        let now = Utc::now().timestamp();
+        let mut batch = WriteBatch::new(SUPERUSER_ID);
+
+        for document_id in self
+            .get_tag(
+                SUPERUSER_ID,
+                Collection::Principal,
+                Property::DKIM.into(),
+                Tag::Default,
+            )?
+            .unwrap_or_default()
+        {
+            let current_fields =
+                if let Some(fields) = self.get_orm::<Principal>(SUPERUSER_ID, document_id)? {
+                    fields
+                } else {
+                    continue;
+                };
+            let mut rotation = match current_fields.get(&Property::DKIMRotation) {
+                Some(Value::DKIMRotation { value }) => value.clone(),
+                _ => continue,
+            };
+            let domain_name = match current_fields.get(&Property::Name) {
+                Some(Value::Text { value }) => value.as_str(),
+                _ => "",
+            };
+            let (selector, expiration) = match current_fields.get(&Property::DKIM) {
+                Some(Value::DKIM { value }) => (
+                    value.dkim_selector.as_deref(),
+                    value.dkim_expiration.unwrap_or(0),
+                ),
+                _ => (None, 0),
+            };
+            let selector = selector.unwrap_or(DEFAULT_DKIM_SELECTOR);
+
+            // Keys are replaced before the signatures they produce expire
+            let mut interval = self.config.dkim_rotation_interval as i64;
+            if interval > 0 && expiration > 0 {
+                interval = std::cmp::min(interval, expiration);
+            }
+            let lead = std::cmp::min(self.config.dkim_rotation_lead as i64, interval);
+            let mut fields = TinyORM::track_changes(&current_fields);
+
+            match &rotation.pending {
+                Some(pending) if rotation.auto_rotate && pending.activate <= now => {
+                    let key = match DkimKey::from_pem(&pending.secret) {
+                        Ok(key) => key,
+                        Err(err) => {
+                            error!("Failed to rotate DKIM key for '{}': {}", domain_name, err);
+                            continue;
+                        }
+                    };
+                    info!(
+                        "Rotated DKIM key for '{}' to selector '{}', selector '{}' can be removed from DNS in {} seconds.",
+                        domain_name,
+                        pending.selector,
+                        selector,
+                        std::cmp::max(grace_period, expiration)
+                    );
+                    fields.dkim_install(
+                        Some(&current_fields),
+                        key,
+                        pending.selector.clone().into(),
+                        true,
+                        grace_period,
+                    );
+                }
+                None if rotation.auto_rotate
+                    && interval > 0
+                    && rotation.created + interval - lead <= now =>
+                {
+                    let algorithm = match current_fields.get(&Property::DKIMAlgorithm) {
+                        Some(Value::Text { value }) => DkimAlgorithm::parse(value),
+                        _ => None,
+                    }
+                    .unwrap_or(DkimAlgorithm::RsaSha256);
+                    let key = match DkimKey::generate(algorithm) {
+                        Ok(key) => key,
+                        Err(err) => {
+                            error!("Failed to rotate DKIM key for '{}': {}", domain_name, err);
+                            continue;
+                        }
+                    };
+                    let pending = DKIMPending {
+                        selector: next_selector(selector, now),
+                        record: key.record(),
+                        secret: key.secret,
+                        activate: std::cmp::max(now + lead, rotation.created + interval),
+                    };
+                    info!(
+                        "Generated the next DKIM key for '{}', publish \"{}\" at {}._domainkey.{}. before {}.",
+                        domain_name,
+                        pending.record,
+                        pending.selector,
+                        domain_name,
+                        Utc.timestamp(pending.activate, 0).to_rfc3339()
+                    );
+                    rotation.retired.retain(|retired| retired.until > now);
+                    rotation.pending = pending.into();
+                    fields.set(
+                        Property::DKIMRotation,
+                        Value::DKIMRotation { value: rotation },
+                    );
+                }
+                _ if rotation.retired.iter().any(|retired| retired.until <= now) => {
+                    rotation.retired.retain(|retired| {
+                        if retired.until > now {
+                            true
+                        } else {
+                            info!(
+                                "DKIM selector '{}' for '{}' has been retired and can be removed from DNS.",
+                                retired.selector, domain_name
+                            );
+                            false
+                        }
+                    });
+                    fields.set(
+                        Property::DKIMRotation,
+                        Value::DKIMRotation { value: rotation },
+                    );
+                }
+                _ => continue,
+            }
+
+            let mut document = Document::new(Collection::Principal, document_id);
+            current_fields.merge(&mut document, fields)?;
+            if !document.is_empty() {
+                batch.update_document(document);
+                batch.log_update(Collection::Principal, document_id);
+            }
+        }
+
+        if !batch.is_empty() {
+            self.write(batch)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn next_selector(selector: &str, now: i64) -> String {
+    // Remove the date suffix added by a previous rotation
+    let base = match selector.rsplit_once('-') {
+        Some((base, suffix))
+            if !base.is_empty()
+                && [8, 14].contains(&suffix.len())
+                && suffix.chars().all(|ch| ch.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => selector,
+    };
+    let date = Utc.timestamp(now, 0);
+    let next = format!("{}-{}", base, date.format("%Y%m%d"));
+    if next != selector {
+        next
+    } else {
+        format!("{}-{}", base, date.format("%Y%m%d%H%M%S"))
+    }
+}
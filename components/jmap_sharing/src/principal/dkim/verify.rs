@@ -0,0 +1,815 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::SystemTime;
+
+use ring::signature::{UnparsedPublicKey, ED25519};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, Hash, PaddingScheme, PublicKey, RsaPublicKey,
+};
+use store::{
+    ahash::AHashMap,
+    sha2::{Digest, Sha256},
+};
+
+use super::{
+    arc::{ChainValidation, ARC_MAX_INSTANCES},
+    DkimAlgorithm,
+};
+
+/// Public keys or lookup failures, indexed by domain and selector.
+pub type DkimKeys = AHashMap<(String, String), Result<String, DkimResult>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DkimResult {
+    Pass,
+    Fail(String),
+    Neutral(String),
+    TempError(String),
+    PermError(String),
+}
+
+pub struct DkimOutput {
+    pub result: DkimResult,
+    pub domain: String,
+    pub selector: String,
+    pub signature: String,
+}
+
+pub struct DkimSignature {
+    pub domain: String,
+    pub selector: String,
+    pub instance: u32,
+    algorithm: DkimAlgorithm,
+    signature: Vec<u8>,
+    body_hash: Vec<u8>,
+    headers: Vec<String>,
+    header_canonicalization: Canonicalization,
+    body_canonicalization: Canonicalization,
+    body_length: Option<usize>,
+    expiration: Option<u64>,
+    pos: usize,
+}
+
+struct ArcSeal {
+    domain: String,
+    selector: String,
+    algorithm: DkimAlgorithm,
+    signature: Vec<u8>,
+    cv: ChainValidation,
+}
+
+enum DkimPublicKey {
+    Rsa(RsaPublicKey),
+    Ed25519(Vec<u8>),
+}
+
+pub struct MessageVerifier<'x> {
+    headers: Vec<Header<'x>>,
+    body: &'x [u8],
+    signatures: Vec<Result<DkimSignature, String>>,
+    arc: Result<Vec<(DkimSignature, ArcSeal)>, String>,
+}
+
+impl DkimResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DkimResult::Pass => "pass",
+            DkimResult::Fail(_) => "fail",
+            DkimResult::Neutral(_) => "neutral",
+            DkimResult::TempError(_) => "temperror",
+            DkimResult::PermError(_) => "permerror",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            DkimResult::Pass => None,
+            DkimResult::Fail(reason)
+            | DkimResult::Neutral(reason)
+            | DkimResult::TempError(reason)
+            | DkimResult::PermError(reason) => Some(reason),
+        }
+    }
+}
+
+impl<'x> MessageVerifier<'x> {
+    pub fn new(message: &'x [u8]) -> Self {
+        let (headers, body) = split_message(message);
+        let mut signatures = Vec::new();
+        let mut ams = Vec::new();
+        let mut seals = Vec::new();
+        let mut aar = Vec::new();
+
+        for (pos, (name, value)) in headers.iter().enumerate() {
+            let name = String::from_utf8_lossy(name);
+            let name = name.trim_end();
+            if name.eq_ignore_ascii_case("DKIM-Signature") {
+                signatures.push(DkimSignature::parse(pos, value, false));
+            } else if name.eq_ignore_ascii_case("ARC-Message-Signature") {
+                ams.push(DkimSignature::parse(pos, value, true));
+            } else if name.eq_ignore_ascii_case("ARC-Seal") {
+                seals.push(ArcSeal::parse(value));
+            } else if name.eq_ignore_ascii_case("ARC-Authentication-Results") {
+                aar.push(arc_instance(value));
+            }
+        }
+
+        MessageVerifier {
+            arc: if !seals.is_empty() || !ams.is_empty() || !aar.is_empty() {
+                build_arc_chain(ams, seals, aar)
+            } else {
+                Ok(Vec::new())
+            },
+            headers,
+            body,
+            signatures,
+        }
+    }
+
+    /// Returns the domains and selectors of the keys needed to verify the message.
+    pub fn keys(&self) -> Vec<(String, String)> {
+        let mut keys = Vec::new();
+        for signature in self.signatures.iter().flatten() {
+            keys.push((signature.domain.clone(), signature.selector.clone()));
+        }
+        if let Ok(chain) = &self.arc {
+            if let Some((ams, _)) = chain.last() {
+                keys.push((ams.domain.clone(), ams.selector.clone()));
+            }
+            for (_, seal) in chain {
+                keys.push((seal.domain.clone(), seal.selector.clone()));
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    pub fn verify_dkim(&self, keys: &DkimKeys) -> Vec<DkimOutput> {
+        self.signatures
+            .iter()
+            .map(|signature| match signature {
+                Ok(signature) => DkimOutput {
+                    result: self.verify_message_signature(signature, keys),
+                    domain: signature.domain.clone(),
+                    selector: signature.selector.clone(),
+                    signature: base64::encode(&signature.signature)
+                        .chars()
+                        .take(8)
+                        .collect(),
+                },
+                Err(reason) => DkimOutput {
+                    result: DkimResult::PermError(reason.clone()),
+                    domain: String::new(),
+                    selector: String::new(),
+                    signature: String::new(),
+                },
+            })
+            .collect()
+    }
+
+    /// Validates the ARC chain (RFC 8617, section 5.2), returning `None`
+    /// when the message has no ARC sets.
+    pub fn verify_arc(&self, keys: &DkimKeys) -> Option<DkimResult> {
+        let chain = match &self.arc {
+            Ok(chain) if chain.is_empty() => return None,
+            Ok(chain) => chain,
+            Err(reason) => return DkimResult::Fail(reason.clone()).into(),
+        };
+
+        // Only the most recent message signature is validated
+        let (ams, _) = chain.last().unwrap();
+        match self.verify_message_signature(ams, keys) {
+            DkimResult::Pass => (),
+            DkimResult::TempError(reason) => return DkimResult::TempError(reason).into(),
+            result => {
+                return DkimResult::Fail(format!(
+                    "message signature {}: {}",
+                    ams.instance,
+                    result.reason().unwrap_or_default()
+                ))
+                .into()
+            }
+        }
+
+        // All seals are validated
+        for (ams, seal) in chain.iter().rev() {
+            let mut data = Vec::new();
+            for instance in 1..=ams.instance {
+                for header_name in [
+                    "ARC-Authentication-Results",
+                    "ARC-Message-Signature",
+                    "ARC-Seal",
+                ] {
+                    if let Some((name, value)) =
+                        find_arc_header(&self.headers, header_name, instance)
+                    {
+                        if instance == ams.instance && header_name == "ARC-Seal" {
+                            Canonicalization::Relaxed.header(
+                                name,
+                                &remove_signature(value),
+                                &mut data,
+                            );
+                        } else {
+                            Canonicalization::Relaxed.header(name, value, &mut data);
+                            data.extend_from_slice(b"\r\n");
+                        }
+                    }
+                }
+            }
+
+            match verify_data(
+                &seal.domain,
+                &seal.selector,
+                seal.algorithm,
+                &data,
+                &seal.signature,
+                keys,
+            ) {
+                DkimResult::Pass => (),
+                DkimResult::TempError(reason) => return DkimResult::TempError(reason).into(),
+                result => {
+                    return DkimResult::Fail(format!(
+                        "seal {}: {}",
+                        ams.instance,
+                        result.reason().unwrap_or_default()
+                    ))
+                    .into()
+                }
+            }
+        }
+
+        DkimResult::Pass.into()
+    }
+
+    /// Returns the chain validation status to use when adding a new ARC set.
+    pub fn chain_validation(&self, keys: &DkimKeys) -> ChainValidation {
+        match self.verify_arc(keys) {
+            None => ChainValidation::None,
+            Some(DkimResult::Pass) => ChainValidation::Pass,
+            Some(_) => ChainValidation::Fail,
+        }
+    }
+
+    fn verify_message_signature(&self, signature: &DkimSignature, keys: &DkimKeys) -> DkimResult {
+        if signature.expiration.map_or(false, |x| x < now()) {
+            return DkimResult::Fail("signature expired".to_string());
+        }
+
+        // Verify body hash
+        let mut body = signature.body_canonicalization.body(self.body);
+        if let Some(body_length) = signature.body_length {
+            if body_length > body.len() {
+                return DkimResult::Fail("body length exceeds message size".to_string());
+            }
+            body.truncate(body_length);
+        }
+        if Sha256::digest(&body).as_slice() != signature.body_hash {
+            return DkimResult::Fail("body hash did not verify".to_string());
+        }
+
+        // Verify headers
+        let mut data = Vec::new();
+        signed_headers(
+            &self.headers,
+            &signature.headers,
+            signature.header_canonicalization,
+            &mut data,
+        );
+        let (name, value) = self.headers[signature.pos];
+        signature
+            .header_canonicalization
+            .header(name, &remove_signature(value), &mut data);
+
+        verify_data(
+            &signature.domain,
+            &signature.selector,
+            signature.algorithm,
+            &data,
+            &signature.signature,
+            keys,
+        )
+    }
+}
+
+impl DkimSignature {
+    fn parse(pos: usize, value: &[u8], is_arc: bool) -> Result<Self, String> {
+        let mut signature = DkimSignature {
+            domain: String::new(),
+            selector: String::new(),
+            instance: 0,
+            algorithm: DkimAlgorithm::RsaSha256,
+            signature: Vec::new(),
+            body_hash: Vec::new(),
+            headers: Vec::new(),
+            header_canonicalization: Canonicalization::Simple,
+            body_canonicalization: Canonicalization::Simple,
+            body_length: None,
+            expiration: None,
+            pos,
+        };
+        let mut has_version = is_arc;
+        let mut has_algorithm = false;
+
+        for (name, value) in parse_tags(value) {
+            match name.as_str() {
+                "v" => {
+                    if value != "1" {
+                        return Err(format!("unsupported version {}", value));
+                    }
+                    has_version = true;
+                }
+                "a" => {
+                    signature.algorithm = DkimAlgorithm::parse(&value)
+                        .ok_or_else(|| format!("unsupported algorithm {}", value))?;
+                    has_algorithm = true;
+                }
+                "b" => signature.signature = decode_base64(&value)?,
+                "bh" => signature.body_hash = decode_base64(&value)?,
+                "c" => {
+                    (
+                        signature.header_canonicalization,
+                        signature.body_canonicalization,
+                    ) = Canonicalization::parse(&value)
+                        .ok_or_else(|| format!("unsupported canonicalization {}", value))?;
+                }
+                "d" => signature.domain = value.to_lowercase(),
+                "s" => signature.selector = value,
+                "h" => {
+                    signature.headers = value.split(':').map(|h| h.to_string()).collect();
+                }
+                "i" if is_arc => {
+                    signature.instance = value.parse().map_err(|_| "invalid instance")?;
+                }
+                "l" => {
+                    signature.body_length = Some(value.parse().map_err(|_| "invalid body length")?);
+                }
+                "x" => {
+                    signature.expiration = Some(value.parse().map_err(|_| "invalid expiration")?);
+                }
+                _ => (),
+            }
+        }
+
+        if !has_version
+            || !has_algorithm
+            || signature.signature.is_empty()
+            || signature.body_hash.is_empty()
+            || signature.domain.is_empty()
+            || signature.selector.is_empty()
+            || signature.headers.is_empty()
+            || (is_arc && signature.instance == 0)
+        {
+            Err("missing required tags".to_string())
+        } else if !is_arc
+            && !signature
+                .headers
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case("From"))
+        {
+            Err("From header not signed".to_string())
+        } else {
+            Ok(signature)
+        }
+    }
+}
+
+impl ArcSeal {
+    fn parse(value: &[u8]) -> Result<(u32, Self), String> {
+        let mut instance = 0;
+        let mut seal = ArcSeal {
+            domain: String::new(),
+            selector: String::new(),
+            algorithm: DkimAlgorithm::RsaSha256,
+            signature: Vec::new(),
+            cv: ChainValidation::None,
+        };
+
+        for (name, value) in parse_tags(value) {
+            match name.as_str() {
+                "i" => instance = value.parse().map_err(|_| "invalid instance")?,
+                "a" => {
+                    seal.algorithm = DkimAlgorithm::parse(&value)
+                        .ok_or_else(|| format!("unsupported algorithm {}", value))?;
+                }
+                "b" => seal.signature = decode_base64(&value)?,
+                "d" => seal.domain = value.to_lowercase(),
+                "s" => seal.selector = value,
+                "cv" => {
+                    seal.cv = match value.as_str() {
+                        "none" => ChainValidation::None,
+                        "pass" => ChainValidation::Pass,
+                        "fail" => ChainValidation::Fail,
+                        _ => return Err(format!("invalid chain validation status {}", value)),
+                    };
+                }
+                _ => (),
+            }
+        }
+
+        if instance == 0
+            || seal.signature.is_empty()
+            || seal.domain.is_empty()
+            || seal.selector.is_empty()
+        {
+            Err("missing required seal tags".to_string())
+        } else {
+            Ok((instance, seal))
+        }
+    }
+}
+
+impl DkimPublicKey {
+    fn parse(record: &str) -> Result<Self, DkimResult> {
+        let mut key_type = "rsa".to_string();
+        let mut public_key = None;
+
+        for (name, value) in parse_tags(record.as_bytes()) {
+            match name.as_str() {
+                "v" if value != "DKIM1" => {
+                    return Err(DkimResult::PermError("invalid key record".to_string()));
+                }
+                "k" => key_type = value,
+                "p" => public_key = value.into(),
+                _ => (),
+            }
+        }
+
+        let public_key = match public_key {
+            Some(public_key) if !public_key.is_empty() => {
+                decode_base64(&public_key).map_err(DkimResult::PermError)?
+            }
+            Some(_) => return Err(DkimResult::PermError("key revoked".to_string())),
+            None => return Err(DkimResult::PermError("invalid key record".to_string())),
+        };
+
+        match key_type.as_str() {
+            "rsa" => RsaPublicKey::from_public_key_der(&public_key)
+                .or_else(|_| RsaPublicKey::from_pkcs1_der(&public_key))
+                .map(DkimPublicKey::Rsa)
+                .map_err(|_| DkimResult::PermError("invalid RSA key".to_string())),
+            "ed25519" => Ok(DkimPublicKey::Ed25519(public_key)),
+            _ => Err(DkimResult::PermError(format!(
+                "unsupported key type {}",
+                key_type
+            ))),
+        }
+    }
+
+    fn verify(&self, algorithm: DkimAlgorithm, hash: &[u8], signature: &[u8]) -> bool {
+        match (self, algorithm) {
+            (DkimPublicKey::Rsa(key), DkimAlgorithm::RsaSha256) => key
+                .verify(
+                    PaddingScheme::new_pkcs1v15_sign(Some(Hash::SHA2_256)),
+                    hash,
+                    signature,
+                )
+                .is_ok(),
+            (DkimPublicKey::Ed25519(key), DkimAlgorithm::Ed25519Sha256) => {
+                UnparsedPublicKey::new(&ED25519, key)
+                    .verify(hash, signature)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn build_arc_chain(
+    ams: Vec<Result<DkimSignature, String>>,
+    seals: Vec<Result<(u32, ArcSeal), String>>,
+    aar: Vec<Option<u32>>,
+) -> Result<Vec<(DkimSignature, ArcSeal)>, String> {
+    let mut ams = ams.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let mut seals = seals.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let total = seals.len() as u32;
+
+    if total == 0
+        || total > ARC_MAX_INSTANCES
+        || ams.len() != seals.len()
+        || aar.len() != seals.len()
+    {
+        return Err("invalid ARC chain".to_string());
+    }
+
+    // Each instance must be present exactly once
+    ams.sort_unstable_by_key(|ams| ams.instance);
+    seals.sort_unstable_by_key(|(instance, _)| *instance);
+    let mut aar = aar
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .ok_or("invalid ARC chain")?;
+    aar.sort_unstable();
+    for (pos, ((ams, (seal_instance, seal)), aar_instance)) in
+        ams.iter().zip(seals.iter()).zip(aar.iter()).enumerate()
+    {
+        let instance = pos as u32 + 1;
+        if ams.instance != instance || *seal_instance != instance || *aar_instance != instance {
+            return Err("invalid ARC chain".to_string());
+        }
+        match (instance, seal.cv) {
+            (1, ChainValidation::None) => (),
+            (1, _) | (_, ChainValidation::None) => {
+                return Err("invalid ARC chain".to_string());
+            }
+            (_, ChainValidation::Fail) => return Err("ARC chain marked as failed".to_string()),
+            _ => (),
+        }
+    }
+
+    Ok(ams
+        .into_iter()
+        .zip(seals.into_iter().map(|(_, seal)| seal))
+        .collect())
+}
+
+fn verify_data(
+    domain: &str,
+    selector: &str,
+    algorithm: DkimAlgorithm,
+    data: &[u8],
+    signature: &[u8],
+    keys: &DkimKeys,
+) -> DkimResult {
+    match keys.get(&(domain.to_string(), selector.to_string())) {
+        Some(Ok(record)) => match DkimPublicKey::parse(record) {
+            Ok(key) => {
+                if key.verify(algorithm, &Sha256::digest(data), signature) {
+                    DkimResult::Pass
+                } else {
+                    DkimResult::Fail("signature did not verify".to_string())
+                }
+            }
+            Err(result) => result,
+        },
+        Some(Err(result)) => result.clone(),
+        None => DkimResult::PermError("no key for signature".to_string()),
+    }
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, String> {
+    base64::decode(value).map_err(|_| "invalid base64 encoding".to_string())
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+pub type Header<'x> = (&'x [u8], &'x [u8]);
+
+impl Canonicalization {
+    /// Parses a "c=" tag value, returning the header and body canonicalization.
+    pub fn parse(value: &str) -> Option<(Self, Self)> {
+        let (header, body) = value.split_once('/').unwrap_or((value, "simple"));
+        Some((Self::parse_single(header)?, Self::parse_single(body)?))
+    }
+
+    fn parse_single(value: &str) -> Option<Self> {
+        match value.trim() {
+            "simple" => Some(Canonicalization::Simple),
+            "relaxed" => Some(Canonicalization::Relaxed),
+            _ => None,
+        }
+    }
+
+    /// Appends the canonical form of a header, without the trailing CRLF.
+    pub fn header(&self, name: &[u8], value: &[u8], data: &mut Vec<u8>) {
+        match self {
+            Canonicalization::Simple => {
+                data.extend_from_slice(name);
+                data.push(b':');
+                data.extend_from_slice(
+                    value
+                        .strip_suffix(b"\r\n")
+                        .or_else(|| value.strip_suffix(b"\n"))
+                        .unwrap_or(value),
+                );
+            }
+            Canonicalization::Relaxed => {
+                data.extend(name.iter().map(|ch| ch.to_ascii_lowercase()));
+                while data.last().map_or(false, |ch| ch.is_ascii_whitespace()) {
+                    data.pop();
+                }
+                data.push(b':');
+
+                let mut pending_space = false;
+                let mut is_start = true;
+                for &ch in value {
+                    match ch {
+                        b'\r' | b'\n' => (),
+                        b' ' | b'\t' => pending_space = !is_start,
+                        _ => {
+                            if pending_space {
+                                data.push(b' ');
+                                pending_space = false;
+                            }
+                            data.push(ch);
+                            is_start = false;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn body(&self, body: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(body.len());
+        let mut empty_lines = 0;
+
+        for line in body.split(|&ch| ch == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let mut canonical_line = Vec::with_capacity(line.len());
+            match self {
+                Canonicalization::Simple => canonical_line.extend_from_slice(line),
+                Canonicalization::Relaxed => {
+                    let mut pending_space = false;
+                    for &ch in line {
+                        if ch == b' ' || ch == b'\t' {
+                            pending_space = true;
+                        } else {
+                            if pending_space {
+                                canonical_line.push(b' ');
+                                pending_space = false;
+                            }
+                            canonical_line.push(ch);
+                        }
+                    }
+                }
+            }
+
+            // Trailing empty lines are ignored
+            if canonical_line.is_empty() {
+                empty_lines += 1;
+            } else {
+                for _ in 0..empty_lines {
+                    result.extend_from_slice(b"\r\n");
+                }
+                empty_lines = 0;
+                result.extend_from_slice(&canonical_line);
+                result.extend_from_slice(b"\r\n");
+            }
+        }
+
+        // An empty body is represented by a single CRLF in simple canonicalization
+        if result.is_empty() && *self == Canonicalization::Simple {
+            result.extend_from_slice(b"\r\n");
+        }
+
+        result
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Canonicalization::Simple => "simple",
+            Canonicalization::Relaxed => "relaxed",
+        }
+    }
+}
+
+/// Splits a message into its headers and body.
+pub fn split_message(message: &[u8]) -> (Vec<Header<'_>>, &[u8]) {
+    let mut headers: Vec<Header<'_>> = Vec::new();
+    let mut pos = 0;
+
+    while pos < message.len() {
+        let line_end = message[pos..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map(|end| pos + end + 1)
+            .unwrap_or(message.len());
+        let line = &message[pos..line_end];
+
+        if line == b"\r\n" || line == b"\n" {
+            return (headers, &message[line_end..]);
+        } else if line.starts_with(b" ") || line.starts_with(b"\t") {
+            // Folded line, extend the previous header's value
+            if let Some((_, value)) = headers.last_mut() {
+                let start = value.as_ptr() as usize - message.as_ptr() as usize;
+                *value = &message[start..line_end];
+            }
+        } else if let Some(colon) = line.iter().position(|&ch| ch == b':') {
+            headers.push((&line[..colon], &line[colon + 1..]));
+        }
+        pos = line_end;
+    }
+
+    (headers, b"")
+}
+
+/// Appends the headers listed in a signature's "h=" tag, selecting
+/// repeated headers from the bottom up.
+pub fn signed_headers(
+    headers: &[Header<'_>],
+    names: &[String],
+    canonicalization: Canonicalization,
+    data: &mut Vec<u8>,
+) {
+    let mut used = vec![false; headers.len()];
+    for name in names {
+        if let Some(pos) = (0..headers.len()).rev().find(|&pos| {
+            !used[pos]
+                && String::from_utf8_lossy(headers[pos].0)
+                    .trim_end()
+                    .eq_ignore_ascii_case(name.trim())
+        }) {
+            used[pos] = true;
+            canonicalization.header(headers[pos].0, headers[pos].1, data);
+            data.extend_from_slice(b"\r\n");
+        }
+    }
+}
+
+/// Removes the value of the "b=" tag from a signature header.
+pub fn remove_signature(value: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(value.len());
+    for (pos, tag) in value.split(|&ch| ch == b';').enumerate() {
+        if pos > 0 {
+            result.push(b';');
+        }
+        match tag.iter().position(|&ch| ch == b'=') {
+            Some(eq)
+                if tag[..eq]
+                    .iter()
+                    .filter(|ch| !ch.is_ascii_whitespace())
+                    .eq(b"b".iter()) =>
+            {
+                result.extend_from_slice(&tag[..eq + 1]);
+            }
+            _ => result.extend_from_slice(tag),
+        }
+    }
+    result
+}
+
+/// Parses a tag list (RFC 6376, section 3.2), removing any whitespace
+/// from the values.
+pub fn parse_tags(value: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(value)
+        .split(';')
+        .filter_map(|tag| {
+            let (name, value) = tag.split_once('=')?;
+            Some((
+                name.trim().to_string(),
+                value
+                    .chars()
+                    .filter(|ch| !ch.is_ascii_whitespace())
+                    .collect(),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the instance number ("i=" tag) of an ARC header.
+pub fn arc_instance(value: &[u8]) -> Option<u32> {
+    parse_tags(value).into_iter().find_map(|(name, value)| {
+        if name == "i" {
+            value.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the header with the given name and ARC instance number.
+pub fn find_arc_header<'x>(
+    headers: &[Header<'x>],
+    name: &str,
+    instance: u32,
+) -> Option<Header<'x>> {
+    headers.iter().copied().find(|(header_name, value)| {
+        String::from_utf8_lossy(header_name)
+            .trim_end()
+            .eq_ignore_ascii_case(name)
+            && arc_instance(value) == Some(instance)
+    })
+}
@@ -23,7 +23,7 @@
 
 use jmap::jmap_store::get::{default_mapper, GetHelper, SharedDocsFnc};
 use jmap::orm::serialize::JMAPOrm;
-use jmap::principal::schema::{Principal, Property, Value};
+use jmap::principal::schema::{Principal, Property, Value, DKIM as DKIMSettings};
 use jmap::principal::store::JMAPPrincipals;
 use jmap::request::get::{GetRequest, GetResponse};
 use jmap::SUPERUSER_ID;
@@ -39,6 +39,8 @@ use store::read::FilterMapper;
 use store::JMAPStore;
 use store::Store;
 
+use super::dkim::{arc::ArcSealer, DEFAULT_DKIM_SELECTOR};
+
 pub trait JMAPGetPrincipal<T>
 where
     T: for<'x> Store<'x> + 'static,
@@ -46,6 +48,8 @@ where
     fn principal_get(&self, request: GetRequest<Principal>)
         -> jmap::Result<GetResponse<Principal>>;
     fn dkim_get(&self, domain_name: String) -> store::Result<Option<DKIM<'_>>>;
+    fn arc_sealer_get(&self, domain_name: String) -> store::Result<Option<ArcSealer>>;
+    fn dkim_record_get(&self, domain_name: &str, selector: &str) -> store::Result<Option<String>>;
 }
 
 impl<T> JMAPGetPrincipal<T> for JMAPStore<T>
@@ -81,7 +85,7 @@ where
                             Value::ACL(acl_get)
                         }
 
-                        Property::Secret => Value::Null,
+                        Property::Secret | Property::DKIMRotation => Value::Null,
                         _ => fields.remove(property).unwrap_or_default(),
                     },
                 );
@@ -93,47 +97,133 @@ where
     }
 
     fn dkim_get(&self, domain_name: String) -> store::Result<Option<DKIM<'_>>> {
+        if let Some((secret, settings)) = dkim_settings_get(self, &domain_name)? {
+            let mut dkim = DKIM::from_pkcs1_pem(&secret)
+                .map_err(|err| StoreError::InternalError(format!("Failed to DKIM sign: {}", err)))?
+                .domain(domain_name)
+                .selector(DEFAULT_DKIM_SELECTOR);
+
+            if let Some(settings) = settings {
+                if let Some(expiration) = settings.dkim_expiration {
+                    dkim = dkim.expiration(expiration as u64);
+                }
+                if let Some(selector) = settings.dkim_selector {
+                    dkim = dkim.selector(selector);
+                }
+            }
+
+            Ok(Some(dkim))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn arc_sealer_get(&self, domain_name: String) -> store::Result<Option<ArcSealer>> {
+        if let Some((secret, settings)) = dkim_settings_get(self, &domain_name)? {
+            ArcSealer::new(
+                &secret,
+                domain_name,
+                settings
+                    .and_then(|settings| settings.dkim_selector)
+                    .unwrap_or_else(|| DEFAULT_DKIM_SELECTOR.to_string()),
+            )
+            .map(Some)
+            .map_err(|err| StoreError::InternalError(format!("Failed to ARC seal: {}", err)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn dkim_record_get(&self, domain_name: &str, selector: &str) -> store::Result<Option<String>> {
         if let Some(domain_id) = self
             .query_store::<FilterMapper>(
                 SUPERUSER_ID,
                 Collection::Principal,
                 Filter::and(vec![
                     Filter::eq(Property::DKIM.into(), Query::Tag(Tag::Default)),
-                    Filter::eq(Property::Name.into(), Query::Index(domain_name.clone())),
+                    Filter::eq(Property::Name.into(), Query::Index(domain_name.to_string())),
                 ]),
                 Comparator::None,
             )?
             .next()
         {
-            if let Some((Value::Text { value: dkim }, dkim_settings)) = self
-                .get_orm::<Principal>(SUPERUSER_ID, domain_id.get_document_id())?
-                .map(|mut p| {
-                    (
-                        p.remove(&Property::Secret).unwrap_or(Value::Null),
-                        p.remove(&Property::DKIM).unwrap_or(Value::Null),
-                    )
-                })
+            if let Some(mut fields) =
+                self.get_orm::<Principal>(SUPERUSER_ID, domain_id.get_document_id())?
             {
-                let mut dkim = DKIM::from_pkcs1_pem(&dkim)
-                    .map_err(|err| {
-                        StoreError::InternalError(format!("Failed to DKIM sign: {}", err))
-                    })?
-                    .domain(domain_name)
-                    .selector("default");
-
-                if let Value::DKIM { value } = dkim_settings {
-                    if let Some(expiration) = value.dkim_expiration {
-                        dkim = dkim.expiration(expiration as u64);
-                    }
-                    if let Some(selector) = value.dkim_selector {
-                        dkim = dkim.selector(selector);
-                    }
+                let current_selector = match fields.get(&Property::DKIM) {
+                    Some(Value::DKIM { value }) => value.dkim_selector.as_deref(),
+                    _ => None,
                 }
+                .unwrap_or(DEFAULT_DKIM_SELECTOR)
+                .to_string();
 
-                return Ok(Some(dkim));
+                if current_selector == selector {
+                    if let Some(Value::Text { value }) = fields.remove(&Property::DKIMRecord) {
+                        return Ok(Some(value));
+                    }
+                } else if let Some(Value::DKIMRotation { value }) =
+                    fields.remove(&Property::DKIMRotation)
+                {
+                    // Retired keys remain valid for messages still in transit and
+                    // pending keys are published ahead of their activation
+                    return Ok(value
+                        .retired
+                        .into_iter()
+                        .find(|retired| retired.selector == selector)
+                        .map(|retired| retired.record)
+                        .or_else(|| {
+                            value
+                                .pending
+                                .filter(|pending| pending.selector == selector)
+                                .map(|pending| pending.record)
+                        }));
+                }
             }
         }
 
         Ok(None)
     }
 }
+
+// Returns the DKIM private key and settings of a domain.
+fn dkim_settings_get<T>(
+    store: &JMAPStore<T>,
+    domain_name: &str,
+) -> store::Result<Option<(String, Option<DKIMSettings>)>>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    if let Some(domain_id) = store
+        .query_store::<FilterMapper>(
+            SUPERUSER_ID,
+            Collection::Principal,
+            Filter::and(vec![
+                Filter::eq(Property::DKIM.into(), Query::Tag(Tag::Default)),
+                Filter::eq(Property::Name.into(), Query::Index(domain_name.to_string())),
+            ]),
+            Comparator::None,
+        )?
+        .next()
+    {
+        if let Some((Value::Text { value: secret }, settings)) = store
+            .get_orm::<Principal>(SUPERUSER_ID, domain_id.get_document_id())?
+            .map(|mut p| {
+                (
+                    p.remove(&Property::Secret).unwrap_or(Value::Null),
+                    p.remove(&Property::DKIM).unwrap_or(Value::Null),
+                )
+            })
+        {
+            return Ok(Some((
+                secret,
+                if let Value::DKIM { value } = settings {
+                    Some(value)
+                } else {
+                    None
+                },
+            )));
+        }
+    }
+
+    Ok(None)
+}
@@ -32,7 +32,6 @@ use jmap::request::set::SetRequest;
 use jmap::request::set::SetResponse;
 use jmap::types::jmap::JMAPId;
 use jmap::{sanitize_domain, sanitize_email, SUPERUSER_ID};
-use jmap_mail::mail_send::dkim::DKIM;
 use jmap_mail::mailbox::schema::Mailbox;
 use jmap_mail::mailbox::CreateMailbox;
 use store::ahash::AHashSet;
@@ -40,6 +39,7 @@ use store::core::collection::Collection;
 use store::core::document::Document;
 use store::core::error::StoreError;
 use store::core::tag::Tag;
+use store::core::vec_map::VecMap;
 use store::rand::Rng;
 use store::read::comparator::Comparator;
 use store::read::filter::{self, Filter, Query};
@@ -48,6 +48,8 @@ use store::write::batch::WriteBatch;
 use store::write::options::IndexOptions;
 use store::{rand, DocumentId, JMAPStore, Store};
 
+use super::dkim::{DkimAlgorithm, DkimKey, DkimKeyInstall};
+
 pub trait JMAPSetPrincipal<T>
 where
     T: for<'x> Store<'x> + 'static,
@@ -108,6 +110,12 @@ where
                     helper.store.recipients.invalidate(email);
                 }
             }
+            if fields.get(&Property::CatchAll).is_some()
+                || fields.get(&Property::AliasPatterns).is_some()
+            {
+                // Previously unknown addresses might now be accepted
+                helper.store.recipients.invalidate_all();
+            }
 
             fields.insert_validate(document)?;
 
@@ -160,6 +168,26 @@ where
             ) {
                 helper.store.recipients.invalidate(email);
             }
+            if fields.get(&Property::CatchAll).is_some()
+                || fields.get(&Property::AliasPatterns).is_some()
+                || (fields.get(&Property::Members).is_some()
+                    && matches!(
+                        current_fields.get(&Property::Type),
+                        Some(Value::Type { value: Type::List })
+                    ))
+            {
+                // Wildcard and catch-all results are cached under any address
+                helper.store.recipients.invalidate_all();
+            }
+
+            // Log quota limit changes in the principal's account
+            if matches!(fields.get(&Property::Quota), Some(quota)
+                        if Some(quota) != current_fields.get(&Property::Quota))
+            {
+                let mut batch = WriteBatch::new(document_id);
+                batch.log_update(Collection::Quota, 0u64);
+                helper.changes.add_linked_batch(batch);
+            }
 
             // Merge changes
             current_fields.merge_validate(document, fields)?;
@@ -230,9 +258,8 @@ where
                 );
                 helper.changes.update_document(tag_deletion);
 
-                if let Some(Value::Text { value }) = fields.get(&Property::Email) {
-                    helper.store.recipients.invalidate(value);
-                }
+                // Wildcard and catch-all results might point to this principal
+                helper.store.recipients.invalidate_all();
                 helper.store.acl_tokens.invalidate(&document.document_id);
                 fields.delete(document);
             }
@@ -484,18 +511,8 @@ where
                 (Property::Secret, Value::Text { value })
                     if !value.is_empty() && ptype == Type::Domain =>
                 {
-                    // Validate DKIM key
-                    match DKIM::from_pkcs1_pem(&value) {
-                        Ok(_) => {
-                            self.tag(Property::DKIM, Tag::Default);
-                            Value::Text { value }
-                        }
-                        Err(err) => {
-                            return Err(SetError::invalid_properties()
-                                .with_property(property)
-                                .with_description(format!("Invalid DKIM key: {}", err)));
-                        }
-                    }
+                    // DKIM key, validated once all properties are processed
+                    Value::Text { value }
                 }
 
                 (Property::Secret, Value::Null) if ptype == Type::Domain => {
@@ -537,6 +554,62 @@ where
 
                 (Property::DKIM, value @ Value::DKIM { .. }) if ptype == Type::Domain => value,
 
+                (Property::DKIMAlgorithm, Value::Text { value }) if ptype == Type::Domain => {
+                    match DkimAlgorithm::parse(&value) {
+                        Some(algorithm) if algorithm.can_sign() => Value::Text { value },
+                        Some(_) => {
+                            return Err(SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("Only RSA keys are supported for signing."));
+                        }
+                        None => {
+                            return Err(SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("Unsupported DKIM algorithm."));
+                        }
+                    }
+                }
+
+                (Property::CatchAll, value @ (Value::Id { .. } | Value::Null))
+                    if ptype == Type::Domain =>
+                {
+                    value
+                }
+
+                (Property::AliasPatterns, Value::AliasPatterns { value })
+                    if ptype == Type::Domain =>
+                {
+                    let mut patterns = VecMap::with_capacity(value.len());
+                    for (pattern, id) in value {
+                        let pattern = pattern.trim().to_lowercase();
+                        if !pattern.contains('*')
+                            || pattern.contains(char::is_whitespace)
+                            || pattern
+                                .split_once('@')
+                                .map_or(true, |(local_part, domain)| {
+                                    local_part.is_empty()
+                                        || domain.is_empty()
+                                        || domain.contains('*')
+                                })
+                        {
+                            return Err(SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description(format!(
+                                    "Invalid alias pattern '{}'.",
+                                    pattern
+                                )));
+                        }
+                        patterns.set(pattern, id);
+                    }
+                    if !patterns.is_empty() {
+                        Value::AliasPatterns { value: patterns }
+                    } else {
+                        Value::Null
+                    }
+                }
+
+                (Property::AliasPatterns, Value::Null) if ptype == Type::Domain => Value::Null,
+
                 (Property::Quota, value @ (Value::Number { .. } | Value::Null)) => value,
 
                 (Property::Picture, value @ (Value::Blob { .. } | Value::Null)) => value,
@@ -721,6 +794,112 @@ where
             self.set(property, value);
         }
 
+        // Install, generate or remove DKIM keys
+        if ptype == Type::Domain {
+            let grace_period = helper.store.config.dkim_rotation_grace as i64;
+            let algorithm = match self.get(&Property::DKIMAlgorithm) {
+                Some(Value::Text { value }) => DkimAlgorithm::parse(value),
+                _ => None,
+            };
+
+            match self.get(&Property::Secret) {
+                Some(Value::Text { value }) => {
+                    let key = DkimKey::from_pem(value).map_err(|err| {
+                        SetError::invalid_properties()
+                            .with_property(Property::Secret)
+                            .with_description(format!("Invalid DKIM key: {}", err))
+                    })?;
+                    if algorithm.map_or(false, |algorithm| algorithm != key.algorithm) {
+                        return Err(SetError::invalid_properties()
+                            .with_property(Property::DKIMAlgorithm)
+                            .with_description("Algorithm does not match the provided key."));
+                    }
+                    self.dkim_install(current_fields, key, None, false, grace_period);
+                }
+                Some(Value::Null) => self.dkim_remove(current_fields),
+                _ => {
+                    // Setting an algorithm without a key generates a new one, which
+                    // replaces an existing key only after it has been published
+                    if let Some(algorithm) = algorithm {
+                        let key = DkimKey::generate(algorithm).map_err(|err| {
+                            SetError::invalid_properties()
+                                .with_property(Property::DKIMAlgorithm)
+                                .with_description(err)
+                        })?;
+                        match current_fields {
+                            Some(current_fields)
+                                if current_fields.has_property(&Property::DKIMRecord) =>
+                            {
+                                self.dkim_schedule(
+                                    current_fields,
+                                    key,
+                                    helper.store.config.dkim_rotation_lead as i64,
+                                );
+                            }
+                            _ => self.dkim_install(current_fields, key, None, true, grace_period),
+                        }
+                    }
+                }
+            }
+        }
+
+        // Catch-all and wildcard aliases must point to individuals or lists
+        // and match this domain's addresses
+        if ptype == Type::Domain {
+            let mut targets = Vec::new();
+            if let Some(Value::Id { value }) = self.get(&Property::CatchAll) {
+                targets.push((Property::CatchAll, *value));
+            }
+            if let Some(Value::AliasPatterns { value }) = self.get(&Property::AliasPatterns) {
+                let domain_name = match self
+                    .get(&Property::Name)
+                    .or_else(|| current_fields.and_then(|f| f.get(&Property::Name)))
+                {
+                    Some(Value::Text { value }) => value.as_str(),
+                    _ => "",
+                };
+                for (pattern, id) in value.iter() {
+                    if pattern
+                        .rsplit_once('@')
+                        .map_or(true, |(_, d)| d != domain_name)
+                    {
+                        return Err(SetError::invalid_properties()
+                            .with_property(Property::AliasPatterns)
+                            .with_description(format!(
+                                "Alias pattern '{}' does not belong to domain '{}'.",
+                                pattern, domain_name
+                            )));
+                    }
+                    targets.push((Property::AliasPatterns, *id));
+                }
+            }
+
+            if !targets.is_empty() {
+                let recipients = helper
+                    .store
+                    .query_store::<FilterMapper>(
+                        SUPERUSER_ID,
+                        Collection::Principal,
+                        Filter::or(vec![
+                            Filter::eq(Property::Type.into(), Query::Keyword("i".to_string())),
+                            Filter::eq(Property::Type.into(), Query::Keyword("t".to_string())),
+                        ]),
+                        Comparator::None,
+                    )?
+                    .into_bitmap();
+                for (property, id) in targets {
+                    if !recipients.contains(id.get_document_id()) {
+                        return Err(SetError::invalid_properties()
+                            .with_property(property)
+                            .with_description(format!(
+                                "Principal '{}' is not an individual or mailing list.",
+                                id
+                            )));
+                    }
+                }
+            }
+        }
+
         // Validate e-mail addresses
         if !validate_emails.is_empty() {
             // Check that the domains exist
@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::jmap_store::changes::JMAPChanges;
+use jmap::jmap_store::get::GetObject;
+use jmap::jmap_store::Object;
+use jmap::principal::store::JMAPPrincipals;
+use jmap::request::get::{GetRequest, GetResponse};
+use jmap::request::MaybeResultReference;
+use jmap::types::jmap::JMAPId;
+use store::core::collection::Collection;
+use store::{AccountId, JMAPStore, Store};
+
+use super::schema::{Property, Quota, ResourceType, Scope};
+
+impl GetObject for Quota {
+    type GetArguments = ();
+
+    fn default_properties() -> Vec<Self::Property> {
+        vec![
+            Property::Id,
+            Property::ResourceType,
+            Property::Used,
+            Property::HardLimit,
+            Property::Scope,
+            Property::Name,
+            Property::Types,
+            Property::WarnLimit,
+            Property::SoftLimit,
+            Property::Description,
+        ]
+    }
+
+    fn get_as_id(&self, property: &Self::Property) -> Option<Vec<JMAPId>> {
+        match property {
+            Property::Id => Some(vec![self.id]),
+            _ => None,
+        }
+    }
+}
+
+pub trait JMAPGetQuota<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_get(&self, request: GetRequest<Quota>) -> jmap::Result<GetResponse<Quota>>;
+    fn quota_build(&self, account_id: AccountId) -> jmap::Result<Option<Quota>>;
+}
+
+impl<T> JMAPGetQuota<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_get(&self, request: GetRequest<Quota>) -> jmap::Result<GetResponse<Quota>> {
+        let account_id = request.account_id.get_document_id();
+        let properties = request
+            .properties
+            .and_then(|p| p.unwrap_value())
+            .unwrap_or_else(Quota::default_properties);
+
+        let mut response = GetResponse {
+            account_id: request.account_id.into(),
+            state: self.get_state(account_id, Collection::Quota)?,
+            list: Vec::with_capacity(1),
+            not_found: Vec::new(),
+        };
+
+        let do_get = if let Some(MaybeResultReference::Value(ids)) = request.ids {
+            let mut do_get = false;
+            for id in ids {
+                if u64::from(id) == 0 {
+                    do_get = true;
+                } else {
+                    response.not_found.push(id);
+                }
+            }
+            do_get
+        } else {
+            true
+        };
+
+        if do_get {
+            if let Some(mut quota) = self.quota_build(account_id)? {
+                let mut result = Quota::new(quota.id);
+
+                for property in properties {
+                    match property {
+                        Property::Id => (),
+                        Property::ResourceType => result.resource_type = quota.resource_type,
+                        Property::Used => result.used = quota.used,
+                        Property::HardLimit => result.hard_limit = quota.hard_limit,
+                        Property::Scope => result.scope = quota.scope,
+                        Property::Name => result.name = quota.name.take(),
+                        Property::Types => result.types = quota.types.take(),
+                        Property::WarnLimit => result.warn_limit = Some(None),
+                        Property::SoftLimit => result.soft_limit = Some(None),
+                        Property::Description => result.description = Some(None),
+                    }
+                }
+
+                response.list.push(result);
+            } else {
+                response.not_found.push(JMAPId::new(0));
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn quota_build(&self, account_id: AccountId) -> jmap::Result<Option<Quota>> {
+        // Accounts without a storage limit do not have a Quota object
+        if let Some(hard_limit) = self.principal_quota(account_id)? {
+            Ok(Some(Quota {
+                id: JMAPId::new(0),
+                resource_type: ResourceType::Octets.into(),
+                used: (self.get_used_quota(account_id)?.max(0) as u64).into(),
+                hard_limit: (hard_limit as u64).into(),
+                scope: Scope::Account.into(),
+                name: self
+                    .principal_to_email(account_id)?
+                    .unwrap_or_default()
+                    .into(),
+                types: vec!["Email".to_string()].into(),
+                warn_limit: None,
+                soft_limit: None,
+                description: None,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::cmp::Ordering;
+
+use jmap::error::method::MethodError;
+use jmap::jmap_store::changes::JMAPChanges;
+use jmap::jmap_store::query::QueryObject;
+use jmap::request::query::{self, Operator, QueryRequest, QueryResponse};
+use store::core::collection::Collection;
+use store::read::comparator::Collation;
+use store::JMAPStore;
+use store::Store;
+
+use super::get::JMAPGetQuota;
+use super::schema::{Comparator, Filter, Quota};
+
+impl QueryObject for Quota {
+    type QueryArguments = ();
+
+    type Filter = Filter;
+
+    type Comparator = Comparator;
+}
+
+pub trait JMAPQuotaQuery<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_query(&self, request: QueryRequest<Quota>) -> jmap::Result<QueryResponse>;
+}
+
+impl<T> JMAPQuotaQuery<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn quota_query(&self, request: QueryRequest<Quota>) -> jmap::Result<QueryResponse> {
+        let account_id = request.account_id.get_document_id();
+        let query_state = self.get_state(account_id, Collection::Quota)?;
+
+        // Accounts have at most one quota, so queries are evaluated in memory.
+        let mut quotas = Vec::with_capacity(1);
+        if let Some(quota) = self.quota_build(account_id)? {
+            if request
+                .filter
+                .map_or(Ok(true), |filter| filter_matches(&quota, filter))?
+            {
+                quotas.push(quota);
+            }
+        }
+        if let Some(sort) = request.sort {
+            let collations = sort
+                .iter()
+                .map(|comparator| comparator.collation())
+                .collect::<jmap::Result<Vec<_>>>()?;
+            let name_key = |quota: &Quota, collation: &Collation| {
+                quota
+                    .name
+                    .as_ref()
+                    .map(|name| collation.sort_key(name.as_bytes()))
+            };
+            quotas.sort_by(|a, b| {
+                sort.iter()
+                    .zip(collations.iter())
+                    .map(|(comparator, collation)| {
+                        let ordering = match comparator.property {
+                            Comparator::Name => name_key(a, collation).cmp(&name_key(b, collation)),
+                            Comparator::Used => a.used.cmp(&b.used),
+                        };
+                        if comparator.is_ascending {
+                            ordering
+                        } else {
+                            ordering.reverse()
+                        }
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+        let ids = quotas.into_iter().map(|quota| quota.id).collect::<Vec<_>>();
+        let total = ids.len();
+
+        if let Some(anchor) = request.anchor {
+            if !ids.contains(&anchor) {
+                return Err(MethodError::AnchorNotFound);
+            }
+        }
+
+        let position = request.position.unwrap_or(0);
+        let skip = if position < 0 {
+            total.saturating_sub(position.unsigned_abs() as usize)
+        } else {
+            position as usize
+        };
+        let ids = ids
+            .into_iter()
+            .skip(skip)
+            .take(request.limit.unwrap_or(usize::MAX))
+            .collect::<Vec<_>>();
+
+        Ok(QueryResponse {
+            account_id: request.account_id,
+            position: skip.min(total) as i32,
+            query_state,
+            total: if request.calculate_total.unwrap_or(false) {
+                Some(total)
+            } else {
+                None
+            },
+            limit: None,
+            ids,
+            is_immutable: false,
+            can_calculate_changes: false,
+        })
+    }
+}
+
+fn filter_matches(quota: &Quota, filter: query::Filter<Filter>) -> jmap::Result<bool> {
+    Ok(match filter {
+        query::Filter::FilterOperator(op) => {
+            let mut results = Vec::with_capacity(op.conditions.len());
+            for condition in op.conditions {
+                results.push(filter_matches(quota, condition)?);
+            }
+            match op.operator {
+                Operator::And => results.into_iter().all(|r| r),
+                Operator::Or => results.into_iter().any(|r| r),
+                Operator::Not => !results.into_iter().any(|r| r),
+            }
+        }
+        query::Filter::FilterCondition(condition) => match condition {
+            Filter::Name { value } => quota
+                .name
+                .as_ref()
+                .map_or(false, |name| name.contains(&value)),
+            Filter::Scope { value } => quota.scope == Some(value),
+            Filter::ResourceType { value } => quota.resource_type == Some(value),
+            Filter::Type { value } => quota
+                .types
+                .as_ref()
+                .map_or(false, |types| types.contains(&value)),
+            Filter::Unsupported { value } => {
+                return Err(MethodError::UnsupportedFilter(value));
+            }
+        },
+        query::Filter::Empty => true,
+    })
+}
@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap::types::jmap::JMAPId;
+use serde::{Deserialize, Serialize};
+use store::FieldId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Quota {
+    pub id: JMAPId,
+    #[serde(rename = "resourceType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<ResourceType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used: Option<u64>,
+    #[serde(rename = "hardLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hard_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub types: Option<Vec<String>>,
+    #[serde(rename = "warnLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_limit: Option<Option<u64>>,
+    #[serde(rename = "softLimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soft_limit: Option<Option<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<Option<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ResourceType {
+    #[serde(rename = "count")]
+    Count,
+    #[serde(rename = "octets")]
+    Octets,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Scope {
+    #[serde(rename = "account")]
+    Account,
+    #[serde(rename = "domain")]
+    Domain,
+    #[serde(rename = "global")]
+    Global,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Copy)]
+#[repr(u8)]
+pub enum Property {
+    #[serde(rename = "id")]
+    Id = 0,
+    #[serde(rename = "resourceType")]
+    ResourceType = 1,
+    #[serde(rename = "used")]
+    Used = 2,
+    #[serde(rename = "hardLimit")]
+    HardLimit = 3,
+    #[serde(rename = "scope")]
+    Scope = 4,
+    #[serde(rename = "name")]
+    Name = 5,
+    #[serde(rename = "types")]
+    Types = 6,
+    #[serde(rename = "warnLimit")]
+    WarnLimit = 7,
+    #[serde(rename = "softLimit")]
+    SoftLimit = 8,
+    #[serde(rename = "description")]
+    Description = 9,
+}
+
+impl Property {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "id" => Property::Id,
+            "resourceType" => Property::ResourceType,
+            "used" => Property::Used,
+            "hardLimit" => Property::HardLimit,
+            "scope" => Property::Scope,
+            "name" => Property::Name,
+            "types" => Property::Types,
+            "warnLimit" => Property::WarnLimit,
+            "softLimit" => Property::SoftLimit,
+            "description" => Property::Description,
+            _ => Property::Id,
+        }
+    }
+}
+
+impl From<Property> for FieldId {
+    fn from(property: Property) -> Self {
+        property as FieldId
+    }
+}
+
+impl From<FieldId> for Property {
+    fn from(field: FieldId) -> Self {
+        match field {
+            0 => Property::Id,
+            1 => Property::ResourceType,
+            2 => Property::Used,
+            3 => Property::HardLimit,
+            4 => Property::Scope,
+            5 => Property::Name,
+            6 => Property::Types,
+            7 => Property::WarnLimit,
+            8 => Property::SoftLimit,
+            _ => Property::Description,
+        }
+    }
+}
+
+impl TryFrom<&str> for Property {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(Property::parse(value))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Name { value: String },
+    Scope { value: Scope },
+    ResourceType { value: ResourceType },
+    Type { value: String },
+    Unsupported { value: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "property")]
+pub enum Comparator {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "used")]
+    Used,
+}
@@ -0,0 +1,493 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::{config::env_settings::EnvSettings, core::error::StoreError};
+
+use super::BlobReader;
+
+/*
+
+ Compressed blobs start with a magic number followed by a codec byte, the
+ blob contents are then split into chunks that are compressed independently.
+ An index of the compressed chunk sizes and the original blob size are
+ appended at the end of the blob, which allows seeking to any offset without
+ decompressing the preceding chunks.
+
+ The footer ends with a checksum of the header, index and sizes. Blobs that
+ do not pass this validation are read as is, so blobs stored before
+ compression was enabled stay readable even if they happen to start with
+ the magic number.
+
+ Chunks that do not shrink are stored as is, blobs that are too small or that
+ contain already compressed data are stored without any header. Blobs are
+ written as byte streams without a content type, so already compressed
+ formats are detected from their magic numbers instead.
+
+ Compression is disabled by default as it changes the on-disk format, older
+ versions are unable to read compressed blobs.
+
+*/
+
+pub const CHUNK_SIZE: usize = 64 * 1024;
+pub const MIN_COMPRESS_SIZE: usize = 1024;
+const MAGIC: &[u8; 8] = b"SJZ\x00\xd1\x3c\x5a\x96";
+const HEADER_SIZE: usize = MAGIC.len() + 1;
+const CHECKSUM_SIZE: usize = 8;
+const FOOTER_SIZE: usize = std::mem::size_of::<u32>() + std::mem::size_of::<u64>() + CHECKSUM_SIZE;
+const RAW_CHUNK: u32 = 1 << 31;
+
+const CODEC_LZ4: u8 = 1;
+const CODEC_ZSTD: u8 = 2;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+pub struct BlobCompressor {
+    codec: u8,
+    state: CompressorState,
+    chunk: Vec<u8>,
+}
+
+enum CompressorState {
+    Pending,
+    Uncompressed,
+    Compressed { index: Vec<u32>, size: u64 },
+}
+
+pub struct BlobDecompressor<R: BlobReader> {
+    inner: R,
+    codec: u8,
+    size: u64,
+    chunks: Vec<(u64, u32)>,
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_index: Option<usize>,
+}
+
+impl BlobCompression {
+    pub fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        match settings
+            .get("blob-compression")
+            .as_deref()
+            .unwrap_or("none")
+        {
+            "lz4" => Ok(BlobCompression::Lz4),
+            "zstd" => Ok(BlobCompression::Zstd),
+            "none" => Ok(BlobCompression::None),
+            other => Err(StoreError::InvalidArguments(format!(
+                "Invalid blob-compression type '{}'.",
+                other
+            ))),
+        }
+    }
+
+    pub fn compressor(&self) -> Option<BlobCompressor> {
+        BlobCompressor {
+            codec: match self {
+                BlobCompression::Lz4 => CODEC_LZ4,
+                BlobCompression::Zstd => CODEC_ZSTD,
+                BlobCompression::None => return None,
+            },
+            state: CompressorState::Pending,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+        }
+        .into()
+    }
+
+    // Returns the reader back if the blob is not compressed
+    pub fn decompressor<R: BlobReader>(
+        mut inner: R,
+    ) -> crate::Result<Result<BlobDecompressor<R>, R>> {
+        let compressed_size = inner.size();
+        let (header, index, size) = match read_header(&mut inner, compressed_size)? {
+            Some(header) => header,
+            None => {
+                inner.seek(SeekFrom::Start(0))?;
+                return Ok(Err(inner));
+            }
+        };
+        let codec = header[MAGIC.len()];
+        if ![CODEC_LZ4, CODEC_ZSTD].contains(&codec) {
+            return Err(StoreError::DataCorruption(format!(
+                "Unsupported blob compression codec {}.",
+                codec
+            )));
+        }
+
+        let mut chunks = Vec::with_capacity(index.len() / std::mem::size_of::<u32>());
+        let mut offset = HEADER_SIZE as u64;
+        for chunk_size in index.chunks_exact(std::mem::size_of::<u32>()) {
+            let chunk_size = u32::from_le_bytes(chunk_size.try_into().unwrap());
+            chunks.push((offset, chunk_size));
+            offset += (chunk_size & !RAW_CHUNK) as u64;
+        }
+        if offset + index.len() as u64 + FOOTER_SIZE as u64 != compressed_size
+            || size > chunks.len() as u64 * CHUNK_SIZE as u64
+        {
+            return Err(StoreError::DataCorruption(
+                "Compressed blob has an invalid index.".to_string(),
+            ));
+        }
+
+        Ok(Ok(BlobDecompressor {
+            inner,
+            codec,
+            size,
+            chunks,
+            pos: 0,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            chunk_index: None,
+        }))
+    }
+}
+
+impl BlobCompressor {
+    pub fn write(&mut self, mut bytes: &[u8], out: &mut impl Write) -> std::io::Result<()> {
+        while !bytes.is_empty() {
+            if let CompressorState::Uncompressed = self.state {
+                return out.write_all(bytes);
+            } else if self.chunk.len() == CHUNK_SIZE {
+                self.write_chunk(out)?;
+            } else {
+                let bytes_read = std::cmp::min(CHUNK_SIZE - self.chunk.len(), bytes.len());
+                self.chunk.extend_from_slice(&bytes[..bytes_read]);
+                bytes = &bytes[bytes_read..];
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        if !self.chunk.is_empty() || matches!(self.state, CompressorState::Pending) {
+            self.write_chunk(out)?;
+        }
+        if let CompressorState::Compressed { index, size } = &self.state {
+            let mut footer = Vec::with_capacity(
+                index.len() * std::mem::size_of::<u32>() + FOOTER_SIZE - CHECKSUM_SIZE,
+            );
+            for chunk_size in index {
+                footer.extend_from_slice(&chunk_size.to_le_bytes());
+            }
+            footer.extend_from_slice(&(index.len() as u32).to_le_bytes());
+            footer.extend_from_slice(&size.to_le_bytes());
+            out.write_all(&footer)?;
+            out.write_all(&checksum(&[MAGIC, &[self.codec], &footer]))?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+        // The first chunk decides whether the blob is compressed
+        if let CompressorState::Pending = self.state {
+            if self.chunk.starts_with(MAGIC)
+                || (self.chunk.len() >= MIN_COMPRESS_SIZE && is_compressible(&self.chunk))
+            {
+                out.write_all(MAGIC)?;
+                out.write_all(&[self.codec])?;
+                self.state = CompressorState::Compressed {
+                    index: Vec::new(),
+                    size: 0,
+                };
+            } else {
+                self.state = CompressorState::Uncompressed;
+            }
+        }
+
+        if let CompressorState::Compressed { index, size } = &mut self.state {
+            let compressed = if self.codec == CODEC_ZSTD {
+                zstd::bulk::compress(&self.chunk, ZSTD_LEVEL)?
+            } else {
+                lz4_flex::block::compress(&self.chunk)
+            };
+            if compressed.len() < self.chunk.len() {
+                out.write_all(&compressed)?;
+                index.push(compressed.len() as u32);
+            } else {
+                out.write_all(&self.chunk)?;
+                index.push(self.chunk.len() as u32 | RAW_CHUNK);
+            }
+            *size += self.chunk.len() as u64;
+        } else {
+            out.write_all(&self.chunk)?;
+        }
+        self.chunk.clear();
+
+        Ok(())
+    }
+}
+
+// Reads and validates the header, chunk index and footer of a compressed blob,
+// returns None if the blob was not written by BlobCompressor.
+fn read_header<R: BlobReader>(
+    inner: &mut R,
+    compressed_size: u64,
+) -> std::io::Result<Option<([u8; HEADER_SIZE], Vec<u8>, u64)>> {
+    let mut header = [0u8; HEADER_SIZE];
+    if compressed_size < (HEADER_SIZE + FOOTER_SIZE) as u64 {
+        return Ok(None);
+    }
+    inner.read_exact(&mut header)?;
+    if !header.starts_with(MAGIC) {
+        return Ok(None);
+    }
+
+    // Read footer and chunk index
+    let mut footer = [0u8; FOOTER_SIZE];
+    inner.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    inner.read_exact(&mut footer)?;
+    let num_chunks = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+    let size = u64::from_le_bytes(footer[4..12].try_into().unwrap());
+    let index_size = num_chunks * std::mem::size_of::<u32>() as u64;
+    if compressed_size < (HEADER_SIZE + FOOTER_SIZE) as u64 + index_size {
+        return Ok(None);
+    }
+    let mut index = vec![0u8; index_size as usize];
+    inner.seek(SeekFrom::End(-((FOOTER_SIZE as u64 + index_size) as i64)))?;
+    inner.read_exact(&mut index)?;
+
+    Ok(
+        if checksum(&[&header, &index, &footer[..FOOTER_SIZE - CHECKSUM_SIZE]])
+            == footer[FOOTER_SIZE - CHECKSUM_SIZE..]
+        {
+            Some((header, index, size))
+        } else {
+            None
+        },
+    )
+}
+
+fn checksum(parts: &[&[u8]]) -> [u8; CHECKSUM_SIZE] {
+    let mut hasher = blake3::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut checksum = [0u8; CHECKSUM_SIZE];
+    checksum.copy_from_slice(&hasher.finalize().as_bytes()[..CHECKSUM_SIZE]);
+    checksum
+}
+
+// Skip formats that are already compressed
+fn is_compressible(bytes: &[u8]) -> bool {
+    const COMPRESSED_FORMATS: &[&[u8]] = &[
+        b"PK\x03\x04",         // ZIP, OOXML, ODF, JAR
+        b"\x1f\x8b",           // GZIP
+        b"BZh",                // BZIP2
+        b"\xfd7zXZ\x00",       // XZ
+        b"\x28\xb5\x2f\xfd",   // ZSTD
+        b"7z\xbc\xaf\x27\x1c", // 7-Zip
+        b"Rar!\x1a\x07",       // RAR
+        b"\xff\xd8\xff",       // JPEG
+        b"\x89PNG\r\n\x1a\n",  // PNG
+        b"GIF8",               // GIF
+        b"\x1aE\xdf\xa3",      // Matroska, WebM
+        b"ID3",                // MP3
+        b"OggS",               // Ogg
+        b"fLaC",               // FLAC
+    ];
+
+    !COMPRESSED_FORMATS
+        .iter()
+        .any(|magic| bytes.starts_with(magic))
+        && !(bytes.len() >= 12
+            && (bytes[4..8] == *b"ftyp" // MP4, MOV, HEIC
+                || (bytes.starts_with(b"RIFF") && bytes[8..12] == *b"WEBP")))
+}
+
+impl<R: BlobReader> BlobDecompressor<R> {
+    fn load_chunk(&mut self, chunk_index: usize) -> std::io::Result<()> {
+        let (offset, chunk_size) = self.chunks[chunk_index];
+        let uncompressed_size = std::cmp::min(
+            CHUNK_SIZE as u64,
+            self.size
+                .checked_sub(chunk_index as u64 * CHUNK_SIZE as u64)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Compressed blob has an invalid index",
+                    )
+                })?,
+        ) as usize;
+
+        self.chunk_index = None;
+        self.chunk.resize((chunk_size & !RAW_CHUNK) as usize, 0);
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut self.chunk)?;
+        if chunk_size & RAW_CHUNK == 0 {
+            self.chunk = if self.codec == CODEC_ZSTD {
+                zstd::bulk::decompress(&self.chunk, uncompressed_size)?
+            } else {
+                lz4_flex::block::decompress(&self.chunk, uncompressed_size)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?
+            };
+        }
+        if self.chunk.len() != uncompressed_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Compressed blob chunk has an invalid size",
+            ));
+        }
+        self.chunk_index = Some(chunk_index);
+        Ok(())
+    }
+}
+
+impl<R: BlobReader> Read for BlobDecompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_index = (self.pos / CHUNK_SIZE as u64) as usize;
+        if self.chunk_index != Some(chunk_index) {
+            self.load_chunk(chunk_index)?;
+        }
+        let offset = (self.pos % CHUNK_SIZE as u64) as usize;
+        let bytes_read = std::cmp::min(buf.len(), self.chunk.len().saturating_sub(offset));
+        buf[..bytes_read].copy_from_slice(&self.chunk[offset..offset + bytes_read]);
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: BlobReader> Seek for BlobDecompressor<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl<R: BlobReader> BlobReader for BlobDecompressor<R> {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use super::{BlobCompression, CHUNK_SIZE, MAGIC, MIN_COMPRESS_SIZE};
+
+    fn compress(blob: &[u8], write_size: usize) -> Vec<u8> {
+        compress_with(BlobCompression::Lz4, blob, write_size)
+    }
+
+    fn compress_with(compression: BlobCompression, blob: &[u8], write_size: usize) -> Vec<u8> {
+        let mut compressor = compression.compressor().unwrap();
+        let mut compressed = Vec::new();
+        for chunk in blob.chunks(write_size) {
+            compressor.write(chunk, &mut compressed).unwrap();
+        }
+        compressor.finish(&mut compressed).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn compress_decompress_blobs() {
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. "
+            .repeat(CHUNK_SIZE / 10)
+            .into_bytes();
+        let random = (0..3 * CHUNK_SIZE as u64)
+            .map(|n| (n.wrapping_mul(0x9e3779b97f4a7c15) >> 56) as u8)
+            .collect::<Vec<_>>();
+        assert!(compress(&text, CHUNK_SIZE).len() < text.len() / 4);
+        let zip = [b"PK\x03\x04".as_ref(), &text].concat();
+
+        for ((blob, expect_compressed), compression) in [
+            (&b""[..], false),
+            (&text[..100], false),
+            (&text[..MIN_COMPRESS_SIZE], true),
+            (&text[..CHUNK_SIZE], true),
+            (&text[..CHUNK_SIZE + 1], true),
+            (&text[..], true),
+            (&random[..], true),
+            (&zip[..], false),
+            (&MAGIC[..], true),
+        ]
+        .into_iter()
+        .flat_map(|test| [(test, BlobCompression::Lz4), (test, BlobCompression::Zstd)])
+        {
+            let compressed = compress_with(compression, blob, 1000);
+            if expect_compressed {
+                assert!(compressed.starts_with(MAGIC));
+            } else {
+                assert_eq!(compressed, blob);
+            }
+
+            let mut decompressor =
+                match BlobCompression::decompressor(Cursor::new(compressed)).unwrap() {
+                    Ok(decompressor) => decompressor,
+                    Err(reader) => {
+                        assert!(!expect_compressed);
+                        assert_eq!(reader.into_inner(), blob);
+                        continue;
+                    }
+                };
+            let mut decompressed = Vec::new();
+            decompressor.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(decompressed, blob);
+
+            // Ranges crossing chunk boundaries
+            if blob.len() > CHUNK_SIZE + 10 {
+                let start = CHUNK_SIZE as u64 - 10;
+                decompressor.seek(SeekFrom::Start(start)).unwrap();
+                let mut buf = vec![0u8; 20];
+                decompressor.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, &blob[start as usize..start as usize + 20]);
+            }
+        }
+
+        // Uncompressed blobs starting with the magic number are read as is
+        let mut legacy = compress(&text, 1000);
+        *legacy.last_mut().unwrap() ^= 1;
+        for blob in [
+            [&MAGIC[..], &[1, 0, 0, 0, 0, 0]].concat(),
+            [&MAGIC[..], &random[..]].concat(),
+            [&b"SJZ\x01"[..], &text[..]].concat(),
+            legacy,
+        ] {
+            assert_eq!(
+                BlobCompression::decompressor(Cursor::new(blob.clone()))
+                    .unwrap()
+                    .err()
+                    .unwrap()
+                    .into_inner(),
+                blob
+            );
+        }
+    }
+}
@@ -0,0 +1,446 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use aes_gcm::{
+    aead::{generic_array::GenericArray, AeadInPlace},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::{config::env_settings::EnvSettings, core::error::StoreError};
+
+use super::{BlobId, BlobReader};
+
+/*
+
+ Encrypted blobs start with a header containing a magic number and a random
+ salt used to derive the blob key from the master key. The blob contents are
+ then split into chunks that are individually sealed with AES-256-GCM, which
+ allows decrypting arbitrary ranges without reading the whole blob.
+
+ The nonce of each chunk is its index, with a flag set on the last chunk so
+ truncated blobs fail authentication. The last chunk is also authenticated
+ with the blob hash, which is only known once all chunks have been written, so
+ encrypted blobs can't be swapped with each other in the blob store. It is
+ verified as soon as the blob is opened.
+
+ Blobs without the header are rejected unless 'blob-encryption-allow-plaintext'
+ is set, which is only meant to be enabled while blobs written before
+ encryption was enabled are still in use.
+
+*/
+
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const SALT_SIZE: usize = 16;
+const MAGIC: &[u8; 4] = b"SJB\x01";
+pub const HEADER_SIZE: usize = MAGIC.len() + SALT_SIZE;
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+
+pub struct BlobCipher {
+    master_key: [u8; 32],
+    allow_plaintext: bool,
+}
+
+pub struct BlobEncryptor {
+    aes: Aes256Gcm,
+    header: Option<[u8; HEADER_SIZE]>,
+    chunk: Vec<u8>,
+    chunk_index: u64,
+}
+
+pub struct BlobDecryptor<R: BlobReader> {
+    inner: R,
+    aes: Aes256Gcm,
+    blob_hash: Vec<u8>,
+    size: u64,
+    total_chunks: u64,
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_index: Option<u64>,
+}
+
+impl BlobCipher {
+    pub fn new(settings: &EnvSettings) -> Option<Self> {
+        let key = settings.get("blob-encryption-key")?;
+        let mut master_key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, key.as_bytes())
+            .expand(b"stalwart-jmap blob master key", &mut master_key)
+            .ok()?;
+        Some(BlobCipher {
+            master_key,
+            allow_plaintext: settings
+                .parse("blob-encryption-allow-plaintext")
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn encryptor(&self) -> BlobEncryptor {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        rand::thread_rng().fill_bytes(&mut header[MAGIC.len()..]);
+
+        BlobEncryptor {
+            aes: self.blob_key(&header[MAGIC.len()..]),
+            header: header.into(),
+            chunk: Vec::with_capacity(CHUNK_SIZE + TAG_SIZE),
+            chunk_index: 0,
+        }
+    }
+
+    // Returns the reader back if the blob was stored before encryption was enabled
+    // and plaintext blobs are allowed.
+    pub fn decryptor<R: BlobReader>(
+        &self,
+        mut inner: R,
+        blob_id: &BlobId,
+    ) -> crate::Result<Result<BlobDecryptor<R>, R>> {
+        let encrypted_size = inner.size();
+        let mut header = [0u8; HEADER_SIZE];
+        if encrypted_size >= (HEADER_SIZE + TAG_SIZE) as u64 {
+            inner.read_exact(&mut header)?;
+            inner.seek(SeekFrom::Start(0))?;
+        }
+        if !header.starts_with(MAGIC) {
+            return if self.allow_plaintext {
+                Ok(Err(inner))
+            } else {
+                Err(StoreError::DataCorruption(
+                    "Blob is not encrypted.".to_string(),
+                ))
+            };
+        }
+        inner.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+
+        // The last chunk is the only one allowed to be shorter
+        let body_size = encrypted_size - HEADER_SIZE as u64;
+        let full_chunks = body_size / ENCRYPTED_CHUNK_SIZE as u64;
+        let last_chunk = body_size % ENCRYPTED_CHUNK_SIZE as u64;
+        if last_chunk != 0 && last_chunk < TAG_SIZE as u64 {
+            return Err(StoreError::DataCorruption(
+                "Encrypted blob has an invalid size.".to_string(),
+            ));
+        }
+
+        let mut decryptor = BlobDecryptor {
+            aes: self.blob_key(&header[MAGIC.len()..]),
+            blob_hash: blob_id.hash().to_vec(),
+            size: full_chunks * CHUNK_SIZE as u64 + last_chunk.saturating_sub(TAG_SIZE as u64),
+            total_chunks: full_chunks + u64::from(last_chunk != 0),
+            inner,
+            pos: 0,
+            chunk: Vec::with_capacity(ENCRYPTED_CHUNK_SIZE),
+            chunk_index: None,
+        };
+
+        // Make sure the blob belongs to this blob id
+        if decryptor.total_chunks > 0 {
+            decryptor.load_chunk(decryptor.total_chunks - 1)?;
+        }
+
+        Ok(Ok(decryptor))
+    }
+
+    fn blob_key(&self, salt: &[u8]) -> Aes256Gcm {
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(salt), &self.master_key)
+            .expand(b"stalwart-jmap blob key", &mut key)
+            .unwrap();
+        Aes256Gcm::new(&GenericArray::clone_from_slice(&key))
+    }
+}
+
+impl BlobEncryptor {
+    // Encrypts the buffered chunk once more data is received, as the
+    // last chunk can only be sealed when the blob is complete.
+    pub fn write(&mut self, mut bytes: &[u8], out: &mut impl Write) -> std::io::Result<()> {
+        if let Some(header) = self.header.take() {
+            out.write_all(&header)?;
+        }
+        while !bytes.is_empty() {
+            if self.chunk.len() == CHUNK_SIZE {
+                self.seal_chunk(None, out)?;
+            }
+            let bytes_read = std::cmp::min(CHUNK_SIZE - self.chunk.len(), bytes.len());
+            self.chunk.extend_from_slice(&bytes[..bytes_read]);
+            bytes = &bytes[bytes_read..];
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self, blob_id: &BlobId, out: &mut impl Write) -> std::io::Result<()> {
+        if let Some(header) = self.header.take() {
+            out.write_all(&header)?;
+        }
+        self.seal_chunk(blob_id.hash().into(), out)
+    }
+
+    // Only the last chunk is sealed with the blob hash
+    fn seal_chunk(
+        &mut self,
+        blob_hash: Option<&[u8]>,
+        out: &mut impl Write,
+    ) -> std::io::Result<()> {
+        self.aes
+            .encrypt_in_place(
+                Nonce::from_slice(&chunk_nonce(self.chunk_index, blob_hash.is_some())),
+                blob_hash.unwrap_or_default(),
+                &mut self.chunk,
+            )
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "Encryption failed"))?;
+        out.write_all(&self.chunk)?;
+        self.chunk.clear();
+        self.chunk_index += 1;
+        Ok(())
+    }
+}
+
+impl<R: BlobReader> BlobDecryptor<R> {
+    fn load_chunk(&mut self, chunk_index: u64) -> std::io::Result<()> {
+        let offset = HEADER_SIZE as u64 + chunk_index * ENCRYPTED_CHUNK_SIZE as u64;
+        let chunk_size = std::cmp::min(
+            ENCRYPTED_CHUNK_SIZE as u64,
+            self.inner.size().saturating_sub(offset),
+        ) as usize;
+
+        let is_last = chunk_index + 1 == self.total_chunks;
+
+        self.chunk_index = None;
+        self.chunk.resize(chunk_size, 0);
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut self.chunk)?;
+        self.aes
+            .decrypt_in_place(
+                Nonce::from_slice(&chunk_nonce(chunk_index, is_last)),
+                if is_last { &self.blob_hash[..] } else { &[] },
+                &mut self.chunk,
+            )
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Failed to decrypt blob chunk",
+                )
+            })?;
+        self.chunk_index = Some(chunk_index);
+        Ok(())
+    }
+}
+
+impl<R: BlobReader> Read for BlobDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_index = self.pos / CHUNK_SIZE as u64;
+        if self.chunk_index != Some(chunk_index) {
+            self.load_chunk(chunk_index)?;
+        }
+        let offset = (self.pos % CHUNK_SIZE as u64) as usize;
+        let bytes_read = std::cmp::min(buf.len(), self.chunk.len().saturating_sub(offset));
+        buf[..bytes_read].copy_from_slice(&self.chunk[offset..offset + bytes_read]);
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl<R: BlobReader> Seek for BlobDecryptor<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.size as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+        let pos = pos as u64;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+impl<R: BlobReader> BlobReader for BlobDecryptor<R> {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+fn chunk_nonce(chunk_index: u64, is_last: bool) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[..8].copy_from_slice(&chunk_index.to_be_bytes());
+    nonce[8] = u8::from(is_last);
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use crate::config::env_settings::EnvSettings;
+
+    use super::{BlobCipher, BlobId, BlobReader, CHUNK_SIZE, HEADER_SIZE};
+
+    impl BlobReader for Cursor<Vec<u8>> {
+        fn size(&self) -> u64 {
+            self.get_ref().len() as u64
+        }
+    }
+
+    fn cipher() -> BlobCipher {
+        BlobCipher::new(&EnvSettings {
+            args: [("blob-encryption-key".to_string(), "secret".to_string())]
+                .into_iter()
+                .collect(),
+        })
+        .unwrap()
+    }
+
+    fn migration_cipher() -> BlobCipher {
+        BlobCipher::new(&EnvSettings {
+            args: [
+                ("blob-encryption-key".to_string(), "secret".to_string()),
+                (
+                    "blob-encryption-allow-plaintext".to_string(),
+                    "true".to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        })
+        .unwrap()
+    }
+
+    fn encrypt(cipher: &BlobCipher, blob: &[u8], write_size: usize) -> Vec<u8> {
+        let mut encryptor = cipher.encryptor();
+        let mut encrypted = Vec::new();
+        for chunk in blob.chunks(write_size) {
+            encryptor.write(chunk, &mut encrypted).unwrap();
+        }
+        encryptor
+            .finish(&BlobId::new_external(blob), &mut encrypted)
+            .unwrap();
+        encrypted
+    }
+
+    // Corrupted blobs fail either when opened or when read
+    fn fails_decryption(cipher: &BlobCipher, encrypted: Vec<u8>, blob_id: &BlobId) -> bool {
+        match cipher.decryptor(Cursor::new(encrypted), blob_id) {
+            Ok(Ok(mut decryptor)) => decryptor.read_to_end(&mut Vec::new()).is_err(),
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn encrypt_decrypt_blobs() {
+        let cipher = cipher();
+
+        for size in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+            3 * CHUNK_SIZE + 100,
+        ] {
+            let blob = (0..size).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+            let blob_id = BlobId::new_external(&blob);
+            let encrypted = encrypt(&cipher, &blob, 1000);
+            assert_ne!(&encrypted[HEADER_SIZE..], &blob[..]);
+
+            // Full read
+            let mut decryptor = cipher
+                .decryptor(Cursor::new(encrypted.clone()), &blob_id)
+                .unwrap()
+                .ok()
+                .unwrap();
+            assert_eq!(decryptor.size(), size as u64);
+            let mut decrypted = Vec::new();
+            decryptor.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, blob, "size {}", size);
+
+            // Ranges crossing chunk boundaries
+            if size > CHUNK_SIZE {
+                let start = CHUNK_SIZE as u64 - 10;
+                decryptor.seek(SeekFrom::Start(start)).unwrap();
+                let mut buf = vec![0u8; 20];
+                decryptor.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, &blob[start as usize..start as usize + 20]);
+            }
+
+            // Each blob uses a different key
+            assert_ne!(encrypt(&cipher, &blob, 1000), encrypted);
+
+            // Tampered, truncated and swapped blobs are rejected
+            if size > 0 {
+                let mut tampered = encrypted.clone();
+                *tampered.last_mut().unwrap() ^= 1;
+                assert!(fails_decryption(&cipher, tampered, &blob_id));
+            }
+            if size > CHUNK_SIZE {
+                let mut truncated = encrypted.clone();
+                truncated.truncate(HEADER_SIZE + CHUNK_SIZE + 16);
+                assert!(fails_decryption(&cipher, truncated, &blob_id));
+            }
+            assert!(fails_decryption(
+                &cipher,
+                encrypted.clone(),
+                &BlobId::new_external(b"other blob")
+            ));
+        }
+
+        // Plaintext blobs are rejected unless explicitly allowed during a migration
+        for size in [0, 10, 100] {
+            let blob = vec![b'a'; size];
+            let blob_id = BlobId::new_external(&blob);
+            assert!(cipher
+                .decryptor(Cursor::new(blob.clone()), &blob_id)
+                .is_err());
+            let mut reader = migration_cipher()
+                .decryptor(Cursor::new(blob.clone()), &blob_id)
+                .unwrap()
+                .err()
+                .unwrap();
+            let mut plaintext = Vec::new();
+            reader.read_to_end(&mut plaintext).unwrap();
+            assert_eq!(plaintext, blob);
+        }
+
+        // Encrypted blobs are still decrypted during a migration
+        let encrypted = encrypt(&cipher, b"hello", 1000);
+        let mut decrypted = Vec::new();
+        migration_cipher()
+            .decryptor(Cursor::new(encrypted), &BlobId::new_external(b"hello"))
+            .unwrap()
+            .ok()
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, b"hello");
+    }
+}
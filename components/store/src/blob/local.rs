@@ -23,83 +23,94 @@
 
 use std::{
     fs::{self, File},
-    io::{Read, Seek, SeekFrom, Write},
-    ops::Range,
-    path::PathBuf,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use crate::{config::env_settings::EnvSettings, write::mutex_map::MutexMap};
+use crate::config::env_settings::EnvSettings;
 
-use super::{BlobId, BlobStore};
+use super::{
+    compress::{BlobCompression, BlobCompressor},
+    crypto::{BlobCipher, BlobEncryptor},
+    BlobHasher, BlobId, BlobReader, BlobStore, BlobWriter,
+};
 
 pub struct LocalBlobStore {
-    pub lock: MutexMap<()>,
     pub base_path: PathBuf,
+    pub temp_dir: BlobTempDir,
     pub hash_levels: usize,
 }
 
+pub struct BlobTempDir {
+    pub path: PathBuf,
+    pub next_id: AtomicU64,
+    pub cipher: Option<BlobCipher>,
+    pub compression: BlobCompression,
+}
+
+pub struct LocalBlobReader {
+    file: File,
+    size: u64,
+}
+
+pub struct LocalBlobWriter {
+    file: Option<BlobFile>,
+    path: PathBuf,
+    hasher: BlobHasher,
+    compressor: Option<BlobCompressor>,
+}
+
+// Blobs are compressed before being encrypted
+struct BlobFile {
+    file: BufWriter<File>,
+    encryptor: Option<BlobEncryptor>,
+}
+
 impl BlobStore for LocalBlobStore {
+    type Reader = LocalBlobReader;
+    type Writer = LocalBlobWriter;
+
     fn new(settings: &EnvSettings) -> crate::Result<Self> {
-        let mut base_path = PathBuf::from(
-            settings
-                .get("db-path")
-                .unwrap_or_else(|| "/usr/local/stalwart-jmap/data".to_string()),
-        );
-        base_path.push("blobs");
         Ok(LocalBlobStore {
-            lock: MutexMap::with_capacity(1024),
-            base_path,
+            base_path: blob_path(settings),
+            temp_dir: BlobTempDir::new(settings)?,
             hash_levels: std::cmp::min(settings.parse("blob-nested-levels").unwrap_or(2), 5),
         })
     }
 
-    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+    fn reader(&self, blob_id: &BlobId) -> crate::Result<Option<Self::Reader>> {
         let blob_path = self.get_path(blob_id)?;
+        if !blob_path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&blob_path)?;
+        let size = file.metadata()?.len();
+        Ok(Some(LocalBlobReader { file, size }))
+    }
+
+    fn writer(&self) -> crate::Result<Self::Writer> {
+        self.temp_dir.writer()
+    }
+
+    fn commit(&self, mut writer: Self::Writer, blob_id: &BlobId) -> crate::Result<bool> {
+        let blob_path = self.get_path(blob_id)?;
+        writer.finish()?;
 
         if blob_path.exists() {
             let metadata = fs::metadata(&blob_path)?;
-            if metadata.len() as usize == blob.len() {
+            if metadata.len() == fs::metadata(&writer.path)?.len() {
                 return Ok(false);
             }
         }
 
         fs::create_dir_all(blob_path.parent().unwrap())?;
-        let mut blob_file = File::create(&blob_path)?;
-        blob_file.write_all(blob)?;
-        blob_file.flush()?;
+        fs::rename(&writer.path, &blob_path)?;
 
         Ok(true)
     }
 
-    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
-        let blob_path = self.get_path(blob_id)?;
-        if !blob_path.exists() {
-            return Ok(None);
-        }
-
-        let blob_size = fs::metadata(&blob_path)?.len();
-        let mut blob = File::open(&blob_path)?;
-        Ok(Some(if range.start != 0 || range.end != u32::MAX {
-            let from_offset = if range.start < blob_size as u32 {
-                range.start
-            } else {
-                0
-            };
-            let mut buf =
-                vec![0; (std::cmp::min(range.end, blob_size as u32) - from_offset) as usize];
-
-            if from_offset > 0 {
-                blob.seek(SeekFrom::Start(from_offset as u64))?;
-            }
-            blob.read_exact(&mut buf)?;
-            buf
-        } else {
-            let mut buf = Vec::with_capacity(blob_size as usize);
-            blob.read_to_end(&mut buf)?;
-            buf
-        }))
-    }
-
     fn delete(&self, blob_id: &BlobId) -> crate::Result<bool> {
         let blob_path = self.get_path(blob_id)?;
         if blob_path.exists() {
@@ -123,3 +134,148 @@ impl LocalBlobStore {
         Ok(path)
     }
 }
+
+impl BlobTempDir {
+    pub fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        let mut path = blob_path(settings);
+        path.push("tmp");
+
+        // Remove any leftovers from incomplete uploads
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+
+        Ok(BlobTempDir {
+            path,
+            next_id: AtomicU64::new(0),
+            cipher: BlobCipher::new(settings),
+            compression: BlobCompression::new(settings)?,
+        })
+    }
+
+    pub fn writer(&self) -> crate::Result<LocalBlobWriter> {
+        fs::create_dir_all(&self.path)?;
+        let mut path = self.path.clone();
+        path.push(format!(
+            "{:x}",
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        Ok(LocalBlobWriter {
+            file: BlobFile {
+                file: BufWriter::new(File::create(&path)?),
+                encryptor: self.cipher.as_ref().map(|cipher| cipher.encryptor()),
+            }
+            .into(),
+            path,
+            hasher: BlobHasher::default(),
+            compressor: self.compression.compressor(),
+        })
+    }
+}
+
+impl LocalBlobWriter {
+    // Flushes the blob to disk, no more writes are possible after this call.
+    pub fn finish(&mut self) -> crate::Result<&Path> {
+        if let Some(mut file) = self.file.take() {
+            if let Some(compressor) = &mut self.compressor {
+                compressor.finish(&mut file)?;
+            }
+            if let Some(encryptor) = &mut file.encryptor {
+                encryptor.finish(&self.hasher.blob_id(), &mut file.file)?;
+            }
+            file.file
+                .into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+        }
+        Ok(&self.path)
+    }
+}
+
+fn blob_path(settings: &EnvSettings) -> PathBuf {
+    let mut base_path = PathBuf::from(
+        settings
+            .get("db-path")
+            .unwrap_or_else(|| "/usr/local/stalwart-jmap/data".to_string()),
+    );
+    base_path.push("blobs");
+    base_path
+}
+
+impl Read for LocalBlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Seek for LocalBlobReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl BlobReader for LocalBlobReader {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Write for LocalBlobWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        let bytes_written = if let Some(compressor) = &mut self.compressor {
+            compressor.write(buf, file)?;
+            buf.len()
+        } else {
+            file.write(buf)?
+        };
+        self.hasher.update(&buf[..bytes_written]);
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for BlobFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.write(buf, &mut self.file)?;
+            Ok(buf.len())
+        } else {
+            self.file.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl BlobWriter for LocalBlobWriter {
+    fn size(&self) -> u64 {
+        self.hasher.size()
+    }
+
+    fn blob_id(&self) -> BlobId {
+        self.hasher.blob_id()
+    }
+}
+
+impl Drop for LocalBlobWriter {
+    fn drop(&mut self) {
+        // Discard uncommitted or duplicate blobs
+        self.file.take();
+        if self.path.exists() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
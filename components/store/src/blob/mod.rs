@@ -21,17 +21,33 @@
  * for more details.
 */
 
-use std::{convert::TryInto, fmt::Display, io::Write, ops::Range};
+use std::{
+    convert::TryInto,
+    fmt::Display,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
 
 use sha2::{Digest, Sha256};
 
 use crate::{
     config::env_settings::EnvSettings,
+    core::error::StoreError,
     serialize::{base32::Base32Writer, StoreDeserialize, StoreSerialize},
 };
 
+use self::{
+    compress::{BlobCompression, BlobDecompressor},
+    crypto::BlobDecryptor,
+    local::{BlobTempDir, LocalBlobReader, LocalBlobStore, LocalBlobWriter},
+    s3::{S3BlobReader, S3BlobStore},
+};
+
+pub mod compress;
+pub mod crypto;
 pub mod local;
 pub mod purge;
+pub mod s3;
 pub mod store;
 
 pub const BLOB_HASH_LEN: usize = 32;
@@ -118,11 +134,230 @@ impl StoreDeserialize for BlobId {
 }
 
 pub trait BlobStore: Sized {
+    type Reader: BlobReader;
+    type Writer: BlobWriter;
+
     fn new(settings: &EnvSettings) -> crate::Result<Self>;
-    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>>;
+    fn reader(&self, blob_id: &BlobId) -> crate::Result<Option<Self::Reader>>;
+    fn writer(&self) -> crate::Result<Self::Writer>;
+    fn commit(&self, writer: Self::Writer, blob_id: &BlobId) -> crate::Result<bool>;
+    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool>;
+
+    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        if let Some(reader) = self.reader(blob_id)? {
+            read_range(reader, range).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn get(&self, blob_id: &BlobId) -> crate::Result<Option<Vec<u8>>> {
         self.get_range(blob_id, 0..u32::MAX)
     }
-    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool>;
-    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool>;
+
+    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        let mut writer = self.writer()?;
+        writer.write_all(blob)?;
+        self.commit(writer, blob_id)
+    }
+
+    // Whether all nodes in a cluster have access to the same blobs
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+pub trait BlobReader: Read + Seek + Send + 'static {
+    fn size(&self) -> u64;
+}
+
+fn read_range(mut reader: impl BlobReader, range: Range<u32>) -> crate::Result<Vec<u8>> {
+    let blob_size = reader.size();
+    let start = if (range.start as u64) < blob_size {
+        range.start as u64
+    } else {
+        0
+    };
+    let end = std::cmp::min(range.end as u64, blob_size);
+    let mut buf = Vec::with_capacity(end.saturating_sub(start) as usize);
+    if start > 0 {
+        reader.seek(SeekFrom::Start(start))?;
+    }
+    reader
+        .take(end.saturating_sub(start))
+        .read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+// Blob writers hash their contents as they are written, the
+// resulting BlobId is only known once the last chunk is received.
+pub trait BlobWriter: Write + Send + 'static {
+    fn size(&self) -> u64;
+    fn blob_id(&self) -> BlobId;
+}
+
+#[derive(Default, Clone)]
+pub struct BlobHasher {
+    hasher: Sha256,
+    size: u64,
+}
+
+impl BlobHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+        self.size += bytes.len() as u64;
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn blob_id(&self) -> BlobId {
+        BlobId::External {
+            hash: self.hasher.clone().finalize().into(),
+        }
+    }
+}
+
+pub enum BlobBackend {
+    Local(LocalBlobStore),
+    S3(S3BlobStore),
+}
+
+pub type BlobBackendWriter = <BlobBackend as BlobStore>::Writer;
+
+pub enum BlobBackendReader {
+    Local(LocalBlobReader),
+    S3(S3BlobReader),
+    Encrypted(Box<BlobDecryptor<BlobBackendReader>>),
+    Compressed(Box<BlobDecompressor<BlobBackendReader>>),
+}
+
+impl BlobStore for BlobBackend {
+    type Reader = BlobBackendReader;
+    type Writer = LocalBlobWriter;
+
+    fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        match settings.get("blob-store").as_deref().unwrap_or("local") {
+            "local" => Ok(BlobBackend::Local(LocalBlobStore::new(settings)?)),
+            "s3" => Ok(BlobBackend::S3(S3BlobStore::new(settings)?)),
+            other => Err(StoreError::InvalidArguments(format!(
+                "Invalid blob-store type '{}'.",
+                other
+            ))),
+        }
+    }
+
+    fn reader(&self, blob_id: &BlobId) -> crate::Result<Option<Self::Reader>> {
+        let reader = match self {
+            BlobBackend::Local(store) => store.reader(blob_id)?.map(BlobBackendReader::Local),
+            BlobBackend::S3(store) => store.reader(blob_id)?.map(BlobBackendReader::S3),
+        };
+
+        let reader = match (reader, &self.temp_dir().cipher) {
+            (Some(reader), Some(cipher)) => match cipher.decryptor(reader, blob_id)? {
+                Ok(decryptor) => BlobBackendReader::Encrypted(Box::new(decryptor)),
+                Err(reader) => reader,
+            },
+            (Some(reader), None) => reader,
+            (None, _) => return Ok(None),
+        };
+
+        // Blobs stay readable after disabling compression
+        Ok(Some(match BlobCompression::decompressor(reader)? {
+            Ok(decompressor) => BlobBackendReader::Compressed(Box::new(decompressor)),
+            Err(reader) => reader,
+        }))
+    }
+
+    fn writer(&self) -> crate::Result<Self::Writer> {
+        match self {
+            BlobBackend::Local(store) => store.writer(),
+            BlobBackend::S3(store) => store.writer(),
+        }
+    }
+
+    fn commit(&self, writer: Self::Writer, blob_id: &BlobId) -> crate::Result<bool> {
+        match self {
+            BlobBackend::Local(store) => store.commit(writer, blob_id),
+            BlobBackend::S3(store) => store.commit(writer, blob_id),
+        }
+    }
+
+    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool> {
+        match self {
+            BlobBackend::Local(store) => store.delete(blob_id),
+            BlobBackend::S3(store) => store.delete(blob_id),
+        }
+    }
+
+    fn get_range(&self, blob_id: &BlobId, range: Range<u32>) -> crate::Result<Option<Vec<u8>>> {
+        // Encrypted or compressed blobs have to go through the reader
+        self.reader(blob_id)?
+            .map(|reader| read_range(reader, range))
+            .transpose()
+    }
+
+    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        let temp_dir = self.temp_dir();
+        if temp_dir.cipher.is_some() || temp_dir.compression != BlobCompression::None {
+            let mut writer = self.writer()?;
+            writer.write_all(blob)?;
+            return self.commit(writer, blob_id);
+        }
+
+        match self {
+            BlobBackend::Local(store) => store.put(blob_id, blob),
+            BlobBackend::S3(store) => store.put(blob_id, blob),
+        }
+    }
+
+    fn is_shared(&self) -> bool {
+        match self {
+            BlobBackend::Local(store) => store.is_shared(),
+            BlobBackend::S3(store) => store.is_shared(),
+        }
+    }
+}
+
+impl BlobBackend {
+    pub fn temp_dir(&self) -> &BlobTempDir {
+        match self {
+            BlobBackend::Local(store) => &store.temp_dir,
+            BlobBackend::S3(store) => &store.temp_dir,
+        }
+    }
+}
+
+impl Read for BlobBackendReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            BlobBackendReader::Local(reader) => reader.read(buf),
+            BlobBackendReader::S3(reader) => reader.read(buf),
+            BlobBackendReader::Encrypted(reader) => reader.read(buf),
+            BlobBackendReader::Compressed(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for BlobBackendReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            BlobBackendReader::Local(reader) => reader.seek(pos),
+            BlobBackendReader::S3(reader) => reader.seek(pos),
+            BlobBackendReader::Encrypted(reader) => reader.seek(pos),
+            BlobBackendReader::Compressed(reader) => reader.seek(pos),
+        }
+    }
+}
+
+impl BlobReader for BlobBackendReader {
+    fn size(&self) -> u64 {
+        match self {
+            BlobBackendReader::Local(reader) => reader.size(),
+            BlobBackendReader::S3(reader) => reader.size(),
+            BlobBackendReader::Encrypted(reader) => reader.size(),
+            BlobBackendReader::Compressed(reader) => reader.size(),
+        }
+    }
 }
@@ -23,12 +23,14 @@
 
 use std::time::SystemTime;
 
+use ahash::AHashSet;
 use tracing::error;
 
+use crate::serialize::key::ValueKey;
 use crate::serialize::leb128::Leb128Reader;
 use crate::serialize::StoreDeserialize;
 use crate::WriteOperation;
-use crate::{ColumnFamily, Direction, JMAPStore, Store, StoreError};
+use crate::{AccountId, ColumnFamily, Direction, JMAPStore, Store, StoreError};
 
 use super::{BlobId, BlobStore, BLOB_EXTERNAL, BLOB_HASH_LEN};
 
@@ -36,8 +38,13 @@ impl<T> JMAPStore<T>
 where
     T: for<'x> Store<'x> + 'static,
 {
-    pub fn purge_blobs(&self) -> crate::Result<()> {
+    // Objects in a shared blob store are only removed when 'delete_shared' is
+    // set, so followers do not delete blobs the leader still references. For
+    // the same reason, released upload quotas are only logged by the leader.
+    pub fn purge_blobs(&self, delete_shared: bool) -> crate::Result<()> {
+        let delete_external = delete_shared || !self.blob_store.is_shared();
         let mut batch = Vec::with_capacity(16);
+        let mut quota_changes = AHashSet::default();
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .map_err(|_| StoreError::InternalError("Failed to get current timestamp".into()))?
@@ -56,23 +63,28 @@ where
             }
 
             if key[..BLOB_HASH_LEN + 1] != blob_id {
-                batch = self.delete_blobs(batch, &blob_id, blob_link_count)?;
+                batch = self.delete_blobs(batch, &blob_id, blob_link_count, delete_external)?;
                 blob_link_count = 0;
                 blob_id.copy_from_slice(&key[..BLOB_HASH_LEN + 1]);
                 drop(_blob_lock);
-                _blob_lock = self.blob_store.lock.lock_hash(&blob_id).into();
+                _blob_lock = self.blob_lock.lock_hash(&blob_id).into();
             }
 
             // Blob link
             if key.len() > BLOB_HASH_LEN + 1 {
-                if let Some(bytes_read) = (&key[BLOB_HASH_LEN + 1..]).skip_leb128() {
+                if let Some((account_id, bytes_read)) =
+                    (&key[BLOB_HASH_LEN + 1..]).read_leb128::<AccountId>()
+                {
                     if key.len() == BLOB_HASH_LEN + 1 + bytes_read {
-                        let timestamp = u64::deserialize(&value).ok_or_else(|| {
-                            StoreError::InternalError(format!(
-                                "Failed to deserialize timestamp from key {:?}",
-                                key
-                            ))
-                        })?;
+                        let timestamp = value
+                            .get(..std::mem::size_of::<u64>())
+                            .and_then(u64::deserialize)
+                            .ok_or_else(|| {
+                                StoreError::InternalError(format!(
+                                    "Failed to deserialize timestamp from key {:?}",
+                                    key
+                                ))
+                            })?;
 
                         if (now >= timestamp && now - timestamp > self.config.blob_temp_ttl)
                             || (now < timestamp && timestamp - now > self.config.blob_temp_ttl)
@@ -82,6 +94,19 @@ where
                                 cf: ColumnFamily::Blobs,
                                 key: key.to_vec(),
                             });
+
+                            // Release the quota charged on upload
+                            if let Some(size) = value
+                                .get(std::mem::size_of::<u64>()..)
+                                .and_then(u64::deserialize)
+                            {
+                                batch.push(WriteOperation::merge(
+                                    ColumnFamily::Values,
+                                    ValueKey::serialize_quota(account_id),
+                                    (-(size as i64)).to_le_bytes().to_vec(),
+                                ));
+                                quota_changes.insert(account_id);
+                            }
                         } else {
                             blob_link_count += 1;
                         }
@@ -92,8 +117,18 @@ where
             }
         }
 
-        self.delete_blobs(batch, &blob_id, blob_link_count)
-            .map(|_| ())
+        let mut batch = self.delete_blobs(batch, &blob_id, blob_link_count, delete_external)?;
+        drop(_blob_lock);
+
+        // Log quota changes
+        if delete_shared && !quota_changes.is_empty() {
+            for account_id in quota_changes {
+                self.prepare_quota_change(&mut batch, account_id)?;
+            }
+            self.db.write(batch)?;
+        }
+
+        Ok(())
     }
 
     fn delete_blobs(
@@ -101,6 +136,7 @@ where
         mut batch: Vec<WriteOperation>,
         blob_id: &[u8],
         blob_link_count: u32,
+        delete_external: bool,
     ) -> crate::Result<Vec<WriteOperation>> {
         if blob_link_count == 0 {
             // Delete blob
@@ -110,7 +146,7 @@ where
             });
 
             // Delete external blob
-            if blob_id[0] == BLOB_EXTERNAL {
+            if blob_id[0] == BLOB_EXTERNAL && delete_external {
                 let blob_id = BlobId::deserialize(blob_id).unwrap();
 
                 if let Err(err) = self.blob_store.delete(&blob_id) {
@@ -0,0 +1,346 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+use s3::{bucket::Bucket, creds::Credentials, error::S3Error, region::Region};
+
+use crate::{config::env_settings::EnvSettings, core::error::StoreError};
+
+use super::{
+    local::{BlobTempDir, LocalBlobWriter},
+    BlobId, BlobReader, BlobStore,
+};
+
+// S3 does not accept multipart chunks smaller than 5 MiB
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+const READ_BUFFER_SIZE: u64 = 1024 * 1024;
+// Enough to detect encrypted and compressed blobs from the first request
+const HEADER_READ_SIZE: u64 = 4096;
+const CONTENT_TYPE: &str = "application/octet-stream";
+
+pub struct S3BlobStore {
+    pub bucket: Arc<Bucket>,
+    pub prefix: String,
+    pub part_size: u64,
+    pub temp_dir: BlobTempDir,
+}
+
+pub struct S3BlobReader {
+    bucket: Arc<Bucket>,
+    key: String,
+    size: u64,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+}
+
+impl BlobStore for S3BlobStore {
+    type Reader = S3BlobReader;
+    type Writer = LocalBlobWriter;
+
+    fn new(settings: &EnvSettings) -> crate::Result<Self> {
+        let region = settings
+            .get("blob-s3-region")
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let region = if let Some(endpoint) = settings.get("blob-s3-endpoint") {
+            Region::Custom { region, endpoint }
+        } else {
+            region.parse::<Region>().map_err(|err| {
+                StoreError::InvalidArguments(format!("Invalid S3 region: {}", err))
+            })?
+        };
+        let credentials = Credentials::new(
+            settings.get("blob-s3-access-key").as_deref(),
+            settings.get("blob-s3-secret-key").as_deref(),
+            None,
+            None,
+            None,
+        )
+        .map_err(|err| StoreError::InvalidArguments(format!("Invalid S3 credentials: {}", err)))?;
+        let bucket = Bucket::new(
+            &settings.get("blob-s3-bucket").ok_or_else(|| {
+                StoreError::InvalidArguments("Missing 'blob-s3-bucket' setting.".to_string())
+            })?,
+            region,
+            credentials,
+        )?;
+
+        Ok(S3BlobStore {
+            bucket: Arc::new(if settings.parse("blob-s3-path-style").unwrap_or(false) {
+                bucket.with_path_style()
+            } else {
+                bucket
+            }),
+            prefix: settings.get("blob-s3-prefix").unwrap_or_default(),
+            part_size: std::cmp::max(
+                settings
+                    .parse("blob-s3-part-size")
+                    .unwrap_or(8 * 1024 * 1024),
+                MIN_PART_SIZE,
+            ),
+            temp_dir: BlobTempDir::new(settings)?,
+        })
+    }
+
+    fn reader(&self, blob_id: &BlobId) -> crate::Result<Option<Self::Reader>> {
+        // Fetch the first bytes along with the object size in a single request
+        let key = self.get_key(blob_id);
+        let response = self
+            .bucket
+            .get_object_range(&key, 0, Some(HEADER_READ_SIZE - 1))?;
+        let (size, buf) = match response.status_code() {
+            200 => (response.bytes().len() as u64, response.bytes().to_vec()),
+            206 => {
+                let size = if let Some(size) = response
+                    .headers()
+                    .get("content-range")
+                    .and_then(|range| range.rsplit_once('/'))
+                    .and_then(|(_, size)| size.parse::<u64>().ok())
+                {
+                    size
+                } else if let Some(size) = self.get_size(&key)? {
+                    size
+                } else {
+                    return Ok(None);
+                };
+                (size, response.bytes().to_vec())
+            }
+            404 => return Ok(None),
+            // Empty objects can't satisfy any range
+            416 => (0, Vec::new()),
+            code => {
+                return Err(StoreError::InternalError(format!(
+                    "Failed to fetch S3 object {}: status {}",
+                    key, code
+                )))
+            }
+        };
+
+        Ok(Some(S3BlobReader {
+            bucket: self.bucket.clone(),
+            key,
+            size,
+            pos: 0,
+            buf,
+            buf_start: 0,
+        }))
+    }
+
+    fn writer(&self) -> crate::Result<Self::Writer> {
+        self.temp_dir.writer()
+    }
+
+    fn commit(&self, mut writer: Self::Writer, blob_id: &BlobId) -> crate::Result<bool> {
+        let key = self.get_key(blob_id);
+        let mut file = File::open(writer.finish()?)?;
+        let size = file.metadata()?.len();
+
+        if self.get_size(&key)? == Some(size) {
+            return Ok(false);
+        }
+
+        if size <= self.part_size {
+            let mut blob = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut blob)?;
+            self.put_object(&key, &blob)?;
+            return Ok(true);
+        }
+
+        // Large blobs are sent in chunks
+        let upload_id = self
+            .bucket
+            .initiate_multipart_upload(&key, CONTENT_TYPE)?
+            .upload_id;
+        let mut parts = Vec::with_capacity((size / self.part_size) as usize + 1);
+        let mut remaining = size;
+        while remaining > 0 {
+            let mut chunk = vec![0; std::cmp::min(remaining, self.part_size) as usize];
+            remaining -= chunk.len() as u64;
+            let result = file
+                .read_exact(&mut chunk)
+                .map_err(StoreError::from)
+                .and_then(|_| {
+                    self.bucket
+                        .put_multipart_chunk(
+                            chunk,
+                            &key,
+                            parts.len() as u32 + 1,
+                            &upload_id,
+                            CONTENT_TYPE,
+                        )
+                        .map_err(StoreError::from)
+                });
+            match result {
+                Ok(part) => parts.push(part),
+                Err(err) => {
+                    self.bucket.abort_upload(&key, &upload_id).ok();
+                    return Err(err);
+                }
+            }
+        }
+
+        let response = self
+            .bucket
+            .complete_multipart_upload(&key, &upload_id, parts)?;
+        if !(200..300).contains(&response.status_code()) {
+            self.bucket.abort_upload(&key, &upload_id).ok();
+            return Err(StoreError::InternalError(format!(
+                "Failed to complete S3 multipart upload of {}: status {}",
+                key,
+                response.status_code()
+            )));
+        }
+
+        Ok(true)
+    }
+
+    fn delete(&self, blob_id: &BlobId) -> crate::Result<bool> {
+        let response = self.bucket.delete_object(self.get_key(blob_id))?;
+        match response.status_code() {
+            200 | 204 => Ok(true),
+            404 => Ok(false),
+            code => Err(StoreError::InternalError(format!(
+                "Failed to delete S3 object {}: status {}",
+                blob_id, code
+            ))),
+        }
+    }
+
+    fn put(&self, blob_id: &BlobId, blob: &[u8]) -> crate::Result<bool> {
+        let key = self.get_key(blob_id);
+        if self.get_size(&key)? == Some(blob.len() as u64) {
+            Ok(false)
+        } else if blob.len() as u64 <= self.part_size {
+            self.put_object(&key, blob)?;
+            Ok(true)
+        } else {
+            let mut writer = self.writer()?;
+            std::io::Write::write_all(&mut writer, blob)?;
+            self.commit(writer, blob_id)
+        }
+    }
+
+    fn is_shared(&self) -> bool {
+        true
+    }
+}
+
+impl S3BlobStore {
+    fn get_key(&self, blob_id: &BlobId) -> String {
+        format!("{}{}", self.prefix, blob_id)
+    }
+
+    fn get_size(&self, key: &str) -> crate::Result<Option<u64>> {
+        let (result, code) = self.bucket.head_object(key)?;
+        match code {
+            200 => Ok(Some(result.content_length.unwrap_or(0) as u64)),
+            404 => Ok(None),
+            code => Err(StoreError::InternalError(format!(
+                "Failed to obtain metadata for S3 object {}: status {}",
+                key, code
+            ))),
+        }
+    }
+
+    fn put_object(&self, key: &str, blob: &[u8]) -> crate::Result<()> {
+        let response = self.bucket.put_object(key, blob)?;
+        if (200..300).contains(&response.status_code()) {
+            Ok(())
+        } else {
+            Err(StoreError::InternalError(format!(
+                "Failed to write S3 object {}: status {}",
+                key,
+                response.status_code()
+            )))
+        }
+    }
+}
+
+impl Read for S3BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Fetch the next chunk when the position is outside the buffer
+        if self.pos < self.buf_start || self.pos >= self.buf_start + self.buf.len() as u64 {
+            let end = std::cmp::min(self.pos + READ_BUFFER_SIZE, self.size);
+            let response = self
+                .bucket
+                .get_object_range(&self.key, self.pos, Some(end - 1))
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+            if !matches!(response.status_code(), 200 | 206) || response.bytes().is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!(
+                        "Failed to fetch S3 object {}: status {}",
+                        self.key,
+                        response.status_code()
+                    ),
+                ));
+            }
+            self.buf = response.bytes().to_vec();
+            self.buf_start = self.pos;
+        }
+
+        let offset = (self.pos - self.buf_start) as usize;
+        let bytes_read = std::cmp::min(buf.len(), self.buf.len() - offset);
+        buf[..bytes_read].copy_from_slice(&self.buf[offset..offset + bytes_read]);
+        self.pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for S3BlobReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => (self.size as i64).checked_add(offset).map(|p| p as u64),
+            SeekFrom::Current(offset) => (self.pos as i64).checked_add(offset).map(|p| p as u64),
+        };
+        match pos {
+            Some(pos) if (pos as i64) >= 0 => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)),
+        }
+    }
+}
+
+impl BlobReader for S3BlobReader {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl From<S3Error> for StoreError {
+    fn from(err: S3Error) -> Self {
+        StoreError::InternalError(format!("S3 error: {}", err))
+    }
+}
@@ -28,13 +28,17 @@ use tracing::error;
 
 use crate::serialize::leb128::Leb128Reader;
 use crate::write::operation::WriteOperation;
+use crate::write::update::Changes;
 use crate::{
     core::collection::Collection,
-    serialize::{key::BlobKey, StoreSerialize},
+    serialize::{
+        key::{BlobKey, ValueKey},
+        StoreDeserialize, StoreSerialize,
+    },
     AccountId, ColumnFamily, Direction, DocumentId, JMAPStore, Store,
 };
 
-use super::{BlobId, BlobStore};
+use super::{BlobBackendReader, BlobBackendWriter, BlobId, BlobStore, BlobWriter};
 
 impl<T> JMAPStore<T>
 where
@@ -44,7 +48,7 @@ where
         let key = BlobKey::serialize(blob_id);
 
         // Lock blob hash
-        let _lock = self.blob_store.lock.lock_hash(blob_id);
+        let _lock = self.blob_lock.lock_hash(blob_id);
 
         // Blob already exists, return.
         if self.db.exists(ColumnFamily::Blobs, &key)? {
@@ -59,6 +63,30 @@ where
             (Vec::new(), bytes)
         };
 
+        self.blob_write_key(blob_id, key, value)?;
+
+        Ok(result)
+    }
+
+    pub fn blob_store_writer(&self, writer: BlobBackendWriter) -> crate::Result<BlobId> {
+        let blob_id = writer.blob_id();
+        let key = BlobKey::serialize(&blob_id);
+
+        // Lock blob hash
+        let _lock = self.blob_lock.lock_hash(&blob_id);
+
+        // Blob already exists, discard the written copy.
+        if self.db.exists(ColumnFamily::Blobs, &key)? {
+            return Ok(blob_id);
+        }
+
+        self.blob_store.commit(writer, &blob_id)?;
+        self.blob_write_key(&blob_id, key, Vec::new())?;
+
+        Ok(blob_id)
+    }
+
+    fn blob_write_key(&self, blob_id: &BlobId, key: Vec<u8>, value: Vec<u8>) -> crate::Result<()> {
         // Write blob or blob reference to database
         let mut batch = Vec::with_capacity(2);
         batch.push(WriteOperation::Set {
@@ -79,8 +107,9 @@ where
 
         // Store blobId including a timestamp
         if let Err(err) = self.db.write(batch) {
-            // There was a problem writing to the store, delete blob.
-            if blob_id.is_external() {
+            // There was a problem writing to the store, delete blob
+            // unless other nodes might be referencing it.
+            if blob_id.is_external() && !self.blob_store.is_shared() {
                 if let Err(err) = self.blob_store.delete(blob_id) {
                     error!("Failed to delete blob {}: {:?}", blob_id, err);
                 }
@@ -88,7 +117,27 @@ where
             return Err(err);
         }
 
-        Ok(result)
+        Ok(())
+    }
+
+    // Links an external blob written by another node to a shared blob store.
+    pub fn blob_register_shared(&self, blob_id: &BlobId) -> crate::Result<bool> {
+        if !blob_id.is_external() || !self.blob_store.is_shared() {
+            return Ok(false);
+        }
+        let key = BlobKey::serialize(blob_id);
+
+        // Lock blob hash
+        let _lock = self.blob_lock.lock_hash(blob_id);
+
+        if self.db.exists(ColumnFamily::Blobs, &key)? {
+            Ok(true)
+        } else if self.blob_store.reader(blob_id)?.is_some() {
+            self.blob_write_key(blob_id, key, Vec::new())?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
     }
 
     pub fn blob_exists(&self, blob_id: &BlobId) -> crate::Result<bool> {
@@ -114,6 +163,103 @@ where
         )
     }
 
+    // Links an uploaded blob to an account and charges its size to the account's
+    // quota until the ephemeral link expires and is removed by purge_blobs.
+    // Quota changes are only logged when 'log_changes' is set, followers
+    // cannot append to the change log.
+    pub fn blob_link_upload(
+        &self,
+        blob_id: &BlobId,
+        account_id: AccountId,
+        size: u64,
+        log_changes: bool,
+    ) -> crate::Result<Option<Changes>> {
+        // Obtain seconds from Unix epoch
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let key = BlobKey::serialize_prefix(blob_id, account_id);
+        let mut value = timestamp.serialize().unwrap();
+        value.extend_from_slice(&size.to_le_bytes());
+
+        // Lock blob hash
+        let _lock = self.blob_lock.lock_hash(&BlobKey::serialize(blob_id));
+
+        // Uploading the same blob again only refreshes the link's timestamp
+        let mut batch = Vec::with_capacity(2);
+        let mut changes = None;
+        if !matches!(self.db.get::<Vec<u8>>(ColumnFamily::Blobs, &key)?,
+                     Some(prev_value) if prev_value.len() == value.len())
+        {
+            batch.push(WriteOperation::merge(
+                ColumnFamily::Values,
+                ValueKey::serialize_quota(account_id),
+                (size as i64).to_le_bytes().to_vec(),
+            ));
+            if log_changes {
+                changes = self.prepare_quota_change(&mut batch, account_id)?;
+            }
+        }
+        batch.push(WriteOperation::set(ColumnFamily::Blobs, key, value));
+
+        self.db.write(batch)?;
+
+        Ok(changes)
+    }
+
+    // Returns the size charged to the account when the blob was uploaded.
+    pub fn blob_upload_size(&self, blob_id: &BlobId, account_id: AccountId) -> crate::Result<u64> {
+        Ok(self
+            .db
+            .get::<Vec<u8>>(
+                ColumnFamily::Blobs,
+                &BlobKey::serialize_prefix(blob_id, account_id),
+            )?
+            .and_then(|value| {
+                value
+                    .get(std::mem::size_of::<u64>()..)
+                    .and_then(u64::deserialize)
+            })
+            .unwrap_or(0))
+    }
+
+    // Releases the quota charged on upload once the blob is stored in a
+    // document that is charged for it, keeping the ephemeral link. The
+    // caller's document write already logs the quota change.
+    pub fn blob_release_upload(
+        &self,
+        blob_id: &BlobId,
+        account_id: AccountId,
+    ) -> crate::Result<()> {
+        let key = BlobKey::serialize_prefix(blob_id, account_id);
+
+        // Lock blob hash
+        let _lock = self.blob_lock.lock_hash(&BlobKey::serialize(blob_id));
+
+        if let Some(value) = self.db.get::<Vec<u8>>(ColumnFamily::Blobs, &key)? {
+            if let Some(size) = value
+                .get(std::mem::size_of::<u64>()..)
+                .and_then(u64::deserialize)
+            {
+                self.db.write(vec![
+                    WriteOperation::merge(
+                        ColumnFamily::Values,
+                        ValueKey::serialize_quota(account_id),
+                        (-(size as i64)).to_le_bytes().to_vec(),
+                    ),
+                    WriteOperation::set(
+                        ColumnFamily::Blobs,
+                        key,
+                        value[..std::mem::size_of::<u64>()].to_vec(),
+                    ),
+                ])?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn blob_get(&self, blob_id: &BlobId) -> crate::Result<Option<Vec<u8>>> {
         if !blob_id.is_local() {
             self.blob_store.get(blob_id)
@@ -123,6 +269,14 @@ where
         }
     }
 
+    pub fn blob_reader(&self, blob_id: &BlobId) -> crate::Result<Option<BlobBackendReader>> {
+        if self.blob_exists(blob_id)? {
+            self.blob_store.reader(blob_id)
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn blob_get_range(
         &self,
         blob_id: &BlobId,
@@ -200,6 +354,33 @@ where
         Ok(false)
     }
 
+    pub fn blob_linked_documents(
+        &self,
+        blob_id: &BlobId,
+        account_id: AccountId,
+        collection: Collection,
+    ) -> crate::Result<RoaringBitmap> {
+        let prefix = BlobKey::serialize_collection(blob_id, account_id, collection);
+        let mut documents = RoaringBitmap::new();
+
+        for (key, _) in self
+            .db
+            .iterator(ColumnFamily::Blobs, &prefix, Direction::Forward)?
+        {
+            if key.starts_with(&prefix) && key.len() > prefix.len() {
+                if let Some((document_id, _)) = (&key[prefix.len()..]).read_leb128() {
+                    documents.insert(document_id);
+                } else {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(documents)
+    }
+
     pub fn blob_any_linked_document(
         &self,
         blob_id: &BlobId,
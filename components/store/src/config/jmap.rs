@@ -36,6 +36,7 @@ pub struct JMAPConfig {
     pub max_calls_in_request: usize,
     pub max_objects_in_get: usize,
     pub max_objects_in_set: usize,
+    pub blob_max_data_sources: usize,
 
     pub rate_limit_authenticated: (u64, u64),
     pub rate_limit_anonymous: (u64, u64),
@@ -43,18 +44,32 @@ pub struct JMAPConfig {
     pub use_forwarded_header: bool,
 
     pub query_max_results: usize,
+    pub query_score_max_items: usize,
     pub changes_max_results: usize,
     pub mailbox_name_max_len: usize,
     pub mailbox_max_total: usize,
     pub mailbox_max_depth: usize,
     pub mail_max_size: usize,
     pub mail_attachments_max_size: usize,
+    pub mail_attachments_index_max_size: usize,
+    pub mail_attachments_index_timeout: u64,
+    pub mail_index_headers: Vec<String>,
+    pub mail_header_scan_max_items: usize,
     pub mail_import_max_items: usize,
     pub mail_parse_max_items: usize,
 
     pub sieve_max_scripts: usize,
     pub sieve_max_script_name: usize,
 
+    pub lmtp_subaddress_separator: String,
+    pub lmtp_subaddress_autofile: bool,
+
+    pub submission_max_delayed_send: u64,
+
+    pub dkim_rotation_interval: u64,
+    pub dkim_rotation_grace: u64,
+    pub dkim_rotation_lead: u64,
+
     pub push_max_total: usize,
     pub ws_heartbeat_interval: u64,
     pub ws_client_timeout: u64,
@@ -74,20 +89,59 @@ impl From<&EnvSettings> for JMAPConfig {
             max_calls_in_request: settings.parse("max-calls-in-request").unwrap_or(16),
             max_objects_in_get: settings.parse("max-objects-in-get").unwrap_or(500),
             max_objects_in_set: settings.parse("max-objects-in-set").unwrap_or(500),
+            blob_max_data_sources: settings.parse("blob-max-data-sources").unwrap_or(64),
             blob_temp_ttl: settings.parse("blob-temp-ttl").unwrap_or(3600),
             changes_max_results: settings.parse("changes-max-results").unwrap_or(5000),
             query_max_results: settings.parse("query-max-results").unwrap_or(5000),
+            query_score_max_items: settings.parse("query-score-max-items").unwrap_or(10000),
             mailbox_name_max_len: settings.parse("mailbox-name-max-len").unwrap_or(255),
             mailbox_max_total: settings.parse("mailbox-max-total").unwrap_or(1000),
             mailbox_max_depth: settings.parse("mailbox-max-depth").unwrap_or(10),
             mail_attachments_max_size: settings
                 .parse("mail-attachments-max-size")
                 .unwrap_or(50000000),
+            mail_attachments_index_max_size: settings
+                .parse("mail-attachments-index-max-size")
+                .unwrap_or(10000000),
+            mail_attachments_index_timeout: settings
+                .parse("mail-attachments-index-timeout")
+                .unwrap_or(2000),
+            mail_index_headers: settings
+                .parse_list("mail-index-headers")
+                .map(|headers| {
+                    headers
+                        .into_iter()
+                        .map(|header| header.trim().to_lowercase())
+                        .filter(|header| !header.is_empty())
+                        .collect()
+                })
+                .unwrap_or_else(|| {
+                    vec![
+                        "list-id".to_string(),
+                        "x-spam-status".to_string(),
+                        "x-mailer".to_string(),
+                    ]
+                }),
+            mail_header_scan_max_items: settings
+                .parse("mail-header-scan-max-items")
+                .unwrap_or(10000),
             mail_max_size: settings.parse("mail-max-size").unwrap_or(104857600),
             mail_import_max_items: settings.parse("mail-import-max-items").unwrap_or(5),
             mail_parse_max_items: settings.parse("mail-parse-max-items").unwrap_or(5),
             sieve_max_script_name: settings.parse("sieve-max-script-name").unwrap_or(512),
             sieve_max_scripts: settings.parse("sieve-max-scripts").unwrap_or(256),
+            lmtp_subaddress_separator: settings
+                .get("lmtp-subaddress-separator")
+                .unwrap_or_else(|| "+".to_string()),
+            lmtp_subaddress_autofile: settings.parse("lmtp-subaddress-autofile").unwrap_or(false),
+            submission_max_delayed_send: settings
+                .parse("submission-max-delayed-send")
+                .unwrap_or(30 * 86400),
+            dkim_rotation_interval: settings
+                .parse("dkim-rotation-interval")
+                .unwrap_or(90 * 86400),
+            dkim_rotation_grace: settings.parse("dkim-rotation-grace").unwrap_or(7 * 86400),
+            dkim_rotation_lead: settings.parse("dkim-rotation-lead").unwrap_or(2 * 86400),
             push_max_total: settings.parse("push-max-total").unwrap_or(100),
             ws_client_timeout: settings.parse("ws-client-timeout").unwrap_or(10 * 1000),
             ws_heartbeat_interval: settings.parse("ws-heartbeat-interval").unwrap_or(5 * 1000),
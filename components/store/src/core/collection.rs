@@ -34,7 +34,12 @@ pub enum Collection {
     Identity = 5,
     EmailSubmission = 6,
     SieveScript = 7,
-    None = 8,
+    Quota = 8,
+    AddressBook = 9,
+    ContactCard = 10,
+    Calendar = 11,
+    CalendarEvent = 12,
+    None = 13,
 }
 
 impl Default for Collection {
@@ -54,6 +59,11 @@ impl From<u8> for Collection {
             5 => Collection::Identity,
             6 => Collection::EmailSubmission,
             7 => Collection::SieveScript,
+            8 => Collection::Quota,
+            9 => Collection::AddressBook,
+            10 => Collection::ContactCard,
+            11 => Collection::Calendar,
+            12 => Collection::CalendarEvent,
             _ => {
                 debug_assert!(false, "Invalid collection value: {}", value);
                 Collection::None
@@ -85,6 +95,11 @@ impl From<u64> for Collection {
             5 => Collection::Identity,
             6 => Collection::EmailSubmission,
             7 => Collection::SieveScript,
+            8 => Collection::Quota,
+            9 => Collection::AddressBook,
+            10 => Collection::ContactCard,
+            11 => Collection::Calendar,
+            12 => Collection::CalendarEvent,
             _ => {
                 debug_assert!(false, "Invalid collection value: {}", value);
                 Collection::None
@@ -47,6 +47,8 @@ pub struct Document {
     pub tag_fields: Vec<Field<Tag>>,
     pub acls: Vec<(Permission, u64)>,
     pub blobs: Vec<(BlobId, u64)>,
+    pub quota: Option<(u64, u64)>,
+    pub deferred_attachments: bool,
 }
 
 impl Document {
@@ -61,6 +63,8 @@ impl Document {
             blobs: Vec::new(),
             acls: Vec::new(),
             term_index: None,
+            quota: None,
+            deferred_attachments: false,
         }
     }
 
@@ -108,6 +112,15 @@ impl Document {
         self.term_index = Some((blob, options));
     }
 
+    pub fn quota(&mut self, size: u64, options: u64) {
+        self.quota = Some((size, options));
+    }
+
+    /// Queues the document for attachment text extraction once it is written.
+    pub fn defer_attachments(&mut self) {
+        self.deferred_attachments = true;
+    }
+
     pub fn is_empty(&self) -> bool {
         self.text_fields.is_empty()
             && self.number_fields.is_empty()
@@ -32,8 +32,8 @@ pub mod write;
 
 use crate::core::acl::ACL;
 use crate::core::{acl::ACLToken, collection::Collection, error::StoreError};
-use crate::nlp::Language;
-use blob::local::LocalBlobStore;
+use crate::nlp::{attachment::AttachmentQueue, Language};
+use blob::BlobBackend;
 use blob::BlobStore;
 use config::{env_settings::EnvSettings, jmap::JMAPConfig};
 use log::raft::{LogIndex, RaftId};
@@ -139,7 +139,8 @@ pub enum RecipientType {
 
 pub struct JMAPStore<T> {
     pub db: T,
-    pub blob_store: LocalBlobStore,
+    pub blob_store: BlobBackend,
+    pub blob_lock: MutexMap<()>,
     pub config: JMAPConfig,
 
     pub account_lock: MutexMap<()>,
@@ -155,6 +156,8 @@ pub struct JMAPStore<T> {
     pub raft_term: AtomicU64,
     pub raft_index: AtomicU64,
     pub tombstone_deletions: AtomicBool,
+
+    pub attachment_queue: AttachmentQueue,
 }
 
 impl<T> JMAPStore<T>
@@ -164,7 +167,8 @@ where
     pub fn new(db: T, config: JMAPConfig, settings: &EnvSettings) -> Self {
         let mut store = Self {
             config,
-            blob_store: LocalBlobStore::new(settings).unwrap(),
+            blob_store: BlobBackend::new(settings).unwrap(),
+            blob_lock: MutexMap::with_capacity(1024),
             id_assigner: Cache::builder()
                 .initial_capacity(128)
                 .max_capacity(settings.parse("cache-size-ids").unwrap_or(32 * 1024 * 1024))
@@ -194,6 +198,11 @@ where
             raft_index: 0.into(),
             raft_term: 0.into(),
             tombstone_deletions: false.into(),
+            attachment_queue: AttachmentQueue::new(
+                settings
+                    .parse("mail-attachments-index-queue")
+                    .unwrap_or(10000),
+            ),
             sieve_compiler: Compiler::new()
                 .with_max_script_size(
                     settings
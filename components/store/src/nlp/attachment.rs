@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tracing::debug;
+
+use crate::{AccountId, DocumentId};
+
+// Maximum number of extraction threads, including those that timed out
+const MAX_ACTIVE_EXTRACTIONS: usize = 8;
+static ACTIVE_EXTRACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Messages waiting to have the text of their attachments extracted and indexed.
+pub struct AttachmentQueue {
+    tx: mpsc::SyncSender<(AccountId, DocumentId)>,
+    rx: Mutex<Option<mpsc::Receiver<(AccountId, DocumentId)>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentType {
+    Pdf,
+    Office,
+}
+
+impl AttachmentType {
+    pub fn parse(content_type: Option<&str>, file_name: Option<&str>) -> Option<Self> {
+        let attachment_type = match content_type.map(|ct| ct.to_ascii_lowercase()).as_deref() {
+            Some("application/pdf") => AttachmentType::Pdf,
+            Some(ct)
+                if ct.starts_with("application/vnd.openxmlformats-officedocument.")
+                    || ct.starts_with("application/vnd.oasis.opendocument.") =>
+            {
+                AttachmentType::Office
+            }
+            _ => match file_name
+                .and_then(|name| name.rsplit_once('.'))
+                .map(|(_, ext)| ext.to_ascii_lowercase())
+                .as_deref()
+            {
+                Some("pdf") => AttachmentType::Pdf,
+                Some("docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp") => AttachmentType::Office,
+                _ => return None,
+            },
+        };
+
+        if attachment_type.is_supported() {
+            Some(attachment_type)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            AttachmentType::Pdf => cfg!(feature = "pdf"),
+            AttachmentType::Office => cfg!(feature = "office"),
+        }
+    }
+}
+
+impl AttachmentQueue {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        AttachmentQueue {
+            tx,
+            rx: Mutex::new(Some(rx)),
+        }
+    }
+
+    /// Queues a message for attachment indexing, dropping it if the queue is full.
+    /// Dropped messages remain tagged as pending and are queued again later.
+    pub fn push(&self, account_id: AccountId, document_id: DocumentId) {
+        if self.tx.try_send((account_id, document_id)).is_err() {
+            debug!(
+                "Attachment queue is full, skipping text extraction of {}:{}.",
+                account_id, document_id
+            );
+        }
+    }
+
+    /// Returns the receiving end of the queue, only available to the first caller.
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<(AccountId, DocumentId)>> {
+        self.rx.lock().take()
+    }
+}
+
+/// Extracts the text contents of an attachment on a separate thread,
+/// giving up once the timeout expires. Attachments larger than
+/// `max_size` are ignored and the extracted text is truncated to `max_size` bytes.
+/// Threads that time out keep running until the extraction completes, so no more
+/// than `MAX_ACTIVE_EXTRACTIONS` are allowed at once.
+pub fn extract_attachment(
+    bytes: Vec<u8>,
+    attachment_type: AttachmentType,
+    max_size: usize,
+    timeout: Duration,
+) -> Option<String> {
+    if bytes.is_empty() || bytes.len() > max_size {
+        return None;
+    }
+
+    if ACTIVE_EXTRACTIONS.fetch_add(1, Ordering::Relaxed) >= MAX_ACTIVE_EXTRACTIONS {
+        ACTIVE_EXTRACTIONS.fetch_sub(1, Ordering::Relaxed);
+        debug!(
+            "Too many active text extractions, skipping {:?} attachment.",
+            attachment_type
+        );
+        return None;
+    }
+
+    let (tx, rx) = mpsc::sync_channel(1);
+    if thread::Builder::new()
+        .name("text-extract".to_string())
+        .spawn(move || {
+            let text = extract_text(&bytes, attachment_type, max_size);
+            ACTIVE_EXTRACTIONS.fetch_sub(1, Ordering::Relaxed);
+            tx.send(text).ok();
+        })
+        .is_err()
+    {
+        ACTIVE_EXTRACTIONS.fetch_sub(1, Ordering::Relaxed);
+        return None;
+    }
+
+    match rx.recv_timeout(timeout) {
+        Ok(Some(mut text)) => {
+            if text.len() > max_size {
+                let mut pos = max_size;
+                while !text.is_char_boundary(pos) {
+                    pos -= 1;
+                }
+                text.truncate(pos);
+            }
+            let text = text.trim();
+            if !text.is_empty() {
+                Some(text.to_string())
+            } else {
+                None
+            }
+        }
+        Ok(None) => None,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            debug!(
+                "Text extraction of {:?} attachment timed out after {:?}.",
+                attachment_type, timeout
+            );
+            None
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => None,
+    }
+}
+
+#[allow(unused_variables)]
+fn extract_text(bytes: &[u8], attachment_type: AttachmentType, max_size: usize) -> Option<String> {
+    match attachment_type {
+        #[cfg(feature = "pdf")]
+        AttachmentType::Pdf => super::pdf::extract_pdf(bytes),
+        #[cfg(feature = "office")]
+        AttachmentType::Office => super::office::extract_office(bytes, max_size),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AttachmentType;
+
+    #[test]
+    fn attachment_type() {
+        for (content_type, file_name, expected) in [
+            (Some("application/pdf"), None, Some(AttachmentType::Pdf)),
+            (
+                Some("APPLICATION/PDF"),
+                Some("report.txt"),
+                Some(AttachmentType::Pdf),
+            ),
+            (
+                Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+                None,
+                Some(AttachmentType::Office),
+            ),
+            (
+                Some("application/vnd.oasis.opendocument.spreadsheet"),
+                None,
+                Some(AttachmentType::Office),
+            ),
+            (
+                Some("application/octet-stream"),
+                Some("slides.PPTX"),
+                Some(AttachmentType::Office),
+            ),
+            (None, Some("scan.pdf"), Some(AttachmentType::Pdf)),
+            (Some("application/octet-stream"), Some("image.png"), None),
+            (Some("application/zip"), None, None),
+            (None, None, None),
+        ] {
+            assert_eq!(
+                AttachmentType::parse(content_type, file_name),
+                expected.filter(|t| t.is_supported()),
+                "{:?} {:?}",
+                content_type,
+                file_name
+            );
+        }
+    }
+}
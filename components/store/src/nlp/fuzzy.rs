@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashSet;
+
+/// Returns every string within a Damerau-Levenshtein distance of one from
+/// `word`, including `word` itself, that is at most `max_len` bytes long.
+pub fn edit_variants(word: &str, max_len: usize) -> AHashSet<String> {
+    let chars = word.chars().collect::<Vec<_>>();
+    if chars.len() > max_len + 1 {
+        return AHashSet::new();
+    }
+    let mut alphabet = ('a'..='z').chain('0'..='9').collect::<Vec<_>>();
+    for ch in &chars {
+        if !alphabet.contains(ch) {
+            alphabet.push(*ch);
+        }
+    }
+
+    let mut variants = AHashSet::with_capacity((chars.len() * 2 + 1) * alphabet.len());
+    for pos in 0..=chars.len() {
+        // Insertions
+        for ch in &alphabet {
+            let mut variant = chars.clone();
+            variant.insert(pos, *ch);
+            insert_variant(&mut variants, variant, max_len);
+        }
+
+        if pos < chars.len() {
+            // Deletions
+            let mut variant = chars.clone();
+            variant.remove(pos);
+            if !variant.is_empty() {
+                insert_variant(&mut variants, variant, max_len);
+            }
+
+            // Substitutions
+            for ch in &alphabet {
+                let mut variant = chars.clone();
+                variant[pos] = *ch;
+                insert_variant(&mut variants, variant, max_len);
+            }
+
+            // Transpositions
+            if pos + 1 < chars.len() {
+                let mut variant = chars.clone();
+                variant.swap(pos, pos + 1);
+                insert_variant(&mut variants, variant, max_len);
+            }
+        }
+    }
+
+    variants
+}
+
+fn insert_variant(variants: &mut AHashSet<String>, variant: Vec<char>, max_len: usize) {
+    let variant = variant.into_iter().collect::<String>();
+    if variant.len() <= max_len {
+        variants.insert(variant);
+    }
+}
+
+/// Returns true if `a` and `b` are equal or one insertion, deletion,
+/// substitution or transposition apart.
+pub fn within_one_edit(a: &str, b: &str) -> bool {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let (a, b) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+
+    match b.len() - a.len() {
+        0 => {
+            let diff = (0..a.len())
+                .filter(|&pos| a[pos] != b[pos])
+                .collect::<Vec<_>>();
+            match diff.as_slice() {
+                [] | [_] => true,
+                [x, y] => *y == x + 1 && a[*x] == b[*y] && a[*y] == b[*x],
+                _ => false,
+            }
+        }
+        1 => {
+            let prefix_len = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+            a[prefix_len..] == b[prefix_len + 1..]
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_variants, within_one_edit};
+
+    #[test]
+    fn fuzzy_match() {
+        let variants = edit_variants("house", 25);
+        for word in [
+            "house", "hose", "mouse", "houses", "hosue", "ohuse", "thouse",
+        ] {
+            assert!(variants.contains(word), "{}", word);
+            assert!(within_one_edit("house", word), "{}", word);
+        }
+        for word in ["mice", "houseboat", "hsoue", "use", "horses"] {
+            assert!(!variants.contains(word), "{}", word);
+            assert!(!within_one_edit("house", word), "{}", word);
+        }
+        assert!(edit_variants("año", 25).contains("ñao"));
+        assert!(edit_variants("houses", 5).contains("house"));
+        assert!(!edit_variants("houses", 5).contains("houses"));
+        assert!(edit_variants("housewarmings", 5).is_empty());
+    }
+}
@@ -21,8 +21,13 @@
  * for more details.
 */
 
+pub mod attachment;
+pub mod fuzzy;
 pub mod lang;
-//pub mod pdf;
+#[cfg(feature = "office")]
+pub mod office;
+#[cfg(feature = "pdf")]
+pub mod pdf;
 pub mod search_snippet;
 pub mod stemmer;
 pub mod term_index;
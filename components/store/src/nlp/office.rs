@@ -0,0 +1,196 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::io::{Cursor, Read};
+
+use zip::ZipArchive;
+
+pub fn extract_office(bytes: &[u8], max_size: usize) -> Option<String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).ok()?;
+    let mut file_names = archive
+        .file_names()
+        .filter(|name| {
+            matches!(
+                *name,
+                "word/document.xml" | "xl/sharedStrings.xml" | "content.xml"
+            ) || (name.starts_with("ppt/slides/slide") && name.ends_with(".xml"))
+        })
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+
+    // Sort slides by number
+    file_names.sort_unstable_by_key(|name| {
+        name.strip_prefix("ppt/slides/slide")
+            .and_then(|name| name.strip_suffix(".xml"))
+            .and_then(|num| num.parse::<u32>().ok())
+            .unwrap_or(0)
+    });
+
+    let mut text = String::new();
+    let mut xml = Vec::new();
+    for file_name in file_names {
+        if text.len() >= max_size {
+            break;
+        }
+        // Truncated files can end in the middle of a multi-byte character
+        xml.clear();
+        archive
+            .by_name(&file_name)
+            .ok()?
+            .take(max_size as u64)
+            .read_to_end(&mut xml)
+            .ok()?;
+        xml_to_text(&String::from_utf8_lossy(&xml), &mut text, max_size);
+    }
+
+    if !text.is_empty() {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+fn xml_to_text(xml: &str, text: &mut String, max_size: usize) {
+    let mut xml = xml;
+    let mut skip_text = false;
+
+    while let Some(pos) = xml.find('<') {
+        if !skip_text {
+            add_text(text, &xml[..pos]);
+        }
+        xml = &xml[pos + 1..];
+
+        let tag = if let Some(pos) = xml.find('>') {
+            let tag = &xml[..pos];
+            xml = &xml[pos + 1..];
+            tag
+        } else {
+            break;
+        };
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let is_closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        match name.rsplit(':').next().unwrap_or(name) {
+            // Field codes and deleted revisions
+            "instrText" | "delText" => {
+                skip_text = !is_closing && !tag.ends_with('/');
+            }
+            // Paragraphs, line breaks, tabs, table cells and spreadsheet strings
+            "p" | "h" | "br" | "cr" | "tab" | "tc" | "si" | "s" | "line-break" | "table-cell" => {
+                if !text.is_empty() && !text.ends_with(' ') {
+                    text.push(' ');
+                }
+            }
+            _ => (),
+        }
+
+        if text.len() >= max_size {
+            break;
+        }
+    }
+}
+
+fn add_text(text: &mut String, xml_text: &str) {
+    let mut xml_text = xml_text;
+
+    while let Some(pos) = xml_text.find('&') {
+        text.push_str(&xml_text[..pos]);
+        xml_text = &xml_text[pos + 1..];
+
+        if let Some(pos) = xml_text.find(';') {
+            let entity = &xml_text[..pos];
+            let ch = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix('#')
+                    .and_then(|code| {
+                        if let Some(hex) = code.strip_prefix('x') {
+                            u32::from_str_radix(hex, 16).ok()
+                        } else {
+                            code.parse::<u32>().ok()
+                        }
+                    })
+                    .and_then(char::from_u32),
+            };
+            if let Some(ch) = ch {
+                text.push(ch);
+                xml_text = &xml_text[pos + 1..];
+                continue;
+            }
+        }
+        text.push('&');
+    }
+    text.push_str(xml_text);
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn xml_to_text() {
+        for (xml, expected) in [
+            (
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>",
+                    "<w:document><w:body><w:p><w:r><w:t>Quarterly </w:t></w:r>",
+                    "<w:r><w:t xml:space=\"preserve\">re</w:t></w:r><w:r><w:t>port</w:t>",
+                    "</w:r></w:p><w:p><w:r><w:instrText>HYPERLINK \"x\"</w:instrText>",
+                    "<w:t>Profit &amp; loss</w:t><w:tab/><w:t>&#8364;10</w:t></w:r></w:p>",
+                    "</w:body></w:document>"
+                ),
+                "Quarterly report Profit & loss €10 ",
+            ),
+            (
+                concat!(
+                    "<sst count=\"2\"><si><t>Revenue</t></si><si><t>",
+                    "&lt;Expenses&gt;</t></si></sst>"
+                ),
+                "Revenue <Expenses> ",
+            ),
+            (
+                concat!(
+                    "<office:document-content><office:body><office:text>",
+                    "<text:h>Minutes</text:h><text:p>Attendees:<text:s/>all",
+                    "</text:p></office:text></office:body></office:document-content>"
+                ),
+                "Minutes Attendees: all ",
+            ),
+            ("<a:p>AT&T &unknown;</a:p>", "AT&T &unknown; "),
+        ] {
+            let mut text = String::new();
+            super::xml_to_text(xml, &mut text, usize::MAX);
+            assert_eq!(text, expected);
+        }
+    }
+}
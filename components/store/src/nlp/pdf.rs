@@ -24,6 +24,7 @@
 use std::panic;
 
 use lopdf::Document;
+use pdf_extract::{output_doc, PlainTextOutput};
 
 pub fn extract_pdf(bytes: &[u8]) -> Option<String> {
     panic::catch_unwind(|| {
@@ -92,12 +92,20 @@ pub fn generate_snippet(terms: &[Term], text: &str) -> Option<String> {
     let mut terms = terms.iter().peekable();
 
     'outer: while let Some(term) = terms.next() {
-        if snippet.len() + ("<mark>".len() * 2) + term.len as usize + 1 > 255 {
+        let term_text = text.get(term.offset as usize..term.offset as usize + term.len as usize)?;
+        if snippet.len()
+            + ("<mark>".len() * 2)
+            + term_text.chars().map(escape_char_len).sum::<usize>()
+            + 1
+            > 255
+        {
             break;
         }
 
         snippet.push_str("<mark>");
-        snippet.push_str(text.get(term.offset as usize..term.offset as usize + term.len as usize)?);
+        for char in term_text.chars() {
+            escape_char(char, &mut snippet);
+        }
         snippet.push_str("</mark>");
 
         let next_offset = if let Some(next_term) = terms.peek() {
@@ -23,7 +23,7 @@
 
 use std::convert::TryInto;
 
-use crate::nlp::{stemmer::StemmedToken, tokenizers::Token};
+use crate::nlp::{fuzzy::within_one_edit, stemmer::StemmedToken, tokenizers::Token};
 
 use crate::serialize::leb128::{Leb128Reader, Leb128Vec};
 use crate::{
@@ -72,6 +72,7 @@ pub struct TermIndexBuilderItem {
 pub struct TermIndexBuilder {
     terms: AHashMap<String, u32>,
     items: Vec<TermIndexBuilderItem>,
+    serialized_items: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -226,9 +227,30 @@ impl TermIndexBuilder {
         TermIndexBuilder {
             items: Vec::new(),
             terms: AHashMap::default(),
+            serialized_items: Vec::new(),
         }
     }
 
+    /// Creates a builder that appends new terms to a serialized term index.
+    pub fn from_serialized(bytes: &[u8]) -> Option<TermIndexBuilder> {
+        let (num_tokens, mut pos) = bytes.read_leb128::<u32>()?;
+        let mut terms = AHashMap::with_capacity(num_tokens as usize);
+        for term_id in 0..num_tokens {
+            let nil_pos = bytes.get(pos..)?.iter().position(|b| b == &0)?;
+            terms.insert(
+                String::from_utf8(bytes.get(pos..pos + nil_pos)?.to_vec()).ok()?,
+                term_id,
+            );
+            pos += nil_pos + 1;
+        }
+
+        Some(TermIndexBuilder {
+            items: Vec::new(),
+            terms,
+            serialized_items: bytes.get(pos..)?.to_vec(),
+        })
+    }
+
     pub fn add_token(&mut self, token: Token) -> Term {
         let id = self.terms.len() as u32;
         let id = self
@@ -301,6 +323,7 @@ impl StoreSerialize for TermIndexBuilder {
             bytes.extend_from_slice(terms.as_bytes());
             bytes.push(0);
         }
+        bytes.extend_from_slice(&self.serialized_items);
 
         // Write terms
         let mut bitpacker = TermIndexPacker::new();
@@ -437,6 +460,28 @@ impl TermIndex {
         MatchTerm { id, id_stemmed }
     }
 
+    pub fn get_prefix_terms(&self, prefix: &str) -> Vec<MatchTerm> {
+        self.token_map
+            .iter()
+            .filter(|(word, _)| word.starts_with(prefix))
+            .map(|(_, id)| MatchTerm {
+                id: *id,
+                id_stemmed: *id,
+            })
+            .collect()
+    }
+
+    pub fn get_fuzzy_terms(&self, word: &str) -> Vec<MatchTerm> {
+        self.token_map
+            .iter()
+            .filter(|(term, _)| within_one_edit(word, term))
+            .map(|(_, id)| MatchTerm {
+                id: *id,
+                id_stemmed: *id,
+            })
+            .collect()
+    }
+
     fn skip_items(&self, bytes: &[u8], mut remaining_items: usize) -> Result<usize> {
         let mut pos = 0;
         while remaining_items > 0 {
@@ -528,14 +573,19 @@ impl TermIndex {
         let mut result = Vec::new();
 
         // Safety check to avoid overflowing the bit mask
-        if !match_phrase && !(1..=64).contains(&match_terms.len()) {
+        if match_terms.is_empty() || (!match_phrase && match_terms.len() > 64) {
             return Err(Error::InvalidArgument);
         }
 
         // Term matching is done using a bit mask, where each bit represents a word.
         // Each time a word is matched, the corresponding bit is cleared.
         // When all bits are cleared, all matching terms are added to the result list.
-        let words_mask: u64 = u64::MAX >> (64 - match_terms.len());
+        // Phrases are matched by position and do not use the mask.
+        let words_mask: u64 = if !match_phrase {
+            u64::MAX >> (64 - match_terms.len())
+        } else {
+            0
+        };
         let mut matched_mask = words_mask;
 
         for item in &self.items {
@@ -565,22 +615,29 @@ impl TermIndex {
                     let term_id_stemmed = encoded_term[1];
 
                     if match_phrase {
-                        let match_pos = partial_match.len();
-                        if match_terms[match_pos].id == term_id {
-                            partial_match.push(Term {
-                                id: term_id,
-                                id_stemmed: term_id_stemmed,
-                                offset: term_pos as u32,
-                                len: 0,
-                            });
-                            if partial_match.len() == match_terms.len() {
-                                terms.append(&mut partial_match);
-                                if !match_many {
-                                    break 'term_loop;
-                                }
+                        partial_match.push(Term {
+                            id: term_id,
+                            id_stemmed: term_id_stemmed,
+                            offset: term_pos as u32,
+                            len: 0,
+                        });
+
+                        // Drop leading terms until the partial match is a prefix of the phrase,
+                        // so that overlapping occurrences such as "a a b" are not missed.
+                        while !partial_match.is_empty()
+                            && !partial_match
+                                .iter()
+                                .zip(match_terms.iter())
+                                .all(|(term, match_term)| term.id == match_term.id)
+                        {
+                            partial_match.remove(0);
+                        }
+
+                        if !partial_match.is_empty() && partial_match.len() == match_terms.len() {
+                            terms.append(&mut partial_match);
+                            if !match_many {
+                                break 'term_loop;
                             }
-                        } else if match_pos > 0 {
-                            partial_match.clear();
                         }
                     } else {
                         'match_loop: for (match_pos, match_term) in match_terms.iter().enumerate() {
@@ -872,6 +929,11 @@ mod tests {
 
         // Build the term index
         for (part_id, (text, field_id)) in parts.iter().enumerate() {
+            // Attachment terms are appended to the serialized term index
+            if *field_id == ATTACHMENT && parts[part_id - 1].1 != ATTACHMENT {
+                builder = TermIndexBuilder::from_serialized(&builder.serialize().unwrap()).unwrap();
+            }
+
             let mut terms = Vec::new();
             for token in Stemmer::new(text, Language::English, 40) {
                 let stemmed_word = if token.stemmed_word.is_some() {
@@ -904,6 +966,7 @@ mod tests {
             (vec!["was", "the", "worse"], None, true, 3),
             (vec!["carri"], None, false, 2),
             (vec!["nothing", "floating"], None, true, 2),
+            (vec!["a", "nothing", "floating"], None, true, 3),
             (vec!["floating", "nothing"], None, false, 6),
             (vec!["floating", "nothing"], None, true, 0),
             (vec!["noth", "floating"], None, true, 0),
@@ -22,8 +22,11 @@
 */
 
 use roaring::RoaringBitmap;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::FieldId;
+use crate::{DocumentId, FieldId};
+
+use super::filter::Text;
 
 #[derive(Debug)]
 pub struct FieldComparator {
@@ -31,6 +34,34 @@ pub struct FieldComparator {
     pub ascending: bool,
 }
 
+#[derive(Debug)]
+pub struct CollatedComparator {
+    pub field: FieldId,
+    pub ascending: bool,
+    pub collation: Collation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collation {
+    AsciiNumeric,
+    AsciiCasemap,
+    UnicodeCasemap,
+}
+
+#[derive(Debug)]
+pub struct RelevanceComparator {
+    pub text: Vec<Text>,
+    pub full_text_fields: Vec<(FieldId, u32)>,
+    pub tokenized_fields: Vec<(FieldId, u32)>,
+    pub ascending: bool,
+}
+
+#[derive(Debug)]
+pub struct ScoreComparator {
+    pub scores: Vec<(u32, DocumentId)>,
+    pub ascending: bool,
+}
+
 #[derive(Debug)]
 pub struct DocumentSetComparator {
     pub set: RoaringBitmap,
@@ -41,6 +72,9 @@ pub struct DocumentSetComparator {
 pub enum Comparator {
     List(Vec<Comparator>),
     Field(FieldComparator),
+    Collated(CollatedComparator),
+    Relevance(RelevanceComparator),
+    Score(ScoreComparator),
     DocumentSet(DocumentSetComparator),
     None,
 }
@@ -65,4 +99,148 @@ impl Comparator {
             ascending: false,
         })
     }
+
+    pub fn collated(field: FieldId, ascending: bool, collation: Collation) -> Self {
+        Comparator::Collated(CollatedComparator {
+            field,
+            ascending,
+            collation,
+        })
+    }
+}
+
+impl Default for Collation {
+    fn default() -> Self {
+        Collation::UnicodeCasemap
+    }
+}
+
+impl Collation {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "i;ascii-numeric" => Collation::AsciiNumeric.into(),
+            "i;ascii-casemap" => Collation::AsciiCasemap.into(),
+            "i;unicode-casemap" => Collation::UnicodeCasemap.into(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Collation::AsciiNumeric => "i;ascii-numeric",
+            Collation::AsciiCasemap => "i;ascii-casemap",
+            Collation::UnicodeCasemap => "i;unicode-casemap",
+        }
+    }
+
+    /// Returns a key that, when compared byte by byte, orders values
+    /// according to this collation.
+    pub fn sort_key(&self, value: &[u8]) -> Vec<u8> {
+        match self {
+            Collation::AsciiNumeric => {
+                // Strings that do not start with a digit sort after all numbers (RFC 4790)
+                let digits = value
+                    .iter()
+                    .position(|ch| !ch.is_ascii_digit())
+                    .map_or(value, |pos| &value[..pos]);
+                if !digits.is_empty() {
+                    let digits = digits
+                        .iter()
+                        .position(|&ch| ch != b'0')
+                        .map_or(&[][..], |pos| &digits[pos..]);
+                    let mut key = Vec::with_capacity(digits.len() + 5);
+                    key.push(0);
+                    key.extend_from_slice(&(digits.len() as u32).to_be_bytes());
+                    key.extend_from_slice(digits);
+                    key
+                } else {
+                    vec![u8::MAX]
+                }
+            }
+            Collation::AsciiCasemap => value.to_ascii_uppercase(),
+            Collation::UnicodeCasemap => match std::str::from_utf8(value) {
+                Ok(value) => {
+                    // Decompose first so that accented letters sort next to their base letter
+                    let mut key = String::with_capacity(value.len());
+                    for ch in value.nfkd() {
+                        for ch in ch.to_lowercase() {
+                            key.push(ch);
+                        }
+                    }
+                    key.into_bytes()
+                }
+                Err(_) => value.to_vec(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Collation;
+
+    #[test]
+    fn collation_sort_key() {
+        for (collation, values) in [
+            (
+                Collation::AsciiNumeric,
+                vec![
+                    "0",
+                    "007",
+                    "9 lives",
+                    "10",
+                    "0100",
+                    "12345678901234567890",
+                    "",
+                    "abc",
+                ],
+            ),
+            (
+                Collation::AsciiCasemap,
+                vec![
+                    "Apple",
+                    "banana",
+                    "Banana split",
+                    "CHERRY",
+                    "zebra",
+                    "ñandú",
+                ],
+            ),
+            (
+                Collation::UnicodeCasemap,
+                vec![
+                    "apple", "Banana", "cherry", "Éclair", "éclairs", "Ñandú", "zebra", "Ωmega",
+                ],
+            ),
+        ] {
+            let mut sorted = values.clone();
+            sorted.sort_by_key(|value| collation.sort_key(value.as_bytes()));
+            assert_eq!(sorted, values, "{:?}", collation);
+        }
+
+        assert_eq!(
+            Collation::AsciiNumeric.sort_key(b"7"),
+            Collation::AsciiNumeric.sort_key(b"007 agents")
+        );
+        assert_eq!(
+            Collation::AsciiNumeric.sort_key(b"none"),
+            Collation::AsciiNumeric.sort_key(b"")
+        );
+        assert_eq!(
+            Collation::UnicodeCasemap.sort_key("ÉCLAIR".as_bytes()),
+            Collation::UnicodeCasemap.sort_key("éclair".as_bytes())
+        );
+        assert_eq!(
+            Collation::UnicodeCasemap.sort_key("e\u{301}clair".as_bytes()),
+            Collation::UnicodeCasemap.sort_key("éclair".as_bytes())
+        );
+        assert_eq!(Collation::parse("i;octet"), None);
+        for collation in [
+            Collation::AsciiNumeric,
+            Collation::AsciiCasemap,
+            Collation::UnicodeCasemap,
+        ] {
+            assert_eq!(Collation::parse(collation.as_str()), Some(collation));
+        }
+    }
 }
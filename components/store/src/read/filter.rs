@@ -155,31 +155,84 @@ pub enum Query {
 pub struct Text {
     pub text: String,
     pub language: Language,
-    pub match_phrase: bool,
+    pub matches: Vec<TextMatch>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextMatch {
+    Words(String),
+    Phrase(String),
+    Prefix(String),
+    Fuzzy(String),
+}
+
+pub const MIN_PREFIX_LENGTH: usize = 2;
+pub const MIN_FUZZY_LENGTH: usize = 3;
+
 impl Text {
     pub fn new(mut text: String, mut language: Language) -> Self {
-        let match_phrase = (text.starts_with('"') && text.ends_with('"'))
-            || (text.starts_with('\'') && text.ends_with('\''));
+        if text.len() > 1 && text.starts_with('\'') && text.ends_with('\'') {
+            return Text {
+                language,
+                matches: vec![TextMatch::Phrase(text[1..text.len() - 1].to_string())],
+                text,
+            };
+        }
 
-        if !match_phrase && language == Language::Unknown {
-            language = if let Some((l, t)) = text
+        if language == Language::Unknown {
+            if let Some((l, t)) = text
                 .split_once(':')
                 .and_then(|(l, t)| (Language::from_iso_639(l)?, t.to_string()).into())
             {
                 text = t;
-                l
-            } else {
-                LanguageDetector::detect_single(&text)
+                language = l;
+            }
+        }
+
+        // Quoted sections are phrases, words ending in '*' are prefixes and
+        // words ending in '~' are fuzzy matches.
+        let mut matches = Vec::new();
+        let mut words = String::new();
+        let parts = text.split('"').collect::<Vec<_>>();
+        for (pos, part) in parts.iter().enumerate() {
+            if pos % 2 == 1 && pos < parts.len() - 1 {
+                let phrase = part.trim();
+                if !phrase.is_empty() {
+                    matches.push(TextMatch::Phrase(phrase.to_string()));
+                }
+                continue;
+            }
+
+            for word in part.split_whitespace() {
+                if let Some(prefix) = word.strip_suffix('*').filter(|w| {
+                    w.chars().count() >= MIN_PREFIX_LENGTH && w.chars().all(char::is_alphanumeric)
+                }) {
+                    matches.push(TextMatch::Prefix(prefix.to_lowercase()));
+                } else if let Some(fuzzy) = word.strip_suffix('~').filter(|w| {
+                    w.chars().count() >= MIN_FUZZY_LENGTH && w.chars().all(char::is_alphanumeric)
+                }) {
+                    matches.push(TextMatch::Fuzzy(fuzzy.to_lowercase()));
+                } else {
+                    if !words.is_empty() {
+                        words.push(' ');
+                    }
+                    words.push_str(word);
+                }
+            }
+        }
+
+        if !words.is_empty() {
+            if language == Language::Unknown {
+                language = LanguageDetector::detect_single(&words)
                     .and_then(|(l, c)| if c > 0.3 { Some(l) } else { None })
-                    .unwrap_or(Language::Unknown)
-            };
+                    .unwrap_or(Language::Unknown);
+            }
+            matches.insert(0, TextMatch::Words(words));
         }
 
         Text {
             language,
-            match_phrase,
+            matches,
             text,
         }
     }
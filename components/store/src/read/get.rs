@@ -180,4 +180,11 @@ where
             &ValueKey::serialize_term_index(account_id, collection, document_id),
         )
     }
+
+    pub fn get_used_quota(&self, account_id: AccountId) -> crate::Result<i64> {
+        Ok(self
+            .db
+            .get::<i64>(ColumnFamily::Values, &ValueKey::serialize_quota(account_id))?
+            .unwrap_or(0))
+    }
 }
@@ -26,11 +26,12 @@ use std::ops::{BitAndAssign, BitXorAssign};
 use roaring::RoaringBitmap;
 
 use crate::{
-    core::collection::Collection, serialize::key::IndexKey, AccountId, ColumnFamily, Direction,
-    DocumentId, FieldId, JMAPId, JMAPStore, Store,
+    core::{collection::Collection, error::StoreError},
+    serialize::key::IndexKey,
+    AccountId, ColumnFamily, Direction, DocumentId, FieldId, JMAPId, JMAPStore, Store,
 };
 
-use super::comparator::Comparator;
+use super::comparator::{Collation, Comparator};
 
 pub struct StoreIterator<'x, T, U>
 where
@@ -60,12 +61,19 @@ where
     prev_key: Option<Box<[u8]>>,
 }
 
+struct GroupedIndex {
+    groups: Vec<RoaringBitmap>,
+    pos: usize,
+    it: Option<roaring::bitmap::IntoIter>,
+}
+
 enum IndexType<'x, T>
 where
     T: Store<'x>,
 {
     DocumentSet(DocumentSetIndex),
     DB(DBIndex<'x, T>),
+    Grouped(GroupedIndex),
     None,
 }
 
@@ -102,8 +110,9 @@ where
         account_id: AccountId,
         collection: Collection,
         sort: Comparator,
-    ) -> Self {
+    ) -> crate::Result<Self> {
         let mut iterators: Vec<IndexIterator<T>> = Vec::new();
+        let result_ids = results.clone();
         for comp in (if let Comparator::List(list) = sort {
             list
         } else {
@@ -137,6 +146,25 @@ where
                             prev_key: None,
                         })
                     }
+                    Comparator::Collated(comp) => {
+                        IndexType::Grouped(GroupedIndex::new(group_keys(
+                            collation_keys(
+                                store,
+                                &IndexKey::serialize_field(
+                                    account_id,
+                                    collection as u8,
+                                    comp.field,
+                                ),
+                                comp.collation,
+                                &result_ids,
+                            )?,
+                            comp.ascending,
+                        )))
+                    }
+                    Comparator::Score(comp) => IndexType::Grouped(GroupedIndex::new(group_keys(
+                        comp.scores,
+                        comp.ascending,
+                    ))),
                     Comparator::DocumentSet(mut comp) => IndexType::DocumentSet(DocumentSetIndex {
                         set: if !comp.ascending {
                             if !comp.set.is_empty() {
@@ -159,12 +187,12 @@ where
             results = RoaringBitmap::new();
         }
 
-        StoreIterator {
+        Ok(StoreIterator {
             store,
             iterators,
             filter_map: None,
             current: 0,
-        }
+        })
     }
 
     pub fn set_filter_map(mut self, filter_map: U) -> Self {
@@ -193,6 +221,81 @@ where
     }
 }
 
+impl GroupedIndex {
+    fn new(groups: Vec<RoaringBitmap>) -> Self {
+        GroupedIndex {
+            groups,
+            pos: 0,
+            it: None,
+        }
+    }
+}
+
+// Generates the collation sort key of each indexed value, limited to the matched documents.
+// The scan stops once a key has been found for every matched document.
+fn collation_keys<'x, T>(
+    store: &'x JMAPStore<T>,
+    prefix: &[u8],
+    collation: Collation,
+    document_ids: &RoaringBitmap,
+) -> crate::Result<Vec<(Vec<u8>, DocumentId)>>
+where
+    T: Store<'x>,
+{
+    let mut keys = Vec::with_capacity(document_ids.len() as usize);
+    if document_ids.is_empty() {
+        return Ok(keys);
+    }
+
+    let mut remaining = document_ids.clone();
+    for (key, _) in store
+        .db
+        .iterator(ColumnFamily::Indexes, prefix, Direction::Forward)?
+    {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        let document_id = IndexKey::deserialize_document_id(&key)
+            .ok_or_else(|| StoreError::DataCorruption("Invalid index key.".to_string()))?;
+        if remaining.remove(document_id) {
+            keys.push((
+                collation.sort_key(
+                    key.get(prefix.len()..key.len() - std::mem::size_of::<DocumentId>())
+                        .ok_or_else(|| {
+                            StoreError::DataCorruption("Invalid index key.".to_string())
+                        })?,
+                ),
+                document_id,
+            ));
+            if remaining.is_empty() {
+                break;
+            }
+        }
+    }
+
+    Ok(keys)
+}
+
+// Sorts the documents by key and groups those with equal keys
+fn group_keys<K: Ord>(mut keys: Vec<(K, DocumentId)>, ascending: bool) -> Vec<RoaringBitmap> {
+    keys.sort_unstable();
+    if !ascending {
+        keys.reverse();
+    }
+
+    let mut groups: Vec<RoaringBitmap> = Vec::new();
+    let mut last_key = None;
+    for (key, document_id) in keys {
+        if last_key.as_ref() != Some(&key) {
+            groups.push(RoaringBitmap::new());
+            last_key = Some(key);
+        }
+        groups.last_mut().unwrap().insert(document_id);
+    }
+
+    groups
+}
+
 impl<'x, T, U> Iterator for StoreIterator<'x, T, U>
 where
     T: Store<'x>,
@@ -366,6 +469,55 @@ where
                             }
                         };
                     }
+                    IndexType::Grouped(index) => {
+                        let groups = &index.groups;
+
+                        loop {
+                            if let Some(it) = &mut index.it {
+                                while let Some(_doc_id) = it.next() {
+                                    if it_opts.remaining.remove(_doc_id) {
+                                        doc_id = _doc_id;
+                                        break 'inner;
+                                    }
+                                }
+                                index.it = None;
+                            }
+
+                            if let Some(group) = groups.get(index.pos) {
+                                index.pos += 1;
+
+                                let mut set = group.clone();
+                                set.bitand_assign(&it_opts.remaining);
+                                let set_len = set.len();
+
+                                match &mut next_it_opts {
+                                    _ if set_len == 0 => (),
+                                    Some(next_it_opts) if set_len > 1 => {
+                                        it_opts.remaining.bitxor_assign(&set);
+                                        next_it_opts.remaining = set;
+                                        break;
+                                    }
+                                    _ if set_len == 1 => {
+                                        doc_id = set.min().unwrap();
+                                        it_opts.remaining.remove(doc_id);
+                                        break 'inner;
+                                    }
+                                    _ => {
+                                        index.it = Some(set.into_iter());
+                                    }
+                                }
+                            } else {
+                                // Documents without a value for this field are sorted last
+                                if !it_opts.remaining.is_empty() {
+                                    if let Some(ref mut next_it_opts) = next_it_opts {
+                                        next_it_opts.remaining =
+                                            std::mem::take(&mut it_opts.remaining);
+                                    }
+                                }
+                                break;
+                            }
+                        }
+                    }
                     IndexType::None => (),
                 };
 
@@ -399,6 +551,10 @@ where
                                 IndexType::DocumentSet(index) => {
                                     index.it = None;
                                 }
+                                IndexType::Grouped(index) => {
+                                    index.it = None;
+                                    index.pos = 0;
+                                }
                                 IndexType::None => (),
                             }
 
@@ -30,6 +30,7 @@ pub mod filter;
 pub mod get;
 pub mod iterator;
 pub mod query;
+pub mod text;
 
 pub type FilterMapper = fn(DocumentId) -> crate::Result<Option<JMAPId>>;
 
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::AHashSet;
+use roaring::RoaringBitmap;
+
+use crate::{
+    core::{collection::Collection, document::MAX_TOKEN_LENGTH, error::StoreError},
+    nlp::{
+        fuzzy::edit_variants,
+        stemmer::Stemmer,
+        term_index::{MatchTerm, TermIndex},
+        tokenizers::Tokenizer,
+        Language,
+    },
+    serialize::key::BitmapKey,
+    AccountId, ColumnFamily, Direction, DocumentId, FieldId, JMAPStore, Store,
+};
+
+use super::{
+    comparator::{Comparator, RelevanceComparator, ScoreComparator},
+    filter::{LogicalOperator, Text, TextMatch},
+};
+
+// Limits on the number of dictionary terms a prefix query may scan and expand to,
+// queries exceeding them fail rather than returning partial results.
+const MAX_PREFIX_SCAN: usize = 10_000;
+const MAX_PREFIX_EXPANSIONS: usize = 100;
+
+impl<T> JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub fn match_text(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        field: FieldId,
+        text: &Text,
+        document_ids: &RoaringBitmap,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let mut text_bitmap = None;
+
+        for text_match in &text.matches {
+            let bitmap = match text_match {
+                TextMatch::Words(words) => {
+                    match self.match_words(
+                        account_id,
+                        collection,
+                        field,
+                        words,
+                        text.language,
+                        document_ids,
+                    )? {
+                        Some(bitmap) => Some(bitmap),
+                        // Ignore words without any tokens when other matches are present
+                        None if text.matches.len() > 1 => continue,
+                        None => None,
+                    }
+                }
+                TextMatch::Phrase(phrase) => {
+                    self.match_phrase(account_id, collection, field, phrase, text.language)?
+                }
+                TextMatch::Prefix(prefix) => {
+                    self.match_prefix(account_id, collection, field, prefix)?
+                }
+                TextMatch::Fuzzy(word) => self.get_bitmaps_union(
+                    edit_variants(word, MAX_TOKEN_LENGTH)
+                        .into_iter()
+                        .map(|word| {
+                            BitmapKey::serialize_term(account_id, collection, field, &word, true)
+                        })
+                        .collect(),
+                )?,
+            };
+
+            LogicalOperator::And.apply(&mut text_bitmap, bitmap, document_ids);
+
+            if text_bitmap.as_ref().unwrap().is_empty() {
+                break;
+            }
+        }
+
+        Ok(text_bitmap)
+    }
+
+    fn match_words(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        field: FieldId,
+        words: &str,
+        language: Language,
+        document_ids: &RoaringBitmap,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let mut requested_keys = AHashSet::default();
+        let mut text_bitmap = None;
+
+        // Default language for stemming
+        let language = if language != Language::Unknown {
+            language
+        } else {
+            self.config.default_language
+        };
+
+        for token in Stemmer::new(words, language, MAX_TOKEN_LENGTH) {
+            let mut keys = Vec::new();
+
+            for (word, is_exact) in [
+                (token.word.as_ref().into(), true),
+                (token.word.as_ref().into(), false),
+                (token.stemmed_word.as_ref().map(|w| w.as_ref()), true),
+                (token.stemmed_word.as_ref().map(|w| w.as_ref()), false),
+            ] {
+                if let Some(word) = word {
+                    let key =
+                        BitmapKey::serialize_term(account_id, collection, field, word, is_exact);
+                    if !requested_keys.contains(&key) {
+                        requested_keys.insert(key.clone());
+                        keys.push(key);
+                    }
+                }
+            }
+
+            // Term already matched on a previous iteration
+            if keys.is_empty() {
+                continue;
+            }
+
+            LogicalOperator::And.apply(
+                &mut text_bitmap,
+                self.get_bitmaps_union(keys)?,
+                document_ids,
+            );
+
+            if text_bitmap.as_ref().unwrap().is_empty() {
+                break;
+            }
+        }
+
+        Ok(text_bitmap)
+    }
+
+    fn match_phrase(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        field: FieldId,
+        phrase: &str,
+        language: Language,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        let mut words: Vec<String> = Vec::new();
+
+        // Retrieve the Term Index for each candidate and match the exact phrase
+        let candidates = if let Some(candidates) = self.get_bitmaps_intersection(
+            Tokenizer::new(phrase, language, MAX_TOKEN_LENGTH)
+                .filter_map(|token| {
+                    let word = token.word.into_owned();
+                    let r = if !words.contains(&word) {
+                        BitmapKey::serialize_term(account_id, collection, field, &word, true).into()
+                    } else {
+                        None
+                    };
+                    words.push(word);
+                    r
+                })
+                .collect(),
+        )? {
+            candidates
+        } else {
+            return Ok(None);
+        };
+
+        let mut results = RoaringBitmap::new();
+        for document_id in candidates.iter() {
+            if let Some(term_index) = self.get_term_index(account_id, collection, document_id)? {
+                if term_index
+                    .match_terms(
+                        &words
+                            .iter()
+                            .map(|w| term_index.get_match_term(w, None))
+                            .collect::<Vec<_>>(),
+                        Some(AHashSet::from([field])),
+                        true,
+                        false,
+                        false,
+                    )
+                    .map_err(|e| {
+                        StoreError::InternalError(format!(
+                            "Corrupted TermIndex for {}: {:?}",
+                            document_id, e
+                        ))
+                    })?
+                    .is_some()
+                {
+                    results.insert(document_id);
+                }
+            }
+        }
+
+        Ok(results.into())
+    }
+
+    fn match_prefix(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        field: FieldId,
+        prefix: &str,
+    ) -> crate::Result<Option<RoaringBitmap>> {
+        // Term keys start with the term itself, followed by a suffix that only
+        // depends on the account, collection and field.
+        let suffix = BitmapKey::serialize_term(account_id, collection, field, "a", true);
+        let suffix = &suffix[1..];
+        let mut keys = Vec::new();
+
+        for (scanned, (key, _)) in self
+            .db
+            .iterator(ColumnFamily::Bitmaps, prefix.as_bytes(), Direction::Forward)?
+            .enumerate()
+        {
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            } else if scanned == MAX_PREFIX_SCAN || keys.len() == MAX_PREFIX_EXPANSIONS {
+                return Err(StoreError::InvalidArguments(format!(
+                    "Prefix '{}' matches too many terms, please use a longer prefix.",
+                    prefix
+                )));
+            }
+            if key.len() > suffix.len() && key.ends_with(suffix) {
+                keys.push(key.to_vec());
+            }
+        }
+
+        self.get_bitmaps_union(keys)
+    }
+
+    /// Replaces any relevance comparators with the scores of the matched documents.
+    pub fn score_relevance(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        documents: &RoaringBitmap,
+        sort: Comparator,
+    ) -> crate::Result<Comparator> {
+        Ok(match sort {
+            Comparator::List(list) => Comparator::List(
+                list.into_iter()
+                    .map(|sort| self.score_relevance(account_id, collection, documents, sort))
+                    .collect::<crate::Result<Vec<_>>>()?,
+            ),
+            Comparator::Relevance(comparator) => Comparator::Score(ScoreComparator {
+                scores: self.score_documents(account_id, collection, documents, &comparator)?,
+                ascending: comparator.ascending,
+            }),
+            sort => sort,
+        })
+    }
+
+    /// Scores each document by the number of times the searched terms appear
+    /// in it, weighted by the field they appear in. Scoring requires loading the
+    /// term index of every document, so it is limited to `query-score-max-items`.
+    pub fn score_documents(
+        &self,
+        account_id: AccountId,
+        collection: Collection,
+        documents: &RoaringBitmap,
+        comparator: &RelevanceComparator,
+    ) -> crate::Result<Vec<(u32, DocumentId)>> {
+        if comparator.text.is_empty() {
+            return Ok(documents
+                .iter()
+                .map(|document_id| (0, document_id))
+                .collect());
+        } else if documents.len() > self.config.query_score_max_items as u64 {
+            return Err(StoreError::InvalidArguments(format!(
+                "Too many results to sort by relevance ({} > {}), please refine the query.",
+                documents.len(),
+                self.config.query_score_max_items
+            )));
+        }
+
+        // Tokenized fields are not part of the term index, only the presence of each term is scored
+        let mut tokenized_terms = Vec::new();
+        for (field, weight) in &comparator.tokenized_fields {
+            for text in &comparator.text {
+                for text_match in &text.matches {
+                    if let TextMatch::Words(words) | TextMatch::Phrase(words) = text_match {
+                        for token in Tokenizer::new(words, Language::English, MAX_TOKEN_LENGTH) {
+                            if let Some(bitmap) = self.get_bitmap(&BitmapKey::serialize_term(
+                                account_id,
+                                collection,
+                                *field,
+                                &token.word,
+                                true,
+                            ))? {
+                                tokenized_terms.push((*weight, bitmap));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut scores = Vec::with_capacity(documents.len() as usize);
+        for document_id in documents {
+            let mut score = tokenized_terms
+                .iter()
+                .filter(|(_, bitmap)| bitmap.contains(document_id))
+                .map(|(weight, _)| *weight)
+                .sum::<u32>();
+
+            if comparator.full_text_fields.is_empty() {
+                scores.push((score, document_id));
+                continue;
+            }
+
+            if let Some(term_index) = self.get_term_index(account_id, collection, document_id)? {
+                let match_terms = get_match_terms(&term_index, &comparator.text);
+                if !match_terms.is_empty() {
+                    for term_group in term_index
+                        .match_terms(&match_terms, None, false, true, false)
+                        .map_err(|e| {
+                            StoreError::InternalError(format!(
+                                "Corrupted TermIndex for {}: {:?}",
+                                document_id, e
+                            ))
+                        })?
+                        .unwrap_or_default()
+                    {
+                        if let Some((_, weight)) = comparator
+                            .full_text_fields
+                            .iter()
+                            .find(|(field, _)| *field == term_group.field_id)
+                        {
+                            score += weight * term_group.terms.len() as u32;
+                        }
+                    }
+                }
+            }
+
+            scores.push((score, document_id));
+        }
+
+        Ok(scores)
+    }
+}
+
+// Returns the ids of the term index entries matching the searched text,
+// limited to the 64 terms supported by TermIndex::match_terms.
+fn get_match_terms(term_index: &TermIndex, text: &[Text]) -> Vec<MatchTerm> {
+    let mut match_terms = Vec::new();
+
+    for text in text {
+        for text_match in &text.matches {
+            match text_match {
+                TextMatch::Words(words) => {
+                    for token in Stemmer::new(words, text.language, MAX_TOKEN_LENGTH) {
+                        match_terms.push(term_index.get_match_term(
+                            token.word.as_ref(),
+                            token.stemmed_word.as_ref().map(|w| w.as_ref()),
+                        ));
+                    }
+                }
+                TextMatch::Phrase(phrase) => {
+                    for token in Tokenizer::new(phrase, text.language, MAX_TOKEN_LENGTH) {
+                        match_terms.push(term_index.get_match_term(token.word.as_ref(), None));
+                    }
+                }
+                TextMatch::Prefix(prefix) => {
+                    match_terms.extend(term_index.get_prefix_terms(prefix));
+                }
+                TextMatch::Fuzzy(word) => {
+                    match_terms.extend(term_index.get_fuzzy_terms(word));
+                }
+            }
+        }
+    }
+
+    match_terms.retain(|term| term.id != u32::MAX || term.id_stemmed != u32::MAX);
+    let mut unique_terms: Vec<MatchTerm> = Vec::with_capacity(match_terms.len());
+    for term in match_terms {
+        if !unique_terms.contains(&term) {
+            unique_terms.push(term);
+        }
+    }
+    unique_terms.truncate(64);
+    unique_terms
+}
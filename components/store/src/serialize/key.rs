@@ -109,6 +109,13 @@ impl ValueKey {
         bytes
     }
 
+    pub fn serialize_quota(account: AccountId) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of::<AccountId>() + 1);
+        bytes.push_leb128(account);
+        bytes.push(u8::MAX - 1);
+        bytes
+    }
+
     pub fn serialize_acl(
         grant_account: AccountId,
         to_account: AccountId,
@@ -72,12 +72,31 @@ where
             self.prepare_batch(&mut ops, sub_batch, tombstone_deletions)?;
         }
 
+        // Documents with attachments pending extraction are queued once written
+        let account_id = batch.account_id;
+        let deferred_attachments = batch
+            .documents
+            .iter()
+            .filter_map(|document| match document {
+                WriteAction::Insert(document) | WriteAction::Update(document)
+                    if document.deferred_attachments =>
+                {
+                    Some(document.document_id)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
         // Prepare main batch
         let changes = self.prepare_batch(&mut ops, batch, tombstone_deletions)?;
 
         // Submit write batch
         self.db.write(ops)?;
 
+        for document_id in deferred_attachments {
+            self.attachment_queue.push(account_id, document_id);
+        }
+
         Ok(changes)
     }
 
@@ -93,16 +112,32 @@ where
         Ok(changes)
     }
 
+    // Logs a quota change for writes that update the used quota outside of a
+    // document batch, such as blob uploads and expired upload links.
+    pub fn prepare_quota_change(
+        &self,
+        ops: &mut Vec<WriteOperation>,
+        account_id: AccountId,
+    ) -> crate::Result<Option<Changes>> {
+        let mut batch = WriteBatch::new(account_id);
+        // Each account has a single octets quota with id 0
+        batch.log_update(Collection::Quota, 0u64);
+        self.prepare_batch(ops, batch, false)
+    }
+
     fn prepare_batch(
         &self,
         ops: &mut Vec<WriteOperation>,
-        batch: WriteBatch,
+        mut batch: WriteBatch,
         tombstone_deletions: bool,
     ) -> crate::Result<Option<Changes>> {
         let mut bitmap_list = AHashMap::default();
         let mut tombstones = Vec::new();
+        let mut used_quota: i64 = 0;
+        let mut has_quota_changes = false;
 
         for document in batch.documents {
+            let is_update = matches!(document, WriteAction::Update(_));
             let mut document = match document {
                 WriteAction::Insert(document) => {
                     // Add document id to collection
@@ -144,6 +179,12 @@ where
                         document
                     } else {
                         debug_assert!(!batch.changes.is_empty());
+                        // Release the quota now, the tombstone is applied without it
+                        if let Some((size, _)) = document.quota.take() {
+                            has_quota_changes = true;
+                            used_quota -= size as i64;
+                        }
+
                         // Add to tombstones
                         tombstones.push(document);
                         continue;
@@ -151,6 +192,16 @@ where
                 }
             };
 
+            // Update used quota
+            if let Some((size, options)) = document.quota {
+                has_quota_changes = true;
+                if !options.is_clear() {
+                    used_quota += size as i64;
+                } else {
+                    used_quota -= size as i64;
+                }
+            }
+
             // Process text fields
             if !document.text_fields.is_empty() {
                 // Detect language for unknown fields
@@ -167,7 +218,41 @@ where
                 let default_language = lang_detector
                     .most_frequent_language()
                     .unwrap_or(self.config.default_language);
-                let mut term_index = TermIndexBuilder::new();
+
+                // Full-text fields added to an existing document extend its term index
+                let mut replaced_term_index = None;
+                let mut term_index = if is_update
+                    && document
+                        .text_fields
+                        .iter()
+                        .any(|field| field.options.is_full_text())
+                {
+                    if let Some(blob_id) = self.db.get::<BlobId>(
+                        ColumnFamily::Values,
+                        &ValueKey::serialize_term_index(
+                            batch.account_id,
+                            document.collection,
+                            document.document_id,
+                        ),
+                    )? {
+                        let term_index = TermIndexBuilder::from_serialized(
+                            &self.blob_get(&blob_id)?.ok_or_else(|| {
+                                StoreError::NotFound("Term Index blob not found.".to_string())
+                            })?,
+                        )
+                        .ok_or_else(|| {
+                            StoreError::InternalError(
+                                "Failed to deserialize Term Index.".to_string(),
+                            )
+                        })?;
+                        replaced_term_index = blob_id.into();
+                        term_index
+                    } else {
+                        TermIndexBuilder::new()
+                    }
+                } else {
+                    TermIndexBuilder::new()
+                };
 
                 for field in document.text_fields {
                     let is_clear = field.is_clear();
@@ -312,6 +397,9 @@ where
                     document
                         .blobs
                         .push((term_index_blob_id, IndexOptions::new()));
+                    if let Some(blob_id) = replaced_term_index {
+                        document.blobs.push((blob_id, IndexOptions::new().clear()));
+                    }
                 }
             }
 
@@ -486,8 +574,26 @@ where
             ));
         }
 
+        // Update quota usage counter
+        if used_quota != 0 {
+            ops.push(WriteOperation::merge(
+                ColumnFamily::Values,
+                ValueKey::serialize_quota(batch.account_id),
+                used_quota.to_le_bytes().to_vec(),
+            ));
+        }
+
         // Serialize Raft and change log
         if !batch.changes.is_empty() {
+            if has_quota_changes {
+                // Each account has a single octets quota with id 0
+                batch
+                    .changes
+                    .get_mut_or_insert(Collection::Quota)
+                    .updates
+                    .insert(0);
+            }
+
             let raft_id = self.assign_raft_id();
             let mut collections = Bitmap::default();
 
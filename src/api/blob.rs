@@ -24,35 +24,80 @@
 use super::{RequestError, RequestLimitError};
 use crate::authorization::auth::RemoteAddress;
 use crate::authorization::Session;
+use crate::services::state_change::StateChange;
 use crate::JMAPServer;
-use actix_web::http::header::ContentType;
+use actix_web::body::{BoxBody, SizedStream};
+use actix_web::http::header::{
+    ContentType, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+    IF_RANGE, RANGE,
+};
 use actix_web::HttpRequest;
 use actix_web::{http::StatusCode, web, HttpResponse};
-use jmap::error::set::SetError;
-use jmap::request::blob::{CopyBlobRequest, CopyBlobResponse};
+use async_stream::stream;
+use futures::StreamExt;
+use jmap::error::method::MethodError;
+use jmap::error::set::{SetError, SetErrorType};
+use jmap::orm::serialize::JMAPOrm;
+use jmap::principal::store::JMAPPrincipals;
+use jmap::request::blob::{
+    BlobLookup, BlobObject, BlobProperty, CopyBlobRequest, CopyBlobResponse, DataSourceObject,
+    GetBlobRequest, GetBlobResponse, LookupBlobRequest, LookupBlobResponse, MaybeBlobReference,
+    UploadBlobRequest, UploadBlobResponse, UploadedBlob,
+};
 use jmap::request::ACLEnforce;
 use jmap::types::blob::JMAPBlob;
 use jmap::types::jmap::JMAPId;
+use jmap::types::type_state::TypeState;
 use jmap::SUPERUSER_ID;
 use jmap_mail::mail::get::{BlobResult, JMAPGetMail};
+use jmap_mail::mail::schema::{Email, Property};
 use jmap_mail::mail::sharing::JMAPShareMail;
+use jmap_mail::mail::MessageField;
 use jmap_sharing::principal::account::JMAPAccountStore;
-use reqwest::header::CONTENT_TYPE;
-use store::blob::BlobId;
+use sha1::Sha1;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::time::SystemTime;
+use store::blob::{BlobBackendReader, BlobBackendWriter, BlobId, BlobReader, BlobStore};
 use store::core::acl::ACL;
 use store::core::collection::Collection;
+use store::core::error::StoreError;
 use store::core::vec_map::VecMap;
-use store::JMAPStore;
-use store::{tracing::error, Store};
+use store::roaring::RoaringBitmap;
+use store::sha2::{Digest, Sha256};
+use store::{
+    tracing::{debug, error},
+    Store,
+};
+use store::{DocumentId, JMAPStore};
+use tokio::sync::mpsc;
 
 #[derive(serde::Deserialize)]
 pub struct Params {
     accept: Option<String>,
 }
 
+// Requests asking for more ranges than this are served in full
+const MAX_RANGES: usize = 32;
+
+// Size of the chunks read from disk and number of chunks to buffer
+const CHUNK_SIZE: u64 = 64 * 1024;
+const CHUNK_QUEUE_SIZE: usize = 8;
+
+enum BlobSource {
+    Reader(BlobBackendReader),
+    Bytes(Vec<u8>),
+}
+
+enum Segment {
+    Text(String),
+    Range(Range<u64>),
+}
+
 pub async fn handle_jmap_download<T>(
     path: web::Path<(JMAPId, JMAPBlob, String)>,
     params: web::Query<Params>,
+    request: HttpRequest,
     core: web::Data<JMAPServer<T>>,
     session: Session,
 ) -> Result<HttpResponse, RequestError>
@@ -63,41 +108,289 @@ where
     let (id, blob_id, filename) = path.into_inner();
     let account_id = id.get_document_id();
 
+    // Blobs are immutable, use the blob id as a strong validator
+    let etag = format!("\"{}\"", blob_id);
+
     let store = core.store.clone();
-    match core
+    let blob = match core
         .spawn_worker(move || {
-            store.mail_blob_get(
-                account_id,
-                &store.get_acl_token(session.account_id())?,
-                &blob_id,
-            )
+            let acl = store.get_acl_token(session.account_id())?;
+
+            // Whole external blobs are streamed from disk, message
+            // sections need to be decoded first.
+            Ok(if blob_id.section.is_none() && blob_id.id.is_external() {
+                if !store.mail_blob_has_access(account_id, &acl, &blob_id)? {
+                    Err(BlobResult::Unauthorized)
+                } else if let Some(reader) = store.blob_reader(&blob_id.id)? {
+                    Ok(BlobSource::Reader(reader))
+                } else {
+                    Err(BlobResult::NotFound)
+                }
+            } else {
+                match store.mail_blob_get(account_id, &acl, &blob_id)? {
+                    BlobResult::Blob(bytes) => Ok(BlobSource::Bytes(bytes)),
+                    result => Err(result),
+                }
+            })
         })
         .await
     {
-        Ok(BlobResult::Blob(bytes)) => Ok(HttpResponse::build(StatusCode::OK)
-            .insert_header((
-                "Content-Type",
-                params
-                    .into_inner()
-                    .accept
-                    .unwrap_or_else(|| "application/octet-stream".to_string()),
-            ))
-            .insert_header((
-                "Content-Disposition",
-                format!(
-                    "attachment; filename=\"{}\"",
-                    filename.replace('\"', "\\\"")
-                ),
-            ))
-            .insert_header(("Cache-Control", "private, immutable, max-age=31536000"))
-            .body(bytes)),
-        Ok(BlobResult::NotFound) => Err(RequestError::not_found()),
-        Ok(BlobResult::Unauthorized) => Err(RequestError::forbidden()),
+        Ok(Ok(blob)) => blob,
+        Ok(Err(BlobResult::Unauthorized)) => return Err(RequestError::forbidden()),
+        Ok(Err(_)) => return Err(RequestError::not_found()),
         Err(err) => {
             error!("Blob download failed: {:?}", err);
-            Err(RequestError::internal_server_error())
+            return Err(RequestError::internal_server_error());
+        }
+    };
+    let headers = request.headers();
+
+    // Client already has the latest version
+    if headers
+        .get(IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .map_or(false, |value| etag_matches(value, &etag))
+    {
+        return Ok(HttpResponse::build(StatusCode::NOT_MODIFIED)
+            .insert_header((ETAG, etag))
+            .insert_header(("Cache-Control", "private, immutable, max-age=31536000"))
+            .finish());
+    }
+
+    let content_type = params
+        .into_inner()
+        .accept
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let mut response = HttpResponse::build(StatusCode::OK);
+    response
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}\"",
+                filename.replace('\"', "\\\"")
+            ),
+        ))
+        .insert_header(("Cache-Control", "private, immutable, max-age=31536000"))
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .insert_header((ETAG, etag.as_str()));
+
+    // Ranges are ignored when the If-Range validator does not match
+    let size = blob.size();
+    let ranges = headers
+        .get(RANGE)
+        .and_then(|h| h.to_str().ok())
+        .filter(|_| {
+            headers
+                .get(IF_RANGE)
+                .map_or(true, |h| h.to_str().map_or(false, |value| value == etag))
+        })
+        .and_then(|value| parse_ranges(value, size));
+
+    Ok(match ranges {
+        None => response
+            .insert_header((CONTENT_TYPE, content_type))
+            .body(blob.into_body(vec![Segment::Range(0..size)])),
+        Some(ranges) if ranges.is_empty() => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .insert_header((CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish(),
+        Some(ranges) if ranges.len() == 1 => {
+            let range = ranges.into_iter().next().unwrap();
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header((CONTENT_TYPE, content_type))
+                .insert_header((
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+                ))
+                .body(blob.into_body(vec![Segment::Range(range)]))
+        }
+        Some(ranges) => {
+            let boundary = format!(
+                "{:x}{:x}",
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_nanos())
+                    .unwrap_or(0),
+                size
+            );
+            let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+            for (pos, range) in ranges.into_iter().enumerate() {
+                segments.push(Segment::Text(format!(
+                    "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    if pos > 0 { "\r\n" } else { "" },
+                    boundary,
+                    content_type,
+                    range.start,
+                    range.end - 1,
+                    size
+                )));
+                segments.push(Segment::Range(range));
+            }
+            segments.push(Segment::Text(format!("\r\n--{}--\r\n", boundary)));
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header((
+                    CONTENT_TYPE,
+                    format!("multipart/byteranges; boundary={}", boundary),
+                ))
+                .body(blob.into_body(segments))
+        }
+    })
+}
+
+impl BlobSource {
+    fn size(&self) -> u64 {
+        match self {
+            BlobSource::Reader(reader) => reader.size(),
+            BlobSource::Bytes(bytes) => bytes.len() as u64,
+        }
+    }
+
+    fn into_body(self, segments: Vec<Segment>) -> BoxBody {
+        match self {
+            BlobSource::Bytes(bytes) if segments.len() == 1 => match segments.into_iter().next() {
+                Some(Segment::Range(range))
+                    if range.start == 0 && range.end == bytes.len() as u64 =>
+                {
+                    BoxBody::new(bytes)
+                }
+                Some(Segment::Range(range)) => {
+                    BoxBody::new(bytes[range.start as usize..range.end as usize].to_vec())
+                }
+                _ => BoxBody::new(()),
+            },
+            BlobSource::Bytes(bytes) => {
+                let mut body =
+                    Vec::with_capacity(segments.iter().map(|s| s.len() as usize).sum::<usize>());
+                for segment in segments {
+                    match segment {
+                        Segment::Text(text) => body.extend_from_slice(text.as_bytes()),
+                        Segment::Range(range) => {
+                            body.extend_from_slice(&bytes[range.start as usize..range.end as usize])
+                        }
+                    }
+                }
+                BoxBody::new(body)
+            }
+            BlobSource::Reader(mut reader) => {
+                let size = segments.iter().map(|s| s.len()).sum();
+                let (tx, mut rx) = mpsc::channel::<std::io::Result<web::Bytes>>(CHUNK_QUEUE_SIZE);
+
+                // Read from disk in a blocking thread, stop if the client goes away
+                tokio::task::spawn_blocking(move || {
+                    for segment in segments {
+                        let is_open = match segment {
+                            Segment::Text(text) => tx.blocking_send(Ok(text.into())).is_ok(),
+                            Segment::Range(range) => match read_range(&mut reader, range, &tx) {
+                                Ok(is_open) => is_open,
+                                Err(err) => {
+                                    error!("Failed to read blob: {:?}", err);
+                                    tx.blocking_send(Err(err)).ok();
+                                    false
+                                }
+                            },
+                        };
+                        if !is_open {
+                            break;
+                        }
+                    }
+                });
+
+                BoxBody::new(SizedStream::new(
+                    size,
+                    stream! {
+                        while let Some(chunk) = rx.recv().await {
+                            yield chunk;
+                        }
+                    },
+                ))
+            }
+        }
+    }
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Segment::Text(text) => text.len() as u64,
+            Segment::Range(range) => range.end - range.start,
+        }
+    }
+}
+
+fn read_range(
+    reader: &mut BlobBackendReader,
+    range: Range<u64>,
+    tx: &mpsc::Sender<std::io::Result<web::Bytes>>,
+) -> std::io::Result<bool> {
+    reader.seek(SeekFrom::Start(range.start))?;
+    let mut remaining = range.end - range.start;
+    while remaining > 0 {
+        let mut buf = vec![0; std::cmp::min(remaining, CHUNK_SIZE) as usize];
+        reader.read_exact(&mut buf)?;
+        remaining -= buf.len() as u64;
+        if tx.blocking_send(Ok(buf.into())).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Weak comparison as required by If-None-Match (RFC 9110, section 13.1.2)
+fn etag_matches(value: &str, etag: &str) -> bool {
+    value.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+    })
+}
+
+// Returns None when the header is invalid and should be ignored, or an
+// empty list when none of the ranges can be satisfied.
+fn parse_ranges(value: &str, len: u64) -> Option<Vec<Range<u64>>> {
+    let specs = value.trim().strip_prefix("bytes=")?.split(',');
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+
+    for (pos, spec) in specs.enumerate() {
+        if pos == MAX_RANGES {
+            return None;
+        }
+        let (start, end) = spec.trim().split_once('-')?;
+        let range = if !start.is_empty() {
+            let start = start.parse::<u64>().ok()?;
+            let end = if !end.is_empty() {
+                let end = end.parse::<u64>().ok()?;
+                if end < start {
+                    return None;
+                }
+                end.saturating_add(1).min(len)
+            } else {
+                len
+            };
+            start..end
+        } else {
+            let suffix = end.parse::<u64>().ok()?;
+            len.saturating_sub(suffix)..len
+        };
+        if range.start < range.end {
+            ranges.push(range);
         }
     }
+
+    // Coalesce overlapping and adjacent ranges
+    ranges.sort_unstable_by_key(|r| r.start);
+    let mut coalesced: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match coalesced.last_mut() {
+            Some(last) if range.start <= last.end => {
+                last.end = last.end.max(range.end);
+            }
+            _ => coalesced.push(range),
+        }
+    }
+
+    Some(coalesced)
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -114,7 +407,7 @@ struct UploadResponse {
 pub async fn handle_jmap_upload<T>(
     path: web::Path<(JMAPId,)>,
     request: HttpRequest,
-    bytes: web::Bytes,
+    mut payload: web::Payload,
     core: web::Data<JMAPServer<T>>,
     session: Session,
 ) -> Result<HttpResponse, RequestError>
@@ -136,32 +429,30 @@ where
         None
     };
 
-    #[cfg(test)]
-    {
-        // Used for concurrent upload tests
-        if bytes == b"sleep"[..] {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-        }
-    }
-
-    if bytes.len() > core.store.config.max_size_upload {
+    // Reject oversized uploads before receiving them
+    let max_size_upload = core.store.config.max_size_upload;
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<usize>().ok());
+    if content_length.map_or(false, |size| size > max_size_upload) {
         return Err(RequestError::limit(RequestLimitError::Size));
     }
 
     let store = core.store.clone();
-    let size = bytes.len();
-    match core
+    let available_quota = match core
         .spawn_worker(move || {
             Ok(
                 if store
                     .get_acl_token(session.account_id())?
                     .is_member(account_id)
                 {
-                    let blob = bytes.to_vec();
-                    let blob_id = BlobId::new_external(&blob);
-                    store.blob_store(&blob_id, blob)?;
-                    store.blob_link_ephemeral(&blob_id, account_id)?;
-                    JMAPBlob::new(blob_id).into()
+                    Some(if let Some(quota) = store.principal_quota(account_id)? {
+                        (quota - store.get_used_quota(account_id)?).max(0) as usize
+                    } else {
+                        usize::MAX
+                    })
                 } else {
                     None
                 },
@@ -169,20 +460,118 @@ where
         })
         .await
     {
-        Ok(Some(blob_id)) => Ok(HttpResponse::build(StatusCode::OK)
-            .insert_header(ContentType::json())
-            .json(UploadResponse {
-                account_id: id,
-                blob_id,
-                c_type: request
-                    .headers()
-                    .get(CONTENT_TYPE)
-                    .and_then(|h| h.to_str().ok())
-                    .unwrap_or("application/octet-stream")
-                    .to_string(),
-                size,
-            })),
-        Ok(None) => Err(RequestError::forbidden()),
+        Ok(Some(available_quota)) => available_quota,
+        Ok(None) => return Err(RequestError::forbidden()),
+        Err(err) => {
+            error!("Blob upload failed: {:?}", err);
+            return Err(RequestError::internal_server_error());
+        }
+    };
+    if content_length.map_or(false, |size| size > available_quota) {
+        return Err(RequestError::over_quota());
+    }
+
+    // Stream the blob to the blob store's writer, its hash is calculated as
+    // it is written
+    let store = core.store.clone();
+    let (tx, mut rx) = mpsc::channel::<web::Bytes>(CHUNK_QUEUE_SIZE);
+    let writer = tokio::task::spawn_blocking(move || -> store::Result<BlobBackendWriter> {
+        let mut writer = store.blob_store.writer()?;
+        while let Some(bytes) = rx.blocking_recv() {
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()?;
+        Ok(writer)
+    });
+
+    let mut size = 0;
+    while let Some(bytes) = payload.next().await {
+        let bytes = bytes.map_err(|err| {
+            debug!("Failed to receive upload: {}", err);
+            RequestError::invalid_parameters()
+        })?;
+
+        #[cfg(test)]
+        {
+            // Used for concurrent upload tests
+            if bytes == b"sleep"[..] {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+
+        size += bytes.len();
+        if size > max_size_upload {
+            return Err(RequestError::limit(RequestLimitError::Size));
+        } else if size > available_quota {
+            return Err(RequestError::over_quota());
+        }
+        if tx.send(bytes).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+
+    let writer = match writer.await {
+        Ok(Ok(writer)) => writer,
+        Ok(Err(err)) => {
+            error!("Blob upload failed: {:?}", err);
+            return Err(RequestError::internal_server_error());
+        }
+        Err(err) => {
+            error!("Blob upload failed: {:?}", err);
+            return Err(RequestError::internal_server_error());
+        }
+    };
+
+    let store = core.store.clone();
+    let is_leader = core.is_leader();
+    match core
+        .spawn_worker(move || {
+            Ok(
+                if matches!(store.principal_quota(account_id)?, Some(quota)
+                            if store.get_used_quota(account_id)? + size as i64 > quota)
+                {
+                    Err(RequestError::over_quota())
+                } else {
+                    let blob_id = store.blob_store_writer(writer)?;
+                    let changes =
+                        store.blob_link_upload(&blob_id, account_id, size as u64, is_leader)?;
+                    Ok((JMAPBlob::new(blob_id), changes))
+                },
+            )
+        })
+        .await
+    {
+        Ok(Ok((blob_id, changes))) => {
+            if let Some(changes) = changes {
+                if !core.is_in_cluster() || core.commit_index(changes.change_id).await {
+                    if let Err(err) = core
+                        .publish_state_change(StateChange::new(
+                            account_id,
+                            vec![(TypeState::Quota, changes.change_id)],
+                        ))
+                        .await
+                    {
+                        error!("Failed to publish state change: {}", err);
+                    }
+                }
+            }
+
+            Ok(HttpResponse::build(StatusCode::OK)
+                .insert_header(ContentType::json())
+                .json(UploadResponse {
+                    account_id: id,
+                    blob_id,
+                    c_type: request
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|h| h.to_str().ok())
+                        .unwrap_or("application/octet-stream")
+                        .to_string(),
+                    size,
+                }))
+        }
+        Ok(Err(err)) => Err(err),
         Err(err) => {
             error!("Blob upload failed: {:?}", err);
             Err(RequestError::internal_server_error())
@@ -258,3 +647,400 @@ where
         })
     }
 }
+
+pub trait JMAPBlobGet<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn get_blob(&self, request: GetBlobRequest) -> jmap::Result<GetBlobResponse>;
+}
+
+impl<T> JMAPBlobGet<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn get_blob(&self, request: GetBlobRequest) -> jmap::Result<GetBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        let blob_ids = request.ids.ok_or_else(|| {
+            MethodError::InvalidArguments("Blob/get requires a list of ids.".to_string())
+        })?;
+        if blob_ids.len() > self.config.max_objects_in_get {
+            return Err(MethodError::RequestTooLarge);
+        }
+        let properties = request
+            .properties
+            .unwrap_or_else(|| vec![BlobProperty::Data, BlobProperty::Size]);
+        let offset = request.offset.unwrap_or(0);
+        let mut list = Vec::with_capacity(blob_ids.len());
+        let mut not_found = Vec::new();
+
+        let read_data = properties.iter().any(|p| *p != BlobProperty::Size);
+        let requested_range = |size: usize| {
+            let end = request
+                .length
+                .map_or(size, |length| offset.saturating_add(length));
+            (
+                std::cmp::min(offset, size)..std::cmp::min(end, size),
+                offset > size || end > size,
+            )
+        };
+
+        for blob_id in blob_ids {
+            // Only the requested range is read, unless the blob is a message
+            // part that has to be decoded first.
+            let (size, bytes, is_truncated) = if blob_id.section.is_none() && !blob_id.id.is_local()
+            {
+                let reader = if self.mail_blob_has_access(account_id, &acl, &blob_id)? {
+                    self.blob_reader(&blob_id.id)?
+                } else {
+                    None
+                };
+                if let Some(mut reader) = reader {
+                    let size = reader.size() as usize;
+                    let (range, is_truncated) = requested_range(size);
+                    let mut bytes = Vec::with_capacity(range.len());
+                    if read_data && !range.is_empty() {
+                        reader
+                            .seek(SeekFrom::Start(range.start as u64))
+                            .and_then(|_| reader.take(range.len() as u64).read_to_end(&mut bytes))
+                            .map_err(StoreError::from)?;
+                    }
+                    (size, bytes, is_truncated)
+                } else {
+                    not_found.push(blob_id);
+                    continue;
+                }
+            } else {
+                match self.mail_blob_get(account_id, &acl, &blob_id)? {
+                    BlobResult::Blob(mut bytes) => {
+                        let size = bytes.len();
+                        let (range, is_truncated) = requested_range(size);
+                        bytes.truncate(range.end);
+                        bytes.drain(..range.start);
+                        (size, bytes, is_truncated)
+                    }
+                    BlobResult::NotFound | BlobResult::Unauthorized => {
+                        not_found.push(blob_id);
+                        continue;
+                    }
+                }
+            };
+            let range = &bytes[..];
+            let mut blob = BlobObject {
+                id: blob_id,
+                is_truncated,
+                ..Default::default()
+            };
+
+            for property in &properties {
+                match property {
+                    BlobProperty::Data => match std::str::from_utf8(range) {
+                        Ok(text) => blob.data_as_text = Some(text.to_string().into()),
+                        Err(_) => blob.data_as_base64 = base64::encode(range).into(),
+                    },
+                    BlobProperty::DataAsText => match std::str::from_utf8(range) {
+                        Ok(text) => blob.data_as_text = Some(text.to_string().into()),
+                        Err(_) => {
+                            blob.data_as_text = Some(None);
+                            blob.is_encoding_problem = true;
+                        }
+                    },
+                    BlobProperty::DataAsBase64 => {
+                        blob.data_as_base64 = base64::encode(range).into();
+                    }
+                    BlobProperty::DigestSha => {
+                        blob.digest_sha = base64::encode(Sha1::digest(range)).into();
+                    }
+                    BlobProperty::DigestSha256 => {
+                        blob.digest_sha256 = base64::encode(Sha256::digest(range)).into();
+                    }
+                    BlobProperty::Size => {
+                        blob.size = size.into();
+                    }
+                }
+            }
+
+            list.push(blob);
+        }
+
+        Ok(GetBlobResponse {
+            account_id: request.account_id,
+            list,
+            not_found,
+        })
+    }
+}
+
+pub trait JMAPBlobUpload<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn upload_blob(&self, request: UploadBlobRequest) -> jmap::Result<UploadBlobResponse>;
+}
+
+impl<T> JMAPBlobUpload<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn upload_blob(&self, request: UploadBlobRequest) -> jmap::Result<UploadBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        if request.create.len() > self.config.max_objects_in_set {
+            return Err(MethodError::RequestTooLarge);
+        }
+        let mut created: VecMap<String, UploadedBlob> = VecMap::with_capacity(request.create.len());
+        let mut not_created = VecMap::new();
+        let mut change_id = None;
+
+        'outer: for (create_id, upload) in request.create {
+            if upload.data.len() > self.config.blob_max_data_sources {
+                not_created.append(
+                    create_id,
+                    SetError::new(SetErrorType::TooLarge)
+                        .with_description("Too many data sources."),
+                );
+                continue;
+            }
+
+            // Concatenate all data sources
+            let mut bytes = Vec::new();
+            for data in upload.data {
+                match data {
+                    DataSourceObject::Text { value } => {
+                        bytes.extend_from_slice(value.as_bytes());
+                    }
+                    DataSourceObject::Base64 { value } => match base64::decode(&value) {
+                        Ok(value) => bytes.extend(value),
+                        Err(_) => {
+                            not_created.append(
+                                create_id,
+                                SetError::invalid_properties()
+                                    .with_description("Failed to decode base64 data."),
+                            );
+                            continue 'outer;
+                        }
+                    },
+                    DataSourceObject::Blob {
+                        blob_id,
+                        offset,
+                        length,
+                    } => {
+                        // Blobs created earlier in this call can be referenced
+                        let blob_id = match blob_id {
+                            MaybeBlobReference::Value(blob_id) => blob_id,
+                            MaybeBlobReference::Reference(reference) => {
+                                if let Some(blob) = created.get(&reference) {
+                                    blob.id.clone()
+                                } else {
+                                    not_created.append(
+                                        create_id,
+                                        SetError::new(SetErrorType::BlobNotFound).with_description(
+                                            format!("Blob reference '#{}' not found.", reference),
+                                        ),
+                                    );
+                                    continue 'outer;
+                                }
+                            }
+                        };
+
+                        let blob = match self.mail_blob_get(account_id, &acl, &blob_id)? {
+                            BlobResult::Blob(blob) => blob,
+                            BlobResult::NotFound | BlobResult::Unauthorized => {
+                                not_created.append(
+                                    create_id,
+                                    SetError::new(SetErrorType::BlobNotFound)
+                                        .with_description(format!("Blob {} not found.", blob_id)),
+                                );
+                                continue 'outer;
+                            }
+                        };
+
+                        let offset = offset.unwrap_or(0);
+                        let end = length.map_or(blob.len(), |length| offset.saturating_add(length));
+                        if let Some(range) = blob.get(offset..end) {
+                            bytes.extend_from_slice(range);
+                        } else {
+                            not_created.append(
+                                create_id,
+                                SetError::invalid_properties()
+                                    .with_description("Requested range exceeds the blob size."),
+                            );
+                            continue 'outer;
+                        }
+                    }
+                }
+
+                if bytes.len() > self.config.max_size_upload {
+                    not_created.append(
+                        create_id,
+                        SetError::new(SetErrorType::TooLarge)
+                            .with_description("Blob exceeds the maximum upload size."),
+                    );
+                    continue 'outer;
+                }
+            }
+
+            if matches!(self.principal_quota(account_id)?, Some(quota)
+                        if self.get_used_quota(account_id)? + bytes.len() as i64 > quota)
+            {
+                not_created.append(create_id, SetError::new(SetErrorType::OverQuota));
+                continue;
+            }
+
+            let blob_id = BlobId::new_external(&bytes);
+            let size = bytes.len();
+            self.blob_store(&blob_id, bytes)?;
+            if let Some(changes) = self.blob_link_upload(&blob_id, account_id, size as u64, true)? {
+                change_id = changes.change_id.into();
+            }
+            created.append(
+                create_id,
+                UploadedBlob {
+                    id: JMAPBlob::new(blob_id),
+                    type_: upload.type_,
+                    size,
+                },
+            );
+        }
+
+        Ok(UploadBlobResponse {
+            account_id: request.account_id,
+            created: if !created.is_empty() {
+                created.into()
+            } else {
+                None
+            },
+            not_created: if !not_created.is_empty() {
+                not_created.into()
+            } else {
+                None
+            },
+            change_id,
+        })
+    }
+}
+
+pub trait JMAPBlobLookup<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn lookup_blob(&self, request: LookupBlobRequest) -> jmap::Result<LookupBlobResponse>;
+}
+
+impl<T> JMAPBlobLookup<T> for JMAPStore<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    fn lookup_blob(&self, request: LookupBlobRequest) -> jmap::Result<LookupBlobResponse> {
+        let acl = request.acl.unwrap();
+        let account_id = request.account_id.get_document_id();
+        for type_name in &request.type_names {
+            if !["Email", "Mailbox", "Thread"].contains(&type_name.as_str()) {
+                return Err(MethodError::UnknownDataType(format!(
+                    "Blob/lookup does not support type '{}'.",
+                    type_name
+                )));
+            }
+        }
+        if request.ids.len() > self.config.max_objects_in_get {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        // Only return shared items the user has access to
+        let (shared_messages, shared_folders) =
+            if acl.is_member(account_id) || acl.is_member(SUPERUSER_ID) {
+                (None, None)
+            } else {
+                (
+                    self.mail_shared_messages(account_id, &acl.member_of, ACL::ReadItems)?
+                        .into(),
+                    self.mail_shared_folders(account_id, &acl.member_of, ACL::ReadItems)?
+                        .into(),
+                )
+            };
+
+        let mut list = Vec::with_capacity(request.ids.len());
+        let mut not_found = Vec::new();
+
+        for blob_id in request.ids {
+            let mut document_ids =
+                self.blob_linked_documents(&blob_id.id, account_id, Collection::Mail)?;
+            if let Some(shared_messages) = &shared_messages {
+                if let Some(shared_messages) = shared_messages.as_ref() {
+                    document_ids &= shared_messages;
+                } else {
+                    document_ids.clear();
+                }
+            }
+            if document_ids.is_empty()
+                && !((self.blob_account_has_access(&blob_id.id, &acl.member_of)?
+                    || acl.is_member(SUPERUSER_ID))
+                    && self.blob_exists(&blob_id.id)?)
+            {
+                not_found.push(blob_id);
+                continue;
+            }
+
+            let thread_ids = self.get_multi_document_value::<DocumentId>(
+                account_id,
+                Collection::Mail,
+                document_ids.iter(),
+                MessageField::ThreadId.into(),
+            )?;
+
+            let mut matched_ids = VecMap::with_capacity(request.type_names.len());
+            for type_name in &request.type_names {
+                let ids: Vec<JMAPId> = match type_name.as_str() {
+                    "Email" => document_ids
+                        .iter()
+                        .zip(thread_ids.iter())
+                        .filter_map(|(document_id, thread_id)| {
+                            JMAPId::from_parts((*thread_id)?, document_id).into()
+                        })
+                        .collect(),
+                    "Thread" => thread_ids
+                        .iter()
+                        .flatten()
+                        .copied()
+                        .collect::<RoaringBitmap>()
+                        .into_iter()
+                        .map(JMAPId::from)
+                        .collect(),
+                    _ => {
+                        let mut mailbox_ids = RoaringBitmap::new();
+                        for document_id in &document_ids {
+                            if let Some(tags) = self
+                                .get_orm::<Email>(account_id, document_id)?
+                                .as_ref()
+                                .and_then(|fields| fields.get_tags(&Property::MailboxIds))
+                            {
+                                mailbox_ids.extend(tags.iter().map(|tag| tag.as_id()));
+                            }
+                        }
+                        if let Some(shared_folders) = &shared_folders {
+                            if let Some(shared_folders) = shared_folders.as_ref() {
+                                mailbox_ids &= shared_folders;
+                            } else {
+                                mailbox_ids.clear();
+                            }
+                        }
+                        mailbox_ids.into_iter().map(JMAPId::from).collect()
+                    }
+                };
+                matched_ids.append(type_name.clone(), ids);
+            }
+
+            list.push(BlobLookup {
+                id: blob_id,
+                matched_ids,
+            });
+        }
+
+        Ok(LookupBlobResponse {
+            account_id: request.account_id,
+            list,
+            not_found,
+        })
+    }
+}
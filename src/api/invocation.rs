@@ -21,7 +21,12 @@
  * for more details.
 */
 
-use super::{blob::JMAPBlobCopy, method, request::Request, response::Response};
+use super::{
+    blob::{JMAPBlobCopy, JMAPBlobGet, JMAPBlobLookup, JMAPBlobUpload},
+    method,
+    request::Request,
+    response::Response,
+};
 use crate::{authorization::Session, services::email_delivery, JMAPServer};
 use actix_web::web;
 use jmap::{
@@ -30,6 +35,26 @@ use jmap::{
     request::ACLEnforce,
     SUPERUSER_ID,
 };
+use jmap_calendars::{
+    calendar::{
+        changes::JMAPCalendarChanges, get::JMAPGetCalendar, query::JMAPCalendarQuery,
+        set::JMAPSetCalendar,
+    },
+    calendar_event::{
+        changes::JMAPCalendarEventChanges, get::JMAPGetCalendarEvent,
+        query::JMAPCalendarEventQuery, set::JMAPSetCalendarEvent,
+    },
+};
+use jmap_contacts::{
+    address_book::{
+        changes::JMAPAddressBookChanges, get::JMAPGetAddressBook, query::JMAPAddressBookQuery,
+        set::JMAPSetAddressBook,
+    },
+    contact_card::{
+        changes::JMAPContactCardChanges, get::JMAPGetContactCard, query::JMAPContactCardQuery,
+        set::JMAPSetContactCard,
+    },
+};
 use jmap_mail::{
     email_submission::{
         changes::JMAPEmailSubmissionChanges, get::JMAPGetEmailSubmission,
@@ -48,9 +73,12 @@ use jmap_mail::{
     thread::{changes::JMAPThreadChanges, get::JMAPGetThread},
     vacation_response::{get::JMAPGetVacationResponse, set::JMAPSetVacationResponse},
 };
-use jmap_sharing::principal::{
-    account::JMAPAccountStore, get::JMAPGetPrincipal, query::JMAPPrincipalQuery,
-    set::JMAPSetPrincipal,
+use jmap_sharing::{
+    principal::{
+        account::JMAPAccountStore, get::JMAPGetPrincipal, query::JMAPPrincipalQuery,
+        set::JMAPSetPrincipal,
+    },
+    quota::{changes::JMAPQuotaChanges, get::JMAPGetQuota, query::JMAPQuotaQuery},
 };
 use jmap_sieve::sieve_script::{
     get::JMAPGetSieveScript, query::JMAPSieveScriptQuery, set::JMAPSetSieveScript,
@@ -224,6 +252,27 @@ where
                     .into();
                 method::Response::CopyBlob(store.copy_blob(request)?)
             }
+            method::Request::GetBlob(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Mail)?
+                    .into();
+                method::Response::GetBlob(store.get_blob(request)?)
+            }
+            method::Request::LookupBlob(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Mail)?
+                    .into();
+                method::Response::LookupBlob(store.lookup_blob(request)?)
+            }
+            method::Request::UploadBlob(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Mail)?
+                    .into();
+                method::Response::UploadBlob(store.upload_blob(request)?)
+            }
             method::Request::GetPushSubscription(mut request) => {
                 request.account_id = account_id.into();
                 request.acl = store.get_acl_token(account_id)?.into();
@@ -468,6 +517,218 @@ where
                     .into();
                 method::Response::SetPrincipal(store.principal_set(request)?)
             }
+            method::Request::GetQuota(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_is_member(request.account_id.get_document_id())?
+                    .into();
+                method::Response::GetQuota(store.quota_get(request)?)
+            }
+            method::Request::ChangesQuota(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_is_member(request.account_id.get_document_id())?
+                    .into();
+                method::Response::ChangesQuota(store.quota_changes(request)?)
+            }
+            method::Request::QueryQuota(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_is_member(request.account_id.get_document_id())?
+                    .into();
+                method::Response::QueryQuota(store.quota_query(request)?)
+            }
+            method::Request::GetAddressBook(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::AddressBook,
+                    )?
+                    .into();
+                method::Response::GetAddressBook(store.address_book_get(request)?)
+            }
+            method::Request::ChangesAddressBook(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::AddressBook,
+                    )?
+                    .into();
+                method::Response::ChangesAddressBook(store.address_book_changes(request)?)
+            }
+            method::Request::QueryAddressBook(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::AddressBook,
+                    )?
+                    .into();
+                method::Response::QueryAddressBook(store.address_book_query(request)?)
+            }
+            method::Request::QueryChangesAddressBook(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::AddressBook,
+                    )?
+                    .into();
+                method::Response::QueryChangesAddressBook(
+                    store.address_book_query_changes(request)?,
+                )
+            }
+            method::Request::SetAddressBook(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::AddressBook,
+                    )?
+                    .into();
+                method::Response::SetAddressBook(store.address_book_set(request)?)
+            }
+            method::Request::GetContactCard(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::ContactCard,
+                    )?
+                    .into();
+                method::Response::GetContactCard(store.contact_card_get(request)?)
+            }
+            method::Request::ChangesContactCard(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::ContactCard,
+                    )?
+                    .into();
+                method::Response::ChangesContactCard(store.contact_card_changes(request)?)
+            }
+            method::Request::QueryContactCard(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::ContactCard,
+                    )?
+                    .into();
+                method::Response::QueryContactCard(store.contact_card_query(request)?)
+            }
+            method::Request::QueryChangesContactCard(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::ContactCard,
+                    )?
+                    .into();
+                method::Response::QueryChangesContactCard(
+                    store.contact_card_query_changes(request)?,
+                )
+            }
+            method::Request::SetContactCard(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::ContactCard,
+                    )?
+                    .into();
+                method::Response::SetContactCard(store.contact_card_set(request)?)
+            }
+            method::Request::GetCalendar(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Calendar)?
+                    .into();
+                method::Response::GetCalendar(store.calendar_get(request)?)
+            }
+            method::Request::ChangesCalendar(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Calendar)?
+                    .into();
+                method::Response::ChangesCalendar(store.calendar_changes(request)?)
+            }
+            method::Request::QueryCalendar(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Calendar)?
+                    .into();
+                method::Response::QueryCalendar(store.calendar_query(request)?)
+            }
+            method::Request::QueryChangesCalendar(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Calendar)?
+                    .into();
+                method::Response::QueryChangesCalendar(store.calendar_query_changes(request)?)
+            }
+            method::Request::SetCalendar(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(request.account_id.get_document_id(), Collection::Calendar)?
+                    .into();
+                method::Response::SetCalendar(store.calendar_set(request)?)
+            }
+            method::Request::GetCalendarEvent(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::CalendarEvent,
+                    )?
+                    .into();
+                method::Response::GetCalendarEvent(store.calendar_event_get(request)?)
+            }
+            method::Request::ChangesCalendarEvent(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::CalendarEvent,
+                    )?
+                    .into();
+                method::Response::ChangesCalendarEvent(store.calendar_event_changes(request)?)
+            }
+            method::Request::QueryCalendarEvent(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::CalendarEvent,
+                    )?
+                    .into();
+                method::Response::QueryCalendarEvent(store.calendar_event_query(request)?)
+            }
+            method::Request::QueryChangesCalendarEvent(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::CalendarEvent,
+                    )?
+                    .into();
+                method::Response::QueryChangesCalendarEvent(
+                    store.calendar_event_query_changes(request)?,
+                )
+            }
+            method::Request::SetCalendarEvent(mut request) => {
+                request.acl = store
+                    .get_acl_token(account_id)?
+                    .assert_has_access(
+                        request.account_id.get_document_id(),
+                        Collection::CalendarEvent,
+                    )?
+                    .into();
+                method::Response::SetCalendarEvent(store.calendar_event_set(request)?)
+            }
             method::Request::Echo(payload) => method::Response::Echo(payload),
             method::Request::Error(err) => return Err(err),
         })
@@ -152,6 +152,14 @@ impl RequestError {
         )
     }
 
+    pub fn over_quota() -> Self {
+        RequestError::blank(
+            413,
+            "Quota Exceeded",
+            "Your account does not have enough quota to store this blob.",
+        )
+    }
+
     pub fn too_many_requests() -> Self {
         RequestError::blank(
             429,
@@ -34,7 +34,7 @@ use jmap_sharing::principal::account::JMAPAccountStore;
 use store::{
     ahash::AHashSet,
     config::{env_settings::EnvSettings, jmap::JMAPConfig},
-    core::{acl::ACL, vec_map::VecMap},
+    core::{acl::ACL, collection::Collection, vec_map::VecMap},
     sieve::compiler::grammar::Capability,
     Store,
 };
@@ -90,6 +90,10 @@ enum Capabilities {
     VacationResponse(VacationResponseCapabilities),
     WebSocket(WebSocketCapabilities),
     Sieve(SieveCapabilities),
+    Quota(QuotaCapabilities),
+    Contacts(ContactsCapabilities),
+    Calendars(CalendarsCapabilities),
+    Blob(BlobCapabilities),
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -165,6 +169,37 @@ struct SubmissionCapabilities {
 #[derive(Debug, Clone, serde::Serialize)]
 struct VacationResponseCapabilities {}
 
+#[derive(Debug, Clone, serde::Serialize)]
+struct QuotaCapabilities {}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct ContactsCapabilities {
+    #[serde(rename(serialize = "maxAddressBooksPerCard"))]
+    max_address_books_per_card: Option<usize>,
+    #[serde(rename(serialize = "mayCreateAddressBook"))]
+    may_create_address_book: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct CalendarsCapabilities {
+    #[serde(rename(serialize = "maxCalendarsPerEvent"))]
+    max_calendars_per_event: Option<usize>,
+    #[serde(rename(serialize = "mayCreateCalendar"))]
+    may_create_calendar: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct BlobCapabilities {
+    #[serde(rename(serialize = "maxSizeBlobSet"))]
+    max_size_blob_set: usize,
+    #[serde(rename(serialize = "maxDataSources"))]
+    max_data_sources: usize,
+    #[serde(rename(serialize = "supportedTypeNames"))]
+    supported_type_names: Vec<String>,
+    #[serde(rename(serialize = "supportedDigestAlgorithms"))]
+    supported_digest_algorithms: Vec<String>,
+}
+
 impl Session {
     pub fn new(settings: &EnvSettings, config: &JMAPConfig) -> Session {
         let base_url = settings.get("jmap-url").unwrap();
@@ -181,6 +216,29 @@ impl Session {
                     URI::Sieve,
                     Capabilities::Sieve(SieveCapabilities::new(settings, config)),
                 ),
+                (URI::Quota, Capabilities::Quota(QuotaCapabilities {})),
+                (
+                    URI::Contacts,
+                    Capabilities::Contacts(ContactsCapabilities {
+                        max_address_books_per_card: None,
+                        may_create_address_book: true,
+                    }),
+                ),
+                (
+                    URI::Calendars,
+                    Capabilities::Calendars(CalendarsCapabilities {
+                        max_calendars_per_event: None,
+                        may_create_calendar: true,
+                    }),
+                ),
+                (URI::Blob, Capabilities::Blob(BlobCapabilities::new(config))),
+                (
+                    URI::Submission,
+                    Capabilities::Submission(SubmissionCapabilities {
+                        max_delayed_send: config.submission_max_delayed_send as usize,
+                        submission_extensions: vec!["FUTURERELEASE".to_string()],
+                    }),
+                ),
             ]),
             accounts: VecMap::new(),
             primary_accounts: VecMap::new(),
@@ -301,6 +359,20 @@ impl CoreCapabilities {
     }
 }
 
+impl BlobCapabilities {
+    pub fn new(config: &JMAPConfig) -> Self {
+        BlobCapabilities {
+            max_size_blob_set: config.max_size_upload,
+            max_data_sources: config.blob_max_data_sources,
+            supported_type_names: ["Email", "Mailbox", "Thread"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            supported_digest_algorithms: vec!["sha".to_string(), "sha-256".to_string()],
+        }
+    }
+}
+
 impl WebSocketCapabilities {
     pub fn new(base_url: &str) -> Self {
         WebSocketCapabilities {
@@ -424,12 +496,21 @@ where
                         false
                     };
 
+                    let mut capabilities = vec![URI::Core, URI::Mail, URI::Blob];
+                    if acl.has_access(*id, Collection::AddressBook) {
+                        capabilities.push(URI::Contacts);
+                    }
+                    if acl.has_access(*id, Collection::Calendar) {
+                        capabilities.push(URI::Calendars);
+                    }
+                    capabilities.push(URI::WebSocket);
+
                     response.add_account(
                         (*id).into(),
                         if !name.is_empty() { name } else { email },
                         matches!(ptype, Type::Individual),
                         is_readonly,
-                        Some(&[URI::Core, URI::Mail, URI::WebSocket]),
+                        Some(capabilities.as_slice()),
                     );
                 }
             }
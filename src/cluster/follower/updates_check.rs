@@ -57,12 +57,14 @@ where
                                 },
                         } if !blobs.is_empty() || term_index.is_some() => {
                             for blob in blobs {
-                                if !store.blob_exists(blob)? {
+                                if !store.blob_exists(blob)? && !store.blob_register_shared(blob)? {
                                     missing_blob_ids.insert(blob.clone());
                                 }
                             }
                             if let Some(term_index) = term_index {
-                                if !store.blob_exists(term_index)? {
+                                if !store.blob_exists(term_index)?
+                                    && !store.blob_register_shared(term_index)?
+                                {
                                     missing_blob_ids.insert(term_index.clone());
                                 }
                             }
@@ -45,9 +45,9 @@ where
         mut updates: Vec<Update>,
     ) -> Option<(State, Response)> {
         loop {
-            // Thread collection does not contain any actual records,
-            // it exists solely for change tracking.
-            if let Collection::Thread = collection {
+            // Thread and Quota collections do not contain any actual records,
+            // they exist solely for change tracking.
+            if let Collection::Thread | Collection::Quota = collection {
                 changes.inserts.clear();
                 changes.updates.clear();
                 changes.deletes.clear();
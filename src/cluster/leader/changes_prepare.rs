@@ -27,6 +27,10 @@ use crate::cluster::log::{DocumentUpdate, Update};
 use crate::JMAPServer;
 use jmap::principal::schema::Principal;
 use jmap::push_subscription::schema::PushSubscription;
+use jmap_calendars::calendar::schema::Calendar;
+use jmap_calendars::calendar_event::schema::CalendarEvent;
+use jmap_contacts::address_book::schema::AddressBook;
+use jmap_contacts::contact_card::schema::ContactCard;
 use jmap_mail::email_submission::schema::EmailSubmission;
 use jmap_mail::identity::schema::Identity;
 use jmap_mail::mail::schema::Email;
@@ -91,9 +95,23 @@ where
                     Collection::SieveScript => {
                         store.raft_prepare_update::<SieveScript>(account_id, document_id, is_insert)
                     }
-                    Collection::Thread | Collection::None => Err(StoreError::InternalError(
-                        "Unsupported collection for changes".into(),
-                    )),
+                    Collection::AddressBook => {
+                        store.raft_prepare_update::<AddressBook>(account_id, document_id, is_insert)
+                    }
+                    Collection::ContactCard => {
+                        store.raft_prepare_update::<ContactCard>(account_id, document_id, is_insert)
+                    }
+                    Collection::Calendar => {
+                        store.raft_prepare_update::<Calendar>(account_id, document_id, is_insert)
+                    }
+                    Collection::CalendarEvent => store.raft_prepare_update::<CalendarEvent>(
+                        account_id,
+                        document_id,
+                        is_insert,
+                    ),
+                    Collection::Thread | Collection::Quota | Collection::None => Err(
+                        StoreError::InternalError("Unsupported collection for changes".into()),
+                    ),
                 })
                 .await?;
 
@@ -23,12 +23,12 @@
 
 use jmap_sharing::principal::account::JMAPAccountStore;
 use serde::{Deserialize, Serialize};
-use store::{tracing::error, RecipientType, Store};
+use store::{tracing::error, AccountId, DocumentId, RecipientType, Store};
 use tokio::sync::oneshot;
 
 use crate::{
     cluster::{self, Cluster},
-    lmtp::session::RcptType,
+    lmtp::{auth::AuthenticationResults, session::RcptType, submission::mail_submit},
     JMAPServer,
 };
 
@@ -43,6 +43,14 @@ pub enum Command {
         mail_from: String,
         rcpt_to: Vec<RcptType>,
         raw_message: Vec<u8>,
+        auth_results: Option<AuthenticationResults>,
+    },
+    SubmitMessage {
+        account_id: AccountId,
+        identity_id: DocumentId,
+        mail_from: String,
+        rcpt_to: Vec<String>,
+        raw_message: Vec<u8>,
     },
 }
 
@@ -54,6 +62,9 @@ pub enum CommandResponse {
     IngestMessage {
         result: Result<Vec<RcptType>, String>,
     },
+    SubmitMessage {
+        result: Result<(), String>,
+    },
     Error {
         message: String,
     },
@@ -122,8 +133,28 @@ where
                         mail_from,
                         rcpt_to,
                         raw_message,
+                        auth_results,
                     } => CommandResponse::IngestMessage {
-                        result: core.mail_ingest(mail_from, rcpt_to, raw_message).await,
+                        result: core
+                            .mail_ingest(mail_from, rcpt_to, raw_message, auth_results)
+                            .await,
+                    },
+                    Command::SubmitMessage {
+                        account_id,
+                        identity_id,
+                        mail_from,
+                        rcpt_to,
+                        raw_message,
+                    } => CommandResponse::SubmitMessage {
+                        result: mail_submit(
+                            core,
+                            account_id,
+                            identity_id,
+                            mail_from,
+                            rcpt_to,
+                            raw_message,
+                        )
+                        .await,
                     },
                 };
 
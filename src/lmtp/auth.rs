@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_sharing::principal::{
+    dkim::{
+        arc::ChainValidation,
+        verify::{split_message, DkimKeys, DkimResult, MessageVerifier},
+    },
+    get::JMAPGetPrincipal,
+};
+use serde::{Deserialize, Serialize};
+use store::{config::env_settings::EnvSettings, tracing::debug, Store};
+
+use crate::{services::mx::MxResolver, JMAPServer};
+
+pub struct MessageAuthenticator {
+    pub verify_dkim: bool,
+    pub resolver: Option<MxResolver>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthenticationResults {
+    pub value: String,
+    pub chain_validation: ChainValidation,
+    #[serde(default)]
+    pub dkim_fail: bool,
+}
+
+impl MessageAuthenticator {
+    pub fn parse(settings: &EnvSettings) -> Self {
+        let verify_dkim = settings.parse("lmtp-dkim-verify").unwrap_or(true);
+        MessageAuthenticator {
+            verify_dkim,
+            // The resolver is only needed to fetch public keys
+            resolver: if verify_dkim {
+                MxResolver::parse(settings).into()
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Verifies the DKIM signatures and ARC chain of an incoming message,
+    /// returning the results to add to it.
+    pub async fn authenticate<T>(
+        &self,
+        core: &JMAPServer<T>,
+        hostname: &str,
+        message: &[u8],
+    ) -> Option<AuthenticationResults>
+    where
+        T: for<'x> Store<'x> + 'static,
+    {
+        if !self.verify_dkim {
+            return None;
+        }
+
+        // Fetch public keys, local domains first
+        let verifier = MessageVerifier::new(message);
+        let mut keys = DkimKeys::new();
+        for (domain, selector) in verifier.keys() {
+            let record = self.key_lookup(core, &domain, &selector).await;
+            keys.insert((domain, selector), record);
+        }
+
+        let mut auth_results = format!("{};", hostname);
+        let dkim = verifier.verify_dkim(&keys);
+        let dkim_fail = dkim
+            .iter()
+            .any(|output| matches!(output.result, DkimResult::Fail(_)))
+            && !dkim.iter().any(|output| output.result == DkimResult::Pass);
+        if !dkim.is_empty() {
+            for output in dkim {
+                auth_results.push_str("\r\n\tdkim=");
+                auth_results.push_str(output.result.as_str());
+                if let Some(reason) = output.result.reason() {
+                    auth_results.push_str(&format!(" ({})", reason));
+                }
+                if !output.domain.is_empty() {
+                    auth_results.push_str(&format!(
+                        " header.d={} header.s={} header.b={}",
+                        output.domain, output.selector, output.signature
+                    ));
+                }
+                auth_results.push(';');
+            }
+        } else {
+            auth_results.push_str("\r\n\tdkim=none;");
+        }
+        auth_results.push_str("\r\n\tarc=");
+        let chain_validation = match verifier.verify_arc(&keys) {
+            Some(result) => {
+                auth_results.push_str(result.as_str());
+                if let Some(reason) = result.reason() {
+                    auth_results.push_str(&format!(" ({})", reason));
+                }
+                if result == DkimResult::Pass {
+                    ChainValidation::Pass
+                } else {
+                    ChainValidation::Fail
+                }
+            }
+            None => {
+                auth_results.push_str("none");
+                ChainValidation::None
+            }
+        };
+
+        Some(AuthenticationResults {
+            value: auth_results,
+            chain_validation,
+            dkim_fail,
+        })
+    }
+
+    async fn key_lookup<T>(
+        &self,
+        core: &JMAPServer<T>,
+        domain: &str,
+        selector: &str,
+    ) -> Result<String, DkimResult>
+    where
+        T: for<'x> Store<'x> + 'static,
+    {
+        let store = core.store.clone();
+        let domain_ = domain.to_string();
+        let selector_ = selector.to_string();
+        match core
+            .spawn_worker(move || store.dkim_record_get(&domain_, &selector_))
+            .await
+        {
+            Ok(Some(record)) => return Ok(record),
+            Ok(None) => (),
+            Err(err) => {
+                debug!("Failed to obtain local DKIM record for {}: {}", domain, err);
+            }
+        }
+
+        let resolver = if let Some(resolver) = &self.resolver {
+            resolver
+        } else {
+            return Err(DkimResult::TempError("no resolver available".to_string()));
+        };
+        match resolver
+            .txt_lookup(&format!("{}._domainkey.{}.", selector, domain))
+            .await
+        {
+            Ok(records) => records
+                .into_iter()
+                .find(|record| record.contains("p="))
+                .ok_or_else(|| DkimResult::PermError("no key record".to_string())),
+            Err(err) if err.is_transient() => {
+                Err(DkimResult::TempError(format!("key lookup failed: {}", err)))
+            }
+            Err(_) => Err(DkimResult::PermError("no key record".to_string())),
+        }
+    }
+}
+
+impl AuthenticationResults {
+    pub fn to_header(&self) -> String {
+        format!("Authentication-Results: {}\r\n", self.value)
+    }
+
+    /// Whether the message was signed but none of its DKIM signatures verified.
+    pub fn is_forged(&self) -> bool {
+        self.dkim_fail
+    }
+}
+
+/// Removes any Authentication-Results headers that claim to have been added
+/// by this server (RFC 8601, section 5).
+pub fn remove_auth_results(message: Vec<u8>, hostname: &str) -> Vec<u8> {
+    let mut remove = Vec::new();
+    for (name, value) in split_message(&message).0 {
+        if String::from_utf8_lossy(name)
+            .trim_end()
+            .eq_ignore_ascii_case("Authentication-Results")
+            && String::from_utf8_lossy(value)
+                .trim_start()
+                .split(|ch: char| ch == ';' || ch == '(' || ch.is_ascii_whitespace())
+                .next()
+                .map_or(false, |authserv_id| {
+                    authserv_id.eq_ignore_ascii_case(hostname)
+                })
+        {
+            let start = name.as_ptr() as usize - message.as_ptr() as usize;
+            remove.push(start..start + name.len() + 1 + value.len());
+        }
+    }
+
+    if !remove.is_empty() {
+        let mut result = Vec::with_capacity(message.len());
+        let mut pos = 0;
+        for range in remove {
+            result.extend_from_slice(&message[pos..range.start]);
+            pos = range.end;
+        }
+        result.extend_from_slice(&message[pos..]);
+        result
+    } else {
+        message
+    }
+}
@@ -25,10 +25,12 @@ use std::{borrow::Cow, sync::Arc, time::SystemTime};
 
 use jmap::{
     orm::TinyORM,
+    principal::store::JMAPPrincipals,
     sanitize_email,
     types::{jmap::JMAPId, type_state::TypeState},
 };
 use jmap_mail::{
+    email_submission::report::JMAPEmailSubmissionReport,
     mail::{
         import::JMAPMailImport,
         schema::{Email, Keyword, Property},
@@ -37,7 +39,7 @@ use jmap_mail::{
     mailbox::{get::JMAPGetMailbox, is_valid_role, set::JMAPSetMailbox},
     INBOX_ID, TRASH_ID,
 };
-use jmap_sharing::principal::account::JMAPAccountStore;
+use jmap_sharing::principal::{account::JMAPAccountStore, get::JMAPGetPrincipal};
 use jmap_sieve::{
     sieve_script::{
         get::JMAPGetSieveScript,
@@ -64,6 +66,7 @@ use crate::{
 };
 
 use super::{
+    auth::{remove_auth_results, AuthenticationResults},
     session::{RcptType, Session},
     OutgoingMessage,
 };
@@ -73,6 +76,11 @@ where
     T: for<'x> Store<'x> + 'static,
 {
     pub async fn ingest_message(&mut self) -> Result<(), ()> {
+        // Authenticated submissions are relayed rather than delivered locally
+        if self.submission.is_some() {
+            return self.submit_message().await;
+        }
+
         // Validate request
         if self.message.is_empty() {
             return self
@@ -87,13 +95,29 @@ where
         } else {
             return self.write_bytes(b"503 5.5.1 Missing MAIL FROM.\r\n").await;
         };
-        let message = std::mem::take(&mut self.message);
         self.rcpt_to_dup.clear();
 
+        // Results claiming to come from this server cannot be trusted
+        let mut message = remove_auth_results(std::mem::take(&mut self.message), &self.hostname);
+
+        // Verify DKIM signatures and ARC chain
+        let auth_results = self
+            .authenticator
+            .authenticate(&self.core, &self.hostname, &message)
+            .await;
+        if let Some(auth_results) = &auth_results {
+            message = [auth_results.to_header().as_bytes(), &message].concat();
+        }
+
         // Ingest
         let result = if self.core.is_leader() {
             self.core
-                .mail_ingest(mail_from, std::mem::take(&mut self.rcpt_to), message)
+                .mail_ingest(
+                    mail_from,
+                    std::mem::take(&mut self.rcpt_to),
+                    message,
+                    auth_results,
+                )
                 .await
         } else {
             // Send request to leader
@@ -103,6 +127,7 @@ where
                     mail_from,
                     rcpt_to: std::mem::take(&mut self.rcpt_to),
                     raw_message: message,
+                    auth_results,
                 })
                 .await
             {
@@ -132,8 +157,9 @@ where
             match status {
                 DeliveryStatus::Success => buf.extend_from_slice(b"250 2.1.5 <"),
                 DeliveryStatus::TemporaryFailure { .. } => buf.extend_from_slice(b"451 4.3.0 <"),
-                DeliveryStatus::PermanentFailure { code, .. } => {
-                    buf.extend_from_slice(b"550 ");
+                DeliveryStatus::PermanentFailure { reply, code, .. } => {
+                    buf.extend_from_slice(reply.to_string().as_bytes());
+                    buf.push(b' ');
                     buf.extend_from_slice(code.as_bytes());
                     buf.extend_from_slice(b" <");
                 }
@@ -203,11 +229,14 @@ where
         mail_from: String,
         rcpt_to: Vec<RcptType>,
         raw_message: Vec<u8>,
+        auth_results: Option<AuthenticationResults>,
     ) -> Result<Vec<RcptType>, String> {
         // Ingest message
         let store = self.store.clone();
         let status = match self
-            .spawn_worker(move || Ok(store.mail_ingest(mail_from, rcpt_to, raw_message)))
+            .spawn_worker(move || {
+                Ok(store.mail_ingest(mail_from, rcpt_to, raw_message, auth_results))
+            })
             .await
             .unwrap()
         {
@@ -273,8 +302,10 @@ pub trait JMAPMailIngest {
         mail_from: String,
         rcpt_to: Vec<RcptType>,
         raw_message: Vec<u8>,
+        auth_results: Option<AuthenticationResults>,
     ) -> Result<IngestResult, Option<&'static str>>;
 
+    #[allow(clippy::too_many_arguments)]
     fn mail_deliver_rcpt(
         &self,
         result: &mut IngestResult,
@@ -283,6 +314,7 @@ pub trait JMAPMailIngest {
         blob_id: &BlobId,
         envelope_from: &str,
         envelope_to: &str,
+        auth_results: Option<&AuthenticationResults>,
     ) -> DeliveryStatus;
 
     #[allow(clippy::result_unit_err)]
@@ -306,6 +338,7 @@ where
         mail_from: String,
         rcpt_to: Vec<RcptType>,
         raw_message: Vec<u8>,
+        auth_results: Option<AuthenticationResults>,
     ) -> Result<IngestResult, Option<&'static str>> {
         // Store raw message as a blob
         let blob_id = BlobId::new_external(&raw_message);
@@ -349,6 +382,7 @@ where
                             &blob_id,
                             &mail_from,
                             &*name,
+                            auth_results.as_ref(),
                         );
                         if let Some(prev_status) = &mut prev_status {
                             prev_status.insert(*id, status.clone());
@@ -376,6 +410,7 @@ where
                                 &blob_id,
                                 &mail_from,
                                 &*name,
+                                auth_results.as_ref(),
                             );
 
                             match &status {
@@ -401,6 +436,7 @@ where
                             }
                         } else {
                             DeliveryStatus::PermanentFailure {
+                                reply: 550,
                                 code: "5.5.0".into(),
                                 reason: "permanent failure".into(),
                             }
@@ -425,6 +461,7 @@ where
         blob_id: &BlobId,
         envelope_from: &str,
         envelope_to: &str,
+        auth_results: Option<&AuthenticationResults>,
     ) -> DeliveryStatus {
         // Verify that this account has an Inbox mailbox
         let mailbox_ids = match self.get_document_ids(account_id, Collection::Mailbox) {
@@ -435,6 +472,25 @@ where
             }
         };
 
+        // Make sure the account has enough quota
+        match self.principal_quota(account_id) {
+            Ok(Some(quota)) => match self.get_used_quota(account_id) {
+                Ok(used_quota) if used_quota + raw_message.len() as i64 > quota => {
+                    return DeliveryStatus::over_quota();
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    error!("Failed to obtain used quota for {}: {}", account_id, err);
+                    return DeliveryStatus::internal_error();
+                }
+            },
+            Ok(None) => (),
+            Err(err) => {
+                error!("Failed to obtain quota for {}: {}", account_id, err);
+                return DeliveryStatus::internal_error();
+            }
+        }
+
         // Parse message
         let message = if let Some(message) = Message::parse(raw_message) {
             message
@@ -442,6 +498,44 @@ where
             return DeliveryStatus::perm_failure("Failed to parse message.");
         };
 
+        // Update the e-mail submissions referenced by delivery and read reports
+        match self.email_submission_report(
+            account_id,
+            &message,
+            blob_id,
+            envelope_from,
+            auth_results.map_or(false, |auth_results| auth_results.is_forged()),
+        ) {
+            Ok(Some(changes)) => {
+                result.add_changes(account_id, changes);
+            }
+            Ok(None) => (),
+            Err(err) => {
+                error!(
+                    "Failed to process delivery report for {}: {}",
+                    account_id, err
+                );
+            }
+        }
+
+        // Obtain the detail part of subaddresses
+        let subaddress = match self.rcpt_subaddress(envelope_to) {
+            Ok(subaddress) => subaddress,
+            Err(err) => {
+                error!("Failed to expand subaddress {}: {}", envelope_to, err);
+                None
+            }
+        };
+        let default_mailbox_id = match &subaddress {
+            Some((_, detail)) if self.config.lmtp_subaddress_autofile && !detail.is_empty() => {
+                match self.mailbox_get_by_name(account_id, detail) {
+                    Ok(Some(mailbox_id)) => mailbox_id,
+                    _ => INBOX_ID,
+                }
+            }
+            _ => INBOX_ID,
+        };
+
         let mut active_script = match self.sieve_script_get_active(account_id) {
             Ok(None) => {
                 return if self
@@ -450,7 +544,7 @@ where
                         account_id,
                         message,
                         blob_id,
-                        &[INBOX_ID],
+                        &[default_mailbox_id],
                         Vec::new(),
                     )
                     .is_ok()
@@ -469,7 +563,7 @@ where
                         account_id,
                         message,
                         blob_id,
-                        &[INBOX_ID],
+                        &[default_mailbox_id],
                         Vec::new(),
                     )
                     .is_ok()
@@ -498,9 +592,17 @@ where
             }
         };
 
-        // Set envelope
+        // Set envelope, Sieve's subaddress extension expects '+' as the separator
         instance.set_envelope(Envelope::From, envelope_from);
-        instance.set_envelope(Envelope::To, envelope_to);
+        if let Some((base_address, detail)) = &subaddress {
+            let (user, domain) = base_address.rsplit_once('@').unwrap_or_default();
+            instance.set_envelope(
+                Envelope::To,
+                format!("{}+{}@{}", user, detail, domain).as_str(),
+            );
+        } else {
+            instance.set_envelope(Envelope::To, envelope_to);
+        }
 
         let mut input = Input::script(
             if let Some(Value::Text { value }) = active_script
@@ -640,8 +742,8 @@ where
                         if let Some(message) = messages.get_mut(message_id) {
                             message.flags =
                                 flags.into_iter().map(|f| Keyword::parse(&f).tag).collect();
-                            if !message.file_into.contains(&INBOX_ID) {
-                                message.file_into.push(INBOX_ID);
+                            if !message.file_into.contains(&default_mailbox_id) {
+                                message.file_into.push(default_mailbox_id);
                             }
                             do_deliver = true;
                         } else {
@@ -700,8 +802,7 @@ where
                             {
                                 target_id = document_id;
                                 if let Some(changes) = changes {
-                                    result.last_change_id = changes.change_id;
-                                    result.changes.insert(account_id, changes);
+                                    result.add_changes(account_id, changes);
                                 }
                             }
                         }
@@ -741,7 +842,12 @@ where
                                 }
                             },
                             message: if let Some(message) = messages.get(message_id) {
-                                message.raw_message.to_vec()
+                                arc_seal(
+                                    self,
+                                    envelope_to,
+                                    auth_results,
+                                    message.raw_message.as_ref(),
+                                )
                             } else {
                                 error!("Sieve filter failed: Unknown message id {}.", message_id);
                                 continue;
@@ -785,7 +891,7 @@ where
 
         // Fail-safe, no discard and no keep seen, assume that something went wrong and file anyway.
         if !do_deliver && !do_discard {
-            messages[0].file_into.push(INBOX_ID);
+            messages[0].file_into.push(default_mailbox_id);
         }
 
         // Deliver messages
@@ -887,6 +993,7 @@ where
 
         if let Some(reject_reason) = reject_reason {
             DeliveryStatus::PermanentFailure {
+                reply: 550,
                 code: "5.7.1".into(),
                 reason: reject_reason.into(),
             }
@@ -953,8 +1060,7 @@ where
                 batch.insert_document(document);
                 match self.write(batch) {
                     Ok(Some(changes)) => {
-                        result.last_change_id = changes.change_id;
-                        result.changes.insert(account_id, changes);
+                        result.add_changes(account_id, changes);
                         Ok(())
                     }
                     Ok(None) => {
@@ -975,6 +1081,61 @@ where
     }
 }
 
+// Adds an ARC set to redirected messages so that receivers can rely on the
+// authentication results obtained at delivery time (RFC 8617).
+fn arc_seal<T>(
+    store: &JMAPStore<T>,
+    envelope_to: &str,
+    auth_results: Option<&AuthenticationResults>,
+    raw_message: &[u8],
+) -> Vec<u8>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Messages that were not authenticated on arrival are not sealed
+    let auth_results = if let Some(auth_results) = auth_results {
+        auth_results
+    } else {
+        return raw_message.to_vec();
+    };
+    let domain = envelope_to
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default();
+    let sealer = match store.arc_sealer_get(domain.clone()) {
+        Ok(Some(sealer)) => sealer.headers(&[
+            "From",
+            "To",
+            "Subject",
+            "Date",
+            "Cc",
+            "Message-ID",
+            "References",
+            "In-Reply-To",
+            "DKIM-Signature",
+            "MIME-Version",
+            "Content-Type",
+        ]),
+        Ok(None) => return raw_message.to_vec(),
+        Err(err) => {
+            error!("Failed to obtain DKIM key for {}: {}", domain, err);
+            return raw_message.to_vec();
+        }
+    };
+
+    match sealer.seal(
+        raw_message,
+        &auth_results.value,
+        auth_results.chain_validation,
+    ) {
+        Ok(seal) => [seal.as_bytes(), raw_message].concat(),
+        Err(err) => {
+            error!("Failed to ARC seal message: {}", err);
+            raw_message.to_vec()
+        }
+    }
+}
+
 struct SieveMessage<'x> {
     pub raw_message: Cow<'x, [u8]>,
     pub file_into: Vec<DocumentId>,
@@ -988,6 +1149,18 @@ pub struct IngestResult {
     pub messages: Vec<OutgoingMessage>,
 }
 
+impl IngestResult {
+    fn add_changes(&mut self, account_id: AccountId, changes: Changes) {
+        self.last_change_id = changes.change_id;
+        if let Some(account_changes) = self.changes.get_mut(&account_id) {
+            account_changes.collections.union(&changes.collections);
+            account_changes.change_id = changes.change_id;
+        } else {
+            self.changes.insert(account_id, changes);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DeliveryStatus {
     Success,
@@ -995,6 +1168,7 @@ pub enum DeliveryStatus {
         reason: Cow<'static, str>,
     },
     PermanentFailure {
+        reply: u16,
         code: Cow<'static, str>,
         reason: Cow<'static, str>,
     },
@@ -1010,8 +1184,17 @@ impl DeliveryStatus {
 
     pub fn perm_failure(reason: impl Into<Cow<'static, str>>) -> Self {
         DeliveryStatus::PermanentFailure {
+            reply: 550,
             code: "5.5.0".into(),
             reason: reason.into(),
         }
     }
+
+    pub fn over_quota() -> Self {
+        DeliveryStatus::PermanentFailure {
+            reply: 552,
+            code: "5.2.2".into(),
+            reason: "Mailbox full".into(),
+        }
+    }
 }
@@ -37,13 +37,24 @@ use tokio::{io::AsyncWriteExt, net::TcpListener, sync::watch};
 use tokio_rustls::TlsAcceptor;
 
 use crate::{
-    cluster::rpc::tls::load_tls_server_config, lmtp::session::Session, server::failed_to,
+    cluster::rpc::tls::load_tls_server_config,
+    lmtp::{auth::MessageAuthenticator, session::Session, submission::Submission},
+    server::failed_to,
     JMAPServer,
 };
 
 const TIMEOUT: Duration = Duration::from_secs(5 * 60); // 5 minutes
 const DEFAULT_LMTP_PORT: u16 = 11200;
 
+struct Listener {
+    protocol: &'static str,
+    bind_addr: SocketAddr,
+    trusted_ips: Option<Vec<IpAddr>>,
+    tls_acceptor: Option<Arc<TlsAcceptor>>,
+    tls_only: bool,
+    is_submission: bool,
+}
+
 pub fn init_lmtp() -> (watch::Sender<bool>, watch::Receiver<bool>) {
     watch::channel::<bool>(true)
 }
@@ -51,58 +62,157 @@ pub fn init_lmtp() -> (watch::Sender<bool>, watch::Receiver<bool>) {
 pub fn spawn_lmtp<T>(
     core: web::Data<JMAPServer<T>>,
     settings: &EnvSettings,
-    mut shutdown_rx: watch::Receiver<bool>,
+    shutdown_rx: watch::Receiver<bool>,
 ) where
     T: for<'x> Store<'x> + 'static,
 {
-    // Parse bind address
-    let bind_addr = SocketAddr::from((
-        settings.parse_ipaddr("lmtp-bind-addr", "127.0.0.1"),
-        settings.parse("lmtp-port").unwrap_or(DEFAULT_LMTP_PORT),
-    ));
-    info!("Starting LMTP service at {}...", bind_addr);
-
-    // Parse allowed IPs
-    let trusted_ips = if let Some(trusted_ips_) = settings.get("lmtp-trusted-ips") {
-        let mut trusted_ips = Vec::new();
-        for ip in trusted_ips_.split(';') {
-            trusted_ips.push(ip.parse::<IpAddr>().unwrap_or_else(|_| {
-                failed_to(&format!("parse 'lmtp-trusted-ips', invalid ip {}.", ip));
-            }));
-        }
-        if !trusted_ips.is_empty() {
-            trusted_ips.into()
+    spawn_listener(
+        core,
+        settings,
+        Listener::parse(
+            settings,
+            "lmtp",
+            SocketAddr::from((
+                settings.parse_ipaddr("lmtp-bind-addr", "127.0.0.1"),
+                settings.parse("lmtp-port").unwrap_or(DEFAULT_LMTP_PORT),
+            )),
+            false,
+        ),
+        shutdown_rx,
+    );
+}
+
+pub fn spawn_submission<T>(
+    core: web::Data<JMAPServer<T>>,
+    settings: &EnvSettings,
+    shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    // The submission service is only started when a port is configured
+    if let Some(port) = settings.parse("submission-port") {
+        spawn_listener(
+            core,
+            settings,
+            Listener::parse(
+                settings,
+                "submission",
+                SocketAddr::from((
+                    settings.parse_ipaddr("submission-bind-addr", "0.0.0.0"),
+                    port,
+                )),
+                true,
+            ),
+            shutdown_rx,
+        );
+    }
+}
+
+impl Listener {
+    fn parse(
+        settings: &EnvSettings,
+        prefix: &str,
+        bind_addr: SocketAddr,
+        is_submission: bool,
+    ) -> Self {
+        let protocol = if is_submission {
+            "SMTP submission"
+        } else {
+            "LMTP"
+        };
+
+        // Parse allowed IPs
+        let trusted_ips =
+            if let Some(trusted_ips_) = settings.get(&format!("{}-trusted-ips", prefix)) {
+                let mut trusted_ips = Vec::new();
+                for ip in trusted_ips_.split(';') {
+                    trusted_ips.push(ip.parse::<IpAddr>().unwrap_or_else(|_| {
+                        failed_to(&format!(
+                            "parse '{}-trusted-ips', invalid ip {}.",
+                            prefix, ip
+                        ));
+                    }));
+                }
+                if !trusted_ips.is_empty() {
+                    trusted_ips.into()
+                } else {
+                    failed_to(&format!(
+                        "parse '{}-trusted-ips', no entries found.",
+                        prefix
+                    ));
+                }
+            } else {
+                None
+            };
+
+        // Build TLS acceptor
+        let tls_acceptor = if let (Some(cert_path), Some(key_path)) = (
+            settings.get(&format!("{}-cert-path", prefix)),
+            settings.get(&format!("{}-key-path", prefix)),
+        ) {
+            Arc::new(TlsAcceptor::from(Arc::new(load_tls_server_config(
+                &cert_path, &key_path,
+            ))))
+            .into()
         } else {
-            failed_to("parse 'lmtp-trusted-ips', no entries found.");
+            None
+        };
+        let mut tls_only = settings
+            .parse(&format!("{}-tls-only", prefix))
+            .unwrap_or(false);
+        if tls_only && tls_acceptor.is_none() {
+            warn!(
+                "{} server is configured to only accept TLS connections, but no TLS certificate was provided.",
+                protocol
+            );
+            tls_only = false;
+        }
+        if is_submission && tls_acceptor.is_none() && trusted_ips.is_none() {
+            warn!(
+                "{} server has no TLS certificate and no trusted IPs configured, authentication will not be available.",
+                protocol
+            );
+        }
+
+        Listener {
+            protocol,
+            bind_addr,
+            trusted_ips,
+            tls_acceptor,
+            tls_only,
+            is_submission,
         }
-    } else {
-        None
-    };
-
-    // Build TLS acceptor
-    let tls_acceptor = if let (Some(cert_path), Some(key_path)) = (
-        settings.get("lmtp-cert-path"),
-        settings.get("lmtp-key-path"),
-    ) {
-        Arc::new(TlsAcceptor::from(Arc::new(load_tls_server_config(
-            &cert_path, &key_path,
-        ))))
-        .into()
-    } else {
-        None
-    };
-    let mut tls_only = settings.parse("lmtp-tls-only").unwrap_or(false);
-    if tls_only && tls_acceptor.is_none() {
-        warn!("LMTP server is configured to only accept TLS connections, but no TLS certificate was provided.");
-        tls_only = false;
     }
+}
+
+fn spawn_listener<T>(
+    core: web::Data<JMAPServer<T>>,
+    settings: &EnvSettings,
+    listener_config: Listener,
+    mut shutdown_rx: watch::Receiver<bool>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    let Listener {
+        protocol,
+        bind_addr,
+        trusted_ips,
+        tls_acceptor,
+        tls_only,
+        is_submission,
+    } = listener_config;
+    info!("Starting {} service at {}...", protocol, bind_addr);
+    let authenticator = Arc::new(MessageAuthenticator::parse(settings));
 
     tokio::spawn(async move {
-        // Start listening for LMTP connections.
+        // Start listening for connections.
         let listener = match TcpListener::bind(bind_addr).await {
             Ok(listener) => listener,
             Err(err) => {
-                error!("Failed to bind LMTP service to {}: {}", bind_addr, err);
+                error!(
+                    "Failed to bind {} service to {}: {}",
+                    protocol, bind_addr, err
+                );
                 return;
             }
         };
@@ -116,11 +226,12 @@ pub fn spawn_lmtp<T>(
         let greeting = Arc::new(
             format!(
                 concat!(
-                    "220 {} Stalwart LMTP v",
+                    "220 {} Stalwart {} v",
                     env!("CARGO_PKG_VERSION"),
                     " at your service.\r\n"
                 ),
-                &hostname
+                &hostname,
+                if is_submission { "ESMTP" } else { "LMTP" }
             )
             .into_bytes(),
         );
@@ -132,16 +243,22 @@ pub fn spawn_lmtp<T>(
                         Ok((mut stream, peer_addr)) => {
                             if let Some(trusted_ips) = &trusted_ips {
                                 if !trusted_ips.contains(&peer_addr.ip()) {
-                                    debug!("Dropping LMTP connection from unknow address {}.", peer_addr.ip());
+                                    debug!("Dropping {} connection from unknow address {}.", protocol, peer_addr.ip());
                                     continue;
                                 }
                             }
+                            let submission = if is_submission {
+                                Submission::new(trusted_ips.is_some()).into()
+                            } else {
+                                None
+                            };
 
                             let shutdown_rx = shutdown_rx.clone();
                             let core = core.clone();
                             let greeting = greeting.clone();
                             let tls_acceptor = tls_acceptor.clone();
                             let hostname = hostname.clone();
+                            let authenticator = authenticator.clone();
 
                             tokio::spawn(async move {
                                 if tls_only {
@@ -160,7 +277,7 @@ pub fn spawn_lmtp<T>(
                                     }
 
                                     handle_conn(
-                                        Session::new(core, peer_addr, stream.into(), None, hostname),
+                                        Session::new(core, peer_addr, stream.into(), None, hostname, authenticator, submission),
                                         shutdown_rx
                                     ).await;
                                 } else {
@@ -171,7 +288,7 @@ pub fn spawn_lmtp<T>(
                                     }
 
                                     handle_conn(
-                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname),
+                                        Session::new(core, peer_addr, stream.into(), tls_acceptor, hostname, authenticator, submission),
                                         shutdown_rx
                                     ).await;
                                 }
@@ -183,7 +300,7 @@ pub fn spawn_lmtp<T>(
                     }
                 },
                 _ = shutdown_rx.changed() => {
-                    debug!("LMTP listener shutting down.");
+                    debug!("{} listener shutting down.", protocol);
                     break;
                 }
             };
@@ -21,11 +21,13 @@
  * for more details.
 */
 
+pub mod auth;
 pub mod ingest;
 pub mod listener;
 pub mod request;
 pub mod response;
 pub mod session;
+pub mod submission;
 
 pub struct OutgoingMessage {
     pub mail_from: String,
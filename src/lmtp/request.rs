@@ -39,6 +39,16 @@ pub enum Request {
     Lhlo {
         domain: String,
     },
+    Ehlo {
+        domain: String,
+    },
+    Auth {
+        mechanism: String,
+        initial_response: Option<String>,
+    },
+    AuthResponse {
+        response: String,
+    },
     Mail {
         sender: String,
         params: Vec<Param>,
@@ -82,6 +92,7 @@ pub enum State {
     Request { in_addr: bool },
     Bdat { chunk_size: usize, is_last: bool },
     Data { state: StateData },
+    AuthResponse,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -205,6 +216,27 @@ impl RequestParser {
                                     },
                                 )?,
                             }),
+                            "ehlo" | "helo" => Ok(Request::Ehlo {
+                                domain: tokens.next().and_then(|t| t.unwrap_text()).ok_or_else(
+                                    || {
+                                        Event::parse_error(
+                                            "EHLO requires a domain name as argument.",
+                                        )
+                                    },
+                                )?,
+                            }),
+                            "auth" => Ok(Request::Auth {
+                                mechanism: tokens
+                                    .next()
+                                    .and_then(|t| t.unwrap_text())
+                                    .ok_or_else(|| {
+                                        Event::parse_error(
+                                            "AUTH requires a SASL mechanism as argument.",
+                                        )
+                                    })?
+                                    .to_ascii_uppercase(),
+                                initial_response: tokens.next().and_then(|t| t.unwrap_text()),
+                            }),
                             "mail" => {
                                 if matches!(tokens.next(), Some(Token::Text(from)) if from == "from")
                                     && matches!(tokens.next(), Some(Token::Colon))
@@ -333,7 +365,38 @@ impl RequestParser {
                                 .push(if in_addr { ch } else { ch.to_ascii_lowercase() });
                         } else {
                             self.push_buf()?;
+
+                            // SASL responses are case sensitive
+                            if !in_addr
+                                && self.tokens.len() == 1
+                                && matches!(self.tokens.first(), Some(Token::Text(cmd)) if cmd == "auth")
+                            {
+                                self.state = State::Request { in_addr: true };
+                            }
+                        }
+                    }
+                },
+                State::AuthResponse => match ch {
+                    b'\r' => (),
+                    b'\n' => {
+                        self.command_size = 0;
+                        self.state = State::Start;
+                        return match String::from_utf8(std::mem::replace(
+                            &mut self.buf,
+                            Vec::with_capacity(10),
+                        )) {
+                            Ok(response) => Ok(Request::AuthResponse {
+                                response: response.trim().to_string(),
+                            }),
+                            Err(_) => Err(Event::parse_error("Invalid UTF-8")),
+                        };
+                    }
+                    _ => {
+                        self.command_size += 1;
+                        if self.command_size > self.max_command_size {
+                            return Err(self.error_reset("Response is too long."));
                         }
+                        self.buf.push(ch);
                     }
                 },
                 State::Data { state } => {
@@ -566,7 +629,7 @@ impl Event {
 #[cfg(test)]
 mod tests {
 
-    use crate::lmtp::request::Event;
+    use crate::lmtp::request::{Event, State};
 
     use super::{Param, Request, RequestParser};
 
@@ -630,6 +693,26 @@ mod tests {
                     },
                 ],
             ),
+            (
+                vec![
+                    "EHLO bar.com\r\n",
+                    "AUTH PLAIN AGpkb2VAZXhhbXBsZS5jb20AU2VjcmV0\r\n",
+                    "auth login\r\n",
+                ],
+                vec![
+                    Request::Ehlo {
+                        domain: "bar.com".to_string(),
+                    },
+                    Request::Auth {
+                        mechanism: "PLAIN".to_string(),
+                        initial_response: "AGpkb2VAZXhhbXBsZS5jb20AU2VjcmV0".to_string().into(),
+                    },
+                    Request::Auth {
+                        mechanism: "LOGIN".to_string(),
+                        initial_response: None,
+                    },
+                ],
+            ),
             (
                 vec![
                     "help my-command \r\n",
@@ -695,5 +778,17 @@ mod tests {
             }
             assert_eq!(commands, expected_commands, "{:#?}", commands);
         }
+
+        // SASL continuations are read as a single line
+        parser.state = State::AuthResponse;
+        match parser.parse(&mut b"amRvZUBFeGFtcGxlLmNvbQ==\r\n".iter()) {
+            Ok(request) => assert_eq!(
+                request,
+                Request::AuthResponse {
+                    response: "amRvZUBFeGFtcGxlLmNvbQ==".to_string()
+                }
+            ),
+            Err(err) => panic!("{:?}", err),
+        }
     }
 }
@@ -49,6 +49,7 @@ pub enum Extension {
     SmtpUtf8,
     StartTls,
     EnhancedStatusCodes,
+    Auth,
 }
 
 impl Response<'_> {
@@ -98,6 +99,9 @@ impl Response<'_> {
                         Extension::EnhancedStatusCodes => {
                             buf.extend_from_slice(b"ENHANCEDSTATUSCODES")
                         }
+                        Extension::Auth => {
+                            buf.extend_from_slice(b"AUTH PLAIN LOGIN OAUTHBEARER XOAUTH2")
+                        }
                     }
                     buf.extend_from_slice(b"\r\n");
                 }
@@ -137,6 +141,7 @@ mod tests {
                         Extension::Chunking,
                         Extension::SmtpUtf8,
                         Extension::StartTls,
+                        Extension::Auth,
                     ],
                 },
                 concat!(
@@ -149,7 +154,8 @@ mod tests {
                     "250-PIPELINING\r\n",
                     "250-CHUNKING\r\n",
                     "250-SMTPUTF8\r\n",
-                    "250 STARTTLS\r\n"
+                    "250-STARTTLS\r\n",
+                    "250 AUTH PLAIN LOGIN OAUTHBEARER XOAUTH2\r\n"
                 ),
             ),
         ] {
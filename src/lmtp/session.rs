@@ -35,9 +35,11 @@ use tokio_rustls::{server::TlsStream, TlsAcceptor};
 use crate::JMAPServer;
 
 use super::{
+    auth::MessageAuthenticator,
     ingest::DeliveryStatus,
     request::{Event, Param, Request, RequestParser},
     response::{Extension, Response},
+    submission::Submission,
 };
 
 const MAX_COMMAND_LENGTH: usize = 1024;
@@ -49,6 +51,7 @@ where
     pub core: web::Data<JMAPServer<T>>,
     pub tls_acceptor: Option<Arc<TlsAcceptor>>,
     pub hostname: Arc<String>,
+    pub authenticator: Arc<MessageAuthenticator>,
     pub parser: RequestParser,
     pub peer_addr: SocketAddr,
     pub stream: Stream,
@@ -60,6 +63,9 @@ where
     pub rcpt_to: Vec<RcptType>,
     pub rcpt_to_dup: AHashSet<AccountId>,
     pub message: Vec<u8>,
+
+    // Authenticated submissions
+    pub submission: Option<Submission>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -93,6 +99,8 @@ where
         stream: Stream,
         tls_acceptor: Option<Arc<TlsAcceptor>>,
         hostname: Arc<String>,
+        authenticator: Arc<MessageAuthenticator>,
+        submission: Option<Submission>,
     ) -> Self {
         Self {
             parser: RequestParser::new(MAX_COMMAND_LENGTH, core.store.config.mail_max_size),
@@ -107,6 +115,8 @@ where
             rcpt_to_dup: AHashSet::new(),
             message: Vec::new(),
             hostname,
+            authenticator,
+            submission,
         }
     }
 
@@ -116,7 +126,15 @@ where
         loop {
             match self.parser.parse(&mut bytes) {
                 Ok(request) => match request {
-                    Request::Lhlo { domain } => {
+                    Request::Lhlo { .. } if self.submission.is_some() => {
+                        self.write_bytes(b"500 5.5.1 Invalid command, use EHLO.\r\n")
+                            .await?;
+                    }
+                    Request::Ehlo { .. } if self.submission.is_none() => {
+                        self.write_bytes(b"500 5.5.1 Invalid command, use LHLO.\r\n")
+                            .await?;
+                    }
+                    Request::Lhlo { domain } | Request::Ehlo { domain } => {
                         let mut extensions = vec![
                             Extension::EnhancedStatusCodes,
                             Extension::Pipelining,
@@ -124,13 +142,20 @@ where
                             Extension::EightBitMime,
                             Extension::BinaryMime,
                             Extension::SmtpUtf8,
-                            Extension::Vrfy,
                             Extension::Help,
                             Extension::Size(self.core.store.config.mail_max_size as u32),
                         ];
+                        if self.submission.is_none() {
+                            extensions.push(Extension::Vrfy);
+                        }
                         if !self.stream.is_tls() {
                             extensions.push(Extension::StartTls);
                         }
+                        if matches!(&self.submission, Some(submission) if submission.account_id.is_none())
+                            && self.is_auth_allowed()
+                        {
+                            extensions.push(Extension::Auth);
+                        }
                         self.write_bytes(
                             &Response::Lhlo {
                                 local_host: self.hostname.as_ref().into(),
@@ -142,7 +167,19 @@ where
                         .await?;
                         self.remote_hostname = domain.into();
                     }
+                    Request::Auth {
+                        mechanism,
+                        initial_response,
+                    } if self.submission.is_some() => {
+                        self.handle_auth(mechanism, initial_response).await?;
+                    }
+                    Request::AuthResponse { response } => {
+                        self.handle_auth_response(response).await?;
+                    }
                     Request::Mail { sender, params } => {
+                        if self.submission.is_some() && !self.validate_sender(&sender).await? {
+                            continue;
+                        }
                         self.write_bytes(
                             format!("250 2.1.0 Sender <{}> accepted.\r\n", sender).as_bytes(),
                         )
@@ -156,6 +193,9 @@ where
                             }
                         });
                     }
+                    Request::Rcpt { recipient, .. } if self.submission.is_some() => {
+                        self.add_recipient(&recipient).await?;
+                    }
                     Request::Rcpt { recipient, .. } => match self.expand_rcpt(&recipient).await {
                         Some(recipient_) => match recipient_.as_ref() {
                             RecipientType::Individual(account_id) => {
@@ -212,6 +252,9 @@ where
                         self.message = data;
                         self.ingest_message().await?;
                     }
+                    Request::Bdat { .. } if self.message.is_empty() && !self.has_recipients() => {
+                        self.write_bytes(b"503 5.5.1 Missing RCPT TO.\r\n").await?;
+                    }
                     Request::Bdat { data, is_last } => {
                         if self.message.len() + data.len() < self.core.store.config.mail_max_size {
                             if self.message.is_empty() {
@@ -241,6 +284,14 @@ where
                             .await?;
                         }
                     }
+                    Request::Vrfy { .. } | Request::Expn { .. } if self.submission.is_some() => {
+                        self.write_bytes(b"502 5.5.1 Command not implemented.\r\n")
+                            .await?;
+                    }
+                    Request::Auth { .. } => {
+                        self.write_bytes(b"502 5.5.1 Command not implemented.\r\n")
+                            .await?;
+                    }
                     Request::Vrfy { mailbox } => match self.expand_rcpt(&mailbox).await {
                         Some(recipient_) => match recipient_.as_ref() {
                             RecipientType::Individual(_) | RecipientType::List(_) => {
@@ -332,6 +383,9 @@ where
                         self.mail_size = None;
                         self.rcpt_to.clear();
                         self.rcpt_to_dup.clear();
+                        if let Some(submission) = &mut self.submission {
+                            submission.reset();
+                        }
                         self.message = Vec::new();
                         self.write_bytes(b"250 2.0.0 OK\r\n").await?;
                     }
@@ -347,7 +401,7 @@ where
                     break;
                 }
                 Err(Event::Data) => {
-                    if !self.rcpt_to_dup.is_empty() {
+                    if self.has_recipients() {
                         let rp = self.build_return_path();
                         self.parser.buf =
                             Vec::with_capacity(self.mail_size.unwrap_or(1024) + rp.len());
@@ -393,16 +447,29 @@ where
         }
     }
 
+    fn has_recipients(&self) -> bool {
+        if let Some(submission) = &self.submission {
+            !submission.rcpt_to.is_empty()
+        } else {
+            !self.rcpt_to_dup.is_empty()
+        }
+    }
+
     fn build_return_path(&self) -> String {
         format!(
             concat!(
                 "Received: from {} ([{}])\r\n",
-                "\tby {} (Stalwart JMAP) with LMTP;\r\n",
+                "\tby {} (Stalwart JMAP) with {};\r\n",
                 "\t{}\r\n"
             ),
             self.remote_hostname.as_deref().unwrap_or("unknown"),
             self.hostname.as_ref(),
             self.peer_addr.ip(),
+            match (&self.submission, self.stream.is_tls()) {
+                (Some(_), true) => "ESMTPSA",
+                (Some(_), false) => "ESMTPA",
+                (None, _) => "LMTP",
+            },
             Local::now().to_rfc2822()
         )
     }
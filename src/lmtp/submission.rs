@@ -0,0 +1,637 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::{
+    orm::serialize::JMAPOrm,
+    request::{set::SetRequest, MaybeIdReference, MaybeResultReference},
+    sanitize_email,
+    types::{blob::JMAPBlob, jmap::JMAPId},
+};
+use jmap_mail::{
+    email_submission::{
+        schema::{Address, EmailSubmission, Envelope, Property, Value},
+        set::SetArguments,
+    },
+    identity::schema::{self as identity, Identity},
+    mail::{
+        import::{EmailImport, EmailImportRequest},
+        schema::Keyword,
+    },
+    mail_parser::decoders::base64::decode_base64,
+    mailbox::get::JMAPGetMailbox,
+};
+use jmap_sharing::principal::account::JMAPAccountStore;
+use store::{
+    blob::BlobId,
+    core::{collection::Collection, error::StoreError, tag::Tag, vec_map::VecMap},
+    tracing::{debug, error},
+    AccountId, DocumentId, Store,
+};
+
+use crate::{
+    api::{invocation::handle_method_calls, method, request::Request},
+    authorization::{self, auth::RemoteAddress},
+    cluster::rpc::command::{Command, CommandResponse},
+    JMAPServer,
+};
+
+use super::{request::State, session::Session};
+
+#[derive(Default)]
+pub struct Submission {
+    pub account_id: Option<AccountId>,
+    pub identity_id: Option<DocumentId>,
+    pub sasl: Option<Mechanism>,
+    pub rcpt_to: Vec<String>,
+    pub is_trusted: bool,
+}
+
+pub enum Mechanism {
+    Plain,
+    LoginUsername,
+    LoginPassword { login: String },
+    OAuthBearer,
+    XOAuth2,
+}
+
+enum Credentials {
+    Password { login: String, secret: String },
+    Bearer { token: String },
+}
+
+impl Submission {
+    pub fn new(is_trusted: bool) -> Self {
+        Submission {
+            is_trusted,
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.identity_id = None;
+        self.rcpt_to.clear();
+    }
+}
+
+impl<T> Session<T>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    pub async fn handle_auth(
+        &mut self,
+        mechanism: String,
+        initial_response: Option<String>,
+    ) -> Result<(), ()> {
+        if self.submission.as_ref().unwrap().account_id.is_some() {
+            return self
+                .write_bytes(b"503 5.5.1 Already authenticated.\r\n")
+                .await;
+        } else if !self.is_auth_allowed() {
+            return self
+                .write_bytes(
+                    b"538 5.7.11 Encryption required for requested authentication mechanism.\r\n",
+                )
+                .await;
+        }
+
+        let mechanism = match mechanism.as_str() {
+            "PLAIN" => Mechanism::Plain,
+            "LOGIN" => Mechanism::LoginUsername,
+            "OAUTHBEARER" => Mechanism::OAuthBearer,
+            "XOAUTH2" => Mechanism::XOAuth2,
+            _ => {
+                return self
+                    .write_bytes(b"504 5.5.4 Unrecognized authentication type.\r\n")
+                    .await;
+            }
+        };
+
+        if let Some(initial_response) = initial_response {
+            self.handle_sasl(mechanism, initial_response).await
+        } else {
+            let challenge: &[u8] = if matches!(mechanism, Mechanism::LoginUsername) {
+                b"334 VXNlcm5hbWU6\r\n"
+            } else {
+                b"334 \r\n"
+            };
+            self.sasl_challenge(mechanism, challenge).await
+        }
+    }
+
+    pub fn is_auth_allowed(&self) -> bool {
+        // Credentials are only accepted over TLS, unless the peer is trusted
+        self.stream.is_tls() || self.submission.as_ref().map_or(false, |s| s.is_trusted)
+    }
+
+    pub async fn handle_auth_response(&mut self, response: String) -> Result<(), ()> {
+        match self.submission.as_mut().and_then(|s| s.sasl.take()) {
+            Some(_) if response == "*" => {
+                self.write_bytes(b"501 5.7.0 Authentication canceled.\r\n")
+                    .await
+            }
+            Some(mechanism) => self.handle_sasl(mechanism, response).await,
+            None => {
+                self.write_bytes(b"503 5.5.1 No authentication in progress.\r\n")
+                    .await
+            }
+        }
+    }
+
+    async fn handle_sasl(&mut self, mechanism: Mechanism, response: String) -> Result<(), ()> {
+        // A single "=" stands for an empty initial response
+        let response = if response == "=" {
+            Vec::new()
+        } else if let Some(response) = decode_base64(response.as_bytes()) {
+            response
+        } else {
+            return self
+                .write_bytes(b"501 5.5.2 Invalid base64 data.\r\n")
+                .await;
+        };
+
+        let credentials = match mechanism {
+            Mechanism::Plain => {
+                // authzid NUL authcid NUL passwd
+                let mut parts = response.split(|&ch| ch == 0).skip(1);
+                match (
+                    parts.next().and_then(|p| std::str::from_utf8(p).ok()),
+                    parts.next().and_then(|p| std::str::from_utf8(p).ok()),
+                ) {
+                    (Some(login), Some(secret)) if !login.is_empty() => Credentials::Password {
+                        login: login.to_string(),
+                        secret: secret.to_string(),
+                    },
+                    _ => {
+                        return self
+                            .write_bytes(b"501 5.5.2 Invalid PLAIN credentials.\r\n")
+                            .await;
+                    }
+                }
+            }
+            Mechanism::LoginUsername => {
+                return if let Ok(login) = String::from_utf8(response) {
+                    self.sasl_challenge(Mechanism::LoginPassword { login }, b"334 UGFzc3dvcmQ6\r\n")
+                        .await
+                } else {
+                    self.write_bytes(b"501 5.5.2 Invalid username.\r\n").await
+                };
+            }
+            Mechanism::LoginPassword { login } => {
+                if let Ok(secret) = String::from_utf8(response) {
+                    Credentials::Password { login, secret }
+                } else {
+                    return self.write_bytes(b"501 5.5.2 Invalid password.\r\n").await;
+                }
+            }
+            Mechanism::OAuthBearer | Mechanism::XOAuth2 => {
+                if let Some(token) = parse_bearer_token(&response) {
+                    Credentials::Bearer { token }
+                } else {
+                    return self
+                        .write_bytes(b"501 5.5.2 Missing bearer token.\r\n")
+                        .await;
+                }
+            }
+        };
+
+        self.authenticate(credentials).await
+    }
+
+    async fn sasl_challenge(&mut self, mechanism: Mechanism, challenge: &[u8]) -> Result<(), ()> {
+        self.submission.as_mut().unwrap().sasl = mechanism.into();
+        self.parser.state = State::AuthResponse;
+        self.write_bytes(challenge).await
+    }
+
+    async fn authenticate(&mut self, credentials: Credentials) -> Result<(), ()> {
+        // Enforce rate limit for authentication requests
+        if self
+            .core
+            .is_auth_allowed(RemoteAddress::IpAddress(self.peer_addr.ip()))
+            .await
+            .is_err()
+        {
+            return self
+                .write_bytes(b"454 4.7.0 Too many authentication attempts.\r\n")
+                .await;
+        }
+
+        let result = match credentials {
+            Credentials::Password { login, secret } => {
+                let store = self.core.store.clone();
+                self.core
+                    .spawn_worker(move || store.authenticate(&login.trim().to_lowercase(), &secret))
+                    .await
+            }
+            Credentials::Bearer { token } => {
+                match self
+                    .core
+                    .validate_access_token("access_token", &token)
+                    .await
+                {
+                    Ok((account_id, _, _)) => Ok(Some(account_id)),
+                    Err(StoreError::DeserializeError(err)) => {
+                        debug!("Failed to validate access token: {}", err);
+                        Ok(None)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        };
+
+        match result {
+            Ok(Some(account_id)) => {
+                self.submission.as_mut().unwrap().account_id = account_id.into();
+                self.write_bytes(b"235 2.7.0 Authentication succeeded.\r\n")
+                    .await
+            }
+            Ok(None) => {
+                self.write_bytes(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                    .await
+            }
+            Err(err) => {
+                error!("Store error during authentication: {}", err);
+                self.write_bytes(b"454 4.7.0 Temporary authentication failure.\r\n")
+                    .await
+            }
+        }
+    }
+
+    pub async fn validate_sender(&mut self, sender: &str) -> Result<bool, ()> {
+        let account_id = if let Some(account_id) = self.submission.as_ref().unwrap().account_id {
+            account_id
+        } else {
+            self.write_bytes(b"530 5.7.0 Authentication required.\r\n")
+                .await?;
+            return Ok(false);
+        };
+
+        // The sender has to match one of the account's identities
+        let store = self.core.store.clone();
+        let email = sender.to_string();
+        match self
+            .core
+            .spawn_worker(move || {
+                for document_id in store
+                    .get_document_ids(account_id, Collection::Identity)?
+                    .unwrap_or_default()
+                {
+                    if let Some(identity) = store.get_orm::<Identity>(account_id, document_id)? {
+                        if matches!(identity.get(&identity::Property::Email),
+                                    Some(identity::Value::Text { value }) if value.eq_ignore_ascii_case(&email))
+                        {
+                            return Ok(Some(document_id));
+                        }
+                    }
+                }
+                Ok(None)
+            })
+            .await
+        {
+            Ok(Some(identity_id)) => {
+                self.submission.as_mut().unwrap().identity_id = identity_id.into();
+                Ok(true)
+            }
+            Ok(None) => {
+                self.write_bytes(
+                    format!("550 5.7.1 Sender <{}> is not allowed for this account.\r\n", sender)
+                        .as_bytes(),
+                )
+                .await?;
+                Ok(false)
+            }
+            Err(err) => {
+                error!("Failed to obtain identities: {}", err);
+                self.write_bytes(b"451 4.3.0 Temporary server failure.\r\n")
+                    .await?;
+                Ok(false)
+            }
+        }
+    }
+
+    pub async fn add_recipient(&mut self, recipient: &str) -> Result<(), ()> {
+        let submission = self.submission.as_mut().unwrap();
+        if submission.identity_id.is_none() {
+            self.write_bytes(b"503 5.5.1 Missing MAIL FROM.\r\n").await
+        } else if let Some(email) = sanitize_email(recipient) {
+            if !submission.rcpt_to.contains(&email) {
+                submission.rcpt_to.push(email);
+            }
+            self.write_bytes(
+                format!("250 2.1.5 Recipient <{}> accepted.\r\n", recipient).as_bytes(),
+            )
+            .await
+        } else {
+            self.write_bytes(b"501 5.1.3 Invalid recipient address.\r\n")
+                .await
+        }
+    }
+
+    pub async fn submit_message(&mut self) -> Result<(), ()> {
+        // Validate request
+        let submission = self.submission.as_mut().unwrap();
+        let message = std::mem::take(&mut self.message);
+        let rcpt_to = std::mem::take(&mut submission.rcpt_to);
+        let (account_id, identity_id, mail_from) = match (
+            submission.account_id,
+            submission.identity_id.take(),
+            self.mail_from.take(),
+        ) {
+            (Some(account_id), Some(identity_id), Some(mail_from)) => {
+                (account_id, identity_id, mail_from)
+            }
+            (None, _, _) => {
+                return self
+                    .write_bytes(b"530 5.7.0 Authentication required.\r\n")
+                    .await;
+            }
+            _ => {
+                return self
+                    .write_bytes(b"503 5.5.1 Bad sequence of commands.\r\n")
+                    .await;
+            }
+        };
+        if rcpt_to.is_empty() {
+            return self.write_bytes(b"503 5.5.1 Missing RCPT TO.\r\n").await;
+        } else if message.is_empty() {
+            return self
+                .write_bytes(b"554 5.7.7 Empty message not accepted.\r\n")
+                .await;
+        }
+
+        let result = if self.core.is_leader() {
+            mail_submit(
+                self.core.clone(),
+                account_id,
+                identity_id,
+                mail_from,
+                rcpt_to,
+                message,
+            )
+            .await
+        } else {
+            // Send request to leader
+            match self
+                .core
+                .rpc_command(Command::SubmitMessage {
+                    account_id,
+                    identity_id,
+                    mail_from,
+                    rcpt_to,
+                    raw_message: message,
+                })
+                .await
+            {
+                Some(CommandResponse::SubmitMessage { result }) => result,
+                Some(CommandResponse::Error { message }) => {
+                    debug!("RPC failed: {}", message);
+                    return self
+                        .write_bytes(b"451 4.3.0 Temporary server failure.\r\n")
+                        .await;
+                }
+                _ => {
+                    return self
+                        .write_bytes(b"451 4.3.0 Temporary server failure.\r\n")
+                        .await;
+                }
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                self.write_bytes(b"250 2.0.0 Message queued for delivery.\r\n")
+                    .await
+            }
+            Err(reply) => self.write_bytes(reply.as_bytes()).await,
+        }
+    }
+}
+
+// Submissions are run on the leader as an Email/import and EmailSubmission/set request
+pub async fn mail_submit<T>(
+    core: web::Data<JMAPServer<T>>,
+    account_id: AccountId,
+    identity_id: DocumentId,
+    mail_from: String,
+    rcpt_to: Vec<String>,
+    message: Vec<u8>,
+) -> Result<(), String>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    // Store the message as a blob owned by the account and locate the Sent mailbox
+    let store = core.store.clone();
+    let (blob_id, sent_id, acl_token) = match core
+        .spawn_worker(move || {
+            let blob_id = BlobId::new_external(&message);
+            store.blob_store(&blob_id, message)?;
+            store.blob_link_ephemeral(&blob_id, account_id)?;
+            Ok((
+                blob_id,
+                store.mailbox_get_by_role(account_id, "sent")?,
+                store.get_acl_token(account_id)?,
+            ))
+        })
+        .await
+    {
+        Ok((blob_id, Some(sent_id), acl_token)) => (blob_id, sent_id, acl_token),
+        Ok((_, None, _)) => {
+            return Err("554 5.3.0 Sent mailbox not found.\r\n".to_string());
+        }
+        Err(err) => {
+            error!("Failed to store submitted message: {}", err);
+            return Err("451 4.3.0 Temporary server failure.\r\n".to_string());
+        }
+    };
+
+    // Save a copy to the Sent mailbox and queue it for delivery,
+    // as if the client had used Email/import and EmailSubmission/set
+    let request = Request {
+        using: Vec::new(),
+        method_calls: vec![
+            method::Call {
+                id: "0".to_string(),
+                method: method::Request::ImportEmail(EmailImportRequest {
+                    acl: None,
+                    account_id: account_id.into(),
+                    if_in_state: None,
+                    emails: VecMap::from_iter([(
+                        "m".to_string(),
+                        EmailImport {
+                            blob_id: JMAPBlob::new(blob_id),
+                            mailbox_ids: MaybeResultReference::Value(VecMap::from_iter([(
+                                MaybeIdReference::Value(sent_id.into()),
+                                true,
+                            )]))
+                            .into(),
+                            keywords: VecMap::from_iter([(
+                                Keyword::new(Tag::Static(Keyword::SEEN)),
+                                true,
+                            )])
+                            .into(),
+                            received_at: None,
+                        },
+                    )]),
+                }),
+            },
+            method::Call {
+                id: "1".to_string(),
+                method: method::Request::SetEmailSubmission(SetRequest {
+                    acl: None,
+                    account_id: account_id.into(),
+                    if_in_state: None,
+                    create: VecMap::from_iter([(
+                        "s".to_string(),
+                        EmailSubmission {
+                            properties: VecMap::from_iter([
+                                (
+                                    Property::EmailId,
+                                    Value::IdReference {
+                                        value: "m".to_string(),
+                                    },
+                                ),
+                                (
+                                    Property::IdentityId,
+                                    Value::Id {
+                                        value: JMAPId::from(identity_id),
+                                    },
+                                ),
+                                (
+                                    Property::Envelope,
+                                    Value::Envelope {
+                                        value: Envelope {
+                                            mail_from: Address {
+                                                email: mail_from,
+                                                parameters: None,
+                                            },
+                                            rcpt_to: rcpt_to
+                                                .into_iter()
+                                                .map(|email| Address {
+                                                    email,
+                                                    parameters: None,
+                                                })
+                                                .collect(),
+                                        },
+                                    },
+                                ),
+                            ]),
+                        },
+                    )])
+                    .into(),
+                    update: None,
+                    destroy: None,
+                    arguments: SetArguments::default(),
+                }),
+            },
+        ],
+        created_ids: None,
+    };
+
+    let response = handle_method_calls(
+        request,
+        core,
+        authorization::Session::new(account_id, &acl_token),
+    )
+    .await;
+
+    // Map any failures to an SMTP reply
+    for call in &response.method_responses {
+        let error = match &call.method {
+            method::Response::ImportEmail(response) => response
+                .not_created
+                .as_ref()
+                .and_then(|errors| errors.values().next())
+                .map(|error| error.type_.as_str()),
+            method::Response::SetEmailSubmission(response) => response
+                .not_created
+                .values()
+                .next()
+                .map(|error| error.type_.as_str()),
+            method::Response::Error(error) => {
+                debug!("Message submission failed: {}", error);
+                return Err("451 4.3.0 Temporary server failure.\r\n".to_string());
+            }
+            _ => None,
+        };
+
+        match error {
+            Some("overQuota") => {
+                return Err("552 5.2.2 Mailbox quota exceeded.\r\n".to_string());
+            }
+            Some(error) => {
+                debug!("Message submission failed: {}", error);
+                return Err("554 5.6.0 Message could not be submitted.\r\n".to_string());
+            }
+            None => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_bearer_token(response: &[u8]) -> Option<String> {
+    // OAUTHBEARER (RFC 7628) and XOAUTH2 both send "auth=Bearer <token>"
+    // as one of the ^A separated key/value pairs
+    std::str::from_utf8(response)
+        .ok()?
+        .split('\u{1}')
+        .find_map(|kv| {
+            let (key, value) = kv.split_once('=')?;
+            if key.trim().eq_ignore_ascii_case("auth") {
+                let (scheme, token) = value.trim().split_once(' ')?;
+                if scheme.eq_ignore_ascii_case("bearer") {
+                    return Some(token.trim().to_string());
+                }
+            }
+            None
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_bearer_token;
+
+    #[test]
+    fn sasl_bearer_token() {
+        for (response, expected_token) in [
+            (
+                "n,a=jdoe@example.com,\u{1}host=server.example.com\u{1}port=587\u{1}auth=Bearer vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg==\u{1}\u{1}",
+                Some("vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg=="),
+            ),
+            (
+                "user=jdoe@example.com\u{1}auth=Bearer ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg\u{1}\u{1}",
+                Some("ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg"),
+            ),
+            ("n,,\u{1}auth=Basic amRvZTpzZWNyZXQ=\u{1}\u{1}", None),
+            ("n,,\u{1}\u{1}", None),
+        ] {
+            assert_eq!(
+                parse_bearer_token(response.as_bytes()).as_deref(),
+                expected_token,
+                "{:?}",
+                response
+            );
+        }
+    }
+}
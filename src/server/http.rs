@@ -61,9 +61,10 @@ use crate::{
         },
     },
     cluster::{rpc::tls::load_tls_server_config, ClusterIpc},
-    lmtp::listener::{init_lmtp, spawn_lmtp},
+    lmtp::listener::{init_lmtp, spawn_lmtp, spawn_submission},
     server::{event_source::handle_jmap_event_source, websocket::handle_ws},
     services::{
+        attachment_index::spawn_attachment_indexer,
         email_delivery::{init_email_delivery, spawn_email_delivery},
         housekeeper::{init_housekeeper, spawn_housekeeper},
         state_change::{init_state_manager, spawn_state_manager},
@@ -216,7 +217,8 @@ where
         is_offline: false.into(),
     });
 
-    // Spawn LMTP service
+    // Spawn LMTP and SMTP submission services
+    spawn_submission(server.clone(), settings, lmtp_rx.clone());
     spawn_lmtp(server.clone(), settings, lmtp_rx);
 
     // Spawn TypeState manager
@@ -228,6 +230,9 @@ where
     // Spawn housekeeper
     spawn_housekeeper(server.clone(), settings, housekeeper_rx);
 
+    // Spawn attachment indexer
+    spawn_attachment_indexer(server.clone(), settings);
+
     server
 }
 
@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use actix_web::web;
+use jmap::{types::type_state::TypeState, SUPERUSER_ID};
+use jmap_mail::mail::import::JMAPMailImport;
+use store::{
+    config::env_settings::EnvSettings,
+    core::collection::Collection,
+    tracing::{debug, error},
+    Store,
+};
+use tokio::runtime::Handle;
+
+use crate::{services::state_change::StateChange, JMAPServer};
+
+// Extracts and indexes the text of queued message attachments using a fixed number
+// of worker threads, so that large or malformed attachments do not slow down writes.
+// Only the leader indexes attachments, followers apply the logged changes. Pending
+// messages are tagged and queued again periodically, as the queue drops items when
+// full and does not survive restarts.
+pub fn spawn_attachment_indexer<T>(core: web::Data<JMAPServer<T>>, settings: &EnvSettings)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let rx = if let Some(rx) = core.store.attachment_queue.take_receiver() {
+        Arc::new(Mutex::new(rx))
+    } else {
+        return;
+    };
+    let handle = Handle::current();
+
+    for _ in 0..settings
+        .parse::<usize>("mail-attachments-index-workers")
+        .filter(|v| *v > 0)
+        .unwrap_or(2)
+    {
+        let core = core.clone();
+        let handle = handle.clone();
+        let rx = rx.clone();
+        thread::Builder::new()
+            .name("attachment-index".to_string())
+            .spawn(move || loop {
+                let next_item = rx.lock().ok().and_then(|rx| rx.recv().ok());
+                if let Some((account_id, document_id)) = next_item {
+                    if !core.is_leader() {
+                        continue;
+                    }
+                    debug!(
+                        "Indexing attachments of message {}:{}.",
+                        account_id, document_id
+                    );
+                    match core.store.mail_index_attachments(account_id, document_id) {
+                        Ok(Some(changes)) => handle.block_on(async {
+                            if core.is_in_cluster() {
+                                core.commit_index(changes.change_id).await;
+                            }
+                            if let Err(err) = core
+                                .publish_state_change(StateChange::new(
+                                    account_id,
+                                    vec![(TypeState::Email, changes.change_id)],
+                                ))
+                                .await
+                            {
+                                error!("Failed to publish state change: {}", err);
+                            }
+                        }),
+                        Ok(None) => (),
+                        Err(err) => {
+                            error!(
+                                "Failed to index attachments of message {}:{}: {}",
+                                account_id, document_id, err
+                            );
+                        }
+                    }
+                } else {
+                    break;
+                }
+            })
+            .unwrap();
+    }
+
+    let scan_interval =
+        Duration::from_secs(settings.parse("mail-attachments-index-scan").unwrap_or(300));
+    tokio::spawn(async move {
+        loop {
+            if core.is_leader() {
+                let store = core.store.clone();
+                if let Err(err) = core
+                    .spawn_worker(move || {
+                        for account_id in store
+                            .get_document_ids(SUPERUSER_ID, Collection::Principal)?
+                            .unwrap_or_default()
+                        {
+                            for document_id in store.mail_pending_attachments(account_id)? {
+                                store.attachment_queue.push(account_id, document_id);
+                            }
+                        }
+                        Ok(())
+                    })
+                    .await
+                {
+                    error!("Failed to queue pending attachments: {}", err);
+                }
+            }
+            tokio::time::sleep(scan_interval).await;
+        }
+    });
+}
@@ -21,34 +21,47 @@
  * for more details.
 */
 
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    time::{Duration, SystemTime},
+};
 
 use actix_web::web;
 use jmap::{
     orm::{serialize::JMAPOrm, TinyORM},
     types::type_state::TypeState,
 };
-use jmap_mail::email_submission::schema::{
-    Delivered, DeliveryStatus, Displayed, EmailSubmission, Property, UndoStatus, Value,
+use jmap_mail::email_submission::{
+    queue::JMAPEmailSubmissionQueue,
+    schema::{Delivered, DeliveryStatus, Displayed, EmailSubmission, Property, UndoStatus, Value},
 };
-use jmap_mail::mail_send::{smtp::message::Message, Transport};
+use jmap_mail::mail_send::{self, smtp::message::Message, Transport};
 use jmap_sharing::principal::get::JMAPGetPrincipal;
 use store::{
     ahash::AHashMap,
     blob::BlobId,
     config::env_settings::EnvSettings,
-    core::{collection::Collection, document::Document},
-    tracing::{debug, log::error},
+    core::{collection::Collection, document::Document, tag::Tag},
+    tracing::{debug, info, log::error, warn},
     write::batch::WriteBatch,
     AccountId, DocumentId, Store,
 };
 use tokio::sync::mpsc;
 
-use crate::{cluster::IPC_CHANNEL_BUFFER, JMAPServer};
+use crate::{cluster::IPC_CHANNEL_BUFFER, server::failed_to, JMAPServer};
 
-use super::state_change::StateChange;
+use super::{
+    mx::{MxError, MxResolver},
+    state_change::StateChange,
+};
 
 const DEFAULT_SMTP_TIMEOUT_MS: u64 = 60000;
+const DEFAULT_MX_PORT: u16 = 25;
+const DEFAULT_RETRY_INTERVAL: u64 = 60;
+const DEFAULT_MAX_RETRY_INTERVAL: u64 = 4 * 3600;
+const DEFAULT_QUEUE_LIFETIME: u64 = 5 * 86400;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(86400);
 
 pub enum Event {
     EmailSubmission {
@@ -61,6 +74,11 @@ pub enum Event {
         to: Vec<String>,
         message: Vec<u8>,
     },
+    Schedule {
+        account_id: AccountId,
+        document_id: DocumentId,
+        due: u64,
+    },
     RelayReady,
     Reload,
     Start,
@@ -97,52 +115,111 @@ pub fn spawn_email_delivery<T>(
 ) where
     T: for<'x> Store<'x> + 'static,
 {
-    // Parse SMTP relay
-    let relay_tx = if let Some(smtp_relay) = parse_smtp_settings(settings) {
-        spawn_email_relay(core, smtp_relay, tx)
+    // Parse delivery settings
+    let relay_tx = if let Some(delivery) = parse_delivery_settings(settings) {
+        spawn_email_relay(core.clone(), delivery, parse_queue_settings(settings), tx)
     } else {
         return;
     };
 
     tokio::spawn(async move {
         let mut queue = VecDeque::new();
+        let mut schedule = BinaryHeap::new();
         let mut is_ready = true;
 
-        while let Some(event) = rx.recv().await {
-            match event {
-                Event::RelayReady => {
-                    if let Some(event) = queue.pop_front() {
-                        if let Err(err) = relay_tx.send(event).await {
-                            error!("Error sending event to relay: {}", err);
-                        }
-                    } else {
+        // Nodes outside a cluster do not receive a Start event
+        if !core.is_in_cluster() {
+            load_queue(&core, &mut schedule).await;
+        }
+
+        loop {
+            let time_to_next = schedule
+                .peek()
+                .map(|Reverse((due, _, _))| Duration::from_secs(due.saturating_sub(unix_now())))
+                .unwrap_or(IDLE_TIMEOUT);
+
+            match tokio::time::timeout(time_to_next, rx.recv()).await {
+                Ok(Some(event)) => match event {
+                    Event::RelayReady => {
                         is_ready = true;
                     }
-                }
-                Event::Stop => {
-                    if let Err(err) = relay_tx.send(Event::Reload).await {
-                        error!("Error sending event to relay: {}", err);
+                    Event::Schedule {
+                        account_id,
+                        document_id,
+                        due,
+                    } => {
+                        schedule.push(Reverse((due, account_id, document_id)));
                     }
-                    queue.clear();
-                }
-                Event::Start => (),
-                event => {
-                    if is_ready {
-                        if let Err(err) = relay_tx.send(event).await {
+                    Event::Start => {
+                        load_queue(&core, &mut schedule).await;
+                    }
+                    Event::Stop => {
+                        if let Err(err) = relay_tx.send(Event::Reload).await {
                             error!("Error sending event to relay: {}", err);
                         }
-                    } else {
+                        queue.clear();
+                        schedule.clear();
+                    }
+                    event => {
                         queue.push_back(event);
                     }
+                },
+                Ok(None) => break,
+                Err(_) => (),
+            }
+
+            // Release submissions that are due for delivery
+            let now = unix_now();
+            while matches!(schedule.peek(), Some(Reverse((due, _, _))) if *due <= now) {
+                let Reverse((_, account_id, document_id)) = schedule.pop().unwrap();
+                queue.push_back(Event::new_submission(account_id, vec![document_id], vec![]));
+            }
+
+            if is_ready {
+                if let Some(event) = queue.pop_front() {
+                    if let Err(err) = relay_tx.send(event).await {
+                        error!("Error sending event to relay: {}", err);
+                    } else {
+                        is_ready = false;
+                    }
                 }
             }
         }
     });
 }
 
+async fn load_queue<T>(
+    core: &web::Data<JMAPServer<T>>,
+    schedule: &mut BinaryHeap<Reverse<(u64, AccountId, DocumentId)>>,
+) where
+    T: for<'x> Store<'x> + 'static,
+{
+    let store = core.store.clone();
+    match core
+        .spawn_worker(move || store.email_submission_queued())
+        .await
+    {
+        Ok(queued) => {
+            debug!("Loaded {} queued e-mail submissions.", queued.len());
+            schedule.clear();
+            for submission in queued {
+                schedule.push(Reverse((
+                    submission.next_attempt,
+                    submission.account_id,
+                    submission.document_id,
+                )));
+            }
+        }
+        Err(err) => {
+            error!("Failed to load queued e-mail submissions: {}", err);
+        }
+    }
+}
+
 fn spawn_email_relay<T>(
     core: web::Data<JMAPServer<T>>,
-    smtp_relay: SMTPRelay,
+    delivery: Delivery,
+    queue_settings: QueueSettings,
     queue_tx: mpsc::Sender<Event>,
 ) -> mpsc::Sender<Event>
 where
@@ -150,15 +227,6 @@ where
 {
     let (tx, mut rx) = mpsc::channel::<Event>(IPC_CHANNEL_BUFFER);
     tokio::spawn(async move {
-        // Setup client
-        let mut client = Transport::new(&smtp_relay.hostname).timeout(smtp_relay.timeout);
-        if smtp_relay.port > 0 {
-            client = client.port(smtp_relay.port);
-        }
-        if let Some((username, secret)) = &smtp_relay.credentials {
-            client = client.credentials(username, secret);
-        }
-        let is_tls = smtp_relay.tls;
         let mut dkim_map = AHashMap::new();
 
         while let Some(event) = rx.recv().await {
@@ -168,17 +236,30 @@ where
                     created_ids,
                     ..
                 } => {
-                    // Fetch submissions
+                    // Fetch submissions that are due for delivery
                     let account_id = account_id;
                     let store = core.store.clone();
-                    let messages = match core
+                    let now = unix_now();
+                    let (messages, scheduled) = match core
                         .spawn_worker(move || {
                             let mut messages = Vec::with_capacity(created_ids.len());
+                            let mut scheduled = Vec::new();
 
                             for created_id in created_ids {
                                 if let Some(email_submission) =
                                     store.get_orm::<EmailSubmission>(account_id, created_id)?
                                 {
+                                    match email_submission.get(&Property::DeliveryQueue) {
+                                        Some(Value::DeliveryQueue { value })
+                                            if value.next_attempt > now =>
+                                        {
+                                            scheduled.push((created_id, value.next_attempt));
+                                            continue;
+                                        }
+                                        Some(Value::DeliveryQueue { .. }) => (),
+                                        _ => continue,
+                                    }
+
                                     if let Some(blob_id) = store.get_document_value::<BlobId>(
                                         account_id,
                                         Collection::EmailSubmission,
@@ -192,296 +273,436 @@ where
                                 }
                             }
 
-                            Ok(messages)
+                            Ok((messages, scheduled))
                         })
                         .await
                     {
-                        Ok(messages) => {
-                            if messages.is_empty() {
-                                continue;
-                            }
-                            messages
-                        }
+                        Ok(result) => result,
                         Err(err) => {
                             error!("Error getting email submissions: {}", err);
-                            continue;
+                            (Vec::new(), Vec::new())
                         }
                     };
 
-                    // Connect to relay server
+                    // Submissions that are not due yet go back to the queue
+                    for (document_id, due) in scheduled {
+                        if let Err(err) = queue_tx
+                            .send(Event::Schedule {
+                                account_id,
+                                document_id,
+                                due,
+                            })
+                            .await
+                        {
+                            error!("Error sending event to queue: {}", err);
+                        }
+                    }
+
+                    if messages.is_empty() {
+                        if let Err(err) = queue_tx.send(Event::RelayReady).await {
+                            error!("Error sending event to relay: {}", err);
+                        }
+                        continue;
+                    }
+
                     let mut results = Vec::with_capacity(messages.len());
-                    match if is_tls {
-                        client.clone().connect_tls().await
-                    } else {
-                        client.clone().connect().await
-                    } {
-                        Ok(mut client) => {
-                            for (email_submission_id, current_email_submission, raw_message) in
-                                messages
-                            {
-                                // Track changes
-                                let mut email_submission =
-                                    TinyORM::track_changes(&current_email_submission);
 
-                                // Access envelope
-                                let envelope = if let Some(envelope) = current_email_submission
-                                    .get(&Property::Envelope)
-                                    .and_then(|value| {
-                                        if let Value::Envelope { value } = value {
-                                            Some(value)
+                    for (email_submission_id, current_email_submission, raw_message) in messages {
+                        // Access envelope and queue status
+                        let (envelope, mut queue) = match (
+                            current_email_submission.get(&Property::Envelope),
+                            current_email_submission.get(&Property::DeliveryQueue),
+                        ) {
+                            (
+                                Some(Value::Envelope { value: envelope }),
+                                Some(Value::DeliveryQueue { value: queue }),
+                            ) => (envelope, queue.clone()),
+                            _ => {
+                                error!(
+                                    "Missing envelope for {}/{}",
+                                    account_id, email_submission_id
+                                );
+                                continue;
+                            }
+                        };
+
+                        // Obtain recipients that are still pending delivery
+                        let prev_delivery_status = if let Some(Value::DeliveryStatus { value }) =
+                            current_email_submission.get(&Property::DeliveryStatus)
+                        {
+                            value.clone()
+                        } else {
+                            AHashMap::with_capacity(envelope.rcpt_to.len())
+                        };
+                        let mut delivery_status = prev_delivery_status.clone();
+                        let rcpt_to = envelope
+                            .rcpt_to
+                            .iter()
+                            .filter(|rcpt| {
+                                delivery_status
+                                    .get(&rcpt.email)
+                                    .map_or(true, |status| status.is_pending())
+                            })
+                            .collect::<Vec<_>>();
+                        let mut dkim_failure = None;
+
+                        // Fetch dkim settings
+                        let domain_name = envelope
+                            .mail_from
+                            .email
+                            .split_once('@')
+                            .unwrap()
+                            .1
+                            .to_string();
+                        let dkim = if let Some(dkim) = dkim_map.get(&domain_name) {
+                            dkim
+                        } else {
+                            match core.store.dkim_get(domain_name.clone()) {
+                                Ok(dkim) => {
+                                    dkim_map.insert(
+                                        domain_name.clone(),
+                                        if let Some(dkim) = dkim {
+                                            dkim.headers([
+                                                "From",
+                                                "To",
+                                                "Subject",
+                                                "Date",
+                                                "Cc",
+                                                "Bcc",
+                                                "Message-ID",
+                                                "References",
+                                                "In-Reply-To",
+                                            ])
+                                            .into()
                                         } else {
                                             None
-                                        }
-                                    }) {
-                                    envelope
-                                } else {
+                                        },
+                                    );
+                                    dkim_map.get(&domain_name).unwrap()
+                                }
+                                Err(err) => {
                                     error!(
-                                        "Missing envelope for {}/{}",
-                                        account_id, email_submission_id
+                                        "Error getting DKIM settings for domain '{}': {}",
+                                        domain_name, err
                                     );
-                                    continue;
-                                };
+                                    dkim_failure =
+                                        Some(("Failed to obtain DKIM settings.".to_string(), true));
+                                    &None
+                                }
+                            }
+                        };
 
-                                // Fetch dkim settings
-                                let domain_name = envelope
-                                    .mail_from
-                                    .email
-                                    .split_once('@')
-                                    .unwrap()
-                                    .1
-                                    .to_string();
-                                let dkim = if let Some(dkim) = dkim_map.get(&domain_name) {
-                                    dkim
-                                } else {
-                                    match core.store.dkim_get(domain_name.clone()) {
-                                        Ok(dkim) => {
-                                            dkim_map.insert(
-                                                domain_name.clone(),
-                                                if let Some(dkim) = dkim {
-                                                    dkim.headers([
-                                                        "From",
-                                                        "To",
-                                                        "Subject",
-                                                        "Date",
-                                                        "Cc",
-                                                        "Bcc",
-                                                        "Message-ID",
-                                                        "References",
-                                                        "In-Reply-To",
-                                                    ])
-                                                    .into()
-                                                } else {
-                                                    None
-                                                },
-                                            );
-                                            dkim_map.get(&domain_name).unwrap()
-                                        }
-                                        Err(err) => {
-                                            error!(
-                                                "Error getting DKIM settings for domain '{}': {}",
-                                                domain_name, err
-                                            );
-                                            continue;
-                                        }
-                                    }
-                                };
+                        // Sign message
+                        let mut headers = None;
+                        if let (None, Some(dkim)) = (&dkim_failure, dkim) {
+                            match dkim.sign(&raw_message) {
+                                Ok(signature) => {
+                                    headers = signature.to_header().into();
+                                }
+                                Err(err) => {
+                                    error!(
+                                        "Error signing message for domain '{}': {}",
+                                        domain_name, err
+                                    );
+                                }
+                            }
+                        }
 
-                                // Create delivery status list
-                                let mut delivery_status =
-                                    AHashMap::with_capacity(envelope.rcpt_to.len());
+                        // Future release is handled by the queue, the remote server should
+                        // not hold the message again
+                        let mut mail_from = envelope.mail_from.clone();
+                        if let Some(parameters) = &mut mail_from.parameters {
+                            parameters.remove("HOLDFOR");
+                            parameters.remove("HOLDUNTIL");
+                        }
 
-                                // Send mail-from
-                                let undo_status = if let Err(err) = client
-                                    .cmd(
-                                        format!("MAIL FROM:{}\r\n", &envelope.mail_from).as_bytes(),
-                                    )
+                        for (rcpt_domain, rcpt_to) in
+                            delivery.routes(rcpt_to, |rcpt| rcpt.email.as_str())
+                        {
+                            let mut failure = dkim_failure.clone();
+                            let mut targets = Vec::new();
+
+                            // Obtain the servers to deliver the message to
+                            if failure.is_none() {
+                                match delivery
+                                    .targets(&envelope.mail_from.email, &rcpt_domain)
                                     .await
                                 {
-                                    let err = err.to_string();
-                                    for rcpt in &envelope.rcpt_to {
-                                        delivery_status.insert(
-                                            rcpt.email.to_string(),
-                                            DeliveryStatus::new(
-                                                err.clone(),
-                                                Delivered::No,
-                                                Displayed::Unknown,
-                                            ),
-                                        );
+                                    Ok(targets_) if !targets_.is_empty() => {
+                                        targets = targets_;
                                     }
-                                    UndoStatus::Canceled
-                                } else {
-                                    // Send recipients
-                                    let mut accepted_rcpt = false;
-                                    for rcpt in &envelope.rcpt_to {
-                                        match client
-                                            .cmd(format!("RCPT TO:{}\r\n", &rcpt).as_bytes())
-                                            .await
-                                        {
-                                            Ok(reply) => {
-                                                delivery_status.insert(
-                                                    rcpt.email.to_string(),
-                                                    DeliveryStatus::new(
-                                                        reply.to_string(),
-                                                        if reply.is_positive_completion() {
-                                                            accepted_rcpt = true;
-                                                            Delivered::Queued
-                                                        } else {
-                                                            Delivered::No
-                                                        },
-                                                        Displayed::Unknown,
-                                                    ),
-                                                );
-                                            }
-                                            Err(err) => {
-                                                delivery_status.insert(
-                                                    rcpt.email.to_string(),
-                                                    DeliveryStatus::new(
-                                                        err.to_string(),
-                                                        Delivered::No,
-                                                        Displayed::Unknown,
-                                                    ),
-                                                );
-                                            }
-                                        }
+                                    Ok(_) => {
+                                        failure = Some((
+                                            "No SMTP relay configured for this sender.".to_string(),
+                                            false,
+                                        ));
                                     }
+                                    Err(err) => {
+                                        debug!("{}", err);
+                                        failure = Some((err.to_string(), err.is_transient()));
+                                    }
+                                }
+                            }
 
-                                    // Do not submit message if no recipients were accepted
-                                    if accepted_rcpt {
-                                        // Sign message
-                                        let mut headers = None;
-                                        if let Some(dkim) = dkim {
-                                            match dkim.sign(&raw_message) {
-                                                Ok(signature) => {
-                                                    headers = signature.to_header().into();
-                                                }
-                                                Err(err) => {
-                                                    error!(
-                                                        "Error signing message for domain '{}': {}",
-                                                        domain_name, err
-                                                    );
-                                                }
+                            // Connect to the first available server
+                            let mut connection = None;
+                            for target in &targets {
+                                let transport = target.transport();
+                                match match target {
+                                    Target::Relay(relay) if relay.tls => {
+                                        transport.connect_tls().await
+                                    }
+                                    Target::Relay(_) => transport.connect().await,
+                                    // Opportunistic TLS, falling back to plain text only
+                                    // when STARTTLS is not advertised
+                                    Target::Mx { .. } => {
+                                        match transport.clone().connect_tls().await {
+                                            Err(mail_send::Error::MissingStartTls) => {
+                                                warn!(
+                                                    "'{}' does not support STARTTLS, delivering without TLS.",
+                                                    target.hostname()
+                                                );
+                                                transport.connect().await
                                             }
+                                            result => result,
                                         }
+                                    }
+                                } {
+                                    Ok(client) => {
+                                        connection = Some(client);
+                                        break;
+                                    }
+                                    Err(err) => {
+                                        error!(
+                                            "Failed to connect to '{}': {}",
+                                            target.hostname(),
+                                            err
+                                        );
+                                        failure = Some((err.to_string(), true));
+                                    }
+                                }
+                            }
+                            if connection.is_some() {
+                                failure = None;
+                            }
 
-                                        // Send message
-                                        let result = if let Some(headers) = headers {
-                                            client
-                                                .data_with_headers(headers.as_bytes(), &raw_message)
+                            if let Some(mut client) = connection {
+                                // Send mail-from
+                                match client
+                                    .cmd(format!("MAIL FROM:{}\r\n", mail_from).as_bytes())
+                                    .await
+                                {
+                                    Ok(reply) if reply.is_positive_completion() => {
+                                        // Send recipients
+                                        let mut accepted_rcpt = Vec::with_capacity(rcpt_to.len());
+                                        for rcpt in &rcpt_to {
+                                            match client
+                                                .cmd(format!("RCPT TO:{}\r\n", rcpt).as_bytes())
                                                 .await
-                                        } else {
-                                            client.data(&raw_message).await
-                                        };
-
-                                        match result {
-                                            Ok(_) => UndoStatus::Final,
-                                            Err(err) => {
-                                                let err = err.to_string();
-                                                for rcpt in &envelope.rcpt_to {
+                                            {
+                                                Ok(reply) if reply.is_positive_completion() => {
+                                                    accepted_rcpt.push((rcpt, reply.to_string()));
+                                                }
+                                                Ok(reply) => {
                                                     delivery_status.insert(
                                                         rcpt.email.to_string(),
                                                         DeliveryStatus::new(
-                                                            err.clone(),
-                                                            Delivered::No,
+                                                            reply.to_string(),
+                                                            if reply.code() / 100 == 4 {
+                                                                Delivered::Queued
+                                                            } else {
+                                                                Delivered::No
+                                                            },
                                                             Displayed::Unknown,
                                                         ),
                                                     );
                                                 }
-                                                UndoStatus::Canceled
+                                                Err(err) => {
+                                                    failure =
+                                                        Some((err.to_string(), is_transient(&err)));
+                                                    break;
+                                                }
                                             }
                                         }
-                                    } else {
-                                        UndoStatus::Canceled
-                                    }
-                                };
 
-                                // Update submission
-                                email_submission.set(
-                                    Property::UndoStatus,
-                                    Value::UndoStatus { value: undo_status },
-                                );
-                                email_submission.set(
-                                    Property::DeliveryStatus,
-                                    Value::DeliveryStatus {
-                                        value: delivery_status,
-                                    },
-                                );
-                                results.push((
-                                    email_submission_id,
-                                    current_email_submission,
-                                    email_submission,
-                                ));
-                                client.rset().await.ok();
-                            }
-
-                            // Send QUIT
-                            client.quit().await.ok();
-                        }
-                        Err(err) => {
-                            // Fail all submissions
-                            let err = err.to_string();
-                            error!("Failed to connect to relay server: {}", err);
+                                        // Do not submit message if no recipients were accepted
+                                        if failure.is_none() && !accepted_rcpt.is_empty() {
+                                            // Send message
+                                            let result = if let Some(headers) = &headers {
+                                                client
+                                                    .data_with_headers(
+                                                        headers.as_bytes(),
+                                                        &raw_message,
+                                                    )
+                                                    .await
+                                            } else {
+                                                client.data(&raw_message).await
+                                            };
 
-                            for (email_submission_id, current_email_submission, _) in messages {
-                                // Track changes
-                                let mut email_submission =
-                                    TinyORM::track_changes(&current_email_submission);
-
-                                // Access envelope
-                                if let Some(envelope) = current_email_submission
-                                    .get(&Property::Envelope)
-                                    .and_then(|value| {
-                                        if let Value::Envelope { value } = value {
-                                            Some(value)
-                                        } else {
-                                            None
+                                            let (delivered, is_delivered) = match &result {
+                                                Ok(_) => (Delivered::Queued, true),
+                                                Err(err) if is_transient(err) => {
+                                                    (Delivered::Queued, false)
+                                                }
+                                                Err(_) => (Delivered::No, false),
+                                            };
+                                            for (rcpt, reply) in accepted_rcpt {
+                                                delivery_status.insert(
+                                                    rcpt.email.to_string(),
+                                                    DeliveryStatus::new(
+                                                        if is_delivered {
+                                                            reply
+                                                        } else {
+                                                            result.as_ref().unwrap_err().to_string()
+                                                        },
+                                                        delivered.clone(),
+                                                        Displayed::Unknown,
+                                                    ),
+                                                );
+                                            }
                                         }
-                                    })
-                                {
-                                    // Create delivery status list
-                                    let mut delivery_status =
-                                        AHashMap::with_capacity(envelope.rcpt_to.len());
+                                    }
+                                    Ok(reply) => {
+                                        failure =
+                                            Some((reply.to_string(), reply.code() / 100 == 4));
+                                    }
+                                    Err(err) => {
+                                        failure = Some((err.to_string(), is_transient(&err)));
+                                    }
+                                }
+
+                                // Send QUIT
+                                client.quit().await.ok();
+                            }
 
-                                    // Fail all recipients
-                                    for rcpt in &envelope.rcpt_to {
+                            // Update the status of the remaining recipients
+                            if let Some((reason, is_transient)) = failure {
+                                for rcpt in &rcpt_to {
+                                    if delivery_status
+                                        .get(&rcpt.email)
+                                        .map_or(true, |status| status.is_pending())
+                                    {
                                         delivery_status.insert(
                                             rcpt.email.to_string(),
                                             DeliveryStatus::new(
-                                                err.clone(),
-                                                Delivered::No,
+                                                reason.clone(),
+                                                if is_transient {
+                                                    Delivered::Queued
+                                                } else {
+                                                    Delivered::No
+                                                },
                                                 Displayed::Unknown,
                                             ),
                                         );
                                     }
-                                    email_submission.set(
-                                        Property::UndoStatus,
-                                        Value::UndoStatus {
-                                            value: UndoStatus::Canceled,
-                                        },
-                                    );
-                                    email_submission.set(
-                                        Property::DeliveryStatus,
-                                        Value::DeliveryStatus {
-                                            value: delivery_status,
-                                        },
-                                    );
-                                    results.push((
-                                        email_submission_id,
-                                        current_email_submission,
-                                        email_submission,
-                                    ));
                                 }
                             }
                         }
+
+                        // Schedule a retry or give up once the queue lifetime is exceeded
+                        let now = unix_now();
+                        queue.attempts += 1;
+                        if now >= queue.queued_at + queue_settings.lifetime {
+                            for status in delivery_status.values_mut() {
+                                if status.is_pending() {
+                                    status.delivered = Delivered::No;
+                                }
+                            }
+                        }
+                        let has_pending = delivery_status.values().any(|s| s.is_pending());
+                        let queue = if has_pending {
+                            queue.next_attempt =
+                                now + queue_settings.retry_interval(queue.attempts);
+                            if let Err(err) = queue_tx
+                                .send(Event::Schedule {
+                                    account_id,
+                                    document_id: email_submission_id,
+                                    due: queue.next_attempt,
+                                })
+                                .await
+                            {
+                                error!("Error sending event to queue: {}", err);
+                            }
+                            Some(queue)
+                        } else {
+                            None
+                        };
+
+                        // Keep only the recipients updated by this attempt
+                        delivery_status.retain(|rcpt, status| {
+                            prev_delivery_status.get(rcpt) != Some(&*status)
+                        });
+                        results.push((email_submission_id, delivery_status, queue));
                     }
 
                     // Update store with submission results
                     let store = core.store.clone();
                     match core
                         .spawn_worker(move || {
+                            // Submissions might have been canceled or updated by a delivery
+                            // report while the message was being delivered.
+                            let _lock =
+                                store.lock_collection(account_id, Collection::EmailSubmission);
                             let mut batch = WriteBatch::new(account_id);
-                            for (email_submission_id, current_email_submission, email_submission) in
-                                results
-                            {
+                            for (email_submission_id, updated_status, queue) in results {
+                                let current_email_submission = match store
+                                    .get_orm::<EmailSubmission>(account_id, email_submission_id)?
+                                {
+                                    Some(current_email_submission)
+                                        if current_email_submission
+                                            .has_property(&Property::DeliveryQueue) =>
+                                    {
+                                        current_email_submission
+                                    }
+                                    _ => continue,
+                                };
+                                let mut email_submission =
+                                    TinyORM::track_changes(&current_email_submission);
+                                let mut delivery_status =
+                                    if let Some(Value::DeliveryStatus { value }) =
+                                        current_email_submission.get(&Property::DeliveryStatus)
+                                    {
+                                        value.clone()
+                                    } else {
+                                        AHashMap::with_capacity(updated_status.len())
+                                    };
+                                delivery_status.extend(updated_status);
+
+                                let has_pending = queue.is_some();
+                                if let Some(queue) = queue {
+                                    email_submission.set(
+                                        Property::DeliveryQueue,
+                                        Value::DeliveryQueue { value: queue },
+                                    );
+                                } else {
+                                    email_submission.set(Property::DeliveryQueue, Value::Null);
+                                    email_submission.untag(&Property::DeliveryQueue, &Tag::Default);
+                                }
+
+                                // Messages can't be recalled once a recipient accepted them
+                                let undo_status = if delivery_status
+                                    .values()
+                                    .any(|s| !s.is_pending() && s.delivered != Delivered::No)
+                                {
+                                    UndoStatus::Final
+                                } else if has_pending {
+                                    UndoStatus::Pending
+                                } else {
+                                    UndoStatus::Canceled
+                                };
+
+                                // Update submission
+                                email_submission.set(
+                                    Property::UndoStatus,
+                                    Value::UndoStatus { value: undo_status },
+                                );
+                                email_submission.set(
+                                    Property::DeliveryStatus,
+                                    Value::DeliveryStatus {
+                                        value: delivery_status,
+                                    },
+                                );
+
                                 let mut document =
                                     Document::new(Collection::EmailSubmission, email_submission_id);
 
@@ -524,19 +745,51 @@ where
                     }
                 }
                 Event::OutgoingMessage { from, to, message } => {
-                    match if is_tls {
-                        client.clone().connect_tls().await
-                    } else {
-                        client.clone().connect().await
-                    } {
-                        Ok(mut client) => {
-                            if let Err(err) = client.send(Message::new(from, to, message)).await {
+                    for (rcpt_domain, rcpt_to) in
+                        delivery.routes(to.iter().collect(), |rcpt| rcpt.as_str())
+                    {
+                        let targets = match delivery.targets(&from, &rcpt_domain).await {
+                            Ok(targets) => targets,
+                            Err(err) => {
                                 debug!("Failed to send vacation response: {}", err);
+                                continue;
+                            }
+                        };
+
+                        for target in &targets {
+                            let transport = target.transport();
+                            match match target {
+                                Target::Relay(relay) if relay.tls => transport.connect_tls().await,
+                                Target::Relay(_) => transport.connect().await,
+                                Target::Mx { .. } => match transport.clone().connect_tls().await {
+                                    Err(mail_send::Error::MissingStartTls) => {
+                                        warn!(
+                                            "'{}' does not support STARTTLS, delivering without TLS.",
+                                            target.hostname()
+                                        );
+                                        transport.connect().await
+                                    }
+                                    result => result,
+                                },
+                            } {
+                                Ok(mut client) => {
+                                    if let Err(err) = client
+                                        .send(Message::new(
+                                            from.as_str(),
+                                            rcpt_to.iter().map(|rcpt| rcpt.as_str()),
+                                            message.as_slice(),
+                                        ))
+                                        .await
+                                    {
+                                        debug!("Failed to send vacation response: {}", err);
+                                    }
+                                    client.quit().await.ok();
+                                    break;
+                                }
+                                Err(err) => {
+                                    error!("Failed to connect to '{}': {}", target.hostname(), err);
+                                }
                             }
-                            client.quit().await.ok();
-                        }
-                        Err(err) => {
-                            error!("Failed to connect to relay server: {}", err);
                         }
                     }
                 }
@@ -555,6 +808,31 @@ where
     tx
 }
 
+fn is_transient(err: &mail_send::Error) -> bool {
+    // Only permanent (5xx) replies should fail a delivery, anything else is retried
+    !matches!(err, mail_send::Error::UnexpectedReply(reply) if reply.code() / 100 == 5)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+enum Delivery {
+    Relay(RelayRoutes),
+    Mx(MxDelivery),
+}
+
+enum Target<'x> {
+    Relay(&'x SMTPRelay),
+    Mx {
+        hostname: String,
+        settings: &'x MxDelivery,
+    },
+}
+
 struct SMTPRelay {
     hostname: String,
     port: u16,
@@ -563,27 +841,284 @@ struct SMTPRelay {
     timeout: Duration,
 }
 
-fn parse_smtp_settings(settings: &EnvSettings) -> Option<SMTPRelay> {
+// Relays are referenced by their position in the relay list, each route
+// holds the primary relay followed by its fallbacks.
+struct RelayRoutes {
+    relays: Vec<SMTPRelay>,
+    default: Vec<usize>,
+    domains: AHashMap<String, Vec<usize>>,
+    identities: AHashMap<String, Vec<usize>>,
+}
+
+struct MxDelivery {
+    resolver: MxResolver,
+    port: u16,
+    timeout: Duration,
+}
+
+impl Delivery {
+    // Groups recipients by the domain they have to be delivered to,
+    // a relay accepts all recipients in a single transaction
+    fn routes<R>(&self, rcpt_to: Vec<R>, address: fn(&R) -> &str) -> Vec<(String, Vec<R>)> {
+        match self {
+            Delivery::Relay(_) => vec![(String::new(), rcpt_to)],
+            Delivery::Mx(_) => {
+                let mut routes: Vec<(String, Vec<R>)> = Vec::new();
+                for rcpt in rcpt_to {
+                    let domain = address(&rcpt)
+                        .rsplit_once('@')
+                        .map(|(_, domain)| domain)
+                        .unwrap_or_default()
+                        .to_lowercase();
+                    if let Some((_, rcpts)) = routes.iter_mut().find(|(d, _)| d == &domain) {
+                        rcpts.push(rcpt);
+                    } else {
+                        routes.push((domain, vec![rcpt]));
+                    }
+                }
+                routes
+            }
+        }
+    }
+
+    // Returns the servers to try, in order, for a sender and recipient domain
+    async fn targets(
+        &self,
+        mail_from: &str,
+        rcpt_domain: &str,
+    ) -> Result<Vec<Target<'_>>, MxError> {
+        match self {
+            Delivery::Relay(routes) => Ok(routes.select(mail_from).map(Target::Relay).collect()),
+            Delivery::Mx(mx) => Ok(mx
+                .resolver
+                .lookup(rcpt_domain)
+                .await?
+                .into_iter()
+                .map(|hostname| Target::Mx {
+                    hostname,
+                    settings: mx,
+                })
+                .collect()),
+        }
+    }
+}
+
+impl RelayRoutes {
+    // Identity routes take precedence over domain routes
+    fn select(&self, mail_from: &str) -> impl Iterator<Item = &SMTPRelay> {
+        let mail_from = mail_from.to_lowercase();
+        self.identities
+            .get(&mail_from)
+            .or_else(|| {
+                self.domains
+                    .get(mail_from.rsplit_once('@').map(|(_, domain)| domain)?)
+            })
+            .unwrap_or(&self.default)
+            .iter()
+            .map(move |&relay_id| &self.relays[relay_id])
+    }
+}
+
+impl Target<'_> {
+    fn hostname(&self) -> &str {
+        match self {
+            Target::Relay(relay) => &relay.hostname,
+            Target::Mx { hostname, .. } => hostname,
+        }
+    }
+
+    fn transport(&self) -> Transport<'_> {
+        match self {
+            Target::Relay(relay) => {
+                let mut transport = Transport::new(&relay.hostname).timeout(relay.timeout);
+                if relay.port > 0 {
+                    transport = transport.port(relay.port);
+                }
+                if let Some((username, secret)) = &relay.credentials {
+                    transport = transport.credentials(username, secret);
+                }
+                transport
+            }
+            // Opportunistic TLS to MX hosts does not verify certificates, as most
+            // of them are self-signed or do not match the MX hostname
+            Target::Mx { hostname, settings } => Transport::new(hostname)
+                .port(settings.port)
+                .timeout(settings.timeout)
+                .allow_invalid_certs(),
+        }
+    }
+}
+
+fn parse_delivery_settings(settings: &EnvSettings) -> Option<Delivery> {
+    match settings
+        .get("smtp-delivery-mode")
+        .unwrap_or_else(|| "relay".to_string())
+        .as_str()
+    {
+        "relay" => {
+            let routes = parse_relay_routes(settings);
+            if routes.is_none() {
+                info!("No SMTP relay configured, outbound e-mail delivery is disabled.");
+            }
+            routes.map(Delivery::Relay)
+        }
+        "mx" => Some(Delivery::Mx(MxDelivery {
+            resolver: MxResolver::parse(settings),
+            port: settings.parse("smtp-mx-port").unwrap_or(DEFAULT_MX_PORT),
+            timeout: Duration::from_millis(
+                settings
+                    .parse("smtp-mx-timeout")
+                    .unwrap_or(DEFAULT_SMTP_TIMEOUT_MS),
+            ),
+        })),
+        mode => failed_to(&format!(
+            "parse 'smtp-delivery-mode', invalid mode '{}'.",
+            mode
+        )),
+    }
+}
+
+fn parse_relay_routes(settings: &EnvSettings) -> Option<RelayRoutes> {
+    // The default relay is configured with the unnamed 'smtp-relay-*' keys,
+    // additional relays are listed in 'smtp-relays' and use 'smtp-relay-<name>-*'
+    let mut names = Vec::new();
+    let mut relays = Vec::new();
+    if let Some(relay) = parse_smtp_settings(settings, "smtp-relay") {
+        names.push("default".to_string());
+        relays.push(relay);
+    }
+    for name in settings.parse_list("smtp-relays").unwrap_or_default() {
+        let name = name.trim().to_lowercase();
+        if name.is_empty() {
+            continue;
+        } else if name == "default" || names.contains(&name) {
+            failed_to(&format!(
+                "parse 'smtp-relays', invalid relay name '{}'.",
+                name
+            ));
+        }
+        relays.push(
+            parse_smtp_settings(settings, &format!("smtp-relay-{}", name)).unwrap_or_else(|| {
+                failed_to(&format!(
+                    "find 'smtp-relay-{}-host' for relay '{}'.",
+                    name, name
+                ))
+            }),
+        );
+        names.push(name);
+    }
+    if relays.is_empty() {
+        return None;
+    }
+
+    let mut routes = RelayRoutes {
+        relays: Vec::with_capacity(relays.len()),
+        default: Vec::new(),
+        domains: AHashMap::new(),
+        identities: AHashMap::new(),
+    };
+
+    for (relay_id, name) in names.iter().enumerate() {
+        let prefix = if name == "default" {
+            "smtp-relay".to_string()
+        } else {
+            format!("smtp-relay-{}", name)
+        };
+
+        // Build route with fallback relays
+        let mut route = vec![relay_id];
+        for fallback in settings
+            .parse_list(&format!("{}-fallback", prefix))
+            .unwrap_or_default()
+        {
+            let fallback = fallback.trim().to_lowercase();
+            if let Some(fallback_id) = names.iter().position(|name| name == &fallback) {
+                if !route.contains(&fallback_id) {
+                    route.push(fallback_id);
+                }
+            } else {
+                failed_to(&format!(
+                    "parse '{}-fallback', relay '{}' does not exist.",
+                    prefix, fallback
+                ));
+            }
+        }
+
+        if name == "default" {
+            routes.default = route.clone();
+        }
+        for domain in settings
+            .parse_list(&format!("{}-domains", prefix))
+            .unwrap_or_default()
+        {
+            routes
+                .domains
+                .insert(domain.trim().to_lowercase(), route.clone());
+        }
+        for identity in settings
+            .parse_list(&format!("{}-identities", prefix))
+            .unwrap_or_default()
+        {
+            routes
+                .identities
+                .insert(identity.trim().to_lowercase(), route.clone());
+        }
+    }
+    routes.relays = relays;
+
+    Some(routes)
+}
+
+fn parse_smtp_settings(settings: &EnvSettings, prefix: &str) -> Option<SMTPRelay> {
     Some(SMTPRelay {
-        hostname: settings.get("smtp-relay-host")?,
-        port: settings.parse("smtp-relay-port").unwrap_or(0),
+        hostname: settings.get(&format!("{}-host", prefix))?,
+        port: settings.parse(&format!("{}-port", prefix)).unwrap_or(0),
         credentials: if let (Some(auth), Some(pass)) = (
-            settings.get("smtp-relay-auth"),
-            settings.get("smtp-relay-secret"),
+            settings.get(&format!("{}-auth", prefix)),
+            settings.get(&format!("{}-secret", prefix)),
         ) {
             (auth, pass).into()
         } else {
             None
         },
-        tls: settings.parse("smtp-relay-tls").unwrap_or(false),
+        tls: settings.parse(&format!("{}-tls", prefix)).unwrap_or(false),
         timeout: Duration::from_millis(
             settings
-                .parse("smtp-relay-timeout")
+                .parse(&format!("{}-timeout", prefix))
                 .unwrap_or(DEFAULT_SMTP_TIMEOUT_MS),
         ),
     })
 }
 
+struct QueueSettings {
+    retry_interval: u64,
+    max_retry_interval: u64,
+    lifetime: u64,
+}
+
+impl QueueSettings {
+    // Exponential backoff between delivery attempts
+    fn retry_interval(&self, attempts: u32) -> u64 {
+        self.retry_interval
+            .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+            .min(self.max_retry_interval)
+    }
+}
+
+fn parse_queue_settings(settings: &EnvSettings) -> QueueSettings {
+    QueueSettings {
+        retry_interval: settings
+            .parse("smtp-retry-interval")
+            .unwrap_or(DEFAULT_RETRY_INTERVAL),
+        max_retry_interval: settings
+            .parse("smtp-retry-max-interval")
+            .unwrap_or(DEFAULT_MAX_RETRY_INTERVAL),
+        lifetime: settings
+            .parse("smtp-queue-lifetime")
+            .unwrap_or(DEFAULT_QUEUE_LIFETIME),
+    }
+}
+
 impl<T> JMAPServer<T>
 where
     T: for<'x> Store<'x> + 'static,
@@ -596,3 +1131,53 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use store::{ahash::AHashMap, config::env_settings::EnvSettings};
+
+    use super::parse_relay_routes;
+
+    #[test]
+    fn relay_routes() {
+        let settings = EnvSettings {
+            args: AHashMap::from_iter(
+                [
+                    ("smtp-relay-host", "mta.example.org"),
+                    ("smtp-relays", "transactional, backup"),
+                    ("smtp-relay-transactional-host", "smtp.provider.com"),
+                    ("smtp-relay-transactional-port", "587"),
+                    ("smtp-relay-transactional-tls", "true"),
+                    ("smtp-relay-transactional-domains", "example.com"),
+                    ("smtp-relay-transactional-fallback", "backup, default"),
+                    ("smtp-relay-backup-host", "backup.example.org"),
+                    ("smtp-relay-backup-identities", "ceo@example.org"),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+            ),
+        };
+        let routes = parse_relay_routes(&settings).unwrap();
+
+        for (mail_from, expected) in [
+            (
+                "jdoe@Example.com",
+                vec!["smtp.provider.com", "backup.example.org", "mta.example.org"],
+            ),
+            ("ceo@example.org", vec!["backup.example.org"]),
+            ("jane@example.org", vec!["mta.example.org"]),
+        ] {
+            assert_eq!(
+                routes
+                    .select(mail_from)
+                    .map(|relay| relay.hostname.as_str())
+                    .collect::<Vec<_>>(),
+                expected,
+                "{}",
+                mail_from
+            );
+        }
+        assert_eq!(routes.relays[1].port, 587);
+        assert!(routes.relays[1].tls);
+    }
+}
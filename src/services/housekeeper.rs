@@ -24,7 +24,7 @@
 use std::time::{Duration, SystemTime};
 
 use actix_web::web;
-use jmap_sharing::principal::set::JMAPSetPrincipal;
+use jmap_sharing::principal::{dkim::JMAPDkimRotate, set::JMAPSetPrincipal};
 use store::{
     chrono::{self, Datelike, TimeZone},
     config::env_settings::EnvSettings,
@@ -36,6 +36,7 @@ use tokio::sync::mpsc;
 use crate::{
     cluster::IPC_CHANNEL_BUFFER,
     server::{failed_to, UnwrapFailure},
+    services::email_delivery,
     JMAPServer,
 };
 
@@ -44,6 +45,7 @@ pub enum Event {
     PurgeBlobs,
     SnapshotLog,
     CompactDb,
+    RotateDkim,
     Exit,
 }
 
@@ -56,6 +58,7 @@ const TASK_PURGE_ACCOUNTS: usize = 0;
 const TASK_PURGE_BLOBS: usize = 1;
 const TASK_SNAPSHOT_LOG: usize = 2;
 const TASK_COMPACT_DB: usize = 3;
+const TASK_ROTATE_DKIM: usize = 4;
 
 pub fn spawn_housekeeper<T>(
     core: web::Data<JMAPServer<T>>,
@@ -84,6 +87,11 @@ pub fn spawn_housekeeper<T>(
             .get("schedule-compact-db")
             .unwrap_or_else(|| "0 4 *".to_string()),
     );
+    let rotate_dkim_at = SimpleCron::parse(
+        &settings
+            .get("schedule-rotate-dkim")
+            .unwrap_or_else(|| "15 4 *".to_string()),
+    );
     let max_log_entries: u64 = settings.parse("max-changelog-entries").unwrap_or(10000);
 
     tokio::spawn(async move {
@@ -94,8 +102,9 @@ pub fn spawn_housekeeper<T>(
                 purge_blobs_at.time_to_next(),
                 snapshot_log_at.time_to_next(),
                 compact_db_at.time_to_next(),
+                rotate_dkim_at.time_to_next(),
             ];
-            let mut tasks_to_run = [false, false, false, false];
+            let mut tasks_to_run = [false, false, false, false, false];
             let start_time = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .map(|d| d.as_secs())
@@ -108,6 +117,7 @@ pub fn spawn_housekeeper<T>(
                     Event::PurgeBlobs => tasks_to_run[TASK_PURGE_BLOBS] = true,
                     Event::SnapshotLog => tasks_to_run[TASK_SNAPSHOT_LOG] = true,
                     Event::CompactDb => tasks_to_run[TASK_COMPACT_DB] = true,
+                    Event::RotateDkim => tasks_to_run[TASK_ROTATE_DKIM] = true,
                     Event::Exit => {
                         debug!("Housekeeper task exiting.");
                         return;
@@ -148,7 +158,9 @@ pub fn spawn_housekeeper<T>(
                         }
                         TASK_PURGE_BLOBS => {
                             info!("Purging removed and expired blobs.");
-                            core.spawn_worker(move || store.purge_blobs()).await
+                            let is_leader = core.is_leader();
+                            core.spawn_worker(move || store.purge_blobs(is_leader))
+                                .await
                         }
                         TASK_SNAPSHOT_LOG => {
                             info!("Compacting changes and Raft logs.");
@@ -160,6 +172,31 @@ pub fn spawn_housekeeper<T>(
                             core.spawn_worker(move || store.db.compact(ColumnFamily::Bitmaps))
                                 .await
                         }
+                        TASK_ROTATE_DKIM => {
+                            let result = if core.is_leader() {
+                                info!("Rotating DKIM keys.");
+                                match core.spawn_worker(move || store.dkim_rotate()).await {
+                                    Ok(Some(changes)) => {
+                                        if core.is_in_cluster() {
+                                            core.commit_index(changes.change_id).await;
+                                        }
+                                        Ok(())
+                                    }
+                                    Ok(None) => Ok(()),
+                                    Err(err) => Err(err),
+                                }
+                            } else {
+                                Ok(())
+                            };
+
+                            // Signing keys are cached by the delivery service of each node
+                            if result.is_ok() {
+                                core.notify_email_delivery(email_delivery::Event::Reload)
+                                    .await
+                                    .ok();
+                            }
+                            result
+                        }
                         _ => unreachable!(),
                     };
 
@@ -21,8 +21,10 @@
  * for more details.
 */
 
+pub mod attachment_index;
 pub mod email_delivery;
 pub mod housekeeper;
+pub mod mx;
 pub mod push_subscription;
 pub mod push_subscription_ece;
 pub mod state_change;
@@ -0,0 +1,238 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Display, net::IpAddr};
+
+use store::{ahash::AHashMap, config::env_settings::EnvSettings};
+use trust_dns_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
+    TokioAsyncResolver,
+};
+
+use crate::server::failed_to;
+
+pub enum MxResolver {
+    Dns(TokioAsyncResolver),
+    Static(AHashMap<String, Vec<String>>),
+}
+
+#[derive(Debug)]
+pub enum MxError {
+    NullMx(String),
+    NoSuchDomain(String),
+    Resolve(ResolveError),
+    Unsupported,
+}
+
+impl MxError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, MxError::Resolve(_) | MxError::Unsupported)
+    }
+}
+
+impl Display for MxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MxError::NullMx(domain) => write!(f, "Domain '{}' does not accept mail.", domain),
+            MxError::NoSuchDomain(domain) => write!(f, "Domain '{}' does not exist.", domain),
+            MxError::Resolve(err) => write!(f, "MX lookup failed: {}", err),
+            MxError::Unsupported => write!(f, "Static resolvers do not support TXT lookups."),
+        }
+    }
+}
+
+impl MxResolver {
+    pub fn parse(settings: &EnvSettings) -> Self {
+        let resolver = settings
+            .get("smtp-mx-resolver")
+            .unwrap_or_else(|| "system".to_string());
+        let config = match resolver.as_str() {
+            "system" => {
+                return MxResolver::Dns(
+                    TokioAsyncResolver::tokio_from_system_conf()
+                        .unwrap_or_else(|err| failed_to(&format!("create DNS resolver: {}", err))),
+                );
+            }
+            "cloudflare" => ResolverConfig::cloudflare(),
+            "google" => ResolverConfig::google(),
+            "quad9" => ResolverConfig::quad9(),
+            "static" => {
+                return MxResolver::Static(parse_static_mx(
+                    &settings.get("smtp-mx-static").unwrap_or_default(),
+                ));
+            }
+            nameservers => {
+                let mut group = NameServerConfigGroup::new();
+                for nameserver in nameservers.split(',') {
+                    let nameserver = nameserver.trim();
+                    let (ip, ns_port) = match nameserver.parse::<IpAddr>() {
+                        Ok(ip) => (ip, None),
+                        Err(_) => match nameserver.rsplit_once(':') {
+                            Some((ip, ns_port)) => (
+                                ip.trim_start_matches('[')
+                                    .trim_end_matches(']')
+                                    .parse::<IpAddr>()
+                                    .ok()
+                                    .unwrap_or_else(|| {
+                                        failed_to(&format!(
+                                            "parse 'smtp-mx-resolver' nameserver '{}'.",
+                                            nameserver
+                                        ))
+                                    }),
+                                ns_port.parse::<u16>().ok(),
+                            ),
+                            None => failed_to(&format!(
+                                "parse 'smtp-mx-resolver' nameserver '{}'.",
+                                nameserver
+                            )),
+                        },
+                    };
+                    // Each nameserver keeps its own port
+                    group.merge(NameServerConfigGroup::from_ips_clear(
+                        &[ip],
+                        ns_port.unwrap_or(53),
+                        true,
+                    ));
+                }
+                ResolverConfig::from_parts(None, vec![], group)
+            }
+        };
+
+        MxResolver::Dns(
+            TokioAsyncResolver::tokio(config, ResolverOpts::default())
+                .unwrap_or_else(|err| failed_to(&format!("create DNS resolver: {}", err))),
+        )
+    }
+
+    /// Returns the hosts accepting mail for a domain, ordered by preference.
+    pub async fn lookup(&self, domain: &str) -> Result<Vec<String>, MxError> {
+        match self {
+            MxResolver::Dns(resolver) => match resolver.mx_lookup(domain).await {
+                Ok(mx) => {
+                    let mut records = mx.iter().collect::<Vec<_>>();
+                    records.sort_by_key(|mx| mx.preference());
+
+                    // A single "." exchange is a null MX (RFC 7505)
+                    if records.len() == 1 && records[0].exchange().is_root() {
+                        return Err(MxError::NullMx(domain.to_string()));
+                    }
+
+                    Ok(records
+                        .into_iter()
+                        .map(|mx| mx.exchange().to_utf8().trim_end_matches('.').to_string())
+                        .collect())
+                }
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                        if *response_code == ResponseCode::NXDomain {
+                            Err(MxError::NoSuchDomain(domain.to_string()))
+                        } else {
+                            // Fall back to the implicit MX (RFC 5321, section 5.1)
+                            Ok(vec![domain.to_string()])
+                        }
+                    }
+                    _ => Err(MxError::Resolve(err)),
+                },
+            },
+            MxResolver::Static(hosts) => Ok(hosts
+                .get(domain)
+                .or_else(|| hosts.get("*"))
+                .cloned()
+                .unwrap_or_else(|| vec![domain.to_string()])),
+        }
+    }
+
+    /// Returns the TXT records published under a name. Static resolvers
+    /// have no TXT records.
+    pub async fn txt_lookup(&self, name: &str) -> Result<Vec<String>, MxError> {
+        match self {
+            MxResolver::Dns(resolver) => match resolver.txt_lookup(name).await {
+                Ok(txt) => Ok(txt
+                    .iter()
+                    .map(|record| {
+                        record
+                            .txt_data()
+                            .iter()
+                            .map(|data| String::from_utf8_lossy(data))
+                            .collect::<String>()
+                    })
+                    .collect()),
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::NoRecordsFound { .. } => {
+                        Err(MxError::NoSuchDomain(name.to_string()))
+                    }
+                    _ => Err(MxError::Resolve(err)),
+                },
+            },
+            MxResolver::Static(_) => Err(MxError::Unsupported),
+        }
+    }
+}
+
+fn parse_static_mx(value: &str) -> AHashMap<String, Vec<String>> {
+    let mut hosts = AHashMap::new();
+    for entry in value.split(';') {
+        if let Some((domain, mx)) = entry.split_once('=') {
+            hosts.insert(
+                domain.trim().to_lowercase(),
+                mx.split(',')
+                    .map(|host| host.trim().to_string())
+                    .filter(|host| !host.is_empty())
+                    .collect(),
+            );
+        } else if !entry.trim().is_empty() {
+            failed_to(&format!("parse 'smtp-mx-static' entry '{}'.", entry));
+        }
+    }
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn static_mx_lookup() {
+        let resolver = MxResolver::Static(parse_static_mx(
+            "example.org=mx1.example.org, mx2.example.org; *=mx.example.net",
+        ));
+
+        assert_eq!(
+            resolver.lookup("example.org").await.unwrap(),
+            vec!["mx1.example.org".to_string(), "mx2.example.org".to_string()]
+        );
+        assert_eq!(
+            resolver.lookup("example.com").await.unwrap(),
+            vec!["mx.example.net".to_string()]
+        );
+        assert_eq!(
+            MxResolver::Static(AHashMap::new())
+                .lookup("example.com")
+                .await
+                .unwrap(),
+            vec!["example.com".to_string()]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use actix_web::web;
+use jmap::types::{blob::JMAPBlob, jmap::JMAPId};
+use jmap_client::{client::Client, mailbox::Role};
+use reqwest::{header, StatusCode};
+use serde_json::{json, Value};
+use store::{blob::BlobId, Store};
+
+use crate::JMAPServer;
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running blob download tests...");
+
+    let blob = (0..10000)
+        .map(|n| b'a' + (n % 26) as u8)
+        .collect::<Vec<_>>();
+    let blob_id = client
+        .upload(None, blob.clone(), None)
+        .await
+        .unwrap()
+        .take_blob_id();
+    let url = format!(
+        "{}/jmap/download/{}/{}/test.bin",
+        server.base_session.base_url(),
+        JMAPId::new(1),
+        blob_id
+    );
+    let etag = format!("\"{}\"", blob_id);
+
+    // Full download
+    let response = download(&url, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(response.bytes().await.unwrap(), blob);
+
+    // Conditional requests
+    for (value, expected_status) in [
+        (etag.clone(), StatusCode::NOT_MODIFIED),
+        (format!("W/{}", etag), StatusCode::NOT_MODIFIED),
+        (format!("\"abc\", {}", etag), StatusCode::NOT_MODIFIED),
+        ("*".to_string(), StatusCode::NOT_MODIFIED),
+        ("\"abc\"".to_string(), StatusCode::OK),
+    ] {
+        let response = download(&url, &[(header::IF_NONE_MATCH, value.as_str())]).await;
+        assert_eq!(response.status(), expected_status, "{}", value);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    }
+
+    // Single ranges
+    for (range, expected_range, expected_bytes) in [
+        ("bytes=0-99", "bytes 0-99/10000", &blob[0..100]),
+        ("bytes=9990-", "bytes 9990-9999/10000", &blob[9990..]),
+        ("bytes=-5", "bytes 9995-9999/10000", &blob[9995..]),
+        ("bytes=9000-20000", "bytes 9000-9999/10000", &blob[9000..]),
+        ("bytes=10-20,15-30", "bytes 10-30/10000", &blob[10..31]),
+    ] {
+        let response = download(&url, &[(header::RANGE, range)]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{}", range);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            expected_range,
+            "{}",
+            range
+        );
+        assert_eq!(response.bytes().await.unwrap(), expected_bytes, "{}", range);
+    }
+
+    // Multiple ranges
+    let response = download(&url, &[(header::RANGE, "bytes=0-4, 100-104")]).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(response.bytes().await.unwrap().to_vec()).unwrap(),
+        format!(
+            concat!(
+                "--{}\r\nContent-Type: application/octet-stream\r\n",
+                "Content-Range: bytes 0-4/10000\r\n\r\nabcde\r\n",
+                "--{}\r\nContent-Type: application/octet-stream\r\n",
+                "Content-Range: bytes 100-104/10000\r\n\r\nwxyza\r\n",
+                "--{}--\r\n"
+            ),
+            boundary, boundary, boundary
+        )
+    );
+
+    // Unsatisfiable ranges
+    let response = download(&url, &[(header::RANGE, "bytes=10000-")]).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10000");
+
+    // Invalid ranges are ignored
+    for range in ["bytes=20-10", "items=0-10", "bytes=a-b"] {
+        let response = download(&url, &[(header::RANGE, range)]).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", range);
+        assert_eq!(response.bytes().await.unwrap(), blob);
+    }
+
+    // If-Range
+    let response = download(
+        &url,
+        &[
+            (header::RANGE, "bytes=0-9"),
+            (header::IF_RANGE, etag.as_str()),
+        ],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.bytes().await.unwrap(), &blob[0..10]);
+    let response = download(
+        &url,
+        &[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"abc\"")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap(), blob);
+}
+
+pub async fn test_methods<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Blob/upload, Blob/get and Blob/lookup tests...");
+
+    let account_id = JMAPId::new(1).to_string();
+    let url = format!("{}/jmap/", server.base_session.base_url());
+    let unknown_id = JMAPBlob::new(BlobId::new_external(b"unknown")).to_string();
+
+    // Upload blobs from text, base64 and previously created blobs
+    let response = jmap_call(
+        &url,
+        json!([[
+            "Blob/upload",
+            {
+                "accountId": account_id,
+                "create": {
+                    "text": {
+                        "data": [{"data:asText": "Hello, "}, {"data:asBase64": "d29ybGQh"}],
+                        "type": "text/plain"
+                    },
+                    "copy": {
+                        "data": [
+                            {"blobId": "#text", "offset": 7, "length": 5},
+                            {"data:asText": "?"}
+                        ]
+                    },
+                    "missing": {
+                        "data": [{"blobId": "#unknown"}]
+                    },
+                    "range": {
+                        "data": [{"blobId": "#text", "offset": 100}]
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let created = &response[0][1]["created"];
+    assert_eq!(created["text"]["size"], 13);
+    assert_eq!(created["text"]["type"], "text/plain");
+    assert_eq!(created["copy"]["size"], 6);
+    assert_eq!(
+        response[0][1]["notCreated"]["missing"]["type"],
+        "blobNotFound"
+    );
+    assert_eq!(
+        response[0][1]["notCreated"]["range"]["type"],
+        "invalidProperties"
+    );
+    let text_id = created["text"]["id"].as_str().unwrap().to_string();
+    let copy_id = created["copy"]["id"].as_str().unwrap().to_string();
+
+    // Fetch blobs, including ranges and digests
+    let response = jmap_call(
+        &url,
+        json!([[
+            "Blob/get",
+            {
+                "accountId": account_id,
+                "ids": [text_id, copy_id, unknown_id],
+                "properties": ["data:asText", "digest:sha", "size"],
+                "offset": 0,
+                "length": 5
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let list = &response[0][1]["list"];
+    assert_eq!(list[0]["data:asText"], "Hello");
+    assert_eq!(list[0]["digest:sha"], "9/+ei3uy4Jtwk1pdeF4MxdnQq/A=");
+    assert_eq!(list[0]["size"], 13);
+    assert_eq!(list[1]["data:asText"], "world");
+    assert_eq!(list[1]["size"], 6);
+    assert_eq!(response[0][1]["notFound"], json!([unknown_id]));
+
+    let response = jmap_call(
+        &url,
+        json!([[
+            "Blob/get",
+            {
+                "accountId": account_id,
+                "ids": [text_id],
+                "properties": ["data:asBase64"],
+                "offset": 7,
+                "length": 100
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0][1]["list"][0]["data:asBase64"], "d29ybGQh");
+    assert_eq!(response[0][1]["list"][0]["isTruncated"], true);
+
+    // Lookup which objects reference a message blob
+    let mailbox_id = client
+        .set_default_account_id(&account_id)
+        .mailbox_create("Blob Lookup", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let email = client
+        .email_import(
+            b"From: test@example.com\nSubject: Blob lookup\n\nHello world!".to_vec(),
+            [&mailbox_id],
+            None::<Vec<String>>,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let response = jmap_call(
+        &url,
+        json!([[
+            "Blob/lookup",
+            {
+                "accountId": account_id,
+                "typeNames": ["Email", "Mailbox", "Thread"],
+                "ids": [email.blob_id().unwrap(), text_id, unknown_id]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let list = &response[0][1]["list"];
+    assert_eq!(list[0]["matchedIds"]["Email"], json!([email.id().unwrap()]));
+    assert_eq!(list[0]["matchedIds"]["Mailbox"], json!([mailbox_id]));
+    assert_eq!(
+        list[0]["matchedIds"]["Thread"],
+        json!([email.thread_id().unwrap()])
+    );
+    assert_eq!(list[1]["matchedIds"]["Email"], json!([]));
+    assert_eq!(response[0][1]["notFound"], json!([unknown_id]));
+
+    let response = jmap_call(
+        &url,
+        json!([[
+            "Blob/lookup",
+            {
+                "accountId": account_id,
+                "typeNames": ["Calendar"],
+                "ids": [text_id]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0][0], "error");
+    assert_eq!(response[0][1]["type"], "unknownDataType");
+
+    client.mailbox_destroy(&mailbox_id, true).await.unwrap();
+}
+
+async fn jmap_call(url: &str, method_calls: Value) -> Value {
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .post(url)
+        .bearer_auth("DO_NOT_ATTEMPT_THIS_AT_HOME")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(
+            json!({
+                "using": [
+                    "urn:ietf:params:jmap:core",
+                    "urn:ietf:params:jmap:mail",
+                    "urn:ietf:params:jmap:blob"
+                ],
+                "methodCalls": method_calls
+            })
+            .to_string(),
+        )
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let mut response: Value = serde_json::from_slice(&response).unwrap();
+    response["methodResponses"].take()
+}
+
+async fn download(url: &str, headers: &[(header::HeaderName, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(url)
+        .bearer_auth("DO_NOT_ATTEMPT_THIS_AT_HOME");
+    for (name, value) in headers {
+        request = request.header(name.clone(), *value);
+    }
+    request.send().await.unwrap()
+}
@@ -113,6 +113,7 @@ where
             TypeState::Email,
             TypeState::Thread,
             TypeState::Mailbox,
+            TypeState::Quota,
         ],
     )
     .await;
@@ -123,7 +124,12 @@ where
 
     assert_state(
         &mut event_rx,
-        &[TypeState::Email, TypeState::Thread, TypeState::Mailbox],
+        &[
+            TypeState::Email,
+            TypeState::Thread,
+            TypeState::Mailbox,
+            TypeState::Quota,
+        ],
     )
     .await;
     assert_ping(&mut event_rx).await;
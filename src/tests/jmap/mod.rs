@@ -24,8 +24,9 @@
 use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use actix_web::{dev::ServerHandle, web};
-use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID, URI};
 use jmap_client::client::{Client, Credentials};
+use serde_json::{json, Value};
 use store::{core::acl::ACLToken, Store};
 use store_rocksdb::RocksDB;
 use tokio::sync::oneshot;
@@ -40,6 +41,7 @@ use super::store::utils::{destroy_temp_dir, init_settings};
 
 pub mod acl;
 pub mod authorization;
+pub mod blob;
 pub mod event_source;
 pub mod oauth;
 pub mod push_subscription;
@@ -131,6 +133,60 @@ where
         .await;
 }
 
+pub async fn jmap_request<T>(
+    server: &JMAPServer<T>,
+    using: &[URI],
+    method_calls: Value,
+) -> Vec<Value>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    jmap_raw_request(server, using, method_calls)
+        .await
+        .into_iter()
+        .map(|response| {
+            assert_ne!(response[0], "error", "{}", response);
+            response[1].clone()
+        })
+        .collect()
+}
+
+pub async fn jmap_raw_request<T>(
+    server: &JMAPServer<T>,
+    using: &[URI],
+    method_calls: Value,
+) -> Vec<Value>
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let response: Value = serde_json::from_slice(
+        &reqwest::Client::builder()
+            .timeout(Duration::from_millis(1000))
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap_or_default()
+            .post(server.base_session.api_url())
+            .bearer_auth("DO_NOT_ATTEMPT_THIS_AT_HOME")
+            .header("Content-Type", "application/json")
+            .body(
+                json!({
+                    "using": using,
+                    "methodCalls": method_calls
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+
+    response["methodResponses"].as_array().unwrap().clone()
+}
+
 #[actix_web::test]
 #[ignore]
 async fn jmap_core_tests() {
@@ -140,6 +196,8 @@ async fn jmap_core_tests() {
     oauth::test(server.clone(), &mut client).await;
     acl::test(server.clone(), &mut client).await;
     authorization::test(server.clone(), &mut client).await;
+    blob::test(server.clone(), &mut client).await;
+    blob::test_methods(server.clone(), &mut client).await;
     event_source::test(server.clone(), &mut client).await;
     push_subscription::test(server.clone(), &mut client).await;
     websocket::test(server.clone(), &mut client).await;
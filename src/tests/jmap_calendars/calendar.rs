@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use serde_json::json;
+use store::Store;
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+use super::{account_id, jmap_request};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running Calendar tests...");
+    let account_id = account_id();
+
+    // Create calendars
+    let response = jmap_request(
+        &server,
+        json!([[
+            "Calendar/set",
+            {
+                "accountId": account_id,
+                "create": {
+                    "personal": {"name": "Personal", "sortOrder": 2, "timeZone": "Europe/Madrid"},
+                    "work": {"name": "Work", "description": "Colleagues", "sortOrder": 1},
+                    "invalid": {"description": "No name"},
+                    "invalid_tz": {"name": "Mars", "timeZone": "Mars/Olympus_Mons"}
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let personal_id = response[0]["created"]["personal"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let work_id = response[0]["created"]["work"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    for id in ["invalid", "invalid_tz"] {
+        assert_eq!(response[0]["notCreated"][id]["type"], "invalidProperties");
+    }
+    let state = response[0]["newState"].as_str().unwrap().to_string();
+
+    // Only the first calendar is the default one
+    let response = jmap_request(
+        &server,
+        json!([[
+            "Calendar/get",
+            {
+                "accountId": account_id,
+                "ids": [&personal_id, &work_id]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let list = response[0]["list"].as_array().unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0]["name"], "Personal");
+    assert_eq!(list[0]["isDefault"], true);
+    assert_eq!(list[0]["timeZone"], "Europe/Madrid");
+    assert_eq!(list[0]["isVisible"], true);
+    assert_eq!(list[1]["name"], "Work");
+    assert_eq!(list[1]["description"], "Colleagues");
+    assert_eq!(list[1]["isDefault"], false);
+    for item in list {
+        assert_eq!(
+            item["myRights"],
+            json!({
+                "mayReadFreeBusy": true,
+                "mayReadItems": true,
+                "mayWriteAll": true,
+                "mayWriteOwn": true,
+                "mayUpdatePrivate": true,
+                "mayRSVP": true,
+                "mayAdmin": true,
+                "mayDelete": true
+            })
+        );
+    }
+
+    // Query and sort
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "Calendar/query",
+                {
+                    "accountId": account_id,
+                    "sort": [{"property": "sortOrder"}]
+                },
+                "0"
+            ],
+            [
+                "Calendar/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"isDefault": true}
+                },
+                "1"
+            ],
+            [
+                "Calendar/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"name": "work"}
+                },
+                "2"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["ids"], json!([&work_id, &personal_id]));
+    assert_eq!(response[1]["ids"], json!([&personal_id]));
+    assert_eq!(response[2]["ids"], json!([&work_id]));
+
+    // Rename and fetch changes
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "Calendar/set",
+                {
+                    "accountId": account_id,
+                    "update": {&work_id: {"name": "Office"}}
+                },
+                "0"
+            ],
+            [
+                "Calendar/changes",
+                {
+                    "accountId": account_id,
+                    "sinceState": state
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert!(response[0]["updated"]
+        .as_object()
+        .unwrap()
+        .contains_key(&work_id));
+    assert_eq!(response[1]["created"], json!([]));
+    assert_eq!(response[1]["updated"], json!([&work_id]));
+
+    // Calendars with events can't be destroyed unless onDestroyRemoveEvents is set
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "CalendarEvent/set",
+                {
+                    "accountId": account_id,
+                    "create": {
+                        "e1": {"calendarIds": {&work_id: true}, "start": "2022-06-01T09:00:00"},
+                        "e2": {
+                            "calendarIds": {&work_id: true, &personal_id: true},
+                            "start": "2022-06-02T09:00:00"
+                        }
+                    }
+                },
+                "0"
+            ],
+            [
+                "Calendar/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&work_id]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    let event_1 = response[0]["created"]["e1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let event_2 = response[0]["created"]["e2"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        response[1]["notDestroyed"][&work_id]["type"],
+        "calendarHasEvent"
+    );
+
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "Calendar/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&work_id],
+                    "onDestroyRemoveEvents": true
+                },
+                "0"
+            ],
+            [
+                "CalendarEvent/get",
+                {
+                    "accountId": account_id,
+                    "ids": [&event_1, &event_2],
+                    "properties": ["calendarIds"]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["destroyed"], json!([&work_id]));
+    assert_eq!(response[1]["notFound"], json!([&event_1]));
+    assert_eq!(
+        response[1]["list"][0]["calendarIds"],
+        json!({&personal_id: true})
+    );
+
+    // Clean up
+    jmap_request(
+        &server,
+        json!([[
+            "Calendar/set",
+            {
+                "accountId": account_id,
+                "destroy": [&personal_id],
+                "onDestroyRemoveEvents": true
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    server.store.assert_is_empty();
+}
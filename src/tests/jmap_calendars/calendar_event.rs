@@ -0,0 +1,397 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use jmap::URI;
+use serde_json::json;
+use store::Store;
+
+use crate::{
+    tests::{jmap::jmap_raw_request, store::utils::StoreCompareWith},
+    JMAPServer,
+};
+
+use super::{account_id, jmap_request};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running CalendarEvent tests...");
+    let account_id = account_id();
+
+    // Create a calendar and a few events
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "Calendar/set",
+                {
+                    "accountId": account_id,
+                    "create": {"cal": {"name": "Events"}}
+                },
+                "0"
+            ],
+            [
+                "CalendarEvent/set",
+                {
+                    "accountId": account_id,
+                    "create": {
+                        "standup": {
+                            "calendarIds": {"#cal": true},
+                            "title": "Daily standup",
+                            "start": "2022-03-01T09:00:00",
+                            "duration": "PT15M",
+                            "timeZone": "Europe/Madrid",
+                            "recurrenceRules": [{
+                                "@type": "RecurrenceRule",
+                                "frequency": "weekly",
+                                "byDay": [{"day": "mo"}, {"day": "we"}, {"day": "fr"}],
+                                "count": 6
+                            }],
+                            "recurrenceOverrides": {
+                                "2022-03-04T09:00:00": {"excluded": true},
+                                "2022-03-07T09:00:00": {
+                                    "title": "Sprint planning",
+                                    "duration": "PT1H"
+                                }
+                            }
+                        },
+                        "review": {
+                            "calendarIds": {"#cal": true},
+                            "title": "Quarterly review",
+                            "start": "2022-01-31T14:00:00",
+                            "duration": "PT2H",
+                            "timeZone": "America/New_York",
+                            "recurrenceRules": [{
+                                "frequency": "monthly",
+                                "interval": 3,
+                                "byMonthDay": [-1]
+                            }]
+                        },
+                        "lunch": {
+                            "calendarIds": {"#cal": true},
+                            "title": "Lunch with Jane",
+                            "start": "2022-03-03T13:00:00",
+                            "duration": "PT1H",
+                            "locations": {"l1": {"name": "Cafe Central"}}
+                        },
+                        "invalid_rule": {
+                            "calendarIds": {"#cal": true},
+                            "start": "2022-03-03T13:00:00",
+                            "recurrenceRules": [{"frequency": "fortnightly"}]
+                        },
+                        "no_start": {
+                            "calendarIds": {"#cal": true},
+                            "title": "Someday"
+                        },
+                        "orphan": {
+                            "start": "2022-03-03T13:00:00"
+                        }
+                    }
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    let calendar_id = response[0]["created"]["cal"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let standup_id = response[1]["created"]["standup"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let review_id = response[1]["created"]["review"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let lunch_id = response[1]["created"]["lunch"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(response[1]["created"]["standup"]["uid"]
+        .as_str()
+        .unwrap()
+        .starts_with("urn:uuid:"));
+    for id in ["invalid_rule", "no_start", "orphan"] {
+        assert_eq!(
+            response[1]["notCreated"][id]["type"], "invalidProperties",
+            "{}",
+            id
+        );
+    }
+    let state = response[1]["newState"].as_str().unwrap().to_string();
+
+    // Time-range queries match any occurrence
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "after": "2022-03-01T00:00:00Z",
+                        "before": "2022-03-08T00:00:00Z"
+                    },
+                    "sort": [{"property": "start"}]
+                },
+                "0"
+            ],
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "after": "2030-01-01T00:00:00Z",
+                        "before": "2030-02-01T00:00:00Z"
+                    }
+                },
+                "1"
+            ],
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"after": "2022-04-01T00:00:00Z"}
+                },
+                "2"
+            ],
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"text": "cafe"}
+                },
+                "3"
+            ],
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "operator": "AND",
+                        "conditions": [
+                            {"inCalendars": [&calendar_id]},
+                            {"title": "standup"}
+                        ]
+                    }
+                },
+                "4"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["ids"], json!([&standup_id, &lunch_id]));
+    assert_eq!(response[1]["ids"], json!([]));
+    assert_eq!(response[2]["ids"], json!([&review_id]));
+    assert_eq!(response[3]["ids"], json!([&lunch_id]));
+    assert_eq!(response[4]["ids"], json!([&standup_id]));
+
+    // The quarterly review falls on the last day of the month
+    let response = jmap_request(
+        &server,
+        json!([[
+            "CalendarEvent/query",
+            {
+                "accountId": account_id,
+                "filter": {
+                    "after": "2030-04-30T00:00:00Z",
+                    "before": "2030-05-01T00:00:00Z"
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0]["ids"], json!([&review_id]));
+
+    // Time ranges nested under other operators are not supported
+    let response = jmap_raw_request(
+        &server,
+        &[URI::Core, URI::Calendars],
+        json!([[
+            "CalendarEvent/query",
+            {
+                "accountId": account_id,
+                "filter": {
+                    "operator": "OR",
+                    "conditions": [
+                        {"after": "2030-04-30T00:00:00Z"},
+                        {"title": "standup"}
+                    ]
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0][0], "error");
+    assert_eq!(response[0][1]["type"], "unsupportedFilter");
+
+    // Expand recurrences, excluded occurrences are skipped
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "after": "2022-03-01T00:00:00Z",
+                        "before": "2022-03-08T00:00:00Z"
+                    },
+                    "expandRecurrences": true
+                },
+                "0"
+            ],
+            [
+                "CalendarEvent/get",
+                {
+                    "accountId": account_id,
+                    "#ids": {
+                        "resultOf": "0",
+                        "name": "CalendarEvent/query",
+                        "path": "/ids"
+                    },
+                    "properties": [
+                        "title", "start", "duration", "recurrenceId", "recurrenceRules",
+                        "utcStart", "utcEnd"
+                    ]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    let ids = response[0]["ids"].as_array().unwrap();
+    assert_eq!(ids.len(), 4, "{:?}", ids);
+    assert_eq!(ids[2], lunch_id);
+    assert!(!ids.contains(&json!(&standup_id)));
+    let list = response[1]["list"].as_array().unwrap();
+    assert_eq!(
+        list.iter()
+            .map(|event| event["utcStart"].as_str().unwrap())
+            .collect::<Vec<_>>(),
+        [
+            "2022-03-01T08:00:00Z",
+            "2022-03-02T08:00:00Z",
+            "2022-03-03T13:00:00Z",
+            "2022-03-07T08:00:00Z"
+        ]
+    );
+    assert_eq!(list[0]["recurrenceId"], "2022-03-01T09:00:00");
+    assert_eq!(list[0]["title"], "Daily standup");
+    assert_eq!(list[2]["recurrenceId"], json!(null));
+    assert_eq!(list[3]["recurrenceId"], "2022-03-07T09:00:00");
+    assert_eq!(list[3]["title"], "Sprint planning");
+    assert_eq!(list[3]["duration"], "PT1H");
+    assert_eq!(list[3]["utcEnd"], "2022-03-07T09:00:00Z");
+    assert_eq!(list[3]["recurrenceRules"], json!(null));
+
+    // Occurrences can't be modified directly
+    let instance_id = ids[3].as_str().unwrap().to_string();
+    let response = jmap_request(
+        &server,
+        json!([[
+            "CalendarEvent/set",
+            {
+                "accountId": account_id,
+                "update": {&instance_id: {"title": "Retrospective"}}
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0]["notUpdated"][&instance_id]["type"], "forbidden");
+
+    // Shorten the recurrence and fetch changes
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "CalendarEvent/set",
+                {
+                    "accountId": account_id,
+                    "update": {&standup_id: {"recurrenceRules/0/count": 2}}
+                },
+                "0"
+            ],
+            [
+                "CalendarEvent/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "after": "2022-03-05T00:00:00Z",
+                        "before": "2022-03-08T00:00:00Z"
+                    }
+                },
+                "1"
+            ],
+            [
+                "CalendarEvent/changes",
+                {
+                    "accountId": account_id,
+                    "sinceState": state
+                },
+                "2"
+            ]
+        ]),
+    )
+    .await;
+    assert!(response[0]["updated"]
+        .as_object()
+        .unwrap()
+        .contains_key(&standup_id));
+    assert_eq!(response[1]["ids"], json!([]));
+    assert_eq!(response[2]["updated"], json!([&standup_id]));
+
+    // Clean up
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "CalendarEvent/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&standup_id, &review_id, &lunch_id]
+                },
+                "0"
+            ],
+            [
+                "Calendar/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&calendar_id]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["destroyed"].as_array().unwrap().len(), 3);
+    assert_eq!(response[1]["destroyed"], json!([&calendar_id]));
+    server.store.assert_is_empty();
+}
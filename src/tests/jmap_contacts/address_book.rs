@@ -0,0 +1,247 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use serde_json::json;
+use store::Store;
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+use super::{account_id, jmap_request};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running AddressBook tests...");
+    let account_id = account_id();
+
+    // Create address books
+    let response = jmap_request(
+        &server,
+        json!([[
+            "AddressBook/set",
+            {
+                "accountId": account_id,
+                "create": {
+                    "personal": {"name": "Personal", "sortOrder": 2},
+                    "work": {"name": "Work", "description": "Colleagues", "sortOrder": 1},
+                    "invalid": {"description": "No name"}
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let personal_id = response[0]["created"]["personal"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let work_id = response[0]["created"]["work"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        response[0]["notCreated"]["invalid"]["type"],
+        "invalidProperties"
+    );
+    let state = response[0]["newState"].as_str().unwrap().to_string();
+
+    // Only the first address book is the default one
+    let response = jmap_request(
+        &server,
+        json!([[
+            "AddressBook/get",
+            {
+                "accountId": account_id,
+                "ids": [&personal_id, &work_id]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let list = response[0]["list"].as_array().unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0]["name"], "Personal");
+    assert_eq!(list[0]["isDefault"], true);
+    assert_eq!(list[1]["name"], "Work");
+    assert_eq!(list[1]["description"], "Colleagues");
+    assert_eq!(list[1]["isDefault"], false);
+    for item in list {
+        assert_eq!(
+            item["myRights"],
+            json!({"mayRead": true, "mayWrite": true, "mayShare": true, "mayDelete": true})
+        );
+    }
+
+    // Query and sort
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "AddressBook/query",
+                {
+                    "accountId": account_id,
+                    "sort": [{"property": "sortOrder"}]
+                },
+                "0"
+            ],
+            [
+                "AddressBook/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"isDefault": true}
+                },
+                "1"
+            ],
+            [
+                "AddressBook/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"name": "work"}
+                },
+                "2"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["ids"], json!([&work_id, &personal_id]));
+    assert_eq!(response[1]["ids"], json!([&personal_id]));
+    assert_eq!(response[2]["ids"], json!([&work_id]));
+
+    // Rename and fetch changes
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "AddressBook/set",
+                {
+                    "accountId": account_id,
+                    "update": {&work_id: {"name": "Office"}}
+                },
+                "0"
+            ],
+            [
+                "AddressBook/changes",
+                {
+                    "accountId": account_id,
+                    "sinceState": state
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert!(response[0]["updated"]
+        .as_object()
+        .unwrap()
+        .contains_key(&work_id));
+    assert_eq!(response[1]["created"], json!([]));
+    assert_eq!(response[1]["updated"], json!([&work_id]));
+
+    // Address books with cards can't be destroyed unless onDestroyRemoveContents is set
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "ContactCard/set",
+                {
+                    "accountId": account_id,
+                    "create": {
+                        "c1": {"addressBookIds": {&work_id: true}},
+                        "c2": {"addressBookIds": {&work_id: true, &personal_id: true}}
+                    }
+                },
+                "0"
+            ],
+            [
+                "AddressBook/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&work_id]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    let card_1 = response[0]["created"]["c1"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let card_2 = response[0]["created"]["c2"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        response[1]["notDestroyed"][&work_id]["type"],
+        "addressBookHasContents"
+    );
+
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "AddressBook/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&work_id],
+                    "onDestroyRemoveContents": true
+                },
+                "0"
+            ],
+            [
+                "ContactCard/get",
+                {
+                    "accountId": account_id,
+                    "ids": [&card_1, &card_2],
+                    "properties": ["addressBookIds"]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["destroyed"], json!([&work_id]));
+    assert_eq!(response[1]["notFound"], json!([&card_1]));
+    assert_eq!(
+        response[1]["list"][0]["addressBookIds"],
+        json!({&personal_id: true})
+    );
+
+    // Clean up
+    jmap_request(
+        &server,
+        json!([[
+            "AddressBook/set",
+            {
+                "accountId": account_id,
+                "destroy": [&personal_id],
+                "onDestroyRemoveContents": true
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    server.store.assert_is_empty();
+}
@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use actix_web::web;
+use serde_json::json;
+use store::Store;
+
+use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+
+use super::{account_id, jmap_request};
+
+pub async fn test<T>(server: web::Data<JMAPServer<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    println!("Running ContactCard tests...");
+    let account_id = account_id();
+
+    // Create an address book and a few cards
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "AddressBook/set",
+                {
+                    "accountId": account_id,
+                    "create": {"book": {"name": "Contacts"}}
+                },
+                "0"
+            ],
+            [
+                "ContactCard/set",
+                {
+                    "accountId": account_id,
+                    "create": {
+                        "john": {
+                            "addressBookIds": {"#book": true},
+                            "kind": "individual",
+                            "name": {
+                                "components": [
+                                    {"kind": "given", "value": "John"},
+                                    {"kind": "surname", "value": "Doe"}
+                                ]
+                            },
+                            "emails": {
+                                "e1": {"address": "john@example.com"}
+                            }
+                        },
+                        "jane": {
+                            "addressBookIds": {"#book": true},
+                            "kind": "individual",
+                            "name": {
+                                "components": [
+                                    {"kind": "given", "value": "Jane"},
+                                    {"kind": "surname", "value": "Smith"}
+                                ]
+                            },
+                            "organizations": {
+                                "o1": {"name": "Acme Corporation"}
+                            },
+                            "notes": {
+                                "n1": {"note": "Met at the conference"}
+                            }
+                        },
+                        "acme": {
+                            "addressBookIds": {"#book": true},
+                            "kind": "org",
+                            "uid": "urn:uuid:acme",
+                            "name": {"full": "Acme"}
+                        },
+                        "orphan": {
+                            "name": {"full": "Nobody"}
+                        },
+                        "invalid": {
+                            "addressBookIds": {"#book": true},
+                            "kind": "spaceship"
+                        }
+                    }
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    let book_id = response[0]["created"]["book"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let john_id = response[1]["created"]["john"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let jane_id = response[1]["created"]["jane"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let acme_id = response[1]["created"]["acme"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(response[1]["created"]["john"]["uid"]
+        .as_str()
+        .unwrap()
+        .starts_with("urn:uuid:"));
+    assert!(response[1]["created"]["john"]["created"].is_string());
+    assert!(response[1]["created"]["john"]["updated"].is_string());
+    assert_eq!(
+        response[1]["notCreated"]["orphan"]["type"],
+        "invalidProperties"
+    );
+    assert_eq!(
+        response[1]["notCreated"]["invalid"]["type"],
+        "invalidProperties"
+    );
+    let state = response[1]["newState"].as_str().unwrap().to_string();
+
+    // Fetch cards
+    let response = jmap_request(
+        &server,
+        json!([[
+            "ContactCard/get",
+            {
+                "accountId": account_id,
+                "ids": [&john_id, &acme_id]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let john = &response[0]["list"][0];
+    assert_eq!(john["@type"], "Card");
+    assert_eq!(john["version"], "1.0");
+    assert_eq!(john["kind"], "individual");
+    assert_eq!(john["addressBookIds"], json!({&book_id: true}));
+    assert_eq!(
+        john["emails"],
+        json!({"e1": {"address": "john@example.com"}})
+    );
+    assert_eq!(response[0]["list"][1]["uid"], "urn:uuid:acme");
+    assert_eq!(response[0]["list"][1]["name"], json!({"full": "Acme"}));
+
+    // Query cards
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"email": "john@example.com"}
+                },
+                "0"
+            ],
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"text": "acme"}
+                },
+                "1"
+            ],
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"kind": "individual"},
+                    "sort": [{"property": "name/surname"}]
+                },
+                "2"
+            ],
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {
+                        "operator": "AND",
+                        "conditions": [
+                            {"inAddressBook": &book_id},
+                            {"note": "conference"}
+                        ]
+                    }
+                },
+                "3"
+            ],
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"uid": "urn:uuid:acme"}
+                },
+                "4"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["ids"], json!([&john_id]));
+    assert_eq!(response[1]["ids"], json!([&jane_id, &acme_id]));
+    assert_eq!(response[2]["ids"], json!([&john_id, &jane_id]));
+    assert_eq!(response[3]["ids"], json!([&jane_id]));
+    assert_eq!(response[4]["ids"], json!([&acme_id]));
+
+    // Patch a card and fetch changes
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "ContactCard/set",
+                {
+                    "accountId": account_id,
+                    "update": {
+                        &john_id: {
+                            "emails/e1/address": "john.doe@example.com",
+                            "name/components/1/value": "Dough"
+                        }
+                    }
+                },
+                "0"
+            ],
+            [
+                "ContactCard/changes",
+                {
+                    "accountId": account_id,
+                    "sinceState": state
+                },
+                "1"
+            ],
+            [
+                "ContactCard/get",
+                {
+                    "accountId": account_id,
+                    "ids": [&john_id],
+                    "properties": ["emails", "name", "updated"]
+                },
+                "2"
+            ],
+            [
+                "ContactCard/query",
+                {
+                    "accountId": account_id,
+                    "filter": {"name/surname": "dough"}
+                },
+                "3"
+            ]
+        ]),
+    )
+    .await;
+    assert!(response[0]["updated"]
+        .as_object()
+        .unwrap()
+        .contains_key(&john_id));
+    assert_eq!(response[1]["updated"], json!([&john_id]));
+    let john = &response[2]["list"][0];
+    assert_eq!(john["emails"]["e1"]["address"], "john.doe@example.com");
+    assert_eq!(john["name"]["components"][1]["value"], "Dough");
+    assert_eq!(response[3]["ids"], json!([&john_id]));
+
+    // Cards can't be removed from all their address books
+    let response = jmap_request(
+        &server,
+        json!([[
+            "ContactCard/set",
+            {
+                "accountId": account_id,
+                "update": {
+                    &jane_id: {
+                        format!("addressBookIds/{}", book_id): null
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(
+        response[0]["notUpdated"][&jane_id]["type"],
+        "invalidProperties"
+    );
+
+    // Clean up
+    let response = jmap_request(
+        &server,
+        json!([
+            [
+                "ContactCard/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&john_id, &jane_id, &acme_id]
+                },
+                "0"
+            ],
+            [
+                "AddressBook/set",
+                {
+                    "accountId": account_id,
+                    "destroy": [&book_id]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert_eq!(response[0]["destroyed"].as_array().unwrap().len(), 3);
+    assert_eq!(response[1]["destroyed"], json!([&book_id]));
+    server.store.assert_is_empty();
+}
@@ -30,6 +30,7 @@ use jmap_client::{
     client::Client,
     core::query::{Comparator, Filter},
     email,
+    mailbox::Role,
 };
 use jmap_mail::mail_parser::RfcHeader;
 use store::{
@@ -136,9 +137,86 @@ where
         .unwrap_set_email()
         .unwrap();
 
+    println!("Running JMAP Mail header query tests...");
+    query_headers(client).await;
+
     server.store.assert_is_empty();
 }
 
+pub async fn query_headers(client: &mut Client) {
+    let mailbox_id = client
+        .mailbox_create("JMAP Headers", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+
+    let mut email_ids = AHashMap::default();
+    for (name, headers) in [
+        (
+            "rust",
+            concat!(
+                "List-Id: Rust Users <rust-users.lists.example.org>\n",
+                "X-Mailer: Mutt 2.2\n",
+                "X-Tracking: route-a\n"
+            ),
+        ),
+        (
+            "jmap",
+            concat!(
+                "List-Id: <jmap.lists.example.org>\n",
+                "X-Custom-Route: eu-west\n"
+            ),
+        ),
+        ("direct", "X-Mailer: Outlook 16\n"),
+    ] {
+        let email_id = client
+            .email_import(
+                format!("{}Subject: {}\n\nTest message\n", headers, name).into_bytes(),
+                [&mailbox_id],
+                None::<Vec<&str>>,
+                None,
+            )
+            .await
+            .unwrap()
+            .take_id();
+        email_ids.insert(email_id, name);
+    }
+
+    for (header, value, expected_results) in [
+        ("List-Id", None, vec!["jmap", "rust"]),
+        (
+            "list-id",
+            Some("rust-users.lists.example.org"),
+            vec!["rust"],
+        ),
+        ("X-Mailer", Some("mutt"), vec!["rust"]),
+        ("X-Mailer", None, vec!["direct", "rust"]),
+        ("X-Custom-Route", Some("EU-West"), vec!["jmap"]),
+        ("x-custom-route", None, vec!["jmap"]),
+        ("X-Tracking", Some("route-b"), vec![]),
+    ] {
+        assert_eq!(
+            client
+                .email_query(
+                    email::query::Filter::header(header, value).into(),
+                    [email::query::Comparator::subject()].into(),
+                )
+                .await
+                .unwrap()
+                .take_ids()
+                .iter()
+                .map(|id| email_ids[id])
+                .collect::<Vec<_>>(),
+            expected_results,
+            "{} {:?}",
+            header,
+            value
+        );
+    }
+
+    client.mailbox_destroy(&mailbox_id, true).await.unwrap();
+}
+
 pub async fn query(client: &mut Client) {
     for (filter, sort, expected_results) in [
         (
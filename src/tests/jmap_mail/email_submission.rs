@@ -24,7 +24,7 @@
 use std::{sync::Arc, time::Duration};
 
 use actix_web::web;
-use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap::{types::jmap::JMAPId, SUPERUSER_ID, URI};
 use jmap_client::{
     client::Client,
     core::set::{SetError, SetErrorType, SetObject},
@@ -32,8 +32,9 @@ use jmap_client::{
     mailbox::Role,
     Error,
 };
-use jmap_sharing::principal::set::JMAPSetPrincipal;
-use store::{ahash::AHashMap, chrono::DateTime, parking_lot::Mutex, Store};
+use jmap_sharing::principal::{dkim::JMAPDkimRotate, set::JMAPSetPrincipal};
+use serde_json::json;
+use store::{ahash::AHashMap, chrono::Utc, parking_lot::Mutex, Store};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpListener,
@@ -41,7 +42,15 @@ use tokio::{
 };
 
 use crate::{
-    tests::{jmap_mail::email_set::assert_email_properties, store::utils::StoreCompareWith},
+    services::email_delivery,
+    tests::{
+        jmap::jmap_request,
+        jmap_mail::{
+            email_set::assert_email_properties,
+            lmtp::{AssertResult, SmtpConnection},
+        },
+        store::utils::StoreCompareWith,
+    },
     JMAPServer,
 };
 
@@ -70,6 +79,7 @@ impl MockMessage {
 pub struct MockSMTPSettings {
     pub fail_mail_from: bool,
     pub fail_rcpt_to: bool,
+    pub defer_rcpt_to: bool,
     pub fail_message: bool,
     pub do_stop: bool,
 }
@@ -232,7 +242,7 @@ where
     .await;
 
     // Manually add recipients to the envelope and confirm submission
-    let email_submission_id = client
+    let final_submission_id = client
         .email_submission_create_envelope(
             &email_id,
             &identity_id,
@@ -267,7 +277,7 @@ where
     // Confirm that the email submission status was updated
     tokio::time::sleep(Duration::from_millis(100)).await;
     let email_submission = client
-        .email_submission_get(&email_submission_id, None)
+        .email_submission_get(&final_submission_id, None)
         .await
         .unwrap()
         .unwrap();
@@ -344,6 +354,79 @@ where
     );
     smtp_settings.lock().fail_rcpt_to = false;
 
+    // SMTP temporarily rejects some of the recipients, which should be retried
+    smtp_settings.lock().defer_rcpt_to = true;
+    let email_submission_id = client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            "jdoe@example.com",
+            ["tim@foobar.com", "jane@test.com"],
+        )
+        .await
+        .unwrap()
+        .take_id();
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new("<jdoe@example.com>", ["<tim@foobar.com>"], email_body),
+        false,
+    )
+    .await;
+
+    // Deferred recipients are reported as queued
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email_submission.undo_status().unwrap(), &UndoStatus::Final);
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([
+            (
+                "jane@test.com".to_string(),
+                DeliveryStatus::new(
+                    "451 Greylisted, please try again later.",
+                    Delivered::Queued,
+                    Displayed::Unknown
+                )
+            ),
+            (
+                "tim@foobar.com".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+            ),
+        ])
+    );
+
+    // The next attempt should only include the deferred recipient
+    smtp_settings.lock().defer_rcpt_to = false;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new("<jdoe@example.com>", ["<jane@test.com>"], email_body),
+        false,
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([
+            (
+                "jane@test.com".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+            ),
+            (
+                "tim@foobar.com".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Unknown)
+            ),
+        ])
+    );
+
     // SMTP rejects the message
     smtp_settings.lock().fail_message = true;
     let email_submission_id = client
@@ -400,6 +483,189 @@ where
     );
     smtp_settings.lock().fail_message = false;
 
+    // Delivery and disposition reports update the submission status
+    let report_body = concat!(
+        "From: jdoe@example.com\r\n",
+        "To: jane_smith@example.com, bill@example.com\r\n",
+        "Message-ID: <report-test@example.com>\r\n",
+        "Subject: reports\r\n",
+        "\r\n",
+        "test"
+    );
+    let report_email_id = client
+        .email_import(
+            report_body.as_bytes().to_vec(),
+            [&mailbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    let email_submission_id = client
+        .email_submission_create(&report_email_id, &identity_id)
+        .await
+        .unwrap()
+        .take_id();
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<bill@example.com>", "<jane_smith@example.com>"],
+            report_body,
+        ),
+        false,
+    )
+    .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "",
+        &["jdoe@example.com"],
+        concat!(
+            "From: MAILER-DAEMON@example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Undelivered Mail Returned to Sender\r\n",
+            "Content-Type: multipart/report; report-type=delivery-status;\r\n",
+            "\tboundary=\"report\"\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Delivery failed.\r\n",
+            "--report\r\n",
+            "Content-Type: message/delivery-status\r\n",
+            "\r\n",
+            "Reporting-MTA: dns; mx.example.org\r\n",
+            "\r\n",
+            "Final-Recipient: rfc822; jane_smith@example.com\r\n",
+            "Action: failed\r\n",
+            "Status: 5.1.1\r\n",
+            "Diagnostic-Code: smtp; 550 5.1.1 No such user\r\n",
+            "\r\n",
+            "Final-Recipient: rfc822; bill@example.com\r\n",
+            "Action: delayed\r\n",
+            "Status: 4.4.1\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: text/rfc822-headers\r\n",
+            "\r\n",
+            "From: jdoe@example.com\r\n",
+            "Message-ID: <report-test@example.com>\r\n",
+            "\r\n",
+            "--report--\r\n"
+        ),
+    )
+    .await;
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Read: reports\r\n",
+            "Content-Type: multipart/report; report-type=disposition-notification;\r\n",
+            "\tboundary=\"report\"\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "The message was displayed.\r\n",
+            "--report\r\n",
+            "Content-Type: message/disposition-notification\r\n",
+            "\r\n",
+            "Final-Recipient: rfc822; bill@example.com\r\n",
+            "Original-Message-ID: <report-test@example.com>\r\n",
+            "Disposition: manual-action/MDN-sent-manually; displayed\r\n",
+            "\r\n",
+            "--report--\r\n"
+        ),
+    )
+    .await;
+
+    // DSNs with a non-null reverse-path are ignored
+    lmtp.ingest(
+        "mallory@example.net",
+        &["jdoe@example.com"],
+        concat!(
+            "From: MAILER-DAEMON@example.net\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Delivery Status Notification\r\n",
+            "Content-Type: multipart/report; report-type=delivery-status;\r\n",
+            "\tboundary=\"report\"\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: message/delivery-status\r\n",
+            "\r\n",
+            "Reporting-MTA: dns; mx.example.net\r\n",
+            "\r\n",
+            "Final-Recipient: rfc822; jane_smith@example.com\r\n",
+            "Action: delivered\r\n",
+            "Status: 2.0.0\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: text/rfc822-headers\r\n",
+            "\r\n",
+            "Message-ID: <report-test@example.com>\r\n",
+            "\r\n",
+            "--report--\r\n"
+        ),
+    )
+    .await;
+
+    // Reports about recipients not in the envelope are ignored
+    lmtp.ingest(
+        "",
+        &["jdoe@example.com"],
+        concat!(
+            "From: MAILER-DAEMON@example.net\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Delivery Status Notification\r\n",
+            "Content-Type: multipart/report; report-type=delivery-status;\r\n",
+            "\tboundary=\"report\"\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: message/delivery-status\r\n",
+            "\r\n",
+            "Reporting-MTA: dns; mx.example.net\r\n",
+            "Original-Envelope-Id: report-test\r\n",
+            "\r\n",
+            "Final-Recipient: rfc822; mallory@example.net\r\n",
+            "Action: delivered\r\n",
+            "Status: 2.0.0\r\n",
+            "\r\n",
+            "--report\r\n",
+            "Content-Type: text/rfc822-headers\r\n",
+            "\r\n",
+            "Message-ID: <report-test@example.com>\r\n",
+            "\r\n",
+            "--report--\r\n"
+        ),
+    )
+    .await;
+
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.delivery_status().unwrap(),
+        &AHashMap::from_iter([
+            (
+                "jane_smith@example.com".to_string(),
+                DeliveryStatus::new("550 5.1.1 No such user", Delivered::No, Displayed::Unknown)
+            ),
+            (
+                "bill@example.com".to_string(),
+                DeliveryStatus::new("250 OK", Delivered::Queued, Displayed::Yes)
+            ),
+        ])
+    );
+    assert_eq!(email_submission.dsn_blob_ids().unwrap().len(), 1);
+    client.email_destroy(&report_email_id).await.unwrap();
+
     // Enable DKIM for the domain
     client
         .set_default_account_id(JMAPId::from(SUPERUSER_ID))
@@ -408,21 +674,89 @@ where
         .unwrap();
     client.set_default_account_id(&account_id);
 
-    // Confirm that the sendAt property is updated when using FUTURERELEASE
+    // Submissions with a future sendAt should be held
     let email_submission_id = client
         .email_submission_create_envelope(
             &email_id,
             &identity_id,
-            Address::new("jdoe@example.com").parameter("HOLDUNTIL", Some("2079-11-20T05:00:00Z")),
+            Address::new("jdoe@example.com").parameter("HOLDFOR", Some("3600")),
             ["jane_smith@example.com"],
         )
         .await
         .unwrap()
         .take_id();
+    expect_nothing(&mut smtp_rx).await;
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.undo_status().unwrap(),
+        &UndoStatus::Pending
+    );
+    assert!(email_submission.send_at().unwrap() > Utc::now().timestamp() + 3500);
+
+    // Held submissions can be canceled until they are released
+    client
+        .email_submission_change_status(&email_submission_id, UndoStatus::Canceled)
+        .await
+        .unwrap();
+    let email_submission = client
+        .email_submission_get(&email_submission_id, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        email_submission.undo_status().unwrap(),
+        &UndoStatus::Canceled
+    );
+
+    // Submissions that were already sent cannot be canceled
+    assert!(matches!(
+        client
+            .email_submission_change_status(&final_submission_id, UndoStatus::Canceled)
+            .await,
+        Err(Error::Set(SetError {
+            type_: SetErrorType::CannotUnsend,
+            ..
+        }))
+    ));
+
+    // Delays over the maximum allowed should fail
+    assert!(matches!(
+        client
+            .email_submission_create_envelope(
+                &email_id,
+                &identity_id,
+                Address::new("jdoe@example.com")
+                    .parameter("HOLDUNTIL", Some("2079-11-20T05:00:00Z")),
+                ["jane_smith@example.com"],
+            )
+            .await,
+        Err(Error::Set(SetError {
+            type_: SetErrorType::InvalidProperties,
+            ..
+        }))
+    ));
+
+    // Held submissions are released once sendAt is reached,
+    // the relay should not receive the FUTURERELEASE parameters
+    let email_submission_id = client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            Address::new("jdoe@example.com").parameter("HOLDFOR", Some("2")),
+            ["jane_smith@example.com"],
+        )
+        .await
+        .unwrap()
+        .take_id();
+    expect_nothing(&mut smtp_rx).await;
     assert_message_delivery(
         &mut smtp_rx,
         MockMessage::new(
-            "<jdoe@example.com> HOLDUNTIL=2079-11-20T05:00:00Z",
+            "<jdoe@example.com>",
             ["<jane_smith@example.com>"],
             email_body,
         ),
@@ -435,12 +769,215 @@ where
         .await
         .unwrap()
         .unwrap();
-    assert_eq!(
-        email_submission.send_at().unwrap(),
-        DateTime::parse_from_rfc3339("2079-11-20T05:00:00Z")
-            .unwrap()
-            .timestamp()
+    assert_eq!(email_submission.undo_status().unwrap(), &UndoStatus::Final);
+
+    // Generate a new RSA key for the domain, which should be published
+    // under a new selector before it replaces the current key
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([
+            [
+                "Principal/set",
+                {
+                    "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                    "update": {
+                        &domain_id: {
+                            "dkimAlgorithm": "rsa-sha256"
+                        }
+                    }
+                },
+                "0"
+            ],
+            [
+                "Principal/get",
+                {
+                    "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                    "ids": [&domain_id],
+                    "properties": ["dkim", "dkimAlgorithm", "dkimRecord", "secret"]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+    assert!(
+        response[0]["updated"][&domain_id].is_object(),
+        "{}",
+        response[0]
     );
+    let domain = &response[1]["list"][0];
+    assert_eq!(domain["dkimAlgorithm"], "rsa-sha256");
+    assert!(domain["secret"].is_null());
+    assert_eq!(domain["dkim"]["dkimSelector"], "my-selector");
+    let previous_record = domain["dkimRecord"].as_str().unwrap().to_string();
+
+    server.store.dkim_rotate().unwrap();
+    server
+        .notify_email_delivery(email_delivery::Event::Reload)
+        .await
+        .unwrap();
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([[
+            "Principal/get",
+            {
+                "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                "ids": [&domain_id],
+                "properties": ["dkim", "dkimRecord"]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let domain = &response[0]["list"][0];
+    assert_ne!(domain["dkimRecord"], previous_record.as_str());
+    assert!(domain["dkimRecord"]
+        .as_str()
+        .unwrap()
+        .starts_with("v=DKIM1; k=rsa; p="));
+    assert!(domain["dkim"]["dkimSelector"]
+        .as_str()
+        .unwrap()
+        .starts_with("my-selector-"));
+
+    // Unsupported algorithms should fail, Ed25519 keys cannot be used for signing
+    for algorithm in ["rsa-sha1", "ed25519-sha256"] {
+        let response = jmap_request(
+            &server,
+            &[URI::Core],
+            json!([[
+                "Principal/set",
+                {
+                    "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                    "update": {
+                        &domain_id: {
+                            "dkimAlgorithm": algorithm
+                        }
+                    }
+                },
+                "0"
+            ]]),
+        )
+        .await;
+        assert!(
+            response[0]["notUpdated"][&domain_id].is_object(),
+            "{}",
+            response[0]
+        );
+    }
+
+    // Messages should now be signed using the new key
+    client
+        .email_submission_create_envelope(
+            &email_id,
+            &identity_id,
+            "jdoe@example.com",
+            ["jane_smith@example.com"],
+        )
+        .await
+        .unwrap();
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<jane_smith@example.com>"],
+            "@s=my-selector-",
+        ),
+        true,
+    )
+    .await;
+
+    // Messages sent through the SMTP submission service require authentication
+    let mut smtp = SmtpConnection::connect_submission().await;
+    smtp.ehlo()
+        .await
+        .assert_contains("AUTH PLAIN LOGIN OAUTHBEARER XOAUTH2");
+    smtp.mail_from("jdoe@example.com", 5).await;
+    smtp.auth("PLAIN AGpkb2VAZXhhbXBsZS5jb20Ad3JvbmdwYXNz", 5)
+        .await;
+    smtp.auth("LOGIN", 3).await;
+    smtp.send("amRvZUBleGFtcGxlLmNvbQ==").await;
+    smtp.read(1, 3).await;
+    smtp.send("MTIzNDU=").await;
+    smtp.read(1, 2).await;
+
+    // Only addresses of the account's identities can be used as senders
+    smtp.mail_from("jane_smith@example.com", 5).await;
+
+    // Submitted messages are signed, relayed and saved to the Sent mailbox
+    smtp.mail_from("jdoe@example.com", 2).await;
+    smtp.rcpt_to("jane_smith@example.com", 2).await;
+    smtp.data(3).await;
+    smtp.data_bytes(
+        concat!(
+            "From: jdoe@example.com\r\n",
+            "To: jane_smith@example.com\r\n",
+            "Subject: Scanned document\r\n",
+            "\r\n",
+            "Please find the scan attached."
+        ),
+        1,
+        2,
+    )
+    .await;
+    smtp.quit().await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new(
+            "<jdoe@example.com>",
+            ["<jane_smith@example.com>"],
+            "@s=my-selector-",
+        ),
+        true,
+    )
+    .await;
+
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([
+            [
+                "Mailbox/query",
+                {
+                    "accountId": &account_id,
+                    "filter": {
+                        "role": "sent"
+                    }
+                },
+                "0"
+            ],
+            [
+                "Email/query",
+                {
+                    "accountId": &account_id,
+                    "filter": {
+                        "subject": "Scanned document"
+                    }
+                },
+                "1"
+            ],
+            [
+                "Email/get",
+                {
+                    "accountId": &account_id,
+                    "#ids": {
+                        "resultOf": "1",
+                        "name": "Email/query",
+                        "path": "/ids"
+                    },
+                    "properties": ["mailboxIds", "keywords"]
+                },
+                "2"
+            ]
+        ]),
+    )
+    .await;
+    let sent_id = response[0]["ids"][0].as_str().unwrap();
+    let email = &response[2]["list"][0];
+    assert_eq!(email["mailboxIds"][sent_id], true, "{}", response[2]);
+    assert_eq!(email["keywords"]["$seen"], true, "{}", response[2]);
 
     // Verify onSuccessUpdateEmail action
     let mut request = client.build();
@@ -537,6 +1074,12 @@ pub fn spawn_mock_smtp_server() -> (mpsc::Receiver<MockMessage>, Arc<Mutex<MockS
                         )
                         .await
                         .unwrap();
+                    } else if settings.lock().defer_rcpt_to && !buf.contains("foobar.com") {
+                        tx.write_all(
+                            "451-Greylisted,\r\n451 please try again later.\r\n".as_bytes(),
+                        )
+                        .await
+                        .unwrap();
                     } else {
                         message
                             .rcpt_to
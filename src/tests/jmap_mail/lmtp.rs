@@ -24,19 +24,28 @@
 use std::time::Duration;
 
 use actix_web::web;
-use jmap::{types::jmap::JMAPId, SUPERUSER_ID};
+use jmap::{request::get::GetRequest, types::jmap::JMAPId, SUPERUSER_ID, URI};
 use jmap_client::{
     client::Client,
     core::set::{SetError, SetErrorType},
+    email,
+    mailbox::Role,
 };
-use jmap_sharing::principal::set::JMAPSetPrincipal;
+use jmap_sharing::{
+    principal::{get::JMAPGetPrincipal, set::JMAPSetPrincipal},
+    quota::get::JMAPGetQuota,
+};
+use serde_json::json;
 use store::{core::collection::Collection, Store};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
     net::TcpStream,
 };
 
-use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+use crate::{
+    tests::{jmap::jmap_request, store::utils::StoreCompareWith},
+    JMAPServer,
+};
 
 pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
 where
@@ -300,6 +309,350 @@ where
         );
     }
 
+    // Subaddresses are delivered to the base address, and filed into
+    // the mailbox named after the detail when it exists
+    let mailbox_id = client
+        .set_default_account_id(&account_id_1)
+        .mailbox_create("lists", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    lmtp.vrfy("jdoe+lists@example.com", 2).await;
+    for (rcpt, subject) in [
+        ("jdoe+lists@example.com", "Subaddressed"),
+        ("john.doe+unknown@example.com", "Unknown detail"),
+    ] {
+        lmtp.ingest(
+            "bill@example.com",
+            &[rcpt],
+            &format!(
+                "From: bill@example.com\r\nTo: {}\r\nSubject: {}\r\n\r\nTPS reports.",
+                rcpt, subject
+            ),
+        )
+        .await;
+    }
+    assert_eq!(
+        client
+            .email_query(
+                email::query::Filter::in_mailbox(&mailbox_id).into(),
+                None::<Vec<_>>,
+            )
+            .await
+            .unwrap()
+            .ids()
+            .len(),
+        1
+    );
+    assert_eq!(
+        server
+            .store
+            .get_document_ids(
+                JMAPId::parse(&account_id_1).unwrap().get_document_id(),
+                Collection::Mail
+            )
+            .unwrap()
+            .unwrap()
+            .len(),
+        6
+    );
+    lmtp.mail_from("bill@example.com", 2).await;
+    lmtp.rcpt_to("nobody+lists@example.com", 5).await;
+    lmtp.rset().await;
+    client.set_default_account_id(JMAPId::new(SUPERUSER_ID as u64));
+
+    // Wildcard aliases and catch-all addresses are resolved after exact matches,
+    // previously cached unknown addresses should be accepted as well
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([[
+            "Principal/set",
+            {
+                "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                "update": {
+                    &domain_id: {
+                        "catchAll": &account_id_2,
+                        "aliasPatterns": {
+                            "sales-*@example.com": &list_id,
+                            "*@other.org": &account_id_1
+                        }
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert!(
+        response[0]["notUpdated"][&domain_id].is_object(),
+        "{}",
+        response[0]
+    );
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([[
+            "Principal/set",
+            {
+                "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                "update": {
+                    &domain_id: {
+                        "catchAll": &account_id_2,
+                        "aliasPatterns": {
+                            "sales-*@example.com": &list_id
+                        }
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert!(
+        response[0]["updated"][&domain_id].is_object(),
+        "{}",
+        response[0]
+    );
+    lmtp.vrfy("non_existant@example.com", 2).await;
+    lmtp.expn("sales-emea@example.com", 2)
+        .await
+        .assert_contains("jane@example.com")
+        .assert_contains("bill@example.com");
+    for rcpt in ["sales-emea@example.com", "nobody@example.com"] {
+        lmtp.ingest(
+            "bill@example.com",
+            &[rcpt],
+            &format!(
+                "From: bill@example.com\r\nTo: {}\r\nSubject: Wildcard\r\n\r\nTPS reports.",
+                rcpt
+            ),
+        )
+        .await;
+    }
+    for (account_id, num_messages) in [(&account_id_1, 6), (&account_id_2, 5), (&account_id_3, 4)] {
+        assert_eq!(
+            server
+                .store
+                .get_document_ids(
+                    JMAPId::parse(account_id).unwrap().get_document_id(),
+                    Collection::Mail
+                )
+                .unwrap()
+                .unwrap()
+                .len(),
+            num_messages,
+            "for {}",
+            account_id
+        );
+    }
+    jmap_request(
+        &server,
+        &[URI::Core],
+        json!([[
+            "Principal/set",
+            {
+                "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                "update": {
+                    &domain_id: {
+                        "catchAll": null,
+                        "aliasPatterns": null
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    lmtp.vrfy("non_existant@example.com", 5).await;
+    lmtp.vrfy("sales-emea@example.com", 5).await;
+
+    // Quota enforcement
+    let used_quota = server
+        .store
+        .get_used_quota(JMAPId::parse(&account_id_3).unwrap().get_document_id())
+        .unwrap();
+    assert!(used_quota > 0);
+
+    // Uploads are charged to the account
+    client
+        .upload(Some(&account_id_3), b"quota test upload".to_vec(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .store
+            .get_used_quota(JMAPId::parse(&account_id_3).unwrap().get_document_id())
+            .unwrap(),
+        used_quota + 17
+    );
+    let used_quota = used_quota + 17;
+
+    // Imported uploads are only charged once
+    let mailbox_id = client
+        .set_default_account_id(&account_id_3)
+        .mailbox_create("imported", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let message = b"From: bill@example.com\r\nSubject: Imported\r\n\r\nImported message.\r\n";
+    client
+        .email_import(message.to_vec(), [mailbox_id], None::<Vec<String>>, None)
+        .await
+        .unwrap();
+    client.set_default_account_id(JMAPId::new(SUPERUSER_ID as u64));
+    assert_eq!(
+        server
+            .store
+            .get_used_quota(JMAPId::parse(&account_id_3).unwrap().get_document_id())
+            .unwrap(),
+        used_quota + message.len() as i64
+    );
+    let used_quota = used_quota + message.len() as i64;
+    client
+        .principal_set_quota(&account_id_3, Some(used_quota as u32 + 10))
+        .await
+        .unwrap();
+    let quota = server
+        .store
+        .quota_get(GetRequest {
+            account_id: JMAPId::parse(&account_id_3).unwrap(),
+            ..Default::default()
+        })
+        .unwrap()
+        .list
+        .pop()
+        .unwrap();
+    assert_eq!(quota.used, Some(used_quota as u64));
+    assert_eq!(quota.hard_limit, Some(used_quota as u64 + 10));
+    assert_eq!(quota.name.as_deref(), Some("bill@example.com"));
+    lmtp.ingest_with_code(
+        "jane@example.com",
+        &["bill@example.com"],
+        concat!(
+            "From: jane@example.com\r\n",
+            "To: bill@example.com\r\n",
+            "Subject: Over quota\r\n",
+            "\r\n",
+            "This message should not fit in Bill's mailbox."
+        ),
+        5,
+    )
+    .await
+    .assert_contains("552 5.2.2");
+    client
+        .principal_set_quota(&account_id_3, None)
+        .await
+        .unwrap();
+    assert!(server
+        .store
+        .quota_get(GetRequest {
+            account_id: JMAPId::parse(&account_id_3).unwrap(),
+            ..Default::default()
+        })
+        .unwrap()
+        .list
+        .is_empty());
+
+    // Signatures from local domains are verified using the domain's key
+    let response = jmap_request(
+        &server,
+        &[URI::Core],
+        json!([[
+            "Principal/set",
+            {
+                "accountId": JMAPId::from(SUPERUSER_ID).to_string(),
+                "update": {
+                    &domain_id: {
+                        "dkimAlgorithm": "rsa-sha256"
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert!(
+        response[0]["updated"][&domain_id].is_object(),
+        "{}",
+        response[0]
+    );
+    let signer = server
+        .store
+        .dkim_get("example.com".to_string())
+        .unwrap()
+        .unwrap()
+        .headers(["From", "To", "Subject"]);
+    for (subject, body, expected) in [
+        (
+            "Signed report",
+            "Did you get the memo?\r\n",
+            "dkim=pass header.d=example.com",
+        ),
+        (
+            "Tampered memo",
+            "Did you get the memo?!\r\n",
+            "dkim=fail (body hash did not verify) header.d=example.com",
+        ),
+    ] {
+        let message = format!(
+            "From: bill@example.com\r\nTo: jane@example.com\r\nSubject: {}\r\n\r\n",
+            subject
+        );
+        let signature = signer
+            .sign(format!("{}Did you get the memo?\r\n", message).as_bytes())
+            .unwrap()
+            .to_header();
+        lmtp.ingest(
+            "bill@example.com",
+            &["jane@example.com"],
+            &format!("{}{}{}", signature, message, body),
+        )
+        .await;
+
+        let raw_message = raw_message_by_subject(client, &account_id_2, subject).await;
+        assert!(
+            raw_message.starts_with("Authentication-Results: ") && raw_message.contains(expected),
+            "{}",
+            raw_message
+        );
+        assert!(raw_message.contains("arc=none"), "{}", raw_message);
+    }
+
+    // Results claiming to come from this server are removed
+    let hostname = gethostname::gethostname()
+        .to_str()
+        .unwrap_or("localhost")
+        .to_string();
+    lmtp.ingest(
+        "bill@example.com",
+        &["jane@example.com"],
+        &format!(
+            concat!(
+                "Authentication-Results: {};\r\n",
+                "\tdkim=pass header.d=forged.example.com\r\n",
+                "Authentication-Results: mx.example.org; dkim=none\r\n",
+                "From: bill@example.com\r\n",
+                "To: jane@example.com\r\n",
+                "Subject: Forged results\r\n",
+                "\r\n",
+                "Trust me.\r\n"
+            ),
+            hostname.to_uppercase()
+        ),
+    )
+    .await;
+    let raw_message = raw_message_by_subject(client, &account_id_2, "Forged results").await;
+    assert!(
+        raw_message.starts_with(&format!("Authentication-Results: {};", hostname))
+            && raw_message.contains("dkim=none;")
+            && raw_message.contains("Authentication-Results: mx.example.org; dkim=none")
+            && !raw_message.contains("forged.example.com"),
+        "{}",
+        raw_message
+    );
+    client.set_default_account_id(JMAPId::new(SUPERUSER_ID as u64));
+
     // Size checks
     lmtp.send("MAIL FROM:<hello@world> SIZE=943718400").await;
     lmtp.read(1, 5).await;
@@ -320,6 +673,26 @@ where
     server.store.assert_is_empty();
 }
 
+async fn raw_message_by_subject(client: &mut Client, account_id: &str, subject: &str) -> String {
+    client.set_default_account_id(account_id);
+    let message_id = client
+        .email_query(
+            email::query::Filter::subject(subject).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    let email = client
+        .email_get(&message_id, [email::Property::BlobId].into())
+        .await
+        .unwrap()
+        .unwrap();
+    String::from_utf8(client.download(email.blob_id().unwrap()).await.unwrap()).unwrap()
+}
+
 pub struct SmtpConnection {
     reader: Lines<BufReader<ReadHalf<TcpStream>>>,
     writer: WriteHalf<TcpStream>,
@@ -367,8 +740,16 @@ impl SmtpConnection {
     }
 
     pub async fn connect_peer(peer_num: usize) -> Self {
+        SmtpConnection::connect_port(11200 + peer_num).await
+    }
+
+    pub async fn connect_submission() -> Self {
+        SmtpConnection::connect_port(11501).await
+    }
+
+    pub async fn connect_port(port: usize) -> Self {
         let (reader, writer) = tokio::io::split(
-            TcpStream::connect(format!("127.0.0.1:{}", 11200 + peer_num))
+            TcpStream::connect(format!("127.0.0.1:{}", port))
                 .await
                 .unwrap(),
         );
@@ -385,6 +766,16 @@ impl SmtpConnection {
         self.read(1, 2).await
     }
 
+    pub async fn ehlo(&mut self) -> Vec<String> {
+        self.send("EHLO localhost").await;
+        self.read(1, 2).await
+    }
+
+    pub async fn auth(&mut self, mechanism: &str, code: u8) -> Vec<String> {
+        self.send(&format!("AUTH {}", mechanism)).await;
+        self.read(1, code).await
+    }
+
     pub async fn mail_from(&mut self, sender: &str, code: u8) -> Vec<String> {
         self.send(&format!("MAIL FROM:<{}>", sender)).await;
         self.read(1, code).await
@@ -22,7 +22,10 @@
 */
 
 use actix_web::web;
-use jmap::types::{jmap::JMAPId, state::JMAPState};
+use jmap::{
+    types::{jmap::JMAPId, state::JMAPState},
+    SUPERUSER_ID, URI,
+};
 use jmap_client::{
     client::Client,
     core::{
@@ -33,10 +36,17 @@ use jmap_client::{
     Error, Set,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use store::{ahash::AHashMap, Store};
 
-use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+use crate::{
+    tests::{
+        jmap::{jmap_raw_request, jmap_request},
+        store::utils::StoreCompareWith,
+    },
+    JMAPServer,
+};
 
 pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
 where
@@ -607,6 +617,154 @@ where
         ["inbox", "sent", "spam"]
     );
 
+    // Sort by name using collations
+    let account_id = JMAPId::new(SUPERUSER_ID as u64).to_string();
+    let response = jmap_request(
+        &server,
+        &[URI::Core, URI::Mail],
+        json!([[
+            "Mailbox/set",
+            {
+                "accountId": &account_id,
+                "create": {
+                    "p": {
+                        "name": "Collations"
+                    }
+                }
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let parent_id = response[0]["created"]["p"]["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let names = ["apple", "Banana", "10 Downing", "9 Elms", "Cherry"];
+    let create = names
+        .iter()
+        .enumerate()
+        .map(|(pos, name)| {
+            (
+                format!("m{}", pos),
+                json!({"name": name, "parentId": &parent_id}),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let response = jmap_request(
+        &server,
+        &[URI::Core, URI::Mail],
+        json!([[
+            "Mailbox/set",
+            {
+                "accountId": &account_id,
+                "create": create
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    let mut collation_ids = AHashMap::default();
+    for (pos, name) in names.iter().enumerate() {
+        collation_ids.insert(
+            response[0]["created"][format!("m{}", pos)]["id"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+            *name,
+        );
+    }
+
+    for (collation, is_ascending, expected) in [
+        (
+            None,
+            true,
+            vec!["10 Downing", "9 Elms", "apple", "Banana", "Cherry"],
+        ),
+        (
+            Some("i;unicode-casemap"),
+            false,
+            vec!["Cherry", "Banana", "apple", "9 Elms", "10 Downing"],
+        ),
+        (
+            Some("i;ascii-casemap"),
+            true,
+            vec!["10 Downing", "9 Elms", "apple", "Banana", "Cherry"],
+        ),
+        (Some("i;ascii-numeric"), true, vec!["9 Elms", "10 Downing"]),
+    ] {
+        let mut comparator = json!({"property": "name", "isAscending": is_ascending});
+        if let Some(collation) = collation {
+            comparator["collation"] = collation.into();
+        }
+        let response = jmap_request(
+            &server,
+            &[URI::Core, URI::Mail],
+            json!([[
+                "Mailbox/query",
+                {
+                    "accountId": &account_id,
+                    "filter": {
+                        "parentId": &parent_id
+                    },
+                    "sort": [comparator]
+                },
+                "0"
+            ]]),
+        )
+        .await;
+        let results = response[0]["ids"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| collation_ids[id.as_str().unwrap()])
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), names.len(), "{:?}", collation);
+
+        // Non-numeric names have the same sort key under i;ascii-numeric
+        assert_eq!(&results[..expected.len()], expected, "{:?}", collation);
+    }
+
+    let response = jmap_raw_request(
+        &server,
+        &[URI::Core, URI::Mail],
+        json!([[
+            "Mailbox/query",
+            {
+                "accountId": &account_id,
+                "sort": [{"property": "name", "collation": "i;octet"}]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(response[0][0], "error");
+    assert_eq!(response[0][1]["type"], "unsupportedSort");
+
+    jmap_request(
+        &server,
+        &[URI::Core, URI::Mail],
+        json!([
+            [
+                "Mailbox/set",
+                {
+                    "accountId": &account_id,
+                    "destroy": collation_ids.keys().collect::<Vec<_>>()
+                },
+                "0"
+            ],
+            [
+                "Mailbox/set",
+                {
+                    "accountId": &account_id,
+                    "destroy": [&parent_id]
+                },
+                "1"
+            ]
+        ]),
+    )
+    .await;
+
     let mut request = client.build();
     request.query_mailbox().arguments().sort_as_tree(true);
     let mut ids = request.send_query_mailbox().await.unwrap().take_ids();
@@ -21,14 +21,18 @@
  * for more details.
 */
 
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::Duration};
 
 use actix_web::web;
-use jmap::types::jmap::JMAPId;
+use jmap::{types::jmap::JMAPId, URI};
 use jmap_client::{client::Client, core::query, email::query::Filter, mailbox::Role};
+use serde_json::json;
 use store::{ahash::AHashMap, Store};
 
-use crate::{tests::store::utils::StoreCompareWith, JMAPServer};
+use crate::{
+    tests::{jmap::jmap_request, store::utils::StoreCompareWith},
+    JMAPServer,
+};
 
 pub async fn test<T>(server: web::Data<JMAPServer<T>>, client: &mut Client)
 where
@@ -58,6 +62,7 @@ where
         "mixed",
         "text_plain",
         "text_plain_chinese",
+        "docx",
     ] {
         let mut file_name = test_dir.clone();
         file_name.push(format!("{}.eml", email_name));
@@ -74,6 +79,9 @@ where
         email_ids.insert(email_name, email_id);
     }
 
+    // Attachments are indexed in the background
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
     // Run tests
     for (filter, email_name, snippet_subject, snippet_preview) in [
         (
@@ -155,6 +163,51 @@ where
                 "cualquier hexágono se "
             )),
         ),
+        (
+            Filter::text("hydroponic").into(),
+            "docx",
+            None,
+            Some(concat!(
+                "Quarterly Report Revenue from the <mark>hydroponic</mark> greenhouse ",
+                "division grew by 12% during the third quarter."
+            )),
+        ),
+        (
+            Filter::body("greenhouse").into(),
+            "docx",
+            None,
+            Some(concat!(
+                "Quarterly Report Revenue from the hydroponic <mark>greenhouse</mark> ",
+                "division grew by 12% during the third quarter."
+            )),
+        ),
+        (
+            Filter::text("hydropon*").into(),
+            "docx",
+            None,
+            Some(concat!(
+                "Quarterly Report Revenue from the <mark>hydroponic</mark> greenhouse ",
+                "division grew by 12% during the third quarter."
+            )),
+        ),
+        (
+            Filter::body("\"greenhouse division\" revenue").into(),
+            "docx",
+            None,
+            Some(concat!(
+                "Quarterly Report <mark>Revenue</mark> from the hydroponic ",
+                "<mark>greenhouse division</mark> grew by 12% during the third quarter."
+            )),
+        ),
+        (
+            Filter::body("greenhuose~").into(),
+            "docx",
+            None,
+            Some(concat!(
+                "Quarterly Report Revenue from the hydroponic <mark>greenhouse</mark> ",
+                "division grew by 12% during the third quarter."
+            )),
+        ),
     ] {
         let mut request = client.build();
         let result_ref = request
@@ -186,6 +239,59 @@ where
         );
     }
 
+    // Sort by relevance, using receivedAt as a tiebreaker
+    let mut relevance_ids = AHashMap::default();
+    for (name, subject, body, received_at) in [
+        ("body_new", "Garden notes", "An orchid.", 2000),
+        ("subject", "Orchid care", "Water the orchid weekly.", 1000),
+        ("body_old", "Garden notes", "An orchid.", 1000),
+        (
+            "body_many",
+            "Weekly digest",
+            "Orchid, orchid and another orchid.",
+            1000,
+        ),
+    ] {
+        let email_id = client
+            .email_import(
+                format!("From: john@example.org\nSubject: {}\n\n{}\n", subject, body).into_bytes(),
+                [&mailbox_id],
+                None::<Vec<&str>>,
+                Some(received_at),
+            )
+            .await
+            .unwrap()
+            .take_id();
+        relevance_ids.insert(email_id, name);
+    }
+
+    let response = jmap_request(
+        &server,
+        &[URI::Core, URI::Mail],
+        json!([[
+            "Email/query",
+            {
+                "accountId": JMAPId::new(1).to_string(),
+                "filter": {"text": "orchid"},
+                "sort": [
+                    {"property": "relevance", "isAscending": false},
+                    {"property": "receivedAt", "isAscending": false}
+                ]
+            },
+            "0"
+        ]]),
+    )
+    .await;
+    assert_eq!(
+        response[0]["ids"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| relevance_ids[id.as_str().unwrap()])
+            .collect::<Vec<_>>(),
+        ["subject", "body_many", "body_new", "body_old"]
+    );
+
     // Destroy test data
     client.mailbox_destroy(&mailbox_id, true).await.unwrap();
 
@@ -342,6 +342,40 @@ where
     .await
     .assert_contains("Rejected from an included script");
 
+    // Implicit keep should file subaddressed messages into the mailbox
+    // named after the detail
+    let mailbox_id = client
+        .mailbox_create("lists", None::<String>, mailbox::Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    client
+        .sieve_script_create("test_keep", b"keep;".to_vec(), true)
+        .await
+        .unwrap();
+    lmtp.ingest(
+        "bill@example.com",
+        &["jdoe+lists@example.com"],
+        concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe+lists@example.com\r\n",
+            "Subject: TPS Reports\r\n",
+            "\r\n",
+            "Did you get the memo?"
+        ),
+    )
+    .await;
+    let email_ids = client
+        .email_query(
+            email::query::Filter::in_mailbox(&mailbox_id).into(),
+            None::<Vec<_>>,
+        )
+        .await
+        .unwrap()
+        .take_ids();
+    assert_eq!(email_ids.len(), 1, "Subaddressed message was not filed.");
+    client.email_destroy(&email_ids[0]).await.unwrap();
+
     // Start mock SMTP server
     let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
 
@@ -21,11 +21,16 @@
  * for more details.
 */
 
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use store::{
     ahash::AHashMap,
-    blob::{BlobId, BLOB_HASH_LEN},
+    blob::{BlobBackend, BlobId, BlobReader, BlobStore, BlobWriter, BLOB_HASH_LEN},
     core::{collection::Collection, document::Document},
     serialize::{key::BlobKey, leb128::Leb128Reader, StoreDeserialize, StoreSerialize},
     write::{
@@ -83,7 +88,7 @@ where
     assert_eq!(expected_count, db.get_all_blobs());
 
     // Purgimg should not delete any blobs at this point
-    db.purge_blobs().unwrap();
+    db.purge_blobs(true).unwrap();
     assert_eq!(expected_count, db.get_all_blobs());
 
     // Link blob to an account
@@ -103,7 +108,7 @@ where
             &expired_timestamp.serialize().unwrap(),
         )
         .unwrap();
-    db.purge_blobs().unwrap();
+    db.purge_blobs(true).unwrap();
     expected_count.insert(blob_local.clone(), (1, 0));
     assert_eq!(expected_count, db.get_all_blobs());
 
@@ -113,7 +118,7 @@ where
     let mut wb = WriteBatch::new(2);
     wb.update_document(document);
     db.write(wb).unwrap();
-    db.purge_blobs().unwrap();
+    db.purge_blobs(true).unwrap();
     expected_count.remove(&blob_local);
     assert_eq!(expected_count, db.get_all_blobs());
 
@@ -127,9 +132,192 @@ where
             )
             .unwrap();
     }
-    db.purge_blobs().unwrap();
+    db.purge_blobs(true).unwrap();
     expected_count.remove(&blob_external);
     assert_eq!(expected_count, db.get_all_blobs());
+
+    // Stream a blob in chunks and read it back
+    let blob_3 = (0..300000u32).map(|n| (n % 251) as u8).collect::<Vec<_>>();
+    let blob_streamed = BlobId::new_external(&blob_3);
+    for _ in 0..2 {
+        let mut writer = db.blob_store.writer().unwrap();
+        for chunk in blob_3.chunks(4096) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.size(), blob_3.len() as u64);
+        assert_eq!(db.blob_store_writer(writer).unwrap(), blob_streamed);
+    }
+    expected_count.insert(blob_streamed.clone(), (0, 1));
+    assert_eq!(expected_count, db.get_all_blobs());
+
+    let mut reader = db.blob_reader(&blob_streamed).unwrap().unwrap();
+    assert_eq!(reader.size(), blob_3.len() as u64);
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, blob_3);
+    assert_eq!(
+        db.blob_get_range(&blob_streamed, 1000..2000)
+            .unwrap()
+            .unwrap(),
+        &blob_3[1000..2000]
+    );
+
+    // Abandoned writers leave nothing behind
+    let mut writer = db.blob_store.writer().unwrap();
+    writer.write_all(b"incomplete").unwrap();
+    drop(writer);
+    assert_eq!(
+        std::fs::read_dir(&db.blob_store.temp_dir().path)
+            .unwrap()
+            .count(),
+        0
+    );
+
+    db.db
+        .set(
+            ColumnFamily::Blobs,
+            &BlobKey::serialize_prefix(&blob_streamed, 0),
+            &expired_timestamp.serialize().unwrap(),
+        )
+        .unwrap();
+    db.purge_blobs(true).unwrap();
+    expected_count.remove(&blob_streamed);
+    assert_eq!(expected_count, db.get_all_blobs());
+    assert!(db.blob_reader(&blob_streamed).unwrap().is_none());
+}
+
+pub fn test_encryption<T>(db: Arc<JMAPStore<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let base_path = match &db.blob_store {
+        BlobBackend::Local(store) => store.base_path.clone(),
+        _ => unreachable!(),
+    };
+    assert!(db.blob_store.temp_dir().cipher.is_some());
+
+    // Use incompressible contents so each chunk maps to a known file offset
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let blob = (0..200000)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        })
+        .collect::<Vec<_>>();
+    let blob_id = BlobId::new_external(&blob);
+    db.blob_store(&blob_id, blob.clone()).unwrap();
+    db.blob_link_ephemeral(&blob_id, 0).unwrap();
+
+    // Blobs are stored encrypted
+    let blob_path = find_blob_file(&base_path, &blob_id.to_string()).unwrap();
+    let contents = std::fs::read(&blob_path).unwrap();
+    assert!(contents.len() > blob.len());
+    assert!(!contents.windows(64).any(|window| window == &blob[..64]));
+
+    // Decrypted on read, including ranges spanning several chunks
+    assert_eq!(db.blob_get(&blob_id).unwrap().unwrap(), blob);
+    assert_eq!(
+        db.blob_get_range(&blob_id, 65000..140000).unwrap().unwrap(),
+        &blob[65000..140000]
+    );
+    let mut reader = db.blob_reader(&blob_id).unwrap().unwrap();
+    assert_eq!(reader.size(), blob.len() as u64);
+    reader.seek(SeekFrom::Start(131072)).unwrap();
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, &blob[131072..]);
+
+    // Storing the same blob again does not create a new copy
+    let mut writer = db.blob_store.writer().unwrap();
+    writer.write_all(&blob).unwrap();
+    assert_eq!(db.blob_store_writer(writer).unwrap(), blob_id);
+    assert_eq!(std::fs::read(&blob_path).unwrap(), contents);
+
+    // Tampered blobs are rejected, the remaining chunks can still be read
+    let mut tampered = contents;
+    tampered[70000] ^= 0xff;
+    std::fs::write(&blob_path, &tampered).unwrap();
+    assert!(db.blob_get(&blob_id).is_err());
+    assert_eq!(
+        db.blob_get_range(&blob_id, 150000..160000)
+            .unwrap()
+            .unwrap(),
+        &blob[150000..160000]
+    );
+
+    // Blobs replaced with plaintext are not served
+    std::fs::write(&blob_path, &blob).unwrap();
+    assert!(db.blob_get(&blob_id).is_err());
+}
+
+pub fn test_compression<T>(db: Arc<JMAPStore<T>>)
+where
+    T: for<'x> Store<'x> + 'static,
+{
+    let base_path = match &db.blob_store {
+        BlobBackend::Local(store) => store.base_path.clone(),
+        _ => unreachable!(),
+    };
+
+    // Text is stored compressed
+    let text =
+        "Subject: Lorem ipsum\r\n\r\nLorem ipsum dolor sit amet, consectetur adipiscing elit.\r\n"
+            .repeat(5000)
+            .into_bytes();
+    let text_id = BlobId::new_external(&text);
+    let mut writer = db.blob_store.writer().unwrap();
+    for chunk in text.chunks(5000) {
+        writer.write_all(chunk).unwrap();
+    }
+    assert_eq!(db.blob_store_writer(writer).unwrap(), text_id);
+    let contents =
+        std::fs::read(find_blob_file(&base_path, &text_id.to_string()).unwrap()).unwrap();
+    assert!(contents.starts_with(b"SJZ"));
+    assert!(contents.len() < text.len() / 4);
+
+    assert_eq!(db.blob_get(&text_id).unwrap().unwrap(), text);
+    assert_eq!(
+        db.blob_get_range(&text_id, 65000..200000).unwrap().unwrap(),
+        &text[65000..200000]
+    );
+    let mut reader = db.blob_reader(&text_id).unwrap().unwrap();
+    assert_eq!(reader.size(), text.len() as u64);
+    reader.seek(SeekFrom::End(-100)).unwrap();
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, &text[text.len() - 100..]);
+
+    // Already compressed formats are stored as is
+    let gzip = [b"\x1f\x8b\x08\x00".as_ref(), &text].concat();
+    let gzip_id = BlobId::new_external(&gzip);
+    db.blob_store(&gzip_id, gzip.clone()).unwrap();
+    assert_eq!(
+        std::fs::read(find_blob_file(&base_path, &gzip_id.to_string()).unwrap()).unwrap(),
+        gzip
+    );
+    assert_eq!(
+        db.blob_get_range(&gzip_id, 10..20).unwrap().unwrap(),
+        &gzip[10..20]
+    );
+}
+
+fn find_blob_file(path: &Path, name: &str) -> Option<PathBuf> {
+    for entry in std::fs::read_dir(path).ok()? {
+        let path = entry.ok()?.path();
+        if path.is_dir() {
+            if let Some(path) = find_blob_file(&path, name) {
+                return Some(path);
+            }
+        } else if path
+            .file_name()
+            .map_or(false, |file_name| file_name == name)
+        {
+            return Some(path);
+        }
+    }
+    None
 }
 
 trait GetAllBlobs {
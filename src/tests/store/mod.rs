@@ -24,6 +24,7 @@
 pub mod blobs;
 pub mod log;
 pub mod query;
+pub mod s3;
 pub mod utils;
 
 use std::{path::PathBuf, sync::Arc};
@@ -68,8 +69,28 @@ fn store_tests() {
     let db = Arc::new(db);
 
     blobs::test(db.clone());
+    blobs::test_compression(db.clone());
     log::test(db.clone());
     query::test(db, true);
 
     destroy_temp_dir(&temp_dir);
 }
+
+#[test]
+#[ignore]
+fn encrypted_store_tests() {
+    let (mut settings, temp_dir) = init_settings("strdb_encrypted", 1, 1, true);
+    settings
+        .args
+        .insert("blob-encryption-key".to_string(), "secret key".to_string());
+    let db = Arc::new(JMAPStore::<RocksDB>::new(
+        RocksDB::open(&settings).unwrap(),
+        JMAPConfig::from(&settings),
+        &settings,
+    ));
+
+    blobs::test(db.clone());
+    blobs::test_encryption(db);
+
+    destroy_temp_dir(&temp_dir);
+}
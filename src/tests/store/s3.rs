@@ -0,0 +1,296 @@
+/*
+ * Copyright (c) 2020-2022, Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart JMAP Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use actix_web::{
+    http::{header, StatusCode},
+    web, App, HttpRequest, HttpResponse, HttpServer,
+};
+use store::{
+    ahash::AHashMap,
+    blob::{BlobBackend, BlobId, BlobReader, BlobStore},
+    config::jmap::JMAPConfig,
+    parking_lot::Mutex,
+    JMAPStore, Store,
+};
+use store_rocksdb::RocksDB;
+
+use super::utils::{destroy_temp_dir, init_settings};
+
+const S3_PORT: u16 = 9600;
+const S3_BUCKET: &str = "stalwart";
+
+// Minimal in-memory S3 stand-in
+#[derive(Default)]
+struct FakeS3 {
+    objects: AHashMap<String, Vec<u8>>,
+    uploads: AHashMap<String, BTreeMap<u32, Vec<u8>>>,
+    next_upload_id: u32,
+    multipart_uploads: usize,
+    requests: usize,
+}
+
+#[actix_web::test]
+#[ignore]
+async fn s3_store_tests() {
+    let s3 = web::Data::new(Mutex::new(FakeS3::default()));
+
+    // Start S3 server
+    let s3_ = s3.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(s3_.clone())
+            .app_data(web::PayloadConfig::new(64 * 1024 * 1024))
+            .default_service(web::to(handle_s3_request))
+    })
+    .bind(("127.0.0.1", S3_PORT))
+    .unwrap()
+    .run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    // The S3 client is blocking, run the store tests in a separate thread
+    let s3_ = s3.clone();
+    tokio::task::spawn_blocking(move || {
+        let (mut settings, temp_dir) = init_settings("strdb_s3", 1, 1, true);
+        for (key, value) in [
+            ("blob-store", "s3".to_string()),
+            ("blob-s3-bucket", S3_BUCKET.to_string()),
+            ("blob-s3-region", "local".to_string()),
+            ("blob-s3-endpoint", format!("http://127.0.0.1:{}", S3_PORT)),
+            ("blob-s3-access-key", "minio".to_string()),
+            ("blob-s3-secret-key", "minio123".to_string()),
+            ("blob-s3-prefix", "blobs/".to_string()),
+            ("blob-s3-path-style", "true".to_string()),
+            ("blob-s3-part-size", "5242880".to_string()),
+            ("blob-compression", "none".to_string()),
+        ] {
+            settings.args.insert(key.to_string(), value);
+        }
+        let db = Arc::new(JMAPStore::new(
+            RocksDB::open(&settings).unwrap(),
+            JMAPConfig::from(&settings),
+            &settings,
+        ));
+        assert!(matches!(db.blob_store, BlobBackend::S3(_)));
+        assert!(db.blob_store.is_shared());
+
+        // Run the regular blob tests against S3
+        super::blobs::test(db.clone());
+        assert!(s3_.lock().objects.is_empty());
+
+        // Large blobs are sent using multipart uploads
+        let blob = (0..12 * 1024 * 1024u32)
+            .map(|n| (n % 253) as u8)
+            .collect::<Vec<_>>();
+        let blob_id = BlobId::new_external(&blob);
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        assert_eq!(s3_.lock().multipart_uploads, 1);
+        assert_eq!(
+            s3_.lock().objects.get(&format!("blobs/{}", blob_id)),
+            Some(&blob)
+        );
+        assert!(s3_.lock().uploads.is_empty());
+
+        // Storing the same blob again is a no-op
+        db.blob_store(&blob_id, blob.clone()).unwrap();
+        assert_eq!(s3_.lock().multipart_uploads, 1);
+
+        // Opening a reader takes a single request
+        let requests = s3_.lock().requests;
+        let mut reader = db.blob_reader(&blob_id).unwrap().unwrap();
+        assert_eq!(s3_.lock().requests, requests + 1);
+
+        // Read ranges crossing the read buffer boundaries
+        assert_eq!(reader.size(), blob.len() as u64);
+        let mut buf = vec![0u8; 2 * 1024 * 1024];
+        std::io::Seek::seek(&mut reader, std::io::SeekFrom::Start(1000)).unwrap();
+        std::io::Read::read_exact(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, &blob[1000..1000 + buf.len()]);
+        assert_eq!(
+            db.blob_get_range(&blob_id, 5000..6000).unwrap().unwrap(),
+            &blob[5000..6000]
+        );
+        assert_eq!(db.blob_get(&blob_id).unwrap().unwrap(), blob);
+
+        // Followers only remove the database reference
+        db.db
+            .delete(
+                store::ColumnFamily::Blobs,
+                &store::serialize::key::BlobKey::serialize_prefix(&blob_id, 0),
+            )
+            .unwrap();
+        db.purge_blobs(false).unwrap();
+        assert!(!db.blob_exists(&blob_id).unwrap());
+        assert_eq!(s3_.lock().objects.len(), 1);
+
+        // A node sharing the store can register the blob without fetching it
+        assert!(db.blob_register_shared(&blob_id).unwrap());
+        assert!(db.blob_exists(&blob_id).unwrap());
+        db.db
+            .delete(
+                store::ColumnFamily::Blobs,
+                &store::serialize::key::BlobKey::serialize_prefix(&blob_id, 0),
+            )
+            .unwrap();
+        db.purge_blobs(true).unwrap();
+        assert!(s3_.lock().objects.is_empty());
+        assert!(!db.blob_register_shared(&blob_id).unwrap());
+
+        destroy_temp_dir(&temp_dir);
+    })
+    .await
+    .unwrap();
+
+    handle.stop(true).await;
+}
+
+async fn handle_s3_request(
+    request: HttpRequest,
+    body: web::Bytes,
+    s3: web::Data<Mutex<FakeS3>>,
+) -> HttpResponse {
+    let key = if let Some(key) = request
+        .path()
+        .strip_prefix(&format!("/{}/", S3_BUCKET))
+        .filter(|key| !key.is_empty())
+    {
+        key.to_string()
+    } else {
+        return HttpResponse::NotFound().finish();
+    };
+    let query = request
+        .query_string()
+        .split('&')
+        .filter_map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            if !name.is_empty() {
+                Some((name.to_string(), value.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect::<AHashMap<_, _>>();
+    let mut s3 = s3.lock();
+    s3.requests += 1;
+
+    match (request.method().as_str(), query.get("uploadId")) {
+        ("POST", None) if query.contains_key("uploads") => {
+            s3.next_upload_id += 1;
+            let upload_id = format!("upload{}", s3.next_upload_id);
+            s3.uploads.insert(upload_id.clone(), BTreeMap::new());
+            HttpResponse::Ok().body(format!(
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+                    "<InitiateMultipartUploadResult>",
+                    "<Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId>",
+                    "</InitiateMultipartUploadResult>"
+                ),
+                S3_BUCKET, key, upload_id
+            ))
+        }
+        ("PUT", Some(upload_id)) => {
+            let part_number = query
+                .get("partNumber")
+                .and_then(|p| p.parse::<u32>().ok())
+                .unwrap_or(0);
+            if let Some(parts) = s3.uploads.get_mut(upload_id) {
+                parts.insert(part_number, body.to_vec());
+                HttpResponse::Ok()
+                    .insert_header((header::ETAG, format!("\"part{}\"", part_number)))
+                    .finish()
+            } else {
+                HttpResponse::NotFound().finish()
+            }
+        }
+        ("POST", Some(upload_id)) => {
+            if let Some(parts) = s3.uploads.remove(upload_id) {
+                s3.objects
+                    .insert(key.clone(), parts.into_values().flatten().collect());
+                s3.multipart_uploads += 1;
+                HttpResponse::Ok().body(format!(
+                    concat!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+                        "<CompleteMultipartUploadResult>",
+                        "<Bucket>{}</Bucket><Key>{}</Key><ETag>\"object\"</ETag>",
+                        "</CompleteMultipartUploadResult>"
+                    ),
+                    S3_BUCKET, key
+                ))
+            } else {
+                HttpResponse::NotFound().finish()
+            }
+        }
+        ("DELETE", Some(upload_id)) => {
+            s3.uploads.remove(upload_id);
+            HttpResponse::NoContent().finish()
+        }
+        ("PUT", None) => {
+            s3.objects.insert(key, body.to_vec());
+            HttpResponse::Ok()
+                .insert_header((header::ETAG, "\"object\""))
+                .finish()
+        }
+        ("DELETE", None) => {
+            s3.objects.remove(&key);
+            HttpResponse::NoContent().finish()
+        }
+        ("HEAD", None) => match s3.objects.get(&key) {
+            Some(object) => HttpResponse::Ok().body(object.clone()),
+            None => HttpResponse::NotFound().finish(),
+        },
+        ("GET", None) => match s3.objects.get(&key) {
+            Some(object) => {
+                let range = request
+                    .headers()
+                    .get(header::RANGE)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.strip_prefix("bytes="))
+                    .and_then(|h| h.split_once('-'))
+                    .map(|(start, end)| {
+                        let start = start.parse::<usize>().unwrap();
+                        let end = end
+                            .parse::<usize>()
+                            .map(|end| std::cmp::min(end + 1, object.len()))
+                            .unwrap_or(object.len());
+                        start..end
+                    });
+                match range {
+                    Some(range) if range.start >= object.len() => {
+                        HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE).finish()
+                    }
+                    Some(range) => HttpResponse::build(StatusCode::PARTIAL_CONTENT)
+                        .insert_header((
+                            header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", range.start, range.end - 1, object.len()),
+                        ))
+                        .body(object[range].to_vec()),
+                    None => HttpResponse::Ok().body(object.clone()),
+                }
+            }
+            None => HttpResponse::NotFound().finish(),
+        },
+        _ => HttpResponse::BadRequest().finish(),
+    }
+}
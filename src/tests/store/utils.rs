@@ -28,6 +28,10 @@ use flate2::bufread::GzDecoder;
 use jmap::orm::TinyORM;
 use jmap::principal::schema::Principal;
 use jmap::push_subscription::schema::PushSubscription;
+use jmap_calendars::calendar::schema::Calendar;
+use jmap_calendars::calendar_event::schema::CalendarEvent;
+use jmap_contacts::address_book::schema::AddressBook;
+use jmap_contacts::contact_card::schema::ContactCard;
 use jmap_mail::email_submission::schema::EmailSubmission;
 use jmap_mail::identity::schema::Identity;
 use jmap_mail::mail::schema::Email;
@@ -176,13 +180,41 @@ pub fn init_settings(
                 format!("http://127.0.0.1:{}", 8000 + peer_num),
             ),
             ("lmtp-port".to_string(), (11200 + peer_num).to_string()),
+            ("lmtp-subaddress-autofile".to_string(), "true".to_string()),
+            (
+                "submission-port".to_string(),
+                (11500 + peer_num).to_string(),
+            ),
+            (
+                "submission-trusted-ips".to_string(),
+                "127.0.0.1".to_string(),
+            ),
             ("max-objects-in-set".to_string(), "100000".to_string()),
             ("query-max-results".to_string(), "100000".to_string()),
             ("jmap-port".to_string(), (8000 + peer_num).to_string()),
             ("smtp-relay-host".to_string(), "127.0.0.1".to_string()),
             ("smtp-relay-port".to_string(), "9999".to_string()),
             ("smtp-relay-tls".to_string(), "false".to_string()),
+            // Submissions from jdoe@example.com go through an unreachable relay first
+            ("smtp-relays".to_string(), "offline".to_string()),
+            (
+                "smtp-relay-offline-host".to_string(),
+                "127.0.0.1".to_string(),
+            ),
+            ("smtp-relay-offline-port".to_string(), "9998".to_string()),
+            (
+                "smtp-relay-offline-identities".to_string(),
+                "jdoe@example.com".to_string(),
+            ),
+            (
+                "smtp-relay-offline-fallback".to_string(),
+                "default".to_string(),
+            ),
+            ("smtp-retry-interval".to_string(), "1".to_string()),
+            ("smtp-mx-resolver".to_string(), "static".to_string()),
+            ("dkim-rotation-lead".to_string(), "0".to_string()),
             ("max-concurrent-uploads".to_string(), "4".to_string()),
+            ("blob-compression".to_string(), "lz4".to_string()),
             ("max-concurrent-requests".to_string(), "8".to_string()),
             ("push-attempt-interval".to_string(), "500".to_string()),
             ("push-throttle".to_string(), "500".to_string()),
@@ -339,7 +371,15 @@ where
                         }
                     }
                     ColumnFamily::Values => {
-                        if (0..=9).contains(&key[0])
+                        if let Some(account_id) = quota_key_account(&key) {
+                            // Quota usage counters
+                            assert_eq!(
+                                i64::deserialize(&value).unwrap(),
+                                other.db.get::<i64>(cf, &key).unwrap().unwrap_or(0),
+                                "Quota mismatch for account {}",
+                                account_id
+                            );
+                        } else if (0..=9).contains(&key[0])
                             && &key[..] != FOLLOWER_COMMIT_INDEX_KEY
                             && &key[..] != LEADER_COMMIT_INDEX_KEY
                         {
@@ -429,7 +469,32 @@ where
                                                 TinyORM::<SieveScript>::deserialize(&other_value)
                                                     .unwrap()
                                             ),
-                                            Collection::Thread | Collection::None => unreachable!(),
+                                            Collection::AddressBook => assert_eq!(
+                                                TinyORM::<AddressBook>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<AddressBook>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::ContactCard => assert_eq!(
+                                                TinyORM::<ContactCard>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<ContactCard>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::Calendar => assert_eq!(
+                                                TinyORM::<Calendar>::deserialize(&value).unwrap(),
+                                                TinyORM::<Calendar>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::CalendarEvent => assert_eq!(
+                                                TinyORM::<CalendarEvent>::deserialize(&value)
+                                                    .unwrap(),
+                                                TinyORM::<CalendarEvent>::deserialize(&other_value)
+                                                    .unwrap()
+                                            ),
+                                            Collection::Thread
+                                            | Collection::Quota
+                                            | Collection::None => unreachable!(),
                                         }
                                     } else if ASSERT {
                                         panic!(
@@ -611,6 +676,25 @@ where
 
     fn assert_is_empty(&self) {
         let mut keys = std::collections::BTreeMap::new();
+
+        // Uploaded blobs remain charged to the account until their ephemeral links expire
+        let mut uploaded_quota = AHashMap::default();
+        for (key, value) in self
+            .db
+            .iterator(ColumnFamily::Blobs, &[0u8], store::Direction::Forward)
+            .unwrap()
+        {
+            if key.len() > BLOB_HASH_LEN + 1 && value.len() == 2 * std::mem::size_of::<u64>() {
+                let (account_id, bytes_read) = (&key[BLOB_HASH_LEN + 1..])
+                    .read_leb128::<AccountId>()
+                    .unwrap();
+                if key.len() == BLOB_HASH_LEN + 1 + bytes_read {
+                    *uploaded_quota.entry(account_id).or_insert(0) +=
+                        i64::deserialize(&value[std::mem::size_of::<u64>()..]).unwrap();
+                }
+            }
+        }
+
         for cf in [
             ColumnFamily::Bitmaps,
             ColumnFamily::Values,
@@ -633,6 +717,18 @@ where
                             value
                         );
                     }
+                    ColumnFamily::Values if quota_key_account(&key).is_some() => {
+                        assert_eq!(
+                            i64::deserialize(&value).unwrap(),
+                            uploaded_quota
+                                .get(&quota_key_account(&key).unwrap())
+                                .copied()
+                                .unwrap_or(0),
+                            "{:?}: {:?}",
+                            key,
+                            value
+                        );
+                    }
                     ColumnFamily::Values if (0..=9).contains(&key[0]) => {
                         panic!("{:?} {:?}={:?}", cf, key, value);
                     }
@@ -662,3 +758,13 @@ where
         self.id_assigner.invalidate_all();
     }
 }
+
+// Returns the account id of a quota usage counter key.
+fn quota_key_account(key: &[u8]) -> Option<AccountId> {
+    let (account_id, _) = key.read_leb128::<AccountId>()?;
+    if key == ValueKey::serialize_quota(account_id) {
+        Some(account_id)
+    } else {
+        None
+    }
+}